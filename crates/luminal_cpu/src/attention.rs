@@ -0,0 +1,478 @@
+use std::borrow::Cow;
+
+use itertools::Itertools;
+use luminal::{
+    op::*,
+    prelude::{
+        petgraph::{visit::EdgeRef, Direction},
+        *,
+    },
+};
+
+use super::{get_contiguous, unary::Softmax};
+
+/// Number of keys scored at a time before folding them into the running softmax
+const KEY_TILE: usize = 64;
+
+/// Scaled dot product attention: softmax(q * k^T * scale + mask) * v
+///
+/// Inputs are queries [batch, s2, d], keys [batch, s1, d], values [batch, s1, dv] and an optional
+/// additive mask laid out as [batch, s2, s1]. Keys are processed in tiles with a streaming softmax,
+/// so the full score matrix is never materialized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attention {
+    pub scale: f32,
+}
+
+impl Operator for Attention {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (q_shape, k_shape, v_shape) = (
+            inp[0].1.shape_usize(),
+            inp[1].1.shape_usize(),
+            inp[2].1.shape_usize(),
+        );
+        let (s2, d) = (q_shape[q_shape.len() - 2], q_shape[q_shape.len() - 1]);
+        let (s1, dv) = (k_shape[k_shape.len() - 2], v_shape[v_shape.len() - 1]);
+        let batch = q_shape[..q_shape.len() - 2].iter().product::<usize>();
+        let q = get_contiguous(&inp[0]);
+        let ((k, k_rows), (v, v_rows)) = (get_rows(&inp[1]), get_rows(&inp[2]));
+        let mask = inp.get(3).map(|(t, sh)| {
+            (
+                t.borrowed().downcast_ref::<Vec<f32>>().unwrap(),
+                sh.index_expression(),
+                sh.valid_expression(),
+            )
+        });
+
+        let mut out = vec![0.; batch * s2 * dv];
+        let mut scores = vec![0.; KEY_TILE];
+        let mut stack = vec![];
+        for (row, acc) in out.chunks_exact_mut(dv).enumerate() {
+            let b = row / s2;
+            let q_row = &q[row * d..(row + 1) * d];
+            let (mut max, mut sum) = (f32::NEG_INFINITY, 0.);
+            for start in (0..s1).step_by(KEY_TILE) {
+                let scores = &mut scores[..KEY_TILE.min(s1 - start)];
+                for (j, s) in scores.iter_mut().enumerate() {
+                    let key = b * k_rows + start + j;
+                    *s = q_row
+                        .iter()
+                        .zip(&k[key * d..(key + 1) * d])
+                        .map(|(a, b)| a * b)
+                        .sum::<f32>()
+                        * self.scale;
+                    if let Some((data, ind, val)) = &mask {
+                        let i = row * s1 + start + j;
+                        if val.exec_single_var_stack(i, &mut stack) != 0 {
+                            *s += data[ind.exec_single_var_stack(i, &mut stack)];
+                        }
+                    }
+                }
+                let new_max = scores.iter().fold(max, |a, b| a.max(*b));
+                if new_max == f32::NEG_INFINITY {
+                    // Everything so far is fully masked
+                    continue;
+                }
+                // Rescale what's been accumulated to the new max
+                let correction = (max - new_max).exp();
+                sum *= correction;
+                for a in acc.iter_mut() {
+                    *a *= correction;
+                }
+                for (j, s) in scores.iter().enumerate() {
+                    let p = (s - new_max).exp();
+                    sum += p;
+                    let value = b * v_rows + start + j;
+                    for (a, v) in acc.iter_mut().zip(&v[value * dv..(value + 1) * dv]) {
+                        *a += p * v;
+                    }
+                }
+                max = new_max;
+            }
+            for a in acc.iter_mut() {
+                *a /= sum;
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+/// Get the rows of keys or values, along with how many rows apart each batch starts.
+///
+/// A prefix of a larger buffer, like the filled part of a kv cache, is read in place rather than copied out.
+fn get_rows<'a>(inp: &'a (InputTensor<'a>, ShapeTracker)) -> (Cow<'a, [f32]>, usize) {
+    let sh = &inp.1;
+    let n = sh.len();
+    let unmasked = |i: usize| {
+        sh.mask[i].0.to_usize() == Some(0)
+            && (i == n - 2 || sh.mask[i].1.to_usize() == Some(i32::MAX as usize))
+    };
+    if sh.is_contiguous() && !sh.is_padded() && (0..n).all(unmasked) {
+        let data = inp.0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
+        return (Cow::Borrowed(data), sh.dims[n - 2].to_usize().unwrap());
+    }
+    (get_contiguous(inp), sh.shape_usize()[n - 2])
+}
+
+/// Replace attention subgraphs with a single streaming attention op
+#[derive(Debug, Default)]
+pub struct AttentionCompiler;
+
+impl Compiler for AttentionCompiler {
+    type Output = ();
+    fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut ids: T) {
+        // Look for the attention pattern
+        // matmul(softmax(add(mul(matmul(q, k), scale), mask)), v), where the scale and mask are optional
+        for softmax in graph.graph.node_indices().collect_vec() {
+            if !graph.graph.contains_node(softmax) || !graph.check_node_type::<Softmax>(softmax) {
+                continue;
+            }
+            let Some(Matched {
+                q,
+                k,
+                v,
+                mask,
+                scale,
+                intermediates,
+                output,
+            }) = match_attention(graph, softmax)
+            else {
+                continue;
+            };
+
+            // Insert Attention op
+            let mut attention = graph
+                .add_op(Attention { scale })
+                .input(q.0, q.1, q.2)
+                .input(k.0, k.1, k.2)
+                .input(v.0, v.1, v.2);
+            if let Some(mask) = mask {
+                attention = attention.input(mask.0, mask.1, mask.2);
+            }
+            let attention = attention.finish();
+
+            // Create edges to dests
+            move_outgoing_edge(output, attention, graph);
+            remap(output, attention, &mut ids, graph);
+
+            // Remove the old ops
+            graph.remove_node(output);
+            for node in intermediates {
+                graph.safe_remove_node(node, 0);
+            }
+        }
+    }
+}
+
+type Source = (NodeIndex, u8, ShapeTracker);
+
+struct Matched {
+    q: Source,
+    k: Source,
+    v: Source,
+    mask: Option<Source>,
+    scale: f32,
+    /// Nodes to remove, in dependency order from the output back
+    intermediates: Vec<NodeIndex>,
+    output: NodeIndex,
+}
+
+/// Walk outwards from a softmax to find the surrounding attention subgraph
+fn match_attention(graph: &Graph, softmax: NodeIndex) -> Option<Matched> {
+    // Softmax over the last dimension of a contiguous score tensor
+    let [(scores, _, scores_shape)] = graph.get_sources(softmax)[..] else {
+        return None;
+    };
+    if scores_shape.is_reshaped() || graph.get_op::<Softmax>(softmax).0 != scores_shape.len() - 1 {
+        return None;
+    }
+
+    // Forwards: the softmax output is only used as the lhs of a matmul with the values
+    let (wv_mul, _) = single_dest(graph, softmax)?;
+    let (wv_sum, _) = single_dest(graph, wv_mul)?;
+    let (w, v) = matmul_sources(graph, wv_mul, wv_sum)?;
+    if w.0 != softmax || w.2.is_reshaped() {
+        return None;
+    }
+    let mut v = v;
+    let n = v.2.len();
+    let mut axes = (0..n).collect_vec();
+    axes.swap(n - 2, n - 1);
+    v.2.permute(&axes);
+
+    // Backwards: an optional additive mask, an optional scale, then a matmul of queries and keys
+    let mut intermediates = vec![wv_mul, softmax];
+    let (mut chain, mut mask) = (scores, None);
+    if graph.check_node_type::<Add>(chain) {
+        single_dest(graph, chain)?;
+        intermediates.push(chain);
+        let srcs = graph.get_sources(chain);
+        let (s, m) = [(0, 1), (1, 0)]
+            .into_iter()
+            .find(|(s, _)| !srcs[*s].2.is_reshaped() && match_qk(graph, srcs[*s].0).is_some())?;
+        chain = srcs[s].0;
+        mask = Some(srcs[m]);
+    }
+    let (q, k, scale, mut qk_nodes) = match_qk(graph, chain)?;
+    intermediates.append(&mut qk_nodes);
+
+    // Sequence lengths must line up between both matmuls
+    if k.2.dims()[k.2.len() - 2].simplify() != w.2.dims()[w.2.len() - 1].simplify() {
+        return None;
+    }
+    if intermediates.iter().any(|n| graph.no_delete.contains(n)) {
+        return None;
+    }
+    Some(Matched {
+        q,
+        k,
+        v,
+        mask,
+        scale,
+        intermediates,
+        output: wv_sum,
+    })
+}
+
+/// Match mul(matmul(q, k), scale) or matmul(q, k), returning q, k, the scale and the nodes matched
+fn match_qk(graph: &Graph, mut node: NodeIndex) -> Option<(Source, Source, f32, Vec<NodeIndex>)> {
+    let mut nodes = vec![];
+    let mut scale = 1.0;
+    if graph.check_node_type::<Mul>(node) {
+        single_dest(graph, node)?;
+        let srcs = graph.get_sources(node);
+        let (c, x) = [(0, 1), (1, 0)].into_iter().find(|(c, _)| {
+            graph
+                .try_get_op::<Constant>(srcs[*c].0)
+                .map(|c| matches!(c.0, ConstantValue::Float(_)))
+                .unwrap_or_default()
+        })?;
+        let ConstantValue::Float(f) = graph.get_op::<Constant>(srcs[c].0).0 else {
+            return None;
+        };
+        if srcs[x].2.is_reshaped() {
+            return None;
+        }
+        scale = f;
+        nodes.push(node);
+        nodes.push(srcs[c].0);
+        node = srcs[x].0;
+    }
+    single_dest(graph, node)?;
+    let [(mul, _, _)] = graph.get_sources(node)[..] else {
+        return None;
+    };
+    let (q, k) = matmul_sources(graph, mul, node)?;
+    nodes.push(node);
+    nodes.push(mul);
+    Some((q, k, scale, nodes))
+}
+
+/// Get the un-expanded sources of a broadcasted-multiply-and-sum matmul: [.., M, N(fake), K] x [.., M(fake), N, K]
+fn matmul_sources(graph: &Graph, mul: NodeIndex, sum: NodeIndex) -> Option<(Source, Source)> {
+    if !graph.check_node_type::<Mul>(mul) {
+        return None;
+    }
+    let sum_dim = graph.try_get_op::<SumReduce>(sum)?.0;
+    let [mut a, mut b] = graph.get_sources(mul)[..] else {
+        return None;
+    };
+    let n = a.2.len();
+    let fake = |sh: &ShapeTracker, i: usize| sh.fake[sh.indexes[i]];
+    if n < 3
+        || sum_dim != n - 1
+        || single_dest(graph, mul)?.0 != sum
+        || !fake(&a.2, n - 2)
+        || fake(&a.2, n - 3)
+        || !fake(&b.2, n - 3)
+        || fake(&b.2, n - 2)
+    {
+        return None;
+    }
+    a.2.remove_dim(n - 2);
+    b.2.remove_dim(n - 3);
+    Some((a, b))
+}
+
+/// Get the only consumer of a node and the shape it's consumed with
+fn single_dest(graph: &Graph, node: NodeIndex) -> Option<(NodeIndex, ShapeTracker)> {
+    let [(dest, shape)] = graph
+        .graph
+        .edges_directed(node, Direction::Outgoing)
+        .filter_map(|e| e.weight().as_data().map(|(_, _, sh)| (e.target(), sh)))
+        .collect_vec()[..]
+    else {
+        return None;
+    };
+    Some((dest, shape))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::Attention;
+    use crate::CPUCompiler;
+    luminal::test_imports!();
+
+    fn has_attention(cx: &Graph) -> bool {
+        cx.graph
+            .node_indices()
+            .any(|n| cx.check_node_type::<Attention>(n))
+    }
+
+    #[test]
+    fn test_attention() {
+        let mut cx = Graph::new();
+        // batch, seq, heads, head_dim with queries and keys / values of different lengths
+        let q = cx.tensor((2, 's', 3, 8));
+        let k = cx.tensor((2, 't', 3, 8));
+        let v = cx.tensor((2, 't', 3, 5));
+        let scores = q.permute((0, 2, 1, 3)).matmul(k.permute((0, 2, 3, 1))) * 0.3;
+        let mut out = scores.softmax(3).matmul(v.permute((0, 2, 1, 3))).retrieve();
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        assert!(has_attention(&cx));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for (s, t) in [(1, 1), (4, 9), (7, 130)] {
+            let q_data = random_vec_rng(2 * s * 3 * 8, &mut rng);
+            let k_data = random_vec_rng(2 * t * 3 * 8, &mut rng);
+            let v_data = random_vec_rng(2 * t * 3 * 5, &mut rng);
+            q.set_dyn(q_data.clone(), (2, s, 3, 8));
+            k.set_dyn(k_data.clone(), (2, t, 3, 8));
+            v.set_dyn(v_data.clone(), (2, t, 3, 5));
+            cx.execute();
+
+            let d_dev = Cpu::default();
+            let d_q = d_dev
+                .tensor_from_vec(q_data, (2, s, 3, 8))
+                .permute::<_, DAxes4<0, 2, 1, 3>>();
+            let d_k = d_dev
+                .tensor_from_vec(k_data, (2, t, 3, 8))
+                .permute::<_, DAxes4<0, 2, 3, 1>>();
+            let d_v = d_dev
+                .tensor_from_vec(v_data, (2, t, 3, 5))
+                .permute::<_, DAxes4<0, 2, 1, 3>>();
+            let d_out = (d_q.matmul(d_k) * 0.3).softmax::<DAxis<3>>().matmul(d_v);
+            assert_close(&out.data(), &d_out.as_vec());
+            out.drop();
+        }
+    }
+
+    #[test]
+    fn test_causal_attention_with_cache() {
+        let mut cx = Graph::new();
+        // batch, kv heads, query groups, seq, head_dim, with previous keys and values cached
+        let q = cx.tensor((1, 1, 2, 's', 4));
+        let k = cx.tensor((1, 1, 't', 4));
+        let v = cx.tensor((1, 1, 't', 4));
+        let (_, _, _, seq, _) = q.dims5();
+        let (_, _, total, _) = k.dims4();
+        let mask = (cx.triu(seq, 1) * -1e9).pad(((0, 0), (total - seq, 0)));
+        let scores = q.matmul(k.expand(2, 2).permute((0, 1, 2, 4, 3))) / 2.
+            + mask.expand(0, 1).expand(1, 1).expand(2, 2);
+        let mut out = scores.softmax(4).matmul(v.expand(2, 2)).retrieve();
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let inputs = [(3, 3), (2, 70), (1, 5)]
+            .into_iter()
+            .map(|(s, t)| {
+                (
+                    (s, t),
+                    random_vec_rng(2 * s * 4, &mut rng),
+                    random_vec_rng(t * 4, &mut rng),
+                    random_vec_rng(t * 4, &mut rng),
+                )
+            })
+            .collect::<Vec<_>>();
+        let run = move |cx: &mut Graph, out: GraphTensor| {
+            inputs
+                .iter()
+                .map(|((s, t), q_data, k_data, v_data)| {
+                    q.set_dyn(q_data.clone(), (1, 1, 2, *s, 4));
+                    k.set_dyn(k_data.clone(), (1, 1, *t, 4));
+                    v.set_dyn(v_data.clone(), (1, 1, *t, 4));
+                    cx.execute();
+                    let o = out.data();
+                    out.drop();
+                    o
+                })
+                .collect::<Vec<_>>()
+        };
+        let unoptimized = run(&mut cx, out);
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        assert!(has_attention(&cx));
+        for (a, b) in run(&mut cx, out).iter().zip(&unoptimized) {
+            assert_close(a, b);
+        }
+    }
+
+    #[test]
+    fn test_kv_cache_attention() {
+        // Attention reading a prefix of a preallocated kv cache
+        let mut cx = Graph::new();
+        let cache_src = luminal_nn::KVCache::new(1, 2, 16, 4, 'p', &mut cx);
+        let q = cx.tensor((1, 2, 's', 4));
+        let k = cx.tensor((1, 2, 's', 4));
+        let v = cx.tensor((1, 2, 's', 4));
+        let (mut cache_dest, keys, values) = cache_src.append(k, v);
+        let mut out = (q.matmul(keys.permute((0, 1, 3, 2))) * 0.5)
+            .softmax(3)
+            .matmul(values)
+            .retrieve();
+        cx.keep_tensors(cache_dest);
+        cx.compile(
+            <(GenericCompiler, CPUCompiler)>::default(),
+            (&mut out, &mut cache_dest),
+        );
+        assert!(has_attention(&cx));
+
+        // The same attention over concatenated caches
+        let mut concat_cx = Graph::new();
+        let prev_k = concat_cx.tensor((1, 2, 'p', 4));
+        let prev_v = concat_cx.tensor((1, 2, 'p', 4));
+        let concat_q = concat_cx.tensor((1, 2, 's', 4));
+        let concat_k = concat_cx.tensor((1, 2, 's', 4));
+        let concat_v = concat_cx.tensor((1, 2, 's', 4));
+        let all_k = prev_k.concat_along(concat_k, 2).retrieve();
+        let all_v = prev_v.concat_along(concat_v, 2).retrieve();
+        let concat_out = (concat_q.matmul(all_k.permute((0, 1, 3, 2))) * 0.5)
+            .softmax(3)
+            .matmul(all_v)
+            .retrieve();
+        prev_k.set_dyn(vec![], (1, 2, 0, 4));
+        prev_v.set_dyn(vec![], (1, 2, 0, 4));
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut pos = 0;
+        for seq in [5, 1, 1, 3] {
+            let q_data = random_vec_rng(2 * seq * 4, &mut rng);
+            let k_data = random_vec_rng(2 * seq * 4, &mut rng);
+            let v_data = random_vec_rng(2 * seq * 4, &mut rng);
+            q.set_dyn(q_data.clone(), (1, 2, seq, 4));
+            k.set_dyn(k_data.clone(), (1, 2, seq, 4));
+            v.set_dyn(v_data.clone(), (1, 2, seq, 4));
+            cx.set_dyn_dim('p', pos);
+            cx.execute();
+
+            concat_q.set_dyn(q_data, (1, 2, seq, 4));
+            concat_k.set_dyn(k_data, (1, 2, seq, 4));
+            concat_v.set_dyn(v_data, (1, 2, seq, 4));
+            concat_cx.set_dyn_dim('p', pos);
+            concat_cx.execute();
+
+            assert_close(&out.data(), &concat_out.data());
+            out.drop();
+            concat_out.drop();
+
+            // Feed the caches back in for the next step
+            transfer_data_same_graph(cache_dest, cache_src, &mut cx);
+            pos += seq;
+            prev_k.set_dyn(all_k.data(), (1, 2, pos, 4));
+            prev_v.set_dyn(all_v.data(), (1, 2, pos, 4));
+            all_k.drop();
+            all_v.drop();
+        }
+    }
+}
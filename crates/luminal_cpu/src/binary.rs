@@ -3,35 +3,12 @@ use luminal::{
     prelude::{petgraph::visit::EdgeRef, *},
 };
 
-use super::other::ARange;
-
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct Sub;
 
 impl Operator for Sub {
     fn process(&mut self, tensors: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let (a_data, b_data) = (get_vec(&tensors[0].0), get_vec(&tensors[1].0));
-        let (a_ind, a_val, b_ind, b_val) = (
-            tensors[0].1.index_expression(),
-            tensors[0].1.valid_expression(),
-            tensors[1].1.index_expression(),
-            tensors[1].1.valid_expression(),
-        );
-        let mut data = vec![0.; tensors[0].1.n_elements().to_usize().unwrap()];
-        for i in 0..data.len() {
-            let lhs = if a_val.exec_single_var(i) != 0 {
-                a_data[a_ind.exec_single_var(i)]
-            } else {
-                0.0
-            };
-            let rhs = if b_val.exec_single_var(i) != 0 {
-                b_data[b_ind.exec_single_var(i)]
-            } else {
-                0.0
-            };
-            data[i] = lhs - rhs;
-        }
-        vec![Tensor::new(data)]
+        binary_elementwise(tensors, |a, b| a - b)
     }
 }
 
@@ -40,7 +17,7 @@ pub struct SubtractionCompiler;
 
 impl Compiler for SubtractionCompiler {
     type Output = ();
-    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, _: To) {
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
         let (lhs, rhs) = (node(), node());
         let mul = binary::<Mul>(rhs.clone(), super::constant(-1.));
         let add = binary::<Add>(lhs.clone(), mul.clone());
@@ -82,6 +59,7 @@ impl Compiler for SubtractionCompiler {
                 .input(b, b_edge.1, b_edge.2)
                 .finish();
             move_outgoing_edge(add, sub, &mut graph.graph);
+            remap(add, sub, &mut ids, graph);
 
             graph.graph.remove_node(add);
             s.try_delete();
@@ -94,28 +72,7 @@ pub struct Equal;
 
 impl Operator for Equal {
     fn process(&mut self, tensors: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let (a_data, b_data) = (get_vec(&tensors[0].0), get_vec(&tensors[1].0));
-        let mut data = vec![0.; tensors[0].1.n_elements().to_usize().unwrap()];
-        let (a_ind, a_val, b_ind, b_val) = (
-            tensors[0].1.index_expression(),
-            tensors[0].1.valid_expression(),
-            tensors[1].1.index_expression(),
-            tensors[1].1.valid_expression(),
-        );
-        for i in 0..data.len() {
-            let a = if a_val.exec_single_var(i) != 0 {
-                a_data[a_ind.exec_single_var(i)]
-            } else {
-                0.0
-            };
-            let b = if b_val.exec_single_var(i) != 0 {
-                b_data[b_ind.exec_single_var(i)]
-            } else {
-                0.0
-            };
-            data[i] = if a < b { 1. } else { 0. };
-        }
-        vec![Tensor::new(data)]
+        binary_elementwise(tensors, |a, b| if a == b { 1. } else { 0. })
     }
 }
 
@@ -124,7 +81,7 @@ pub struct EqualCompiler;
 
 impl Compiler for EqualCompiler {
     type Output = ();
-    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, _: To) {
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
         let one = super::constant(1.);
         let (lhs, rhs) = (node(), node());
         let lt1 = binary::<LessThan>(lhs.clone(), rhs.clone());
@@ -160,93 +117,10 @@ impl Compiler for EqualCompiler {
                 .input(rhs, b_edge.1, b_edge.2)
                 .finish();
             move_outgoing_edge(eq, equals, &mut graph.graph);
+            remap(eq, equals, &mut ids, graph);
 
             graph.graph.remove_node(eq);
             s.try_delete();
         }
     }
 }
-
-#[derive(Debug, Clone, PartialEq)]
-pub struct Gather {
-    pub embed_dim: usize,
-}
-
-impl Operator for Gather {
-    fn process(&mut self, tensors: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        // Inp 1 should be Vec<f32> and inp 2 should be a CudaSlice<T>
-        let indexes = tensors[0].0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
-        let weights = tensors[1].0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
-
-        let mut out = vec![0.; indexes.len() * self.embed_dim];
-        for token in 0..indexes.len() {
-            let e = indexes[token] as usize;
-            for dim in 0..self.embed_dim {
-                out[token * self.embed_dim + dim] = weights[e * self.embed_dim + dim];
-            }
-        }
-
-        vec![Tensor::new(out)]
-    }
-}
-
-#[derive(Debug, Default)]
-pub struct GatherCompiler;
-
-impl Compiler for GatherCompiler {
-    type Output = ();
-    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, _: To) {
-        let indexes = node();
-        let eq = binary::<Equal>(indexes.clone(), op::<ARange>());
-        let embedding = node();
-        let mul = binary::<Mul>(embedding.clone(), eq.clone());
-        let sum_reduce = unary::<SumReduce>(mul.clone());
-        let mut s = sum_reduce.clone().search(graph);
-        while s.next_match() {
-            if s.check_no_delete(&[embedding.id]) {
-                continue;
-            }
-            let emb_shape = graph
-                .edges_connecting(s.get(&embedding), s.get(&mul))
-                .next()
-                .unwrap()
-                .weight()
-                .as_data()
-                .unwrap()
-                .2;
-            let index_shape = graph
-                .edges_connecting(s.get(&indexes), s.get(&eq))
-                .next()
-                .unwrap()
-                .weight()
-                .as_data()
-                .unwrap()
-                .2;
-            let embed_dim = graph
-                .graph
-                .edges_connecting(s.get(&embedding), s.get(&mul))
-                .next()
-                .unwrap()
-                .weight()
-                .as_data()
-                .unwrap()
-                .2
-                .dims()[2]
-                .to_usize()
-                .unwrap();
-
-            let gather = graph
-                .add_op(Gather { embed_dim })
-                .input(s.get(&indexes), 0, index_shape)
-                .input(s.get(&embedding), 0, emb_shape)
-                .finish();
-            move_outgoing_edge(s.get(&sum_reduce), gather, &mut graph.graph);
-            graph.remove_node(s.get(&sum_reduce));
-            s.try_delete();
-        }
-    }
-}
-
-fn get_vec<'a>(tensor: &'a InputTensor<'a>) -> &'a Vec<f32> {
-    tensor.borrowed().downcast_ref::<Vec<f32>>().unwrap()
-}
@@ -1,14 +1,19 @@
+mod attention;
 mod binary;
 mod matmul;
+mod movement;
 mod other;
+mod unary;
 
-use std::any::Any;
+use std::{any::Any, borrow::Cow};
 
 use itertools::Itertools;
 use petgraph::visit::EdgeRef;
 
 use luminal::{
-    op::{Constant, ConstantValue, Exp2, InputTensor, Log2, Operator, Recip, Sin},
+    op::{
+        unary_elementwise, Constant, ConstantValue, Exp2, InputTensor, Log2, Operator, Recip, Sin,
+    },
     prelude::*,
 };
 
@@ -19,8 +24,21 @@ pub type CPUCompiler = (
     binary::SubtractionCompiler,
     binary::EqualCompiler,
     other::ARangeCompiler,
-    binary::GatherCompiler,
+    FusedOpsCompiler,
+    matmul::BatchedMatMulCompiler,
+    movement::ContiguousFusionCompiler,
     UnaryFusionCompiler,
+    other::LoopCompiler,
+);
+
+/// Compiler to replace subgraphs of primops with fused row kernels
+pub type FusedOpsCompiler = (
+    unary::MeanReduceCompiler,
+    unary::StdNormCompiler,
+    unary::LayerNormCompiler,
+    unary::SoftmaxCompiler,
+    unary::ArgMaxCompiler,
+    attention::AttentionCompiler,
 );
 
 pub(crate) fn constant(num: f32) -> SelectGraph {
@@ -38,6 +56,39 @@ pub(crate) fn constant(num: f32) -> SelectGraph {
     n
 }
 
+/// Get the data of an input laid out contiguously, only copying it if the shape has been modified
+pub(crate) fn get_contiguous<'a>(
+    (tensor, shape): &'a (InputTensor<'a>, ShapeTracker),
+) -> Cow<'a, [f32]> {
+    let data = tensor.borrowed().downcast_ref::<Vec<f32>>().unwrap();
+    if !shape.is_reshaped() {
+        return Cow::Borrowed(data);
+    }
+    let (ind, val) = (shape.index_expression(), shape.valid_expression());
+    let mut stack = vec![];
+    Cow::Owned(
+        (0..shape.n_elements().to_usize().unwrap())
+            .map(|i| {
+                if val.exec_single_var_stack(i, &mut stack) != 0 {
+                    data[ind.exec_single_var_stack(i, &mut stack)]
+                } else {
+                    0.0
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Split a shape into (front, dim, back) sizes around a reduction dimension
+pub(crate) fn reduction_sizes(shape: &ShapeTracker, dim: usize) -> (usize, usize, usize) {
+    let sh = shape.shape_usize();
+    (
+        sh[..dim].iter().product(),
+        sh[dim],
+        sh[dim + 1..].iter().product(),
+    )
+}
+
 /// Apply multiple unary ops in sequence, without having to reindex / rewrite to memory between each
 #[derive(Debug, Default)]
 pub struct UnaryFusionCompiler;
@@ -121,15 +172,13 @@ impl Compiler for UnaryFusionCompiler {
 pub struct FusedUnary(Vec<fn(f32) -> f32>);
 
 impl Operator for FusedUnary {
-    fn process(&mut self, mut inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let mut t = inp.pop().unwrap().0.cloned();
-        for a in t.downcast_mut::<Vec<f32>>().unwrap().iter_mut() {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        unary_elementwise(inp, |mut a| {
             for f in &self.0 {
-                *a = (f)(*a);
+                a = (f)(a);
             }
-        }
-
-        vec![t]
+            a
+        })
     }
 }
 
@@ -190,4 +239,341 @@ mod tests {
         cx.execute();
         assert_close(&c.data(), &unoptimized_c);
     }
+
+    #[test]
+    fn test_einsum_matmul() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(random_vec(6));
+        let b = cx.tensor((3, 4)).set(random_vec(12));
+        let q = cx.tensor((2, 3, 4, 5)).set(random_vec(120));
+        let k = cx.tensor((2, 3, 6, 5)).set(random_vec(180));
+        let mut outs = (
+            GraphTensor::einsum("ij,jk->ik", &[a, b]).retrieve(),
+            GraphTensor::einsum("bhqd,bhkd->bhqk", &[q, k]).retrieve(),
+        );
+        cx.execute();
+        let unoptimized = (outs.0.data(), outs.1.data());
+        outs.0.drop();
+        outs.1.drop();
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut outs);
+        assert!(has_op::<crate::matmul::MatMul2D>(&cx));
+        assert!(has_op::<crate::matmul::BatchedMatMul>(&cx));
+        cx.execute();
+        assert_close(&outs.0.data(), &unoptimized.0);
+        assert_close(&outs.1.data(), &unoptimized.1);
+    }
+
+    #[test]
+    fn test_pad_modes() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((3, 4)).set(random_vec(12));
+        let mut outs = (
+            (a.pad_with(((1, 2), (3, 1)), PadMode::Reflect) * 2.).retrieve(),
+            (a.pad_with(((2, 0), (1, 1)), PadMode::Replicate) + 1.).retrieve(),
+            (a.pad_with(((0, 3), (4, 0)), PadMode::Circular)).retrieve(),
+        );
+        cx.execute();
+        let unoptimized = (outs.0.data(), outs.1.data(), outs.2.data());
+        outs.0.drop();
+        outs.1.drop();
+        outs.2.drop();
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut outs);
+        cx.execute();
+        assert_exact(&outs.0.data(), &unoptimized.0);
+        assert_exact(&outs.1.data(), &unoptimized.1);
+        assert_exact(&outs.2.data(), &unoptimized.2);
+    }
+
+    fn has_op<T: Operator + 'static>(cx: &Graph) -> bool {
+        cx.graph.node_indices().any(|n| cx.check_node_type::<T>(n))
+    }
+
+    /// Reference NCHW convolution computed directly with nested loops, for checking the
+    /// unfolded convolutions against
+    #[allow(clippy::too_many_arguments)]
+    fn reference_conv2d(
+        inp: &[f32],
+        (batch, ch_in, h, w): (usize, usize, usize, usize),
+        weight: &[f32],
+        (ch_out, kh, kw): (usize, usize, usize),
+        (sh, sw): (usize, usize),
+        (dh, dw): (usize, usize),
+        (ph, pw): (usize, usize),
+    ) -> Vec<f32> {
+        let oh = (h + 2 * ph - dh * (kh - 1) - 1) / sh + 1;
+        let ow = (w + 2 * pw - dw * (kw - 1) - 1) / sw + 1;
+        let mut out = vec![0.; batch * ch_out * oh * ow];
+        for (b, o, y, x) in itertools::iproduct!(0..batch, 0..ch_out, 0..oh, 0..ow) {
+            let mut acc = 0.;
+            for (c, i, j) in itertools::iproduct!(0..ch_in, 0..kh, 0..kw) {
+                let (iy, ix) = (y * sh + i * dh, x * sw + j * dw);
+                if iy < ph || ix < pw || iy - ph >= h || ix - pw >= w {
+                    continue;
+                }
+                acc += inp[((b * ch_in + c) * h + iy - ph) * w + ix - pw]
+                    * weight[((o * ch_in + c) * kh + i) * kw + j];
+            }
+            out[((b * ch_out + o) * oh + y) * ow + x] = acc;
+        }
+        out
+    }
+
+    #[test]
+    fn test_conv1d() {
+        let mut cx = Graph::new();
+        let model = luminal_nn::Conv1D::new(3, 5, 3, 1, 2, 0, 1, true, &mut cx);
+        let mut rng = StdRng::seed_from_u64(0);
+        let (weight, bias) = (
+            random_vec_rng(5 * 3 * 3, &mut rng),
+            random_vec_rng(5, &mut rng),
+        );
+        model.weight.set(weight.clone());
+        model.bias.unwrap().set(bias.clone());
+        let inp = cx.tensor((2, 3, 's'));
+        let mut out = model.forward(inp).retrieve();
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        assert!(has_op::<crate::movement::FusedContiguous>(&cx));
+        assert!(has_op::<crate::matmul::BatchedMatMul>(&cx));
+
+        for s in [7, 12, 9] {
+            let inp_data = random_vec_rng(2 * 3 * s, &mut rng);
+            inp.set_dyn(inp_data.clone(), (2, 3, s));
+            cx.execute();
+
+            let conv = reference_conv2d(
+                &inp_data,
+                (2, 3, 1, s),
+                &weight,
+                (5, 1, 3),
+                (1, 1),
+                (1, 2),
+                (0, 0),
+            );
+            let expected = conv
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| x + bias[(i / (s - 4)) % 5])
+                .collect::<Vec<_>>();
+            assert_close(&out.data(), &expected);
+            out.drop();
+        }
+    }
+
+    #[test]
+    fn test_conv2d() {
+        let mut cx = Graph::new();
+        let model =
+            luminal_nn::Conv2D::new(3, 4, (3, 2), (1, 1), (1, 1), (0, 0), 1, false, &mut cx);
+        let mut rng = StdRng::seed_from_u64(0);
+        let weight = random_vec_rng(4 * 3 * 3 * 2, &mut rng);
+        model.weight.set(weight.clone());
+        let inp = cx.tensor((2, 3, 'h', 'w'));
+        let mut out = model.forward(inp).retrieve();
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        assert!(has_op::<crate::movement::FusedContiguous>(&cx));
+        assert!(has_op::<crate::matmul::BatchedMatMul>(&cx));
+
+        for (h, w) in [(9, 11), (14, 8)] {
+            let inp_data = random_vec_rng(2 * 3 * h * w, &mut rng);
+            inp.set_dyn(inp_data.clone(), (2, 3, h, w));
+            cx.execute();
+
+            let expected = reference_conv2d(
+                &inp_data,
+                (2, 3, h, w),
+                &weight,
+                (4, 3, 2),
+                (1, 1),
+                (1, 1),
+                (0, 0),
+            );
+            assert_close(&out.data(), &expected);
+            out.drop();
+        }
+    }
+
+    #[test]
+    fn test_conv2d_strided() {
+        let mut cx = Graph::new();
+        let model =
+            luminal_nn::Conv2D::new(3, 4, (3, 3), (2, 3), (2, 1), (1, 2), 1, false, &mut cx);
+        let mut rng = StdRng::seed_from_u64(0);
+        let weight = random_vec_rng(4 * 3 * 3 * 3, &mut rng);
+        model.weight.set(weight.clone());
+        let inp = cx.tensor((2, 3, 'h', 'w'));
+        let mut out = model.forward(inp).retrieve();
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        assert!(has_op::<crate::movement::FusedContiguous>(&cx));
+
+        for (h, w) in [(9, 11), (14, 8)] {
+            let inp_data = random_vec_rng(2 * 3 * h * w, &mut rng);
+            inp.set_dyn(inp_data.clone(), (2, 3, h, w));
+            cx.execute();
+
+            let expected = reference_conv2d(
+                &inp_data,
+                (2, 3, h, w),
+                &weight,
+                (4, 3, 3),
+                (2, 3),
+                (2, 1),
+                (1, 2),
+            );
+            assert_close(&out.data(), &expected);
+            out.drop();
+        }
+    }
+
+    #[test]
+    fn test_conv3d() {
+        let mut cx = Graph::new();
+        let model = luminal_nn::Conv3D::new(
+            2,
+            3,
+            (2, 2, 2),
+            (2, 2, 2),
+            (1, 1, 1),
+            (0, 0, 0),
+            1,
+            false,
+            &mut cx,
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        model
+            .weight
+            .set(random_vec_rng(3 * 2 * 2 * 2 * 2, &mut rng));
+        let inp = cx
+            .tensor((2, 4, 7, 6))
+            .set(random_vec_rng(2 * 4 * 7 * 6, &mut rng));
+        let mut out = model.forward(inp).retrieve();
+        cx.execute();
+        let unoptimized = out.data();
+        out.drop();
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        assert!(has_op::<crate::movement::FusedContiguous>(&cx));
+        cx.execute();
+        assert_close(&out.data(), &unoptimized);
+    }
+
+    #[test]
+    fn test_topk() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let a = cx.tensor((3, 10)).set(random_vec_rng(30, &mut rng));
+        let (values, indices) = (a * 2.).topk(4, 1);
+        let mut out = ((values.exp() + indices) * indices.sin()).retrieve();
+        cx.execute();
+        let unoptimized = out.data();
+        out.drop();
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut out);
+        cx.execute();
+        assert_close(&out.data(), &unoptimized);
+    }
+
+    #[test]
+    fn test_fused_op_outputs() {
+        // Fused ops that replace a retrieved node take over its id
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(random_vec(6));
+        let b = cx.tensor((2, 3)).set(random_vec(6));
+        let mut out = (a - b).retrieve();
+        cx.execute();
+        let unoptimized = out.data();
+        out.drop();
+
+        cx.compile(CPUCompiler::default(), &mut out);
+        assert!(has_op::<crate::binary::Sub>(&cx));
+        cx.execute();
+        assert_close(&out.data(), &unoptimized);
+    }
+
+    #[test]
+    fn test_equal() {
+        let mut cx = Graph::new();
+        let a = cx.tensor(5).set(vec![1., 2., 3., -1., 0.]);
+        let b = cx.tensor(5).set(vec![1., 3., 2., -1., 0.5]);
+        let mut out = a.equals(b).retrieve();
+
+        cx.compile(CPUCompiler::default(), &mut out);
+        assert!(has_op::<crate::binary::Equal>(&cx));
+        cx.execute();
+        assert_exact(&out.data(), &[1., 0., 0., 1., 0.]);
+    }
+
+    #[test]
+    fn test_gather() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = random_vec_rng(3 * 4 * 5, &mut rng);
+        let mut cx = Graph::new();
+        let a = cx.tensor((3, 4, 5)).set(data);
+        let rows = cx.tensor(6).set(vec![2., 0., 2., 1., 0., 1.]);
+        let batch = cx.tensor((2, 2)).set(vec![3., 0., 4., 4.]);
+        let indices = cx
+            .tensor((3, 4, 2))
+            .set((0..24).map(|i| ((i * 7) % 5) as f32).collect::<Vec<_>>());
+        let column_indices = cx
+            .tensor((5, 4, 2))
+            .set((0..40).map(|i| ((i * 7) % 3) as f32).collect::<Vec<_>>());
+        let mut outs = vec![
+            a.index_select(0, rows).retrieve(),
+            a.index_select(1, rows).retrieve(),
+            a.index_select(2, batch).retrieve(),
+            a.permute((2, 0, 1)).index_select(1, rows).retrieve(),
+            a.gather(2, indices).retrieve(),
+            a.gather(0, column_indices.permute((2, 1, 0))).retrieve(),
+        ];
+        cx.execute();
+        let unoptimized = outs.iter().map(|o| o.data()).collect::<Vec<_>>();
+        for o in &outs {
+            o.drop();
+        }
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut outs);
+        assert_eq!(
+            cx.graph
+                .node_indices()
+                .filter(|n| cx.check_node_type::<luminal::op::Gather>(*n))
+                .count(),
+            outs.len()
+        );
+        cx.execute();
+        for (o, u) in outs.iter().zip(&unoptimized) {
+            assert_exact(&o.data(), u);
+        }
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut cx = Graph::new();
+        let xs = cx.tensor(('s', 2, 4));
+        let h0 = cx.tensor((2, 3)).set(random_vec(6));
+        let w_x = cx.tensor((4, 3)).set(random_vec(12));
+        let w_h = cx.tensor((3, 3)).set(random_vec(9));
+        let (finals, outputs) = cx.scan_loop(&[xs], &[h0], &[w_x, w_h], false, |_, x, h, w| {
+            let h = (x[0].matmul(w[0]) + h[0].matmul(w[1])).tanh();
+            (vec![h], vec![h.softmax(1)])
+        });
+        let mut outs = (finals[0].retrieve(), outputs[0].retrieve());
+        xs.set_dyn(random_vec(5 * 8), (5, 2, 4));
+        cx.execute();
+        let unoptimized = (outs.0.data(), outs.1.data());
+        outs.0.drop();
+        outs.1.drop();
+
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut outs);
+        let node = cx
+            .graph
+            .node_indices()
+            .find(|n| cx.check_node_type::<luminal::op::Loop>(*n))
+            .unwrap();
+        let body = &cx.try_get_op::<luminal::op::Loop>(node).unwrap().body;
+        assert!(has_op::<crate::matmul::MatMul2D>(body));
+        cx.execute();
+        assert_close(&outs.0.data(), &unoptimized.0);
+        assert_close(&outs.1.data(), &unoptimized.1);
+    }
 }
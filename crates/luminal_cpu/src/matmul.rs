@@ -187,3 +187,129 @@ impl Operator for BatchedMatMul2D {
         vec![Tensor::new(c)]
     }
 }
+
+#[derive(Debug, Default)]
+pub struct BatchedMatMulCompiler;
+
+impl Compiler for BatchedMatMulCompiler {
+    type Output = ();
+    fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut ids: T) {
+        // Look for the general matmul pattern with any number of (possibly broadcasted) batch dims
+        // Mul ([.., M, N(fake), K] | [.., M(fake), N, K]) -> SumReduce(last) -> [.., M, N]
+        let mul = op::<Mul>();
+        let sum_reduce = unary::<SumReduce>(mul.clone());
+        let mut s = sum_reduce.clone().search(graph);
+        while s.next_match() {
+            if s.check_no_delete(&[sum_reduce.id]) {
+                // The intermediate mul can't be deleted
+                continue;
+            }
+            let (mul, sum_reduce) = (s.get(&mul), s.get(&sum_reduce));
+            let mut srcs = graph.get_sources(mul);
+            let n = srcs[0].2.len();
+            let fake = |sh: &ShapeTracker, i: usize| sh.fake[sh.indexes[i]];
+            if n < 4
+                || graph.get_op::<SumReduce>(sum_reduce).0 != n - 1
+                || !fake(&srcs[0].2, n - 2)
+                || fake(&srcs[0].2, n - 3)
+                || !fake(&srcs[1].2, n - 3)
+                || fake(&srcs[1].2, n - 2)
+            {
+                continue;
+            }
+            // Undo expansions and permute
+            srcs[0].2.remove_dim(n - 2);
+            srcs[1].2.remove_dim(n - 3);
+            let mut axes = (0..n - 1).collect::<Vec<_>>();
+            axes.swap(n - 3, n - 2);
+            srcs[1].2.permute(&axes);
+            // Inputs need to be expressible with strides
+            if srcs
+                .iter()
+                .any(|(_, _, sh)| sh.is_sliced() || sh.is_padded())
+            {
+                continue;
+            }
+            let new_op = graph
+                .add_op(BatchedMatMul)
+                .input(srcs[0].0, srcs[0].1, srcs[0].2)
+                .input(srcs[1].0, srcs[1].1, srcs[1].2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(sum_reduce, new_op, graph);
+            remap(sum_reduce, new_op, &mut ids, graph);
+            remap(mul, new_op, &mut ids, graph);
+
+            // Remove the old ops
+            graph.graph.remove_node(sum_reduce);
+            graph.safe_remove_node(mul, 0);
+        }
+    }
+}
+
+/// Matmul with any number of batch dimensions, which can be broadcasted on either side
+// [.., M, K] x [.., K, N] -> [.., M, N]
+#[derive(Debug, PartialEq)]
+pub struct BatchedMatMul;
+
+impl Operator for BatchedMatMul {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (a_shape, b_shape) = (inp[0].1.shape_usize(), inp[1].1.shape_usize());
+        let n_dims = a_shape.len();
+        let (m, k, n) = (
+            a_shape[n_dims - 2],
+            a_shape[n_dims - 1],
+            b_shape[n_dims - 1],
+        );
+        // Broadcasted dimensions don't move through memory
+        let strides = |sh: &ShapeTracker| {
+            sh.strides()
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    if sh.fake[sh.indexes[i]] {
+                        0
+                    } else {
+                        s.to_usize().unwrap()
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let (a_strides, b_strides) = (strides(&inp[0].1), strides(&inp[1].1));
+        let a_data = inp[0].0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
+        let b_data = inp[1].0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
+        let batch_dims = &a_shape[..n_dims - 2];
+        let mut c = vec![0.; batch_dims.iter().product::<usize>() * m * n];
+
+        for (i, c_mat) in c.chunks_exact_mut(m * n).enumerate() {
+            // Find where this batch starts in each input
+            let (mut a_offset, mut b_offset, mut rem) = (0, 0, i);
+            for (d, size) in batch_dims.iter().enumerate().rev() {
+                a_offset += (rem % size) * a_strides[d];
+                b_offset += (rem % size) * b_strides[d];
+                rem /= size;
+            }
+            unsafe {
+                matrixmultiply::sgemm(
+                    m,
+                    k,
+                    n,
+                    1.0,
+                    a_data.as_ptr().add(a_offset),
+                    a_strides[n_dims - 2] as isize,
+                    a_strides[n_dims - 1] as isize,
+                    b_data.as_ptr().add(b_offset),
+                    b_strides[n_dims - 2] as isize,
+                    b_strides[n_dims - 1] as isize,
+                    0.0,
+                    c_mat.as_mut_ptr(),
+                    n as isize,
+                    1,
+                );
+            }
+        }
+
+        vec![Tensor::new(c)]
+    }
+}
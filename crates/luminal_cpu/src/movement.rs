@@ -0,0 +1,177 @@
+use std::fmt::Debug;
+
+use itertools::Itertools;
+use luminal::{
+    op::*,
+    prelude::{
+        petgraph::{visit::EdgeRef, Direction},
+        *,
+    },
+};
+use rustc_hash::FxHashMap;
+
+/// A chain of contiguous copies collapsed into a single gather.
+///
+/// This is what the windowed views used by convolutions (`pool_last_dim`) turn into: rather than
+/// copying through every intermediate view, the source index of each output element is worked out
+/// once per set of dynamic dimensions (an im2col table) and reused on every run.
+#[derive(Clone)]
+pub struct FusedContiguous {
+    /// Input shapes of each copy in the chain, from the source outwards
+    shapes: Vec<ShapeTracker>,
+    /// Dynamic dimensions the shapes depend on
+    symbols: Vec<char>,
+    /// Source index of each output element (u32::MAX for padding), keyed by the dynamic dimensions it was built for
+    table: Option<(Vec<usize>, Vec<u32>)>,
+    dyn_map: *const FxHashMap<char, usize>,
+}
+
+impl Debug for FusedContiguous {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FusedContiguous({:?})", self.shapes)
+    }
+}
+
+impl FusedContiguous {
+    fn build_table(&self, dyn_map: &FxHashMap<char, usize>) -> Vec<u32> {
+        let mut stack = vec![];
+        let exprs = self
+            .shapes
+            .iter()
+            .rev()
+            .map(|sh| {
+                let mut sh = *sh;
+                sh.resolve_global_dyn_dims_stack(dyn_map, &mut stack);
+                (sh.index_expression(), sh.valid_expression())
+            })
+            .collect::<Vec<_>>();
+        let mut out_shape = *self.shapes.last().unwrap();
+        out_shape.resolve_global_dyn_dims_stack(dyn_map, &mut stack);
+        (0..out_shape.n_elements().to_usize().unwrap())
+            .map(|i| {
+                // Walk the output index back through each view to the source
+                let mut index = i;
+                for (ind, val) in &exprs {
+                    if val.exec_single_var_stack(index, &mut stack) == 0 {
+                        return u32::MAX;
+                    }
+                    index = ind.exec_single_var_stack(index, &mut stack);
+                }
+                // u32::MAX is reserved as the padding sentinel
+                assert!(
+                    index < u32::MAX as usize,
+                    "Source index {index} doesn't fit in a fused contiguous table"
+                );
+                index as u32
+            })
+            .collect()
+    }
+}
+
+impl Operator for FusedContiguous {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let dyn_map = unsafe { self.dyn_map.as_ref().unwrap() };
+        let key = self.symbols.iter().map(|c| dyn_map[c]).collect::<Vec<_>>();
+        if self.table.as_ref().map(|(k, _)| *k != key).unwrap_or(true) {
+            self.table = Some((key, self.build_table(dyn_map)));
+        }
+        let data = inp[0].0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
+        vec![Tensor::new(
+            self.table
+                .as_ref()
+                .unwrap()
+                .1
+                .iter()
+                .map(|i| {
+                    if *i == u32::MAX {
+                        0.0
+                    } else {
+                        data[*i as usize]
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )]
+    }
+}
+
+/// Collapse chains of contiguous copies into a single gather
+#[derive(Debug, Default)]
+pub struct ContiguousFusionCompiler;
+
+impl Compiler for ContiguousFusionCompiler {
+    type Output = ();
+    fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut ids: T) {
+        for end in graph.graph.node_indices().collect_vec() {
+            if !graph.graph.contains_node(end) || !graph.check_node_type::<Contiguous>(end) {
+                continue;
+            }
+            // Only start from the last copy in a chain
+            if single_contiguous_dest(graph, end).is_some() {
+                continue;
+            }
+            // Walk back through copies that are only used by the next copy
+            let mut chain = vec![end];
+            let mut src = graph.get_sources(end)[0];
+            while graph.check_node_type::<Contiguous>(src.0)
+                && single_contiguous_dest(graph, src.0).is_some()
+                && !graph.no_delete.contains(&src.0)
+            {
+                chain.push(src.0);
+                src = graph.get_sources(src.0)[0];
+            }
+            if chain.len() < 2 {
+                continue;
+            }
+            chain.reverse();
+            let shapes = chain
+                .iter()
+                .map(|n| graph.get_sources(*n)[0].2)
+                .collect::<Vec<_>>();
+            let symbols = shapes
+                .iter()
+                .flat_map(|sh| {
+                    sh.dims
+                        .into_iter()
+                        .chain(sh.padding.into_iter().flat_map(|(a, b)| [a, b]))
+                        .chain(sh.mask.into_iter().flat_map(|(a, b)| [a, b]))
+                        .flat_map(|e| e.to_symbols())
+                        .collect::<Vec<_>>()
+                })
+                .sorted()
+                .dedup()
+                .collect();
+            let fused = graph
+                .add_op(FusedContiguous {
+                    shapes,
+                    symbols,
+                    table: None,
+                    dyn_map: &graph.dyn_map,
+                })
+                .input(src.0, src.1, src.2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(end, fused, graph);
+            remap(end, fused, &mut ids, graph);
+
+            // Remove the old ops
+            for node in chain.into_iter().rev() {
+                graph.remove_node(node);
+            }
+        }
+    }
+}
+
+/// Get the only consumer of a node, if it's a contiguous copy
+fn single_contiguous_dest(graph: &Graph, node: NodeIndex) -> Option<NodeIndex> {
+    let [dest] = graph
+        .graph
+        .edges_directed(node, Direction::Outgoing)
+        .filter(|e| e.weight().as_data().is_some())
+        .map(|e| e.target())
+        .collect_vec()[..]
+    else {
+        return None;
+    };
+    Some(dest).filter(|d| graph.check_node_type::<Contiguous>(*d))
+}
@@ -71,3 +71,18 @@ impl Compiler for ARangeCompiler {
         }
     }
 }
+
+/// Compile the body of each loop for the CPU, so each step runs the same fused kernels as the outer graph
+#[derive(Debug, Default)]
+pub struct LoopCompiler;
+
+impl Compiler for LoopCompiler {
+    type Output = ();
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, _: To) {
+        for node in graph.graph.node_indices().collect::<Vec<_>>() {
+            if let Some(l) = graph.try_get_op_mut::<Loop>(node) {
+                l.compile_body(<(GenericCompiler, super::CPUCompiler)>::default());
+            }
+        }
+    }
+}
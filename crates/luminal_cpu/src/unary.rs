@@ -0,0 +1,564 @@
+use luminal::{op::*, prelude::*};
+
+use super::{
+    binary::{Equal, Sub},
+    get_contiguous,
+    other::ARange,
+    reduction_sizes,
+};
+
+/// Mean of all elements along a dimension
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeanReduce(pub usize);
+
+impl Operator for MeanReduce {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (front, dim, back) = reduction_sizes(&inp[0].1, self.0);
+        let data = get_contiguous(&inp[0]);
+        let mut out = vec![0.; front * back];
+        for (src, dst) in data
+            .chunks_exact(dim * back)
+            .zip(out.chunks_exact_mut(back))
+        {
+            for row in src.chunks_exact(back) {
+                for (o, x) in dst.iter_mut().zip(row) {
+                    *o += *x;
+                }
+            }
+            for o in dst {
+                *o /= dim as f32;
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MeanReduceCompiler;
+
+impl Compiler for MeanReduceCompiler {
+    type Output = ();
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
+        // Look for the mean-reduce pattern
+        // sum_reduce(mul(x, recip(const_n)))
+        let inp = node();
+        let n = op::<Constant>();
+        let mul = binary::<Mul>(inp.clone(), unary::<Recip>(n.clone()));
+        let sum_reduce = unary::<SumReduce>(mul.clone());
+        let mut s = sum_reduce.clone().search(graph);
+        while s.next_match() {
+            if s.check_no_delete(&[sum_reduce.id, inp.id]) {
+                // An intermediate node can't be deleted
+                continue;
+            }
+            let (x, mul, sum_reduce) = (s.get(&inp), s.get(&mul), s.get(&sum_reduce));
+            let dim = graph.get_op::<SumReduce>(sum_reduce).0;
+            // The constant must be the size of the reduced dimension
+            let ConstantValue::Expression(n) = graph.get_op::<Constant>(s.get(&n)).0 else {
+                continue;
+            };
+            let Some(src) = graph.get_sources(mul).into_iter().find(|(i, _, _)| *i == x) else {
+                continue;
+            };
+            if n.simplify() != src.2.dims()[dim].simplify() {
+                continue;
+            }
+            let mean_reduce = graph
+                .add_op(MeanReduce(dim))
+                .input(src.0, src.1, src.2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(sum_reduce, mean_reduce, graph);
+            remap(sum_reduce, mean_reduce, &mut ids, graph);
+
+            // Remove the old ops
+            graph.remove_node(sum_reduce);
+            s.try_delete();
+        }
+    }
+}
+
+/// Normalize by the root mean square along a dimension: x / sqrt(mean(x^2) + eps)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StdNorm {
+    pub dim: usize,
+    pub epsilon: f32,
+}
+
+impl Operator for StdNorm {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (_, dim, back) = reduction_sizes(&inp[0].1, self.dim);
+        let data = get_contiguous(&inp[0]);
+        let mut out = vec![0.; data.len()];
+        let mut scale = vec![0.; back];
+        for (src, dst) in data
+            .chunks_exact(dim * back)
+            .zip(out.chunks_exact_mut(dim * back))
+        {
+            scale.fill(0.);
+            for row in src.chunks_exact(back) {
+                for (s, x) in scale.iter_mut().zip(row) {
+                    *s += x * x;
+                }
+            }
+            for s in &mut scale {
+                *s = (*s / dim as f32 + self.epsilon).sqrt().recip();
+            }
+            for (row_in, row_out) in src.chunks_exact(back).zip(dst.chunks_exact_mut(back)) {
+                for ((o, x), s) in row_out.iter_mut().zip(row_in).zip(&scale) {
+                    *o = x * s;
+                }
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StdNormCompiler;
+
+impl Compiler for StdNormCompiler {
+    type Output = ();
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
+        // Look for the RMSNorm pattern
+        // mul(recip(sqrt(add(mean_reduce(mul(x, x)), eps))), x)
+        let eps = op::<Constant>();
+        let inp = node();
+        let square = unary::<Mul>(inp.clone());
+        let mean = unary::<MeanReduce>(square.clone());
+        let add = binary::<Add>(mean.clone(), eps.clone());
+        let mul = unary::<Mul>(unary::<Recip>(unary::<Sqrt>(add.clone())));
+
+        let mut s = mul.clone().search(graph);
+        while s.next_match() {
+            if s.check_no_delete(&[mul.id, inp.id]) {
+                // An intermediate node can't be deleted
+                continue;
+            }
+            let ConstantValue::Float(epsilon) = graph.get_op::<Constant>(s.get(&eps)).0 else {
+                continue;
+            };
+            let (x, square, mul) = (s.get(&inp), s.get(&square), s.get(&mul));
+            let dim = graph.get_op::<MeanReduce>(s.get(&mean)).0;
+            // Both sides of the square and the final multiply must read x the same way
+            let src = graph.get_sources(square)[0];
+            if graph
+                .get_sources(square)
+                .into_iter()
+                .chain(
+                    graph
+                        .get_sources(mul)
+                        .into_iter()
+                        .filter(|(i, _, _)| *i == x),
+                )
+                .filter(|s| *s == src)
+                .count()
+                != 3
+            {
+                continue;
+            }
+
+            // Insert StdNorm op
+            let std_norm = graph
+                .add_op(StdNorm { dim, epsilon })
+                .input(src.0, src.1, src.2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(mul, std_norm, graph);
+            remap(mul, std_norm, &mut ids, graph);
+
+            // Remove the old ops
+            graph.remove_node(mul);
+            s.try_delete();
+        }
+    }
+}
+
+/// Layer norm without an affine transform: (x - mean(x)) / sqrt(var(x) + eps)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerNorm {
+    pub dim: usize,
+    pub epsilon: f32,
+}
+
+impl Operator for LayerNorm {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (_, dim, back) = reduction_sizes(&inp[0].1, self.dim);
+        let data = get_contiguous(&inp[0]);
+        let mut out = vec![0.; data.len()];
+        let (mut mean, mut scale) = (vec![0.; back], vec![0.; back]);
+        for (src, dst) in data
+            .chunks_exact(dim * back)
+            .zip(out.chunks_exact_mut(dim * back))
+        {
+            // Two passes: mean first, then variance around the mean
+            mean.fill(0.);
+            scale.fill(0.);
+            for row in src.chunks_exact(back) {
+                for (m, x) in mean.iter_mut().zip(row) {
+                    *m += x;
+                }
+            }
+            for m in &mut mean {
+                *m /= dim as f32;
+            }
+            for row in src.chunks_exact(back) {
+                for ((s, x), m) in scale.iter_mut().zip(row).zip(&mean) {
+                    *s += (x - m) * (x - m);
+                }
+            }
+            for s in &mut scale {
+                *s = (*s / dim as f32 + self.epsilon).sqrt().recip();
+            }
+            for (row_in, row_out) in src.chunks_exact(back).zip(dst.chunks_exact_mut(back)) {
+                for (((o, x), m), s) in row_out.iter_mut().zip(row_in).zip(&mean).zip(&scale) {
+                    *o = (x - m) * s;
+                }
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LayerNormCompiler;
+
+impl Compiler for LayerNormCompiler {
+    type Output = ();
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
+        // Look for the mean norm followed by a std norm
+        // std_norm(sub(x, mean_reduce(x)))
+        let mean = op::<MeanReduce>();
+        let sub = unary::<Sub>(mean.clone());
+        let std_norm = unary::<StdNorm>(sub.clone());
+
+        let mut s = std_norm.clone().search(graph);
+        while s.next_match() {
+            if s.check_no_delete(&[std_norm.id]) {
+                // An intermediate node can't be deleted
+                continue;
+            }
+            let (mean, sub, std_norm) = (s.get(&mean), s.get(&sub), s.get(&std_norm));
+            let StdNorm { dim, epsilon } = *graph.get_op::<StdNorm>(std_norm);
+            if graph.get_op::<MeanReduce>(mean).0 != dim {
+                continue;
+            }
+            // The subtraction must center the same tensor the mean was taken over
+            let src = graph.get_sources(mean)[0];
+            let sub_srcs = graph.get_sources(sub);
+            if sub_srcs[0] != src || sub_srcs[1].0 != mean {
+                continue;
+            }
+
+            // Insert LayerNorm op
+            let layer_norm = graph
+                .add_op(LayerNorm { dim, epsilon })
+                .input(src.0, src.1, src.2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(std_norm, layer_norm, graph);
+            remap(std_norm, layer_norm, &mut ids, graph);
+
+            // Remove the old ops
+            graph.remove_node(std_norm);
+            s.try_delete();
+        }
+    }
+}
+
+/// Softmax along a dimension
+#[derive(Debug, Clone, PartialEq)]
+pub struct Softmax(pub usize);
+
+impl Operator for Softmax {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (_, dim, back) = reduction_sizes(&inp[0].1, self.0);
+        let data = get_contiguous(&inp[0]);
+        let mut out = vec![0.; data.len()];
+        let (mut max, mut sum) = (vec![0.; back], vec![0.; back]);
+        for (src, dst) in data
+            .chunks_exact(dim * back)
+            .zip(out.chunks_exact_mut(dim * back))
+        {
+            // Subtract the row max before exponentiating so large inputs don't overflow
+            max.fill(f32::NEG_INFINITY);
+            sum.fill(0.);
+            for row in src.chunks_exact(back) {
+                for (m, x) in max.iter_mut().zip(row) {
+                    *m = m.max(*x);
+                }
+            }
+            for (row_in, row_out) in src.chunks_exact(back).zip(dst.chunks_exact_mut(back)) {
+                for (((o, x), m), s) in row_out.iter_mut().zip(row_in).zip(&max).zip(&mut sum) {
+                    *o = (x - m).exp();
+                    *s += *o;
+                }
+            }
+            for row in dst.chunks_exact_mut(back) {
+                for (o, s) in row.iter_mut().zip(&sum) {
+                    *o /= s;
+                }
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SoftmaxCompiler;
+
+impl Compiler for SoftmaxCompiler {
+    type Output = ();
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
+        // Look for the softmax pattern
+        // mul(exp2(mul(sub(x, max_reduce(x)), 1 / ln(2))), recip(sum_reduce(exp2(...))))
+        let max_reduce = op::<MaxReduce>();
+        let sub = unary::<Sub>(max_reduce.clone());
+        let exp = unary::<Exp2>(binary::<Mul>(
+            sub.clone(),
+            super::constant(1.0 / f32::ln(2.)),
+        ));
+        let sum_reduce = unary::<SumReduce>(exp.clone());
+        let mul = unary::<Mul>(unary::<Recip>(sum_reduce.clone()));
+
+        let mut s = mul.clone().search(graph);
+        while s.next_match() {
+            if s.check_no_delete(&[mul.id]) {
+                // An intermediate node can't be deleted
+                continue;
+            }
+            let (max_reduce, sub, exp, sum_reduce, mul) = (
+                s.get(&max_reduce),
+                s.get(&sub),
+                s.get(&exp),
+                s.get(&sum_reduce),
+                s.get(&mul),
+            );
+            let dim = graph.get_op::<MaxReduce>(max_reduce).0;
+            if graph.get_op::<SumReduce>(sum_reduce).0 != dim {
+                continue;
+            }
+            // The max must be subtracted from its own input, and the sum must divide the same exp
+            let src = graph.get_sources(max_reduce)[0];
+            let sub_srcs = graph.get_sources(sub);
+            if sub_srcs[0] != src
+                || sub_srcs[1].0 != max_reduce
+                || !graph.get_sources(mul).iter().any(|(i, _, _)| *i == exp)
+            {
+                continue;
+            }
+
+            // Insert Softmax op
+            let softmax = graph
+                .add_op(Softmax(dim))
+                .input(src.0, src.1, src.2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(mul, softmax, graph);
+            remap(mul, softmax, &mut ids, graph);
+
+            // Remove the old ops
+            graph.remove_node(mul);
+            s.try_delete();
+        }
+    }
+}
+
+/// Index of the first max element along a dimension
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgMax(pub usize);
+
+impl Operator for ArgMax {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (front, dim, back) = reduction_sizes(&inp[0].1, self.0);
+        let data = get_contiguous(&inp[0]);
+        let mut out = vec![0.; front * back];
+        let mut max = vec![0.; back];
+        for (src, dst) in data
+            .chunks_exact(dim * back)
+            .zip(out.chunks_exact_mut(back))
+        {
+            max.fill(f32::NEG_INFINITY);
+            for (i, row) in src.chunks_exact(back).enumerate() {
+                for ((o, m), x) in dst.iter_mut().zip(&mut max).zip(row) {
+                    // Only a strictly greater element moves the index, so ties keep the first
+                    if *x > *m {
+                        *m = *x;
+                        *o = i as f32;
+                    }
+                }
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ArgMaxCompiler;
+
+impl Compiler for ArgMaxCompiler {
+    type Output = ();
+    fn compile<To: ToIdsMut>(&self, graph: &mut Graph, mut ids: To) {
+        // Look for the argmax pattern
+        // sub(n, max_reduce(mul(equal(x, max_reduce(x)), sub(n, arange))))
+        let max_reduce = op::<MaxReduce>();
+        let equal = unary::<Equal>(max_reduce.clone());
+        let countdown = unary::<Sub>(op::<ARange>());
+        let mul = binary::<Mul>(equal.clone(), countdown.clone());
+        let outer_max_reduce = unary::<MaxReduce>(mul.clone());
+        let sub = unary::<Sub>(outer_max_reduce.clone());
+
+        let mut s = sub.clone().search(graph);
+        while s.next_match() {
+            if s.check_no_delete(&[sub.id]) {
+                // An intermediate node can't be deleted
+                continue;
+            }
+            let (max_reduce, equal, countdown, outer_max_reduce, sub) = (
+                s.get(&max_reduce),
+                s.get(&equal),
+                s.get(&countdown),
+                s.get(&outer_max_reduce),
+                s.get(&sub),
+            );
+            let dim = graph.get_op::<MaxReduce>(max_reduce).0;
+            if graph.get_op::<MaxReduce>(outer_max_reduce).0 != dim {
+                continue;
+            }
+            // The max must be compared against its own input, and both subtractions must be from n
+            let src = graph.get_sources(max_reduce)[0];
+            let (equal_srcs, countdown_srcs, sub_srcs) = (
+                graph.get_sources(equal),
+                graph.get_sources(countdown),
+                graph.get_sources(sub),
+            );
+            if equal_srcs[0] != src
+                || equal_srcs[1].0 != max_reduce
+                || sub_srcs[1].0 != outer_max_reduce
+                || sub_srcs[0].0 != countdown_srcs[0].0
+                || !graph.check_node_type::<Constant>(sub_srcs[0].0)
+            {
+                continue;
+            }
+
+            // Insert ArgMax op
+            let argmax = graph
+                .add_op(ArgMax(dim))
+                .input(src.0, src.1, src.2)
+                .finish();
+
+            // Create edges to dests
+            move_outgoing_edge(sub, argmax, graph);
+            remap(sub, argmax, &mut ids, graph);
+
+            // Remove the old ops
+            graph.remove_node(sub);
+            s.try_delete();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{ArgMax, LayerNorm, MeanReduce, Softmax, StdNorm};
+    use crate::CPUCompiler;
+    luminal::test_imports!();
+
+    fn has_op<T: Operator + 'static>(cx: &Graph) -> bool {
+        cx.graph.node_indices().any(|n| cx.check_node_type::<T>(n))
+    }
+
+    /// Run a graph unfused and fused over every axis of a rank-3 input and compare the outputs
+    fn test_fused<T: Operator + 'static>(f: impl Fn(GraphTensor, usize) -> GraphTensor) {
+        for axis in 0..3 {
+            let mut rng = StdRng::seed_from_u64(axis as u64);
+            let data = random_vec_rng(4 * 5 * 6, &mut rng)
+                .into_iter()
+                .map(|i| i * 10.)
+                .collect::<Vec<_>>();
+            let mut cx = Graph::new();
+            let a = cx.tensor(('a', 5, 6)).set_dyn(data, (4, 5, 6));
+            let mut b = f(a, axis).retrieve();
+            // Also check reading a permuted input
+            let mut c = f(a.permute((2, 0, 1)), axis).retrieve();
+            cx.execute();
+            let (unopt_b, unopt_c) = (b.data(), c.data());
+            b.drop();
+            c.drop();
+
+            cx.compile(
+                <(GenericCompiler, CPUCompiler)>::default(),
+                (&mut b, &mut c),
+            );
+            assert!(has_op::<T>(&cx), "{} not fused", std::any::type_name::<T>());
+            cx.execute();
+            assert_close(&b.data(), &unopt_b);
+            assert_close(&c.data(), &unopt_c);
+        }
+    }
+
+    #[test]
+    fn test_fused_mean_reduce() {
+        test_fused::<MeanReduce>(|a, axis| a.mean_reduce(axis));
+    }
+
+    #[test]
+    fn test_fused_std_norm() {
+        test_fused::<StdNorm>(|a, axis| a.std_norm(axis, 1e-5));
+    }
+
+    #[test]
+    fn test_fused_layer_norm() {
+        test_fused::<LayerNorm>(|a, axis| a.layer_norm(axis, 1e-5));
+    }
+
+    #[test]
+    fn test_fused_softmax() {
+        test_fused::<Softmax>(|a, axis| a.softmax(axis));
+    }
+
+    #[test]
+    fn test_fused_softmax_large_inputs() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((2, 3))
+            .set(vec![1000., 1001., 1002., -1000., 0., 1000.]);
+        let mut b = a.softmax(1).retrieve();
+        cx.compile(<(GenericCompiler, CPUCompiler)>::default(), &mut b);
+        assert!(has_op::<Softmax>(&cx));
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_a = d_dev.tensor([[1000., 1001., 1002.], [-1000., 0., 1000.]]);
+        assert_close(&b.data(), &d_a.softmax::<DAxis<1>>().as_vec());
+    }
+
+    #[test]
+    fn test_fused_argmax() {
+        test_fused::<ArgMax>(|a, axis| a.argmax(axis, false));
+        test_fused::<ArgMax>(|a, axis| a.argmin(axis, true));
+    }
+
+    #[test]
+    fn test_fused_argmax_ties() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 4)).set(vec![1., 5., 5., -2., 3., 3., 3., 3.]);
+        let mut b = a.argmax(1, false).retrieve();
+        let mut c = a.argmin(0, false).retrieve();
+        cx.compile(
+            <(GenericCompiler, CPUCompiler)>::default(),
+            (&mut b, &mut c),
+        );
+        assert!(has_op::<ArgMax>(&cx));
+        cx.execute();
+
+        assert_exact(&b.data(), &[1., 0.]);
+        assert_exact(&c.data(), &[0., 1., 1., 0.]);
+    }
+}
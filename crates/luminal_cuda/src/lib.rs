@@ -31,6 +31,8 @@ use luminal::{op::InputTensor, prelude::*};
 
 /// Compile graphs to run on CUDA GPUs in supported data formats
 pub type CudaCompiler<T> = (
+    DecomposeScans,
+    DecomposeGathers,
     prim::PrimitiveCompiler<T>,
     SpecialOpsCompiler<T>,
     other::CopyCompiler<T>,
@@ -498,6 +498,85 @@ impl<T: CudaFloat> Operator for CudaLessThan<T> {
     }
 }
 
+#[derive(Clone)]
+pub struct CudaWhere<T> {
+    function: CudaFunction,
+    device: Arc<CudaDevice>,
+    _phantom: PhantomData<T>,
+    dyn_symbols: Vec<char>,
+    dyn_map: *const FxHashMap<char, usize>,
+}
+crate::debug_type!(CudaWhere);
+
+impl<T: CudaFloat> CudaWhere<T> {
+    pub fn new(
+        cond_shape: ShapeTracker,
+        a_shape: ShapeTracker,
+        b_shape: ShapeTracker,
+        device: Arc<CudaDevice>,
+        dyn_map: *const FxHashMap<char, usize>,
+    ) -> Self {
+        let (c_idx, c_valid) = get_idx_valid_exps(cond_shape);
+        let (a_idx, a_valid) = get_idx_valid_exps(a_shape);
+        let (b_idx, b_valid) = get_idx_valid_exps(b_shape);
+        let (dyn_symbols, rendered) = render_dyn_dim_inputs(&[cond_shape, a_shape, b_shape]);
+        let type_name = T::type_name();
+        let code = format!("
+#include \"cuda_fp16.h\"
+extern \"C\" __global__ void kernel({type_name} *out, const {type_name} *inp_c, const {type_name} *inp_a, const {type_name} *inp_b, int numel{rendered}) {{
+    int idx = blockIdx.x * blockDim.x + threadIdx.x;
+    if (idx < numel) {{
+        if ((({c_valid}) != 0) && (float)inp_c[{c_idx}] != 0.0) {{
+            out[idx] = (({a_valid}) != 0) ? inp_a[{a_idx}] : ({type_name})0.0;
+        }} else {{
+            out[idx] = (({b_valid}) != 0) ? inp_b[{b_idx}] : ({type_name})0.0;
+        }}
+    }}
+}}");
+        Self {
+            function: compile_and_load_kernel(code, &device),
+            device,
+            _phantom: Default::default(),
+            dyn_symbols,
+            dyn_map,
+        }
+    }
+}
+
+impl<T: CudaFloat> Operator for CudaWhere<T> {
+    fn process(&mut self, tensors: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let c = get_buffer_from_tensor::<T>(&tensors[0].0);
+        let a = get_buffer_from_tensor::<T>(&tensors[1].0);
+        let b = get_buffer_from_tensor::<T>(&tensors[2].0);
+        let inp_size = tensors[0].1.n_elements().to_usize().unwrap();
+        let out = unsafe { self.device.alloc::<T>(inp_size).unwrap() };
+        let mut params = vec![
+            (&out).as_kernel_param(),
+            c.as_kernel_param(),
+            a.as_kernel_param(),
+            b.as_kernel_param(),
+            inp_size.as_kernel_param(),
+        ];
+        input_dyn_dims(&mut params, &self.dyn_symbols, self.dyn_map);
+
+        unsafe {
+            self.function
+                .clone()
+                .launch(LaunchConfig::for_num_elems(inp_size as u32), &mut params)
+                .unwrap();
+        }
+
+        vec![Tensor::new(CudaData(out))]
+    }
+
+    fn custom(&mut self, key: &str, _: Box<dyn Any>) -> Option<Box<dyn Any>> {
+        if key == "elementwise" {
+            return Some(Box::new("(input0 != 0.0 ? input1 : input2)".to_string()));
+        }
+        None
+    }
+}
+
 #[derive(Clone)]
 pub struct CudaSumReduce<T> {
     function: CudaFunction,
@@ -682,6 +761,15 @@ impl<T: CudaFloat> Operator for CudaMaxReduce<T> {
     }
 }
 
+/// Ops without a cuda kernel, which are ran on the host with their inputs and outputs copied
+fn is_host_op(op: &dyn Operator) -> bool {
+    op.as_any().is::<LFunction>()
+        || op.as_any().is::<Sort>()
+        || op.as_any().is::<Scatter>()
+        || op.as_any().is::<Loop>()
+        || op.as_any().is::<WriteAlong>()
+}
+
 /// Convert all primitive ops to cuda primitive ops, and insert copy to and from device ops
 #[derive(Debug, Default)]
 pub struct PrimitiveCompiler<T>(PhantomData<T>);
@@ -695,33 +783,52 @@ impl<T: CudaFloat> Compiler for PrimitiveCompiler<T> {
         for function_node in graph
             .node_indices()
             .filter(|n| {
-                graph.node_weight(*n).unwrap().as_any().is::<Function>()
-                    && graph.edges(*n).count() != 0
+                is_host_op(graph.node_weight(*n).unwrap().as_ref()) && graph.edges(*n).count() != 0
             })
             .collect::<Vec<_>>()
         {
-            // Create copy node
-            let copy_node = graph
-                .add_op(CudaCopyToDevice::<T>::new(dev.clone()))
-                .input(function_node, 0, ShapeTracker::new(()))
-                .finish();
-
-            // Switch outgoing edges from input to copy_node
-            for (edge_id, weight, dest) in graph
+            // Copy each used output to device
+            let outputs = graph
                 .edges_directed(function_node, petgraph::Direction::Outgoing)
-                .map(|e| (e.id(), *e.weight(), e.target()))
-                .filter(|(_, _, trg)| *trg != copy_node)
-                .collect::<Vec<_>>()
-            {
-                graph.add_edge(copy_node, dest, weight);
-                graph.remove_edge(edge_id);
-            }
+                .filter_map(|e| e.weight().as_data())
+                .map(|(_, output, _)| output)
+                .unique()
+                .collect::<Vec<_>>();
+            for output in outputs {
+                // Create copy node
+                let copy_node = graph
+                    .add_op(CudaCopyToDevice::<T>::new(dev.clone()))
+                    .input(function_node, output, ShapeTracker::new(()))
+                    .finish();
 
-            if graph.no_delete.remove(&function_node) {
-                graph.no_delete.insert(copy_node);
-            }
-            if let Some(v) = graph.to_retrieve.get(&function_node) {
-                graph.to_retrieve.insert(copy_node, *v);
+                // Switch outgoing edges from this output to copy_node
+                for (edge_id, (input_order, _, shape), dest) in graph
+                    .edges_directed(function_node, petgraph::Direction::Outgoing)
+                    .filter(|e| e.target() != copy_node)
+                    .filter_map(|e| Some((e.id(), e.weight().as_data()?, e.target())))
+                    .filter(|(_, (_, o, _), _)| *o == output)
+                    .collect::<Vec<_>>()
+                {
+                    graph.add_edge(
+                        copy_node,
+                        dest,
+                        Dependency::Data {
+                            input_order,
+                            output_order: 0,
+                            shape,
+                        },
+                    );
+                    graph.remove_edge(edge_id);
+                }
+
+                if output == 0 {
+                    if graph.no_delete.remove(&function_node) {
+                        graph.no_delete.insert(copy_node);
+                    }
+                    if let Some(v) = graph.to_retrieve.get(&function_node) {
+                        graph.to_retrieve.insert(copy_node, *v);
+                    }
+                }
             }
 
             // Insert copy from device for function inputs
@@ -744,8 +851,8 @@ impl<T: CudaFloat> Compiler for PrimitiveCompiler<T> {
             .to_retrieve
             .iter()
             .map(|(a, b)| (*a, *b))
-            // Filter to non-functions
-            .filter(|(n, _)| !graph.node_weight(*n).unwrap().as_any().is::<LFunction>())
+            // Filter to ops not ran on device
+            .filter(|(n, _)| !is_host_op(graph.node_weight(*n).unwrap().as_ref()))
             .collect::<Vec<_>>()
         {
             if graph
@@ -832,6 +939,14 @@ impl<T: CudaFloat> Compiler for PrimitiveCompiler<T> {
                     dev.clone(),
                     &graph.dyn_map,
                 ));
+            } else if is::<Where>(op) {
+                *op_ref = Box::new(CudaWhere::<T>::new(
+                    shapes[0],
+                    shapes[1],
+                    shapes[2],
+                    dev.clone(),
+                    &graph.dyn_map,
+                ));
             } else if is::<Contiguous>(op) {
                 *op_ref = Box::new(CudaContiguous::<T>::new(
                     shapes[0],
@@ -0,0 +1,253 @@
+//! Dense linear algebra on batched matrices.
+//!
+//! Each function works on tensors of shape `[.., M, N]`, where the leading dims are batch dims and
+//! can be static or dynamic. The factorizations are CPU ops that compute in f64 internally.
+
+mod ops;
+pub use ops::*;
+
+use luminal::prelude::*;
+
+/// Check the input is a batch of square matrices, returning the matrix size
+fn check_square(a: GraphTensor, op: &str) -> Expression {
+    let dims = a.dims();
+    assert!(dims.len() >= 2, "{op} needs matrices, got shape {dims:?}");
+    let (rows, cols) = (dims[dims.len() - 2], dims[dims.len() - 1]);
+    if let (Some(r), Some(c)) = (rows.to_usize(), cols.to_usize()) {
+        assert_eq!(r, c, "{op} needs square matrices, got {r}x{c}");
+    }
+    rows
+}
+
+/// Check the right hand side of `A X = B` lines up with `A`
+fn check_rhs(a: GraphTensor, b: GraphTensor, op: &str) {
+    let n = check_square(a, op);
+    let (a_dims, b_dims) = (a.dims(), b.dims());
+    assert_eq!(
+        a_dims.len(),
+        b_dims.len(),
+        "{op} needs A and B to have the same number of dims, got {a_dims:?} and {b_dims:?}"
+    );
+    assert_eq!(
+        a_dims[..a_dims.len() - 2],
+        b_dims[..b_dims.len() - 2],
+        "{op} needs A and B to have the same batch dims"
+    );
+    let rows = b_dims[b_dims.len() - 2];
+    if let (Some(n), Some(rows)) = (n.to_usize(), rows.to_usize()) {
+        assert_eq!(n, rows, "{op} needs B to have {n} rows, got {rows}");
+    }
+}
+
+fn linalg_op(
+    op: impl Operator + 'static,
+    inputs: &[GraphTensor],
+    shape: Vec<Expression>,
+) -> GraphTensor {
+    let mut node = inputs[0].graph().add_op(op);
+    for inp in inputs {
+        node = node.input(inp.id, inp.output, inp.shape);
+    }
+    GraphTensor::from_id(node.finish(), ShapeTracker::new(shape), inputs[0].graph_ref)
+}
+
+/// Swap the last two dims of a batch of matrices
+pub fn transpose(a: GraphTensor) -> GraphTensor {
+    let rank = a.shape.len();
+    let mut axes = (0..rank).collect::<Vec<_>>();
+    axes.swap(rank - 2, rank - 1);
+    a.permute(axes)
+}
+
+/// Identity matrices in the shape of a batch of square matrices
+pub fn eye_like(a: GraphTensor) -> GraphTensor {
+    let n = check_square(a, "eye_like");
+    let cx = a.graph();
+    let mut eye = cx.arange(n).expand(0, n).equals(cx.arange(n).expand(1, n));
+    for (i, dim) in a.dims().into_iter().rev().skip(2).rev().enumerate() {
+        eye = eye.expand(i, dim);
+    }
+    eye
+}
+
+/// Cholesky decomposition of symmetric positive-definite matrices, giving lower triangular `L`
+/// where `A = L L^T`. Only the lower triangle of `A` is read.
+pub fn cholesky(a: GraphTensor) -> GraphTensor {
+    check_square(a, "cholesky");
+    linalg_op(Cholesky, &[a], a.dims())
+}
+
+/// Solve `A X = B` where `A` is lower (or upper) triangular `[.., N, N]` and `B` is `[.., N, K]`.
+/// Only the used triangle of `A` is read.
+pub fn triangular_solve(a: GraphTensor, b: GraphTensor, lower: bool) -> GraphTensor {
+    check_rhs(a, b, "triangular_solve");
+    linalg_op(TriangularSolve { lower }, &[a, b], b.dims())
+}
+
+/// Solve `A X = B` where `A` is `[.., N, N]` and `B` is `[.., N, K]`
+pub fn solve(a: GraphTensor, b: GraphTensor) -> GraphTensor {
+    check_rhs(a, b, "solve");
+    linalg_op(Solve, &[a, b], b.dims())
+}
+
+/// Invert square matrices
+pub fn inverse(a: GraphTensor) -> GraphTensor {
+    solve(a, eye_like(a))
+}
+
+/// The sign and natural log of the absolute value of the determinant of square matrices.
+/// Singular matrices have a sign of 0 and a log determinant of -inf.
+pub fn slogdet(a: GraphTensor) -> (GraphTensor, GraphTensor) {
+    check_square(a, "slogdet");
+    let mut dims = a.dims();
+    dims.truncate(dims.len() - 2);
+    let axis = dims.len();
+    dims.push(2.into());
+    let packed = linalg_op(Slogdet, &[a], dims);
+    (
+        packed.slice_along(..1, axis).squeeze(axis),
+        packed.slice_along(1.., axis).squeeze(axis),
+    )
+}
+
+/// The determinant of square matrices
+pub fn det(a: GraphTensor) -> GraphTensor {
+    let (sign, log_abs) = slogdet(a);
+    sign * log_abs.exp()
+}
+
+/// Reduced QR decomposition of `[.., M, N]` matrices with `M >= N`, giving `Q` with orthonormal
+/// columns `[.., M, N]` and upper triangular `R` `[.., N, N]` with a non-negative diagonal, where
+/// `A = Q R`
+pub fn qr(a: GraphTensor) -> (GraphTensor, GraphTensor) {
+    let mut dims = a.dims();
+    assert!(dims.len() >= 2, "qr needs matrices, got shape {dims:?}");
+    let (m, n) = (dims[dims.len() - 2], dims[dims.len() - 1]);
+    if let (Some(m), Some(n)) = (m.to_usize(), n.to_usize()) {
+        assert!(
+            m >= n,
+            "qr needs at least as many rows as columns, got {m}x{n}"
+        );
+    }
+    let axis = dims.len() - 2;
+    dims[axis] = m + n;
+    let packed = linalg_op(QR, &[a], dims);
+    (packed.slice_along(..m, axis), packed.slice_along(m.., axis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use luminal::tests::assert_close;
+    use luminal_cpu::CPUCompiler;
+
+    // Symmetric positive-definite, so every op can run on it
+    const SPD: [f32; 9] = [4., 2., 0.6, 2., 5., 1., 0.6, 1., 3.];
+
+    fn matmul(a: &[f32], b: &[f32], (m, k, n): (usize, usize, usize)) -> Vec<f32> {
+        (0..m * n)
+            .map(|i| (0..k).map(|j| a[i / n * k + j] * b[j * n + i % n]).sum())
+            .collect()
+    }
+
+    fn transposed(a: &[f32], (m, n): (usize, usize)) -> Vec<f32> {
+        (0..m * n).map(|i| a[i % m * n + i / m]).collect()
+    }
+
+    #[test]
+    fn test_cholesky_solves() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((3, 3)).set(SPD.to_vec());
+        let b = cx.tensor((3, 2)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let l = cholesky(a).retrieve();
+        let x = solve(a, b).retrieve();
+        let inv = inverse(a).retrieve();
+        let lower = triangular_solve(cholesky(a), b, true).retrieve();
+        let upper = triangular_solve(transpose(cholesky(a)), b, false).retrieve();
+        cx.execute();
+
+        let l_data = l.data();
+        assert_close(
+            &matmul(&l_data, &transposed(&l_data, (3, 3)), (3, 3, 3)),
+            &SPD,
+        );
+        assert_eq!(l_data[1], 0.);
+        assert_close(
+            &matmul(&SPD, &x.data(), (3, 3, 2)),
+            &[1., 2., 3., 4., 5., 6.],
+        );
+        assert_close(
+            &matmul(&SPD, &inv.data(), (3, 3, 3)),
+            &[1., 0., 0., 0., 1., 0., 0., 0., 1.],
+        );
+        assert_close(
+            &matmul(&l_data, &lower.data(), (3, 3, 2)),
+            &[1., 2., 3., 4., 5., 6.],
+        );
+        assert_close(
+            &matmul(&transposed(&l_data, (3, 3)), &upper.data(), (3, 3, 2)),
+            &[1., 2., 3., 4., 5., 6.],
+        );
+    }
+
+    #[test]
+    fn test_det_qr() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((2, 3, 3))
+            .set([SPD.to_vec(), vec![0., 2., 1., 1., 1., 1., 2., 2., 2.]].concat());
+        let (sign, log_abs) = slogdet(a);
+        let (sign, log_abs) = (sign.retrieve(), log_abs.retrieve());
+        let det = det(a).retrieve();
+        let tall = cx
+            .tensor((4, 2))
+            .set(vec![1., 2., -3., 4., 5., 6., 7., -8.]);
+        let (q, r) = qr(tall);
+        let (q, r) = (q.retrieve(), r.retrieve());
+        cx.execute();
+
+        // The last row of the second matrix is twice the middle one, so it's singular
+        assert_close(&det.data(), &[44.6, 0.]);
+        assert_close(&sign.data(), &[1., 0.]);
+        assert_close(&log_abs.data()[..1], &[44.6_f32.ln()]);
+        assert_eq!(log_abs.data()[1], f32::NEG_INFINITY);
+
+        let (q, r) = (q.data(), r.data());
+        assert_close(
+            &matmul(&q, &r, (4, 2, 2)),
+            &[1., 2., -3., 4., 5., 6., 7., -8.],
+        );
+        assert_close(
+            &matmul(&transposed(&q, (4, 2)), &q, (2, 4, 2)),
+            &[1., 0., 0., 1.],
+        );
+        assert_eq!(r[2], 0.);
+        assert!(r[0] > 0. && r[3] > 0.);
+    }
+
+    #[test]
+    fn test_dynamic_batched_compiled() {
+        let mut cx = Graph::new();
+        let a_data = [SPD.to_vec(), vec![2., 0., 0., 0., 3., 0., 1., 0., 1.]].concat();
+        let a = cx.tensor(('b', 3, 3)).set_dyn(a_data.clone(), (2, 3, 3));
+        let b = cx
+            .tensor(('b', 3, 1))
+            .set_dyn(vec![1., 2., 3., 4., 5., 6.], (2, 3, 1));
+        let mut x = (solve(a, b) * 2.).retrieve();
+        let mut d = det(a).retrieve();
+        cx.execute();
+        let (unoptimized_x, unoptimized_d) = (x.data(), d.data());
+        x.drop();
+        d.drop();
+
+        cx.compile(
+            <(GenericCompiler, CPUCompiler)>::default(),
+            (&mut x, &mut d),
+        );
+        cx.execute();
+        assert_close(&x.data(), &unoptimized_x);
+        assert_close(&d.data(), &unoptimized_d);
+        assert_close(&d.data()[1..], &[6.]);
+        assert_close(&x.data()[3..], &[4., 10. / 3., 8.]);
+    }
+}
@@ -0,0 +1,239 @@
+use luminal::prelude::*;
+
+/// Read an input of matrices into a contiguous buffer, returning it along with the size of each
+/// matrix (rows, cols)
+fn matrices(inp: &(InputTensor, ShapeTracker)) -> (Vec<f64>, usize, usize) {
+    let sh = inp.1.shape_usize();
+    assert!(sh.len() >= 2, "Linear algebra ops need matrix inputs");
+    let (rows, cols) = (sh[sh.len() - 2], sh[sh.len() - 1]);
+    let data = inp.0.borrowed().downcast_ref::<Vec<f32>>().unwrap();
+    let (ind, val) = (inp.1.index_expression(), inp.1.valid_expression());
+    let mut stack = vec![];
+    let out = (0..sh.iter().product::<usize>())
+        .map(|i| {
+            if val.exec_single_var_stack(i, &mut stack) != 0 {
+                data[ind.exec_single_var_stack(i, &mut stack)] as f64
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    (out, rows, cols)
+}
+
+fn to_tensor(data: Vec<f64>) -> Tensor {
+    Tensor::new(data.into_iter().map(|i| i as f32).collect::<Vec<_>>())
+}
+
+/// Factor a square matrix in place into `P A = L U`, with the unit lower triangle of L below the
+/// diagonal and U on and above it. Returns the row permutation and its sign.
+fn lu_in_place(a: &mut [f64], n: usize) -> (Vec<usize>, f64) {
+    let mut perm = (0..n).collect::<Vec<_>>();
+    let mut sign = 1.0;
+    for col in 0..n {
+        // Partial pivoting: swap up the row with the largest entry in this column
+        let pivot = (col..n)
+            .max_by(|i, j| a[i * n + col].abs().total_cmp(&a[j * n + col].abs()))
+            .unwrap();
+        if pivot != col {
+            for k in 0..n {
+                a.swap(col * n + k, pivot * n + k);
+            }
+            perm.swap(col, pivot);
+            sign = -sign;
+        }
+        let diag = a[col * n + col];
+        if diag == 0.0 {
+            // Singular, so the rest of this column is already zero
+            continue;
+        }
+        for row in col + 1..n {
+            let factor = a[row * n + col] / diag;
+            a[row * n + col] = factor;
+            for k in col + 1..n {
+                a[row * n + k] -= factor * a[col * n + k];
+            }
+        }
+    }
+    (perm, sign)
+}
+
+/// Cholesky decomposition of symmetric positive-definite matrices `[.., N, N]`, giving the lower
+/// triangular `L` where `A = L L^T`.
+///
+/// Only the lower triangle of the input is read. Matrices that aren't positive-definite give NaNs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cholesky;
+impl Operator for Cholesky {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (a, n, _) = matrices(&inp[0]);
+        let mut l = vec![0.0_f64; a.len()];
+        for (a, l) in a.chunks_exact(n * n).zip(l.chunks_exact_mut(n * n)) {
+            for j in 0..n {
+                let diag =
+                    (a[j * n + j] - (0..j).map(|k| l[j * n + k].powi(2)).sum::<f64>()).sqrt();
+                l[j * n + j] = diag;
+                for i in j + 1..n {
+                    let dot = (0..j).map(|k| l[i * n + k] * l[j * n + k]).sum::<f64>();
+                    l[i * n + j] = (a[i * n + j] - dot) / diag;
+                }
+            }
+        }
+        vec![to_tensor(l)]
+    }
+}
+
+/// Solve `A X = B` for `X`, where `A` is a triangular matrix `[.., N, N]` and `B` is `[.., N, K]`.
+///
+/// Only the lower (or upper) triangle of `A` is read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriangularSolve {
+    pub lower: bool,
+}
+impl Operator for TriangularSolve {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (a, n, _) = matrices(&inp[0]);
+        let (mut x, _, k) = matrices(&inp[1]);
+        for (a, x) in a.chunks_exact(n * n).zip(x.chunks_exact_mut(n * k)) {
+            for c in 0..k {
+                for step in 0..n {
+                    let i = if self.lower { step } else { n - 1 - step };
+                    let solved = if self.lower { 0..i } else { i + 1..n };
+                    let dot = solved.map(|j| a[i * n + j] * x[j * k + c]).sum::<f64>();
+                    x[i * k + c] = (x[i * k + c] - dot) / a[i * n + i];
+                }
+            }
+        }
+        vec![to_tensor(x)]
+    }
+}
+
+/// Solve `A X = B` for `X`, where `A` is a square matrix `[.., N, N]` and `B` is `[.., N, K]`.
+///
+/// Uses an LU decomposition with partial pivoting. Singular matrices give infinities or NaNs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solve;
+impl Operator for Solve {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (mut a, n, _) = matrices(&inp[0]);
+        let (b, _, k) = matrices(&inp[1]);
+        let mut x = vec![0.0; b.len()];
+        for ((a, b), x) in a
+            .chunks_exact_mut(n * n)
+            .zip(b.chunks_exact(n * k))
+            .zip(x.chunks_exact_mut(n * k))
+        {
+            let (perm, _) = lu_in_place(a, n);
+            for c in 0..k {
+                // Forward substitute through the unit lower triangle
+                for i in 0..n {
+                    let dot = (0..i).map(|j| a[i * n + j] * x[j * k + c]).sum::<f64>();
+                    x[i * k + c] = b[perm[i] * k + c] - dot;
+                }
+                // Back substitute through the upper triangle
+                for i in (0..n).rev() {
+                    let dot = (i + 1..n).map(|j| a[i * n + j] * x[j * k + c]).sum::<f64>();
+                    x[i * k + c] = (x[i * k + c] - dot) / a[i * n + i];
+                }
+            }
+        }
+        vec![to_tensor(x)]
+    }
+}
+
+/// The sign and natural log of the absolute value of the determinant of square matrices
+/// `[.., N, N]`.
+///
+/// Both are packed into the last dimension of a `[.., 2]` output, so autograd only has one output
+/// to track. Singular matrices have a sign of 0 and a log determinant of -inf.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slogdet;
+impl Operator for Slogdet {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (mut a, n, _) = matrices(&inp[0]);
+        let mut out = vec![];
+        for a in a.chunks_exact_mut(n * n) {
+            let (_, mut sign) = lu_in_place(a, n);
+            let mut log_abs = 0.0;
+            for i in 0..n {
+                let diag = a[i * n + i];
+                sign *= diag.signum();
+                log_abs += diag.abs().ln();
+            }
+            if log_abs == f64::NEG_INFINITY {
+                sign = 0.0;
+            }
+            out.extend([sign, log_abs]);
+        }
+        vec![to_tensor(out)]
+    }
+}
+
+/// Reduced QR decomposition of matrices `[.., M, N]` with `M >= N`, giving `Q` with orthonormal
+/// columns `[.., M, N]` and upper triangular `R` `[.., N, N]` where `A = Q R`. The diagonal of `R`
+/// is kept non-negative, so the decomposition is unique for full rank inputs.
+///
+/// `Q` and `R` are stacked along the rows of a `[.., M + N, N]` output, so autograd only has one
+/// output to track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QR;
+impl Operator for QR {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let (mut a, m, n) = matrices(&inp[0]);
+        assert!(
+            m >= n,
+            "QR needs at least as many rows as columns, got {m}x{n}"
+        );
+        let mut out = vec![];
+        for r in a.chunks_exact_mut(m * n) {
+            // Householder reflections, zeroing each column below the diagonal
+            let mut reflectors = Vec::with_capacity(n);
+            for j in 0..n {
+                let norm = (j..m).map(|i| r[i * n + j].powi(2)).sum::<f64>().sqrt();
+                let alpha = if r[j * n + j] > 0.0 { -norm } else { norm };
+                let mut v = (j..m).map(|i| r[i * n + j]).collect::<Vec<_>>();
+                v[0] -= alpha;
+                let v_norm = v.iter().map(|i| i * i).sum::<f64>();
+                if v_norm != 0.0 {
+                    for c in j..n {
+                        let dot = (j..m).map(|i| v[i - j] * r[i * n + c]).sum::<f64>();
+                        for i in j..m {
+                            r[i * n + c] -= 2.0 * dot / v_norm * v[i - j];
+                        }
+                    }
+                }
+                reflectors.push((v, v_norm));
+            }
+            // Build Q by applying the reflections in reverse to the first N columns of I
+            let mut q = vec![0.0; m * n];
+            for i in 0..n {
+                q[i * n + i] = 1.0;
+            }
+            for (j, (v, v_norm)) in reflectors.iter().enumerate().rev() {
+                if *v_norm == 0.0 {
+                    continue;
+                }
+                for c in 0..n {
+                    let dot = (j..m).map(|i| v[i - j] * q[i * n + c]).sum::<f64>();
+                    for i in j..m {
+                        q[i * n + c] -= 2.0 * dot / v_norm * v[i - j];
+                    }
+                }
+            }
+            // Flip signs so the diagonal of R is non-negative
+            for j in 0..n {
+                if r[j * n + j] < 0.0 {
+                    for c in j..n {
+                        r[j * n + c] = -r[j * n + c];
+                    }
+                    for i in 0..m {
+                        q[i * n + j] = -q[i * n + j];
+                    }
+                }
+            }
+            out.extend(q);
+            out.extend((0..n * n).map(|i| if i % n < i / n { 0.0 } else { r[i] }));
+        }
+        vec![to_tensor(out)]
+    }
+}
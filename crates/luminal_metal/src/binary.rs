@@ -498,6 +498,12 @@ impl<T: MetalFloat> Compiler for MetalGatherCompiler<T> {
                 .as_data()
                 .unwrap()
                 .2;
+            // Only gathers of rows from a matrix, not other axes or higher ranks
+            if graph.get_op::<MetalSumReduce<T>>(s.get(&sum_reduce)).dim != 1
+                || emb_shape.len() != 3
+            {
+                continue;
+            }
             let embed_dim = emb_shape.dims()[2].to_usize().unwrap();
             let index_shape = graph
                 .edges_connecting(s.get(&indexes), s.get(&ind_copy))
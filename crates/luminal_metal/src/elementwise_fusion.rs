@@ -758,7 +758,7 @@ mod tests {
         const SEQ: usize = 2;
         const HEAD_DIM: usize = 4;
         let freqs = (cx.arange(HEAD_DIM / 2) * 2.0) / (HEAD_DIM as f32);
-        let freqs = 1000000_f32.pow(freqs);
+        let freqs = 1000000_f32.pow(freqs).recip();
         let pos = cx.arange(SEQ) + 0;
         let mut emb = pos.expand(1, 1).matmul(freqs.expand(0, SEQ)).retrieve();
 
@@ -822,7 +822,7 @@ mod tests {
             .set(random_vec_rng(BATCH * N_HEADS * SEQ * HEAD_DIM, &mut rng))
             .keep();
         let freqs = (cx.arange(HEAD_DIM / 2) * 2.0) / (HEAD_DIM as f32);
-        let freqs = 1000000_f32.pow(freqs);
+        let freqs = 1000000_f32.pow(freqs).recip();
         let pos = cx.arange(SEQ) + 0;
         let emb = pos.expand(1, 1).matmul(freqs.expand(0, SEQ));
         // Split input into evens and odds
@@ -902,7 +902,7 @@ mod tests {
             // Get freqs
             let freqs =
                 (input.graph().arange(head_dim / 2) * 2.0) / (head_dim.to_usize().unwrap() as f32);
-            let freqs = 500_000_f32.pow(freqs);
+            let freqs = 500_000_f32.pow(freqs).recip();
             let pos = input.graph().arange(seq) + prev_seq;
             let emb = pos.expand(1, 1).matmul(freqs.expand(0, seq));
 
@@ -33,6 +33,8 @@ pub type MetalCompiler<T> = (Timed<MetalCompilerPreBuffer<T>>, Timed<BufferCompi
 
 /// All metal compilers coming before buffer compilers
 pub type MetalCompilerPreBuffer<T> = (
+    Timed<DecomposeScans>,
+    Timed<DecomposeGathers>,
     Timed<prim::PrimitiveCompiler<T>>,
     Timed<SpecialOpsCompiler<T>>,
     Timed<other::CopyCompiler<T>>,
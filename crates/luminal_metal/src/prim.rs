@@ -606,6 +606,139 @@ impl<T: MetalFloat> Operator for MetalLessThan<T> {
     }
 }
 
+#[derive(Clone)]
+pub struct MetalWhere<T> {
+    pipeline: ComputePipelineState,
+    queue: CommandQueue,
+    device: Device,
+    dyn_symbols: Vec<char>,
+    _phantom: PhantomData<T>,
+    dyn_map: *const FxHashMap<char, usize>,
+}
+crate::debug_type!(MetalWhere);
+
+impl<T: MetalFloat> MetalWhere<T> {
+    pub fn new(
+        cond_shape: ShapeTracker,
+        a_shape: ShapeTracker,
+        b_shape: ShapeTracker,
+        device: Device,
+        queue: CommandQueue,
+        dyn_map: *const FxHashMap<char, usize>,
+    ) -> Self {
+        let (c_idx_exp, c_valid_exp) = get_idx_valid_exps(cond_shape);
+        let (a_idx_exp, a_valid_exp) = get_idx_valid_exps(a_shape);
+        let (b_idx_exp, b_valid_exp) = get_idx_valid_exps(b_shape);
+        let type_name = T::type_name();
+        let (dyn_symbols, rendered) = render_dyn_dim_inputs(&[cond_shape, a_shape, b_shape], 5);
+        let code = format!("
+#include <metal_stdlib>
+using namespace metal;
+kernel void mkernel(device {type_name} *inp_c [[buffer(0)]], device {type_name} *inp_a [[buffer(1)]], device {type_name} *inp_b [[buffer(2)]], device {type_name} *out [[buffer(3)]], device int& n_elements [[buffer(4)]], uint idx [[thread_position_in_grid]]{rendered}) {{
+    if (idx < n_elements) {{
+        {type_name} t = 0.0h;
+        if (({c_valid_exp}) != 0 && inp_c[{c_idx_exp}] != 0) {{
+            if (({a_valid_exp}) != 0) {{
+                t = inp_a[{a_idx_exp}];
+            }}
+        }} else if (({b_valid_exp}) != 0) {{
+            t = inp_b[{b_idx_exp}];
+        }}
+        out[idx] = t;
+    }}
+}}
+"
+        );
+        Self {
+            pipeline: compile_function("mkernel", &code, &device),
+            queue,
+            device,
+            dyn_symbols,
+            _phantom: Default::default(),
+            dyn_map,
+        }
+    }
+}
+
+impl<T> MetalKernel for MetalWhere<T> {
+    fn output_buffer_sizes(&self, input_shapes: &[ShapeTracker]) -> Vec<Expression> {
+        vec![input_shapes[0].n_elements() * size_of::<T>()]
+    }
+    fn metal_forward(
+        &self,
+        inputs: &[(&Buffer, ShapeTracker)],
+        command_buffer: &CommandBufferRef,
+        _: &[&Buffer],
+        output_buffers: &[&Buffer],
+    ) {
+        let inp_size = inputs[0].1.n_elements().to_usize().unwrap();
+
+        let encoder =
+            command_buffer.compute_command_encoder_with_descriptor(ComputePassDescriptor::new());
+        encoder.set_compute_pipeline_state(&self.pipeline);
+
+        // Set inputs
+        encoder.set_buffer(0, Some(inputs[0].0), 0);
+        encoder.set_buffer(1, Some(inputs[1].0), 0);
+        encoder.set_buffer(2, Some(inputs[2].0), 0);
+        encoder.set_buffer(3, Some(output_buffers[0]), 0);
+        encoder.set_u32(4, inp_size as u32);
+        input_dyn_dims(
+            &self.dyn_symbols,
+            unsafe { self.dyn_map.as_ref().unwrap() },
+            encoder,
+            5,
+        );
+
+        // Execute
+        encoder.dispatch_1d(inp_size);
+        encoder.end_encoding();
+    }
+}
+
+impl<T: MetalFloat> Operator for MetalWhere<T> {
+    fn process(&mut self, tensors: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        autoreleasepool(|| {
+            let command_buffer = self.queue.new_command_buffer();
+            let inp_size = tensors[0].1.n_elements().to_usize().unwrap();
+            let out = self.device.new_buffer(
+                (inp_size * std::mem::size_of::<T>()) as u64,
+                MTLResourceOptions::StorageModeShared,
+            );
+
+            self.metal_forward(
+                &[
+                    (get_buffer_from_tensor(&tensors[0].0), tensors[0].1),
+                    (get_buffer_from_tensor(&tensors[1].0), tensors[1].1),
+                    (get_buffer_from_tensor(&tensors[2].0), tensors[2].1),
+                ],
+                command_buffer,
+                &[],
+                &[&out],
+            );
+
+            command_buffer.commit();
+            command_buffer.wait_until_completed();
+
+            vec![Tensor::new(MetalBuffer(out))]
+        })
+    }
+
+    fn custom(&mut self, key: &str, _: Box<dyn Any>) -> Option<Box<dyn Any>> {
+        if key == "metal" {
+            return Some(Box::new(MetalKernelWrapper(Arc::new(Box::new(
+                self.clone(),
+            )))));
+        }
+        if key == "elementwise" {
+            return Some(Box::new(
+                "((input0) != 0 ? (input1) : (input2))".to_string(),
+            ));
+        }
+        None
+    }
+}
+
 #[derive(Clone)]
 pub struct MetalMod<T> {
     pipeline: ComputePipelineState,
@@ -1031,6 +1164,15 @@ impl<T: MetalFloat> Operator for MetalMaxReduce<T> {
     }
 }
 
+/// Ops without a metal kernel, which are ran on the host with their inputs and outputs copied
+fn is_host_op(op: &dyn Operator) -> bool {
+    op.as_any().is::<LFunction>()
+        || op.as_any().is::<Sort>()
+        || op.as_any().is::<Scatter>()
+        || op.as_any().is::<Loop>()
+        || op.as_any().is::<WriteAlong>()
+}
+
 #[derive(Default, Debug)]
 pub struct PrimitiveCompiler<T>(PhantomData<T>);
 
@@ -1043,37 +1185,50 @@ impl<T: MetalFloat + 'static> Compiler for PrimitiveCompiler<T> {
         // Copy function output to device and input from device
         for function_node in graph
             .node_indices()
-            .filter(|n| graph.node_weight(*n).unwrap().as_any().is::<LFunction>())
+            .filter(|n| is_host_op(graph.node_weight(*n).unwrap().as_ref()))
             .collect::<Vec<_>>()
         {
-            if graph
+            // Copy each used output to device
+            let outputs = graph
                 .edges_directed(function_node, petgraph::Direction::Outgoing)
-                .count()
-                > 0
-            {
-                // Copy outputs to device
+                .filter_map(|e| e.weight().as_data())
+                .map(|(_, output, _)| output)
+                .unique()
+                .collect::<Vec<_>>();
+            for output in outputs {
                 let sh = ShapeTracker::new(());
                 let copy_node = graph
                     .add_op(MetalCopyToDevice::<T>::new(dev.clone()))
-                    .input(function_node, 0, sh)
+                    .input(function_node, output, sh)
                     .finish();
 
-                // Switch outgoing edges from input to copy_node
-                for (edge_id, weight, dest) in graph
+                // Switch outgoing edges from this output to copy_node
+                for (edge_id, (input_order, _, shape), dest) in graph
                     .edges_directed(function_node, petgraph::Direction::Outgoing)
-                    .map(|e| (e.id(), *e.weight(), e.target()))
-                    .filter(|(_, _, trg)| *trg != copy_node)
+                    .filter(|e| e.target() != copy_node)
+                    .filter_map(|e| Some((e.id(), e.weight().as_data()?, e.target())))
+                    .filter(|(_, (_, o, _), _)| *o == output)
                     .collect::<Vec<_>>()
                 {
-                    graph.add_edge(copy_node, dest, weight);
+                    graph.add_edge(
+                        copy_node,
+                        dest,
+                        Dependency::Data {
+                            input_order,
+                            output_order: 0,
+                            shape,
+                        },
+                    );
                     graph.remove_edge(edge_id);
                 }
 
-                if graph.no_delete.remove(&function_node) {
-                    graph.no_delete.insert(copy_node);
-                }
-                if let Some(w) = graph.to_retrieve.remove(&function_node) {
-                    graph.to_retrieve.insert(copy_node, w);
+                if output == 0 {
+                    if graph.no_delete.remove(&function_node) {
+                        graph.no_delete.insert(copy_node);
+                    }
+                    if let Some(w) = graph.to_retrieve.remove(&function_node) {
+                        graph.to_retrieve.insert(copy_node, w);
+                    }
                 }
             }
 
@@ -1107,8 +1262,8 @@ impl<T: MetalFloat + 'static> Compiler for PrimitiveCompiler<T> {
             .to_retrieve
             .iter()
             .map(|(a, b)| (*a, *b))
-            // Filter to non-functions
-            .filter(|(n, _)| !graph.node_weight(*n).unwrap().as_any().is::<LFunction>())
+            // Filter to ops not ran on device
+            .filter(|(n, _)| !is_host_op(graph.node_weight(*n).unwrap().as_ref()))
             .collect::<Vec<_>>()
         {
             if graph
@@ -1215,6 +1370,15 @@ impl<T: MetalFloat + 'static> Compiler for PrimitiveCompiler<T> {
                     queue.clone(),
                     &graph.dyn_map,
                 ));
+            } else if is::<Where>(op) {
+                *op_ref = Box::new(MetalWhere::<T>::new(
+                    src_shapes[0],
+                    src_shapes[1],
+                    src_shapes[2],
+                    dev.clone(),
+                    queue.clone(),
+                    &graph.dyn_map,
+                ));
             } else if is::<Mod>(op) {
                 *op_ref = Box::new(MetalMod::<T>::new(
                     src_shapes[0],
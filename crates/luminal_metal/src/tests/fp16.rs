@@ -853,6 +853,9 @@ fn test_conv2d() {
         (KERNELX, KERNELY),
         (STRIDEX, STRIDEY),
         (DILATIONX, DILATIONY),
+        (0, 0),
+        1,
+        false,
         &mut cx,
     );
     model.weight.set(vec![
@@ -901,7 +904,7 @@ fn test_conv1d_pad_stride() {
     let input_data = random_vec_rng(CH_IN * DIM_IN, &mut rng);
 
     let model = Conv1D::new(
-        CH_IN, CH_OUT, KERNEL, STRIDE, DILATION, PADDING, false, &mut cx,
+        CH_IN, CH_OUT, KERNEL, STRIDE, DILATION, PADDING, 1, false, &mut cx,
     );
     model.weight.set(kernel_data.clone());
 
@@ -516,6 +516,9 @@ fn test_conv2d() {
         (KERNELX, KERNELY),
         (STRIDEX, STRIDEY),
         (DILATIONX, DILATIONY),
+        (0, 0),
+        1,
+        false,
         &mut cx,
     );
     model.weight.set(vec![
@@ -561,7 +564,7 @@ fn test_conv1d_pad_stride() {
     let kernel_data = random_vec_rng(KERNEL * CH_IN * CH_OUT, &mut rng);
     let input_data = random_vec_rng(CH_IN * DIM_IN, &mut rng);
 
-    let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, STRIDE, 1, PADDING, false, &mut cx);
+    let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, STRIDE, 1, PADDING, 1, false, &mut cx);
     model.weight.set(kernel_data.clone());
 
     let inp1 = cx
@@ -1,17 +1,184 @@
 use luminal::prelude::*;
 
+use crate::pooling::Window;
+
+/// Convolve the trailing spatial dims of a `[..batch, ch_in, ..spatial]` input, one window per
+/// spatial dim. The weight is `[ch_out, ch_in / groups * kernel]`, which is PyTorch's
+/// `[ch_out, ch_in / groups, ..kernel]` with the kernel flattened.
+fn convolve(
+    input: GraphTensor,
+    weight: GraphTensor,
+    bias: Option<GraphTensor>,
+    windows: &[Window],
+    (ch_in, ch_out, groups): (usize, usize, usize),
+) -> GraphTensor {
+    let n = windows.len();
+    let dims = input.dims();
+    assert!(
+        dims.len() > n,
+        "A {n}D convolution needs a [..batch, channels, ..spatial] input, got shape {dims:?}"
+    );
+    let n_batch = dims.len() - n - 1;
+    assert_eq!(
+        dims[n_batch], ch_in,
+        "Expected {ch_in} input channels, got shape {dims:?}"
+    );
+    // Work on a single batch dim
+    let x = match n_batch {
+        0 => input.unsqueeze(0),
+        1 => input,
+        _ => input.flatten(0, n_batch - 1),
+    };
+    let batch = x.dims()[0];
+    // Fold each kernel dim into the batch and channels as it's windowed, so the shape never needs
+    // more dims than a tracker holds
+    let mut x = x.flatten(0, 1);
+    for (i, window) in windows.iter().enumerate() {
+        let mut axes = (0..n + 1).collect::<Vec<_>>();
+        axes.insert(1, n + 1);
+        x = window.apply(x, i + 1, 0.).permute(axes).flatten(0, 1);
+    }
+    // [batch * ch_in * kernel, ..out]
+    let out = x.dims()[1..].to_vec();
+    let n_out = out
+        .iter()
+        .fold(Expression::from(1), |a, d| (a * *d).simplify());
+    let taps = ch_in / groups * windows.iter().map(|w| w.kernel).product::<usize>();
+    let cols = x.reshape((batch, groups, taps, n_out));
+    let mut o = weight
+        .reshape((groups, ch_out / groups, taps))
+        .expand(0, batch)
+        .matmul(cols)
+        .reshape([vec![batch, ch_out.into()], out.clone()].concat());
+    if let Some(b) = bias {
+        let b = (0..n).fold(b.expand(0, batch), |b, i| b.expand(i + 2, out[i]));
+        o += b;
+    }
+
+    // Restore the original batch dims
+    let mut final_shape = dims[..n_batch].to_vec();
+    final_shape.push(ch_out.into());
+    final_shape.extend(out);
+    o.reshape(final_shape)
+}
+
+/// Transposed convolution of the trailing spatial dims, as a convolution over the input with
+/// `stride - 1` zeros between samples. The weight is `[ch_in, ch_out / groups * kernel]`, which is
+/// PyTorch's `[ch_in, ch_out / groups, ..kernel]` with the kernel flattened.
+fn convolve_transpose(
+    input: GraphTensor,
+    weight: GraphTensor,
+    bias: Option<GraphTensor>,
+    windows: &[Window],
+    output_padding: &[usize],
+    (ch_in, ch_out, groups): (usize, usize, usize),
+) -> GraphTensor {
+    let n = windows.len();
+    assert!(
+        input.shape.len() > n,
+        "A {n}D transposed convolution needs a [..batch, channels, ..spatial] input, got shape {:?}",
+        input.dims()
+    );
+    let first = input.shape.len() - n;
+    let mut x = input;
+    for (i, (window, extra)) in windows.iter().zip(output_padding).enumerate() {
+        let axis = first + i;
+        if window.stride > 1 {
+            let mut dims = x.dims();
+            dims[axis] *= window.stride;
+            x = x
+                .unsqueeze(axis + 1)
+                .pad_along(0, window.stride - 1, axis + 1)
+                .reshape(dims);
+        }
+        // Each output sees the whole kernel, so pad out by the kernel span less the padding. The
+        // zeros after the last sample only count towards the output padding.
+        let span = (window.dilation * (window.kernel - 1)) as isize;
+        let left = span - window.padding as isize;
+        let right = left + *extra as isize - (window.stride - 1) as isize;
+        x = x.pad_along(left.max(0) as usize, right.max(0) as usize, axis);
+        if left < 0 || right < 0 {
+            let dim = x.dims()[axis];
+            x = x.slice_along(
+                Expression::from((-left).max(0) as usize)..dim - (-right).max(0) as usize,
+                axis,
+            );
+        }
+    }
+
+    // Swap the channels of each group and flip the kernel, which reverses every spatial axis at
+    // once since the kernel is flattened
+    let kernel = windows.iter().map(|w| w.kernel).product::<usize>();
+    let flip = (kernel - 1) as f32 - weight.graph().arange(kernel);
+    let weight = weight
+        .reshape((groups, ch_in / groups, ch_out / groups, kernel))
+        .permute((0, 2, 1, 3))
+        .index_select(3, flip)
+        .reshape((ch_out, ch_in / groups * kernel));
+    let windows = windows
+        .iter()
+        .map(|w| Window {
+            stride: 1,
+            padding: 0,
+            ..*w
+        })
+        .collect::<Vec<_>>();
+    convolve(x, weight, bias, &windows, (ch_in, ch_out, groups))
+}
+
+fn conv_windows<const N: usize>(
+    kernel: [usize; N],
+    stride: [usize; N],
+    dilation: [usize; N],
+    padding: [usize; N],
+) -> [Window; N] {
+    std::array::from_fn(|i| Window {
+        kernel: kernel[i],
+        stride: stride[i],
+        dilation: dilation[i],
+        padding: padding[i],
+        ceil_mode: false,
+    })
+}
+
+fn check_groups(ch_in: usize, ch_out: usize, groups: usize) {
+    assert!(
+        groups > 0 && ch_in % groups == 0 && ch_out % groups == 0,
+        "{groups} groups must divide both {ch_in} input channels and {ch_out} output channels"
+    );
+}
+
+fn check_output_padding(windows: &[Window], output_padding: &[usize]) {
+    for (w, p) in windows.iter().zip(output_padding) {
+        assert!(
+            *p < w.stride.max(w.dilation),
+            "Output padding {p} must be smaller than either the stride {} or dilation {}",
+            w.stride,
+            w.dilation
+        );
+    }
+}
+
+fn optional_bias(bias: bool, name: &str, ch_out: usize, cx: &mut Graph) -> Option<GraphTensor> {
+    if bias {
+        Some(cx.named_tensor(name, ch_out))
+    } else {
+        None
+    }
+}
+
 pub struct Conv1D {
-    pub weight: GraphTensor, // ch_out, ch_in * kernel
+    pub weight: GraphTensor, // ch_out, ch_in / groups * kernel
     pub bias: Option<GraphTensor>,
-    padding: usize,
-    dilation: usize,
-    stride: usize,
-    kernel: usize,
+    windows: [Window; 1],
     ch_in: usize,
+    ch_out: usize,
+    groups: usize,
 }
 
 impl Conv1D {
-    /// Create a new 1D convolution layer
+    /// Create a new 1D convolution layer. With `groups` the channels are split into that many
+    /// groups that are convolved separately, so `groups == ch_in` is a depthwise convolution.
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         ch_in: usize,
@@ -20,21 +187,18 @@ impl Conv1D {
         stride: usize,
         dilation: usize,
         padding: usize,
+        groups: usize,
         bias: bool,
         cx: &mut Graph,
     ) -> Self {
+        check_groups(ch_in, ch_out, groups);
         Self {
-            weight: cx.named_tensor("Weight", (ch_out, ch_in * kernel)),
-            bias: if bias {
-                Some(cx.named_tensor("Bias", ch_out))
-            } else {
-                None
-            },
-            padding,
-            dilation,
-            stride,
-            kernel,
+            weight: cx.named_tensor("Weight", (ch_out, ch_in / groups * kernel)),
+            bias: optional_bias(bias, "Bias", ch_out, cx),
+            windows: conv_windows([kernel], [stride], [dilation], [padding]),
             ch_in,
+            ch_out,
+            groups,
         }
     }
 }
@@ -51,80 +215,54 @@ impl SerializeModule for Conv1D {
 impl Module<GraphTensor> for Conv1D {
     type Output = GraphTensor;
     fn forward(&self, input: GraphTensor) -> Self::Output {
-        assert_eq!(input.dims()[input.shape.len() - 2], self.ch_in);
         // Input: batch_dims, ch_in, dim_in
-        // Reshape to 2 batch dims
-        let n_expands = 4 - input.shape.len();
-        let mut inp = input;
-        for _ in 0..n_expands {
-            inp = inp.expand(0, 1);
-        }
-
-        let batch1 = inp.dims()[0];
-        let batch2 = inp.dims()[1];
-        let dim_in = *input.dims().last().unwrap();
-        let dim_out = (((dim_in + 2 * self.padding - self.dilation * (self.kernel - 1) - 1)
-            / self.stride)
-            + 1)
-        .simplify();
-        let w = self.weight.permute((1, 0));
-        let mut out = inp
-            // Add padding
-            .pad(((0, 0), (0, 0), (0, 0), (self.padding, 0)))
-            .contiguous()
-            .pad(((0, 0), (0, 0), (0, 0), (0, self.padding)))
-            // Pool
-            .pool_last_dim(self.kernel, self.stride, self.dilation)
-            // Combine channel_in and kernel
-            .permute((0, 1, 3, 2, 4))
-            .reshape((batch1, batch2, dim_out, self.ch_in * self.kernel))
-            .matmul(w)
-            .permute((0, 1, 3, 2));
-        if let Some(b) = self.bias {
-            out += b.expand_to(out.shape);
-        }
-
-        // Reshape back to original shape
-        let mut final_shape = out.dims();
-        for _ in 0..n_expands {
-            final_shape.remove(0);
-        }
-        out.reshape(final_shape) // Output: batch_dims, ch_out, dim_out
+        convolve(
+            input,
+            self.weight,
+            self.bias,
+            &self.windows,
+            (self.ch_in, self.ch_out, self.groups),
+        )
     }
 }
 
 pub struct Conv2D {
-    pub weight: GraphTensor,       // ch_out, ch_in * kernel_x * kernel_y
+    pub weight: GraphTensor,       // ch_out, ch_in / groups * kernel_x * kernel_y
     pub bias: Option<GraphTensor>, // ch_out
-    kernel: (usize, usize),
-    stride: (usize, usize),
-    dilation: (usize, usize),
-    ch_out: usize,
+    windows: [Window; 2],
     ch_in: usize,
+    ch_out: usize,
+    groups: usize,
 }
 
 impl Conv2D {
+    /// Create a new 2D convolution layer. With `groups` the channels are split into that many
+    /// groups that are convolved separately, so `groups == ch_in` is a depthwise convolution.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         ch_in: usize,
         ch_out: usize,
         kernel: (usize, usize),
         stride: (usize, usize),
         dilation: (usize, usize),
+        padding: (usize, usize),
+        groups: usize,
         bias: bool,
         cx: &mut Graph,
     ) -> Self {
+        check_groups(ch_in, ch_out, groups);
         Self {
-            weight: cx.named_tensor("CWeight", (ch_out, ch_in * kernel.0 * kernel.1)),
-            bias: if bias {
-                Some(cx.named_tensor("CBias", ch_out))
-            } else {
-                None
-            },
-            kernel,
-            stride,
-            dilation,
-            ch_out,
+            weight: cx.named_tensor("CWeight", (ch_out, ch_in / groups * kernel.0 * kernel.1)),
+            bias: optional_bias(bias, "CBias", ch_out, cx),
+            windows: conv_windows(
+                kernel.into(),
+                stride.into(),
+                dilation.into(),
+                padding.into(),
+            ),
             ch_in,
+            ch_out,
+            groups,
         }
     }
 }
@@ -138,81 +276,60 @@ impl SerializeModule for Conv2D {
     }
 }
 
-impl Conv2D {
-    pub fn forward(&self, mut input: GraphTensor) -> GraphTensor {
-        // Input: (batch (optional), ch_in, dimx_in, dimy_in)
-        let mut expanded = false;
-        if input.shape.len() == 3 {
-            // Expand batch
-            input = input.expand(0, 1);
-            expanded = true;
-        }
-        let (batch, _, dimx_in, dimy_in) = input.dims4();
-        let dimx_out = (((dimx_in - self.dilation.0 * (self.kernel.0 - 1) - 1) / self.stride.0)
-            + 1)
-        .simplify();
-        let dimy_out = (((dimy_in - self.dilation.1 * (self.kernel.1 - 1) - 1) / self.stride.1)
-            + 1)
-        .simplify();
-        let input_pooled = input
-            .pool_last_dim(self.kernel.1, self.stride.1, self.dilation.1)
-            .permute((0, 1, 3, 4, 2))
-            .pool_last_dim(self.kernel.0, self.stride.0, self.dilation.0)
-            .permute((0, 1, 5, 3, 4, 2))
-            .reshape((
-                batch,
-                self.ch_in * self.kernel.0 * self.kernel.1,
-                dimx_out * dimy_out,
-            ));
-
-        let mut o = self.weight.expand(0, batch).matmul(input_pooled).reshape((
-            batch,
-            self.ch_out,
-            dimx_out,
-            dimy_out,
-        ));
-        if let Some(b) = self.bias {
-            o += b.expand_to(o.shape);
-        }
-        if expanded {
-            o.reshape((self.ch_out, dimx_out, dimy_out))
-        } else {
-            o
-        }
+impl Module<GraphTensor> for Conv2D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, ch_in, dimx_in, dimy_in
+        convolve(
+            input,
+            self.weight,
+            self.bias,
+            &self.windows,
+            (self.ch_in, self.ch_out, self.groups),
+        )
     }
 }
+
 pub struct Conv3D {
-    pub weight: GraphTensor, // ch_out, ch_in * kernel_x * kernel_y * kernel_z
+    pub weight: GraphTensor, // ch_out, ch_in / groups * kernel_x * kernel_y * kernel_z
     pub bias: Option<GraphTensor>, // ch_out
-    kernel: (usize, usize, usize),
-    stride: (usize, usize, usize),
-    dilation: (usize, usize, usize),
+    windows: [Window; 3],
     ch_in: usize,
     ch_out: usize,
+    groups: usize,
 }
 
 impl Conv3D {
+    /// Create a new 3D convolution layer. With `groups` the channels are split into that many
+    /// groups that are convolved separately, so `groups == ch_in` is a depthwise convolution.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         ch_in: usize,
         ch_out: usize,
         kernel: (usize, usize, usize),
         stride: (usize, usize, usize),
         dilation: (usize, usize, usize),
+        padding: (usize, usize, usize),
+        groups: usize,
         bias: bool,
         cx: &mut Graph,
     ) -> Self {
+        check_groups(ch_in, ch_out, groups);
         Self {
-            weight: cx.named_tensor("Weight", (ch_out, ch_in * kernel.0 * kernel.1 * kernel.2)),
-            bias: if bias {
-                Some(cx.named_tensor("Bias", ch_out))
-            } else {
-                None
-            },
-            kernel,
-            stride,
-            dilation,
+            weight: cx.named_tensor(
+                "Weight",
+                (ch_out, ch_in / groups * kernel.0 * kernel.1 * kernel.2),
+            ),
+            bias: optional_bias(bias, "Bias", ch_out, cx),
+            windows: conv_windows(
+                kernel.into(),
+                stride.into(),
+                dilation.into(),
+                padding.into(),
+            ),
             ch_in,
             ch_out,
+            groups,
         }
     }
 }
@@ -226,53 +343,165 @@ impl SerializeModule for Conv3D {
     }
 }
 
-impl Conv3D {
-    pub fn forward(&self, input: GraphTensor) -> GraphTensor {
-        // Input: ch_in, dimx_in, dimy_in, dimz_in
-        let dimx_in = input.dims()[1];
-        let dimy_in = input.dims()[2];
-        let dimz_in = input.dims()[3];
-        let dimx_out = (((dimx_in - self.dilation.0 * (self.kernel.0 - 1) - 1) / self.stride.0)
-            + 1)
-        .simplify();
-        let dimy_out = (((dimy_in - self.dilation.1 * (self.kernel.1 - 1) - 1) / self.stride.1)
-            + 1)
-        .simplify();
-        let dimz_out = (((dimz_in - self.dilation.2 * (self.kernel.2 - 1) - 1) / self.stride.2)
-            + 1)
-        .simplify();
-
-        let input_pooled = input
-            .pool_last_dim(self.kernel.1, self.stride.1, self.dilation.1)
-            .permute((0, 2, 3, 4, 1))
-            .pool_last_dim(self.kernel.0, self.stride.0, self.dilation.0)
-            .reshape((
-                self.ch_in,
-                dimz_out,
-                self.kernel.1,
-                dimx_out * self.kernel.0,
-                dimy_in,
-            ));
-
-        let last_pool = input_pooled
-            .pool_last_dim(self.kernel.2, self.stride.2, self.dilation.2)
-            .permute((0, 2, 5, 3, 1, 4));
-
-        let reshaped = last_pool.reshape((
-            self.ch_in * self.kernel.0 * self.kernel.1 * self.kernel.2,
-            dimx_out * dimy_out * dimz_out,
-        ));
-
-        self.weight
-            .matmul(reshaped)
-            .reshape((self.ch_out, dimx_out, dimy_out, dimz_out))
+impl Module<GraphTensor> for Conv3D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, ch_in, dimx_in, dimy_in, dimz_in
+        convolve(
+            input,
+            self.weight,
+            self.bias,
+            &self.windows,
+            (self.ch_in, self.ch_out, self.groups),
+        )
+    }
+}
+
+/// A 1D transposed convolution, which upsamples by its stride. The output is
+/// `(dim_in - 1) * stride - 2 * padding + dilation * (kernel - 1) + output_padding + 1` long.
+pub struct ConvTranspose1D {
+    pub weight: GraphTensor, // ch_in, ch_out / groups * kernel
+    pub bias: Option<GraphTensor>,
+    windows: [Window; 1],
+    output_padding: usize,
+    ch_in: usize,
+    ch_out: usize,
+    groups: usize,
+}
+
+impl ConvTranspose1D {
+    /// Create a new 1D transposed convolution layer. `output_padding` adds to one side of the
+    /// output, to pick between the input sizes a strided convolution maps to the same size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        ch_in: usize,
+        ch_out: usize,
+        kernel: usize,
+        stride: usize,
+        dilation: usize,
+        padding: usize,
+        output_padding: usize,
+        groups: usize,
+        bias: bool,
+        cx: &mut Graph,
+    ) -> Self {
+        check_groups(ch_in, ch_out, groups);
+        let windows = conv_windows([kernel], [stride], [dilation], [padding]);
+        check_output_padding(&windows, &[output_padding]);
+        Self {
+            weight: cx.named_tensor("Weight", (ch_in, ch_out / groups * kernel)),
+            bias: optional_bias(bias, "Bias", ch_out, cx),
+            windows,
+            output_padding,
+            ch_in,
+            ch_out,
+            groups,
+        }
+    }
+}
+
+impl SerializeModule for ConvTranspose1D {
+    fn serialize(&self, s: &mut luminal::module::Serializer) {
+        s.tensor("weight", self.weight);
+        if let Some(bias) = self.bias {
+            s.tensor("bias", bias);
+        }
+    }
+}
+
+impl Module<GraphTensor> for ConvTranspose1D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, ch_in, dim_in
+        convolve_transpose(
+            input,
+            self.weight,
+            self.bias,
+            &self.windows,
+            &[self.output_padding],
+            (self.ch_in, self.ch_out, self.groups),
+        )
+    }
+}
+
+/// A 2D transposed convolution, which upsamples by its stride. Each output dim is
+/// `(dim_in - 1) * stride - 2 * padding + dilation * (kernel - 1) + output_padding + 1` long.
+pub struct ConvTranspose2D {
+    pub weight: GraphTensor, // ch_in, ch_out / groups * kernel_x * kernel_y
+    pub bias: Option<GraphTensor>,
+    windows: [Window; 2],
+    output_padding: (usize, usize),
+    ch_in: usize,
+    ch_out: usize,
+    groups: usize,
+}
+
+impl ConvTranspose2D {
+    /// Create a new 2D transposed convolution layer. `output_padding` adds to one side of each
+    /// output dim, to pick between the input sizes a strided convolution maps to the same size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        ch_in: usize,
+        ch_out: usize,
+        kernel: (usize, usize),
+        stride: (usize, usize),
+        dilation: (usize, usize),
+        padding: (usize, usize),
+        output_padding: (usize, usize),
+        groups: usize,
+        bias: bool,
+        cx: &mut Graph,
+    ) -> Self {
+        check_groups(ch_in, ch_out, groups);
+        let windows = conv_windows(
+            kernel.into(),
+            stride.into(),
+            dilation.into(),
+            padding.into(),
+        );
+        check_output_padding(&windows, &[output_padding.0, output_padding.1]);
+        Self {
+            weight: cx.named_tensor("Weight", (ch_in, ch_out / groups * kernel.0 * kernel.1)),
+            bias: optional_bias(bias, "Bias", ch_out, cx),
+            windows,
+            output_padding,
+            ch_in,
+            ch_out,
+            groups,
+        }
+    }
+}
+
+impl SerializeModule for ConvTranspose2D {
+    fn serialize(&self, s: &mut luminal::module::Serializer) {
+        s.tensor("weight", self.weight);
+        if let Some(bias) = self.bias {
+            s.tensor("bias", bias);
+        }
+    }
+}
+
+impl Module<GraphTensor> for ConvTranspose2D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, ch_in, dimx_in, dimy_in
+        convolve_transpose(
+            input,
+            self.weight,
+            self.bias,
+            &self.windows,
+            &[self.output_padding.0, self.output_padding.1],
+            (self.ch_in, self.ch_out, self.groups),
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Conv1D, Conv2D, Conv3D};
+    use super::{Conv1D, Conv2D, Conv3D, ConvTranspose1D, ConvTranspose2D};
     use candle_core::{Device, Tensor};
+    use dfdx::prelude::{Axes2, Cpu, PermuteTo, TensorFromVec, TryMatMul};
+    use itertools::iproduct;
     use luminal::{
         prelude::*,
         tests::{assert_close, random_vec_rng},
@@ -290,7 +519,7 @@ mod tests {
         const DIM_IN: usize = 6;
         const DIM_OUT: usize = ((DIM_IN - (KERNEL - 1) - 1) / STRIDE) + 1;
 
-        let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, KERNEL, 1, 0, false, &mut cx);
+        let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, KERNEL, 1, 0, 1, false, &mut cx);
         model.weight.set([[[0.0316, -0.2057]]]);
 
         let inp1 = cx.tensor((CH_IN, DIM_IN)).set([[3., 0., 9., 6., 0., 6.]]);
@@ -319,7 +548,7 @@ mod tests {
         let kernel_data = random_vec_rng(KERNEL * CH_IN * CH_OUT, &mut rng);
         let input_data = random_vec_rng(CH_IN * DIM_IN, &mut rng);
 
-        let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, STRIDE, 1, PADDING, false, &mut cx);
+        let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, STRIDE, 1, PADDING, 1, false, &mut cx);
         model.weight.set(kernel_data.clone());
 
         let inp1 = cx
@@ -349,7 +578,7 @@ mod tests {
         const STRIDE: usize = 2;
         const DIM_IN: usize = 12;
 
-        let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, STRIDE, 1, 0, false, &mut cx);
+        let model = Conv1D::new(CH_IN, CH_OUT, KERNEL, STRIDE, 1, 0, 1, false, &mut cx);
         model.weight.set(vec![
             -0.1700, -0.2000, 0.1000, -0.0200, 0.1000, 0.0200, -0.2100, -0.2300, -0.0600, 0.1500,
             0.1200, 0.1000, 0.1800, 0.0600, -0.1700, -0.0400, 0.1000, -0.0200, -0.1700, 0.1000,
@@ -456,6 +685,8 @@ mod tests {
             (KERNELX, KERNELY),
             (STRIDEX, STRIDEY),
             (DILATIONX, DILATIONY),
+            (0, 0),
+            1,
             false,
             &mut cx,
         );
@@ -509,8 +740,8 @@ mod tests {
 
         let exp_out1 = cx.tensor((CH_OUT, DIMX_OUT, DIMY_OUT, DIMZ_OUT));
         exp_out1.set(vec![
-            // Example expected output data (2 channels, 1x1x2 volume)
-            90.6935, 98.7138, 98.8273, 102.6553,
+            // Expected output data (2 channels, 1x1x2 volume)
+            85.48617, 87.94911, 105.160065, 99.40267,
         ]);
 
         exp_out1.retrieve();
@@ -521,6 +752,8 @@ mod tests {
             (KERNELX, KERNELY, KERNELZ),
             (STRIDEX, STRIDEY, STRIDEZ),
             (DILATIONX, DILATIONY, DILATIONZ),
+            (0, 0, 0),
+            1,
             false,
             &mut cx,
         );
@@ -544,4 +777,293 @@ mod tests {
 
         assert_close(&out1.data(), &exp_out1.data());
     }
+
+    type Nchw = (usize, usize, usize, usize);
+
+    /// Space the elements of an NCHW input `stride` apart like a transposed convolution does, then
+    /// zero pad them by `((top, bottom), (left, right))`
+    fn spread_pad(
+        inp: &[f32],
+        (batch, ch, h, w): Nchw,
+        (sh, sw): (usize, usize),
+        ((top, bottom), (left, right)): ((usize, usize), (usize, usize)),
+    ) -> (Vec<f32>, Nchw) {
+        let (ph, pw) = (
+            (h - 1) * sh + 1 + top + bottom,
+            (w - 1) * sw + 1 + left + right,
+        );
+        let mut out = vec![0.; batch * ch * ph * pw];
+        for (i, v) in inp.iter().enumerate() {
+            let (image, y, x) = (i / (h * w), i / w % h, i % w);
+            out[(image * ph + top + y * sh) * pw + left + x * sw] = *v;
+        }
+        (out, (batch, ch, ph, pw))
+    }
+
+    /// Grouped convolution of an NCHW input in dfdx, which only has convolution kernels on
+    /// nightly. Each group's patches are unfolded and multiplied with that group's weights.
+    fn dfdx_conv2d(
+        inp: &[f32],
+        (batch, ch_in, h, w): Nchw,
+        weight: &[f32],
+        (ch_out, kh, kw): (usize, usize, usize),
+        (sh, sw): (usize, usize),
+        (dh, dw): (usize, usize),
+        groups: usize,
+    ) -> Vec<f32> {
+        let (cig, cog) = (ch_in / groups, ch_out / groups);
+        let (oh, ow) = (
+            (h - dh * (kh - 1) - 1) / sh + 1,
+            (w - dw * (kw - 1) - 1) / sw + 1,
+        );
+        let dev = Cpu::default();
+        let mut out = vec![0.; batch * ch_out * oh * ow];
+        for (g, group_weight) in weight.chunks(cog * cig * kh * kw).enumerate() {
+            let mut patches = vec![];
+            for (b, y, x) in iproduct!(0..batch, 0..oh, 0..ow) {
+                for (c, i, j) in iproduct!(0..cig, 0..kh, 0..kw) {
+                    let (c, y, x) = (g * cig + c, y * sh + i * dh, x * sw + j * dw);
+                    patches.push(inp[((b * ch_in + c) * h + y) * w + x]);
+                }
+            }
+            let patches = dev.tensor_from_vec(patches, (batch * oh * ow, cig * kh * kw));
+            let group_weight = dev.tensor_from_vec(group_weight.to_vec(), (cog, cig * kh * kw));
+            let group_out = patches.matmul(group_weight.permute::<_, Axes2<1, 0>>());
+            for (i, v) in group_out.as_vec().into_iter().enumerate() {
+                let (b, pos, o) = (i / (oh * ow * cog), i / cog % (oh * ow), i % cog);
+                out[(b * ch_out + g * cog + o) * oh * ow + pos] = v;
+            }
+        }
+        out
+    }
+
+    /// A transposed convolution is a convolution over the spread out input, with the kernel
+    /// flipped and its channels swapped within each group
+    #[allow(clippy::too_many_arguments)]
+    fn dfdx_conv_transpose2d(
+        inp: &[f32],
+        shape: Nchw,
+        weight: &[f32],
+        (ch_out, kh, kw): (usize, usize, usize),
+        (sh, sw): (usize, usize),
+        (dh, dw): (usize, usize),
+        (ph, pw): (usize, usize),
+        (oph, opw): (usize, usize),
+        groups: usize,
+    ) -> Vec<f32> {
+        let (cig, cog) = (shape.1 / groups, ch_out / groups);
+        let mut flipped = vec![0.; weight.len()];
+        for (i, v) in weight.iter().enumerate() {
+            let (c, o, y, x) = (
+                i / (cog * kh * kw),
+                i / (kh * kw) % cog,
+                i / kw % kh,
+                i % kw,
+            );
+            let (g, c) = (c / cig, c % cig);
+            flipped[(((g * cog + o) * cig + c) * kh + kh - 1 - y) * kw + kw - 1 - x] = *v;
+        }
+        let (top, left) = (dh * (kh - 1) - ph, dw * (kw - 1) - pw);
+        let (spread, shape) =
+            spread_pad(inp, shape, (sh, sw), ((top, top + oph), (left, left + opw)));
+        dfdx_conv2d(
+            &spread,
+            shape,
+            &flipped,
+            (ch_out, kh, kw),
+            (1, 1),
+            (dh, dw),
+            groups,
+        )
+    }
+
+    /// Add a bias to each channel of a `[batch, channels, positions]` output
+    fn add_bias(out: Vec<f32>, bias: &[f32], positions: usize) -> Vec<f32> {
+        out.into_iter()
+            .enumerate()
+            .map(|(i, v)| v + bias[i / positions % bias.len()])
+            .collect()
+    }
+
+    #[test]
+    fn test_conv1d_groups_transpose() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let conv = Conv1D::new(6, 4, 3, 2, 2, 3, 2, true, &mut cx);
+        let transpose = ConvTranspose1D::new(6, 4, 3, 2, 2, 1, 1, 2, true, &mut cx);
+        let conv_weight = random_vec_rng(4 * 3 * 3, &mut rng);
+        let transpose_weight = random_vec_rng(6 * 2 * 3, &mut rng);
+        let (conv_bias, transpose_bias) =
+            (random_vec_rng(4, &mut rng), random_vec_rng(4, &mut rng));
+        conv.weight.set(conv_weight.clone());
+        conv.bias.unwrap().set(conv_bias.clone());
+        transpose.weight.set(transpose_weight.clone());
+        transpose.bias.unwrap().set(transpose_bias.clone());
+        let inp_data = random_vec_rng(2 * 6 * 9, &mut rng);
+        let inp = cx.tensor((2, 6, 's')).set_dyn(inp_data.clone(), (2, 6, 9));
+        let conv_out = conv.forward(inp).retrieve();
+        let transpose_out = transpose.forward(inp).retrieve();
+        cx.execute();
+
+        // 1D convolutions are 2D ones over a single row
+        let (padded, shape) = spread_pad(&inp_data, (2, 6, 1, 9), (1, 1), ((0, 0), (3, 3)));
+        let d_conv = dfdx_conv2d(&padded, shape, &conv_weight, (4, 1, 3), (1, 2), (1, 2), 2);
+        let d_transpose = dfdx_conv_transpose2d(
+            &inp_data,
+            (2, 6, 1, 9),
+            &transpose_weight,
+            (4, 1, 3),
+            (1, 2),
+            (1, 2),
+            (0, 1),
+            (0, 1),
+            2,
+        );
+        assert_eq!(transpose_out.data().len(), 2 * 4 * 20);
+        assert_close(&conv_out.data(), &add_bias(d_conv, &conv_bias, 6));
+        assert_close(
+            &transpose_out.data(),
+            &add_bias(d_transpose, &transpose_bias, 20),
+        );
+    }
+
+    #[test]
+    fn test_conv2d_groups_padding() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        // Depthwise, with a channel multiplier of 2
+        let depthwise = Conv2D::new(4, 8, (3, 2), (2, 2), (1, 1), (1, 1), 4, true, &mut cx);
+        let grouped = Conv2D::new(4, 6, (2, 2), (1, 1), (2, 2), (1, 1), 2, false, &mut cx);
+        let depthwise_weight = random_vec_rng(8 * 3 * 2, &mut rng);
+        let depthwise_bias = random_vec_rng(8, &mut rng);
+        let grouped_weight = random_vec_rng(6 * 2 * 2 * 2, &mut rng);
+        depthwise.weight.set(depthwise_weight.clone());
+        depthwise.bias.unwrap().set(depthwise_bias.clone());
+        grouped.weight.set(grouped_weight.clone());
+        let inp_data = random_vec_rng(2 * 4 * 7 * 5, &mut rng);
+        let inp = cx
+            .tensor((2, 4, 'h', 'w'))
+            .set_dyn(inp_data.clone(), (2, 4, 7, 5));
+        let depthwise_out = depthwise.forward(inp).retrieve();
+        let grouped_out = grouped.forward(inp).retrieve();
+        cx.execute();
+
+        let (padded, shape) = spread_pad(&inp_data, (2, 4, 7, 5), (1, 1), ((1, 1), (1, 1)));
+        let d_depthwise = dfdx_conv2d(
+            &padded,
+            shape,
+            &depthwise_weight,
+            (8, 3, 2),
+            (2, 2),
+            (1, 1),
+            4,
+        );
+        let d_grouped = dfdx_conv2d(
+            &padded,
+            shape,
+            &grouped_weight,
+            (6, 2, 2),
+            (1, 1),
+            (2, 2),
+            2,
+        );
+        assert_close(
+            &depthwise_out.data(),
+            &add_bias(d_depthwise, &depthwise_bias, 4 * 3),
+        );
+        assert_close(&grouped_out.data(), &d_grouped);
+    }
+
+    #[test]
+    fn test_conv_transpose2d() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(2);
+        let model = ConvTranspose2D::new(
+            3,
+            2,
+            (3, 2),
+            (2, 2),
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            1,
+            true,
+            &mut cx,
+        );
+        let weight = random_vec_rng(3 * 2 * 3 * 2, &mut rng);
+        let bias = random_vec_rng(2, &mut rng);
+        model.weight.set(weight.clone());
+        model.bias.unwrap().set(bias.clone());
+        // Unbatched
+        let inp_data = random_vec_rng(3 * 4 * 5, &mut rng);
+        let inp = cx.tensor((3, 4, 5)).set(inp_data.clone());
+        let out = model.forward(inp).retrieve();
+        cx.execute();
+
+        let d_out = dfdx_conv_transpose2d(
+            &inp_data,
+            (1, 3, 4, 5),
+            &weight,
+            (2, 3, 2),
+            (2, 2),
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            1,
+        );
+        assert_eq!(out.dims(), [2, 8, 9].map(Expression::from).to_vec());
+        assert_close(&out.data(), &add_bias(d_out, &bias, 8 * 9));
+    }
+
+    #[test]
+    fn test_conv3d_padding_groups() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(3);
+        let (kernel, stride, dilation, padding) = ((2, 3, 1), (1, 2, 1), (2, 1, 1), (1, 0, 2));
+        let model = Conv3D::new(4, 2, kernel, stride, dilation, padding, 2, true, &mut cx);
+        let weight = random_vec_rng(2 * 2 * 2 * 3, &mut rng);
+        let bias = random_vec_rng(2, &mut rng);
+        model.weight.set(weight.clone());
+        model.bias.unwrap().set(bias.clone());
+        let inp_data = random_vec_rng(2 * 4 * 3 * 5 * 2, &mut rng);
+        let inp = cx.tensor((2, 4, 3, 5, 2)).set(inp_data.clone());
+        let out = model.forward(inp).retrieve();
+        cx.execute();
+
+        // Direct convolution over the zero padded input
+        let (dims_in, dims_out) = ([3, 5, 2], [3, 2, 6]);
+        let mut expected = vec![];
+        for b in 0..2 {
+            for o in 0..2 {
+                for x in 0..dims_out[0] {
+                    for y in 0..dims_out[1] {
+                        for z in 0..dims_out[2] {
+                            let mut sum = bias[o];
+                            for c in 0..2 {
+                                for (i, j) in (0..3 * 2).map(|t| (t / 3, t % 3)) {
+                                    let pos = [
+                                        (x * stride.0 + i * dilation.0) as isize
+                                            - padding.0 as isize,
+                                        (y * stride.1 + j * dilation.1) as isize
+                                            - padding.1 as isize,
+                                        (z * stride.2) as isize - padding.2 as isize,
+                                    ];
+                                    if pos.iter().zip(dims_in).any(|(p, d)| *p < 0 || *p >= d) {
+                                        continue;
+                                    }
+                                    let [px, py, pz] = pos.map(|p| p as usize);
+                                    let ch = o * 2 + c;
+                                    sum += weight[(o * 2 + c) * 6 + i * 3 + j]
+                                        * inp_data[(((b * 4 + ch) * 3 + px) * 5 + py) * 2 + pz];
+                                }
+                            }
+                            expected.push(sum);
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(out.dims(), [2, 2, 3, 2, 6].map(Expression::from).to_vec());
+        assert_close(&out.data(), &expected);
+    }
 }
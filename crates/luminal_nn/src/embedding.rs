@@ -3,7 +3,6 @@ use luminal::{prelude::*, tests::random_vec};
 pub struct Embedding {
     permute: bool,
     pub weight: GraphTensor, // n embeddings x embedding dim
-    embedding_dim: usize,
 }
 
 impl Embedding {
@@ -11,7 +10,6 @@ impl Embedding {
         Self {
             weight: cx.named_tensor("Embedding Weight", (n_embeddings, embedding_dim)),
             permute: false,
-            embedding_dim,
         }
     }
 
@@ -19,7 +17,6 @@ impl Embedding {
         Self {
             weight: cx.named_tensor("Embedding Weight", (embedding_dim, n_embeddings)),
             permute: true,
-            embedding_dim,
         }
     }
 
@@ -41,19 +38,11 @@ impl Module<GraphTensor> for Embedding {
     type Output = GraphTensor;
 
     fn forward(&self, input: GraphTensor) -> Self::Output {
-        // Flatten batches
-        let batch_size = input.shape.n_elements();
-        let inp = input.reshape(batch_size);
-        // Gather
-        let out = if self.permute {
-            self.weight.permute((1, 0)).gather(inp)
+        if self.permute {
+            self.weight.permute((1, 0)).index_select(0, input)
         } else {
-            self.weight.gather(inp)
-        };
-        // Unflatten
-        let mut new_shape = input.dims();
-        new_shape.push(self.embedding_dim.into());
-        out.reshape(new_shape)
+            self.weight.index_select(0, input)
+        }
     }
 }
 
@@ -8,5 +8,11 @@ mod linear;
 pub use linear::*;
 mod norm;
 pub use norm::*;
+mod pooling;
+pub use pooling::*;
+mod positional;
+pub use positional::*;
+mod recurrent;
+pub use recurrent::*;
 mod transformer;
 pub use transformer::*;
@@ -80,3 +80,378 @@ impl SerializeModule for LayerNorm {
         }
     }
 }
+
+/// Broadcast a per-channel `[channels]` tensor over `dims`, where the channels are on `axis`
+fn broadcast_along(t: GraphTensor, dims: &[Expression], axis: usize) -> GraphTensor {
+    dims.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != axis)
+        .fold(t, |t, (i, d)| t.expand(i, *d))
+}
+
+/// Scale and shift each channel of a normalized `[batch, channels, ..]` input
+fn affine(
+    mut input: GraphTensor,
+    weight: Option<GraphTensor>,
+    bias: Option<GraphTensor>,
+) -> GraphTensor {
+    let dims = input.dims();
+    if let Some(w) = weight {
+        input *= broadcast_along(w, &dims, 1);
+    }
+    if let Some(b) = bias {
+        input += broadcast_along(b, &dims, 1);
+    }
+    input
+}
+
+fn check_channels(input: GraphTensor, channels: usize, norm: &str) {
+    let dims = input.dims();
+    assert!(
+        dims.len() >= 2,
+        "{norm} needs a [batch, channels, ..] input, got shape {dims:?}"
+    );
+    if let Some(c) = dims[1].to_usize() {
+        assert_eq!(c, channels, "{norm} expected {channels} channels, got {c}");
+    }
+}
+
+/// Root mean square norm over the last dim with a learned scale, as used in llama
+pub struct RMSNorm {
+    pub weight: GraphTensor,
+    epsilon: f32,
+}
+
+impl RMSNorm {
+    pub fn new(dim: usize, epsilon: f32, cx: &mut Graph) -> Self {
+        Self {
+            weight: cx.named_tensor("RMSNorm Weight", dim),
+            epsilon,
+        }
+    }
+}
+
+impl Module<GraphTensor> for RMSNorm {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        let axis = input.shape.last_axis();
+        input.std_norm(axis, self.epsilon) * broadcast_along(self.weight, &input.dims(), axis)
+    }
+}
+
+impl SerializeModule for RMSNorm {
+    fn serialize(&self, s: &mut Serializer) {
+        s.tensor("weight", self.weight);
+    }
+}
+
+/// Normalize groups of channels of a `[batch, channels, ..]` input, with an optional per-channel
+/// scale and shift
+pub struct GroupNorm {
+    pub weight: Option<GraphTensor>,
+    pub bias: Option<GraphTensor>,
+    groups: usize,
+    channels: usize,
+    epsilon: f32,
+}
+
+impl GroupNorm {
+    pub fn new(groups: usize, channels: usize, affine: bool, epsilon: f32, cx: &mut Graph) -> Self {
+        assert!(
+            groups > 0 && channels % groups == 0,
+            "{groups} groups must divide {channels} channels"
+        );
+        Self {
+            weight: affine.then(|| {
+                cx.named_tensor("GroupNorm Weight", channels)
+                    .set(vec![1.; channels])
+            }),
+            bias: affine.then(|| {
+                cx.named_tensor("GroupNorm Bias", channels)
+                    .set(vec![0.; channels])
+            }),
+            groups,
+            channels,
+            epsilon,
+        }
+    }
+}
+
+impl Module<GraphTensor> for GroupNorm {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        check_channels(input, self.channels, "GroupNorm");
+        let dims = input.dims();
+        let group_size = dims[2..]
+            .iter()
+            .fold(Expression::from(self.channels / self.groups), |a, d| {
+                (a * *d).simplify()
+            });
+        let normed = input
+            .reshape((dims[0], self.groups, group_size))
+            .layer_norm(2, self.epsilon)
+            .reshape(dims);
+        affine(normed, self.weight, self.bias)
+    }
+}
+
+impl SerializeModule for GroupNorm {
+    fn serialize(&self, s: &mut Serializer) {
+        if let Some(w) = self.weight {
+            s.tensor("weight", w);
+        }
+        if let Some(b) = self.bias {
+            s.tensor("bias", b);
+        }
+    }
+}
+
+/// Normalize each channel of each item of a `[batch, channels, ..spatial]` input over its
+/// spatial dims, with an optional per-channel scale and shift. Works for any number of spatial
+/// dims.
+pub struct InstanceNorm {
+    pub weight: Option<GraphTensor>,
+    pub bias: Option<GraphTensor>,
+    channels: usize,
+    epsilon: f32,
+}
+
+impl InstanceNorm {
+    pub fn new(channels: usize, affine: bool, epsilon: f32, cx: &mut Graph) -> Self {
+        Self {
+            weight: affine.then(|| {
+                cx.named_tensor("InstanceNorm Weight", channels)
+                    .set(vec![1.; channels])
+            }),
+            bias: affine.then(|| {
+                cx.named_tensor("InstanceNorm Bias", channels)
+                    .set(vec![0.; channels])
+            }),
+            channels,
+            epsilon,
+        }
+    }
+}
+
+impl Module<GraphTensor> for InstanceNorm {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        check_channels(input, self.channels, "InstanceNorm");
+        let rank = input.shape.len();
+        assert!(
+            rank > 2,
+            "InstanceNorm needs spatial dims to normalize over"
+        );
+        affine(
+            input.layer_norm((2..rank).collect::<Vec<_>>(), self.epsilon),
+            self.weight,
+            self.bias,
+        )
+    }
+}
+
+impl SerializeModule for InstanceNorm {
+    fn serialize(&self, s: &mut Serializer) {
+        if let Some(w) = self.weight {
+            s.tensor("weight", w);
+        }
+        if let Some(b) = self.bias {
+            s.tensor("bias", b);
+        }
+    }
+}
+
+/// Normalize each channel of a `[batch, channels, ..]` input over the batch and any other dims.
+///
+/// In inference (`forward`) the running statistics are used. `forward_training` normalizes with
+/// the batch statistics instead, and also gives the updated running statistics.
+pub struct BatchNorm {
+    pub weight: Option<GraphTensor>,
+    pub bias: Option<GraphTensor>,
+    pub running_mean: GraphTensor,
+    pub running_var: GraphTensor,
+    channels: usize,
+    epsilon: f32,
+    momentum: f32,
+}
+
+/// Batch norm over `[batch, channels]` or `[batch, channels, length]` inputs
+pub type BatchNorm1D = BatchNorm;
+/// Batch norm over `[batch, channels, height, width]` inputs
+pub type BatchNorm2D = BatchNorm;
+
+impl BatchNorm {
+    /// Create a batch norm, with running statistics starting at a mean of 0 and variance of 1.
+    /// Each training step moves them `momentum` of the way towards the batch statistics.
+    pub fn new(channels: usize, affine: bool, epsilon: f32, momentum: f32, cx: &mut Graph) -> Self {
+        Self {
+            weight: affine.then(|| {
+                cx.named_tensor("BatchNorm Weight", channels)
+                    .set(vec![1.; channels])
+            }),
+            bias: affine.then(|| {
+                cx.named_tensor("BatchNorm Bias", channels)
+                    .set(vec![0.; channels])
+            }),
+            running_mean: cx
+                .named_tensor("BatchNorm Running Mean", channels)
+                .set(vec![0.; channels]),
+            running_var: cx
+                .named_tensor("BatchNorm Running Var", channels)
+                .set(vec![1.; channels]),
+            channels,
+            epsilon,
+            momentum,
+        }
+    }
+
+    fn normalize(&self, input: GraphTensor, mean: GraphTensor, var: GraphTensor) -> GraphTensor {
+        let dims = input.dims();
+        let normed = (input - broadcast_along(mean, &dims, 1))
+            * broadcast_along((var + self.epsilon).sqrt().recip(), &dims, 1);
+        affine(normed, self.weight, self.bias)
+    }
+
+    /// Normalize with the statistics of this batch, returning `(output, new running mean, new
+    /// running var)`. Like the weights in an optimizer step, keep the new running statistics and
+    /// transfer them back into `running_mean` and `running_var` after each execution.
+    pub fn forward_training(&self, input: GraphTensor) -> (GraphTensor, GraphTensor, GraphTensor) {
+        check_channels(input, self.channels, "BatchNorm");
+        let axes = (0..input.shape.len())
+            .filter(|i| *i != 1)
+            .collect::<Vec<_>>();
+        let mean = input.mean_reduce(axes.clone());
+        let out = self.normalize(input, mean, input.var(axes.clone(), 0));
+        // The running variance is unbiased, like PyTorch
+        let m = self.momentum;
+        let running_mean = self.running_mean * (1. - m) + mean * m;
+        let running_var = self.running_var * (1. - m) + input.var(axes, 1) * m;
+        (out, running_mean, running_var)
+    }
+}
+
+impl Module<GraphTensor> for BatchNorm {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        check_channels(input, self.channels, "BatchNorm");
+        self.normalize(input, self.running_mean, self.running_var)
+    }
+}
+
+impl SerializeModule for BatchNorm {
+    fn serialize(&self, s: &mut Serializer) {
+        if let Some(w) = self.weight {
+            s.tensor("weight", w);
+        }
+        if let Some(b) = self.bias {
+            s.tensor("bias", b);
+        }
+        s.tensor("running_mean", self.running_mean);
+        s.tensor("running_var", self.running_var);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchNorm2D, GroupNorm, InstanceNorm, RMSNorm};
+    use dfdx::prelude::{Module as DfdxModule, *};
+    use luminal::{
+        prelude::{Module, *},
+        tests::{assert_close, random_vec_rng},
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Normalize each contiguous chunk of `size` elements
+    fn normalize_chunks(data: &[f32], size: usize, mean_norm: bool) -> Vec<f32> {
+        data.chunks(size)
+            .flat_map(|c| {
+                let n = c.len() as f32;
+                let mean = if mean_norm {
+                    c.iter().sum::<f32>() / n
+                } else {
+                    0.
+                };
+                let var = c.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+                c.iter().map(move |x| (x - mean) / (var + 1e-5).sqrt())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rms_group_instance_norm() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = random_vec_rng(2 * 4 * 3 * 2, &mut rng);
+        let weight = random_vec_rng(4, &mut rng);
+        let bias = random_vec_rng(4, &mut rng);
+        let inp = cx
+            .tensor((2, 4, 'h', 2))
+            .set_dyn(data.clone(), (2, 4, 3, 2));
+        let rms = RMSNorm::new(2, 1e-5, &mut cx);
+        rms.weight.set(weight[..2].to_vec());
+        let group = GroupNorm::new(2, 4, true, 1e-5, &mut cx);
+        group.weight.unwrap().set(weight.clone());
+        group.bias.unwrap().set(bias.clone());
+        let instance = InstanceNorm::new(4, false, 1e-5, &mut cx);
+        let rms_out = rms.forward(inp).retrieve();
+        let group_out = group.forward(inp).retrieve();
+        let instance_out = instance.forward(inp).retrieve();
+        cx.execute();
+
+        let rms_expected = normalize_chunks(&data, 2, false)
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| x * weight[i % 2])
+            .collect::<Vec<_>>();
+        let group_expected = normalize_chunks(&data, 2 * 6, true)
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| x * weight[i / 6 % 4] + bias[i / 6 % 4])
+            .collect::<Vec<_>>();
+        assert_close(&rms_out.data(), &rms_expected);
+        assert_close(&group_out.data(), &group_expected);
+        assert_close(&instance_out.data(), &normalize_chunks(&data, 6, true));
+    }
+
+    #[test]
+    fn test_batch_norm() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        let model = BatchNorm2D::new(3, true, 1e-5, 0.1, &mut cx);
+        let (weight, bias) = (random_vec_rng(3, &mut rng), random_vec_rng(3, &mut rng));
+        model.weight.unwrap().set(weight.clone());
+        model.bias.unwrap().set(bias.clone());
+        let inp = cx.tensor((2, 3, 2, 'w'));
+        let (train_out, new_mean, new_var) = model.forward_training(inp);
+        let train_out = train_out.retrieve();
+        let infer_out = model.forward(inp).retrieve();
+        cx.keep_tensors((new_mean, new_var));
+        cx.keep_tensors((model.running_mean, model.running_var));
+
+        let dev = Cpu::default();
+        let mut d_model = dev.build_module::<dfdx::nn::builders::BatchNorm2D<3>, f32>();
+        d_model.scale = dev.tensor_from_vec(weight, (dfdx::shapes::Const::<3>,));
+        d_model.bias = dev.tensor_from_vec(bias, (dfdx::shapes::Const::<3>,));
+        for w in [4, 5] {
+            let data = random_vec_rng(2 * 3 * 2 * w, &mut rng);
+            inp.set_dyn(data.clone(), (2, 3, 2, w));
+            cx.execute();
+
+            let d_inp = dev.tensor_from_vec(data, (2, dfdx::shapes::Const::<3>, 2, w));
+            // Inference runs on the running statistics from before this step
+            let d_infer = d_model.forward(d_inp.clone());
+            let d_train = d_model.forward_mut(d_inp.leaky_trace());
+            assert_close(&infer_out.data(), &d_infer.as_vec());
+            assert_close(&train_out.data(), &d_train.as_vec());
+            assert_close(&new_mean.data(), &d_model.running_mean.as_vec());
+            assert_close(&new_var.data(), &d_model.running_var.as_vec());
+            train_out.drop();
+            infer_out.drop();
+            transfer_data_same_graph(
+                (new_mean, new_var),
+                (model.running_mean, model.running_var),
+                &mut cx,
+            );
+        }
+    }
+}
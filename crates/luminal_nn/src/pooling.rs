@@ -0,0 +1,479 @@
+use luminal::prelude::*;
+
+/// How a pooling (or convolution) layer slides its window along one dim
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Window {
+    pub(crate) kernel: usize,
+    pub(crate) stride: usize,
+    pub(crate) dilation: usize,
+    pub(crate) padding: usize,
+    pub(crate) ceil_mode: bool,
+}
+
+impl Window {
+    /// Number of windows, following PyTorch: with `ceil_mode` a partial window at the end is kept
+    /// as long as it starts inside the input or the left padding
+    fn output_size(&self, dim: Expression) -> Expression {
+        let span = dim + 2 * self.padding - self.dilation * (self.kernel - 1) - 1;
+        if self.ceil_mode {
+            ((span + self.stride - 1) / self.stride + 1)
+                .min((dim + self.padding + self.stride - 1) / self.stride)
+                .simplify()
+        } else {
+            (span / self.stride + 1).simplify()
+        }
+    }
+
+    /// Window `axis` of `x`, which becomes the window index, with the kernel as a new last dim.
+    /// Padding (and the overhang of a `ceil_mode` window) is filled with `fill`.
+    pub(crate) fn apply(&self, x: GraphTensor, axis: usize, fill: f32) -> GraphTensor {
+        let rank = x.shape.len();
+        let dim = x.dims()[axis];
+        // Pooling floors the number of windows, so padding past the end by up to a stride fits
+        // every ceil_mode window, and any extras get sliced off
+        let right = self.padding + if self.ceil_mode { self.stride - 1 } else { 0 };
+        // Move the pooled axis to the end
+        let mut axes = (0..rank).filter(|i| *i != axis).collect::<Vec<_>>();
+        axes.push(axis);
+        let mut x = x.permute(axes);
+        x = if fill == 0. {
+            x.pad_along(self.padding, right, rank - 1)
+        } else {
+            let mut padding = vec![(Expression::from(0), Expression::from(0)); rank];
+            padding[rank - 1] = (self.padding.into(), right.into());
+            x.pad_with(padding, PadMode::Constant(fill))
+        };
+        x = x.pool_last_dim(self.kernel, self.stride, self.dilation);
+        if self.ceil_mode {
+            x = x.slice_along(..self.output_size(dim), rank - 1);
+        }
+        // Move the windows back, leaving the kernel last
+        let mut axes = (0..rank - 1).collect::<Vec<_>>();
+        axes.insert(axis, rank - 1);
+        axes.push(rank);
+        x.permute(axes)
+    }
+
+    /// How many taps of each window land inside the input or its padding
+    fn counts(&self, cx: &mut Graph, dim: Expression) -> GraphTensor {
+        let ones = cx.constant(1.).expand(0, dim + 2 * self.padding);
+        // Count over the padded input, so only the overhang is left out
+        let unpadded = Window {
+            padding: 0,
+            ..*self
+        };
+        let counts = unpadded.apply(ones, 0, 0.).sum_reduce(1);
+        if self.ceil_mode {
+            counts.slice_along(..self.output_size(dim), 0)
+        } else {
+            counts
+        }
+    }
+}
+
+/// Window the trailing dims of `x`, one window per dim, appending a kernel dim for each
+fn pool(x: GraphTensor, windows: &[Window], fill: f32) -> GraphTensor {
+    assert!(
+        x.shape.len() >= windows.len(),
+        "Can't pool {} dims of a tensor with shape {:?}",
+        windows.len(),
+        x.dims()
+    );
+    let first = x.shape.len() - windows.len();
+    windows
+        .iter()
+        .enumerate()
+        .rev()
+        .fold(x, |x, (i, w)| w.apply(x, first + i, fill))
+}
+
+fn max_pool(x: GraphTensor, windows: &[Window]) -> GraphTensor {
+    let rank = x.shape.len();
+    let kernel_axes = (rank..rank + windows.len()).collect::<Vec<_>>();
+    pool(x, windows, f32::NEG_INFINITY).max_reduce(kernel_axes)
+}
+
+/// Average pool, dividing by the number of taps inside the input or its padding like PyTorch's
+/// `count_include_pad`
+fn avg_pool(x: GraphTensor, windows: &[Window]) -> GraphTensor {
+    let rank = x.shape.len();
+    let first = rank - windows.len();
+    let dims = x.dims();
+    let kernel_axes = (rank..rank + windows.len()).collect::<Vec<_>>();
+    let sums = pool(x, windows, 0.).sum_reduce(kernel_axes);
+    let out_dims = sums.dims();
+    let counts = windows
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            // Broadcast the counts along this dim over the rest of the output
+            let counts = w.counts(x.graph(), dims[first + i]);
+            out_dims
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != first + i)
+                .fold(counts, |c, (j, d)| c.expand(j, *d))
+        })
+        .reduce(|a, b| a * b)
+        .unwrap();
+    sums / counts
+}
+
+/// Max pooling over the last dim. Padding is filled with -inf, so it never wins.
+pub struct MaxPool1D {
+    window: Window,
+}
+
+impl MaxPool1D {
+    pub fn new(
+        kernel: usize,
+        stride: usize,
+        dilation: usize,
+        padding: usize,
+        ceil_mode: bool,
+    ) -> Self {
+        Self {
+            window: Window {
+                kernel,
+                stride,
+                dilation,
+                padding,
+                ceil_mode,
+            },
+        }
+    }
+}
+
+impl SerializeModule for MaxPool1D {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+impl Module<GraphTensor> for MaxPool1D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, dim_in
+        max_pool(input, &[self.window])
+    }
+}
+
+/// Max pooling over the last two dims. Padding is filled with -inf, so it never wins.
+pub struct MaxPool2D {
+    windows: [Window; 2],
+}
+
+impl MaxPool2D {
+    pub fn new(
+        kernel: (usize, usize),
+        stride: (usize, usize),
+        dilation: (usize, usize),
+        padding: (usize, usize),
+        ceil_mode: bool,
+    ) -> Self {
+        Self {
+            windows: [
+                Window {
+                    kernel: kernel.0,
+                    stride: stride.0,
+                    dilation: dilation.0,
+                    padding: padding.0,
+                    ceil_mode,
+                },
+                Window {
+                    kernel: kernel.1,
+                    stride: stride.1,
+                    dilation: dilation.1,
+                    padding: padding.1,
+                    ceil_mode,
+                },
+            ],
+        }
+    }
+}
+
+impl SerializeModule for MaxPool2D {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+impl Module<GraphTensor> for MaxPool2D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, dimx_in, dimy_in
+        max_pool(input, &self.windows)
+    }
+}
+
+/// Average pooling over the last dim. Zero padding counts towards the average, but the overhang
+/// of a `ceil_mode` window doesn't, like PyTorch.
+pub struct AvgPool1D {
+    window: Window,
+}
+
+impl AvgPool1D {
+    pub fn new(
+        kernel: usize,
+        stride: usize,
+        dilation: usize,
+        padding: usize,
+        ceil_mode: bool,
+    ) -> Self {
+        Self {
+            window: Window {
+                kernel,
+                stride,
+                dilation,
+                padding,
+                ceil_mode,
+            },
+        }
+    }
+}
+
+impl SerializeModule for AvgPool1D {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+impl Module<GraphTensor> for AvgPool1D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, dim_in
+        avg_pool(input, &[self.window])
+    }
+}
+
+/// Average pooling over the last two dims. Zero padding counts towards the average, but the
+/// overhang of a `ceil_mode` window doesn't, like PyTorch.
+pub struct AvgPool2D {
+    windows: [Window; 2],
+}
+
+impl AvgPool2D {
+    pub fn new(
+        kernel: (usize, usize),
+        stride: (usize, usize),
+        dilation: (usize, usize),
+        padding: (usize, usize),
+        ceil_mode: bool,
+    ) -> Self {
+        Self {
+            windows: MaxPool2D::new(kernel, stride, dilation, padding, ceil_mode).windows,
+        }
+    }
+}
+
+impl SerializeModule for AvgPool2D {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+impl Module<GraphTensor> for AvgPool2D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, dimx_in, dimy_in
+        avg_pool(input, &self.windows)
+    }
+}
+
+/// Average pool the last two dims down to a fixed output size. Like PyTorch, output `i` of `o`
+/// averages inputs `floor(i * n / o)..ceil((i + 1) * n / o)`, so the windows can overlap.
+pub struct AdaptiveAvgPool2D {
+    output_size: (usize, usize),
+}
+
+impl AdaptiveAvgPool2D {
+    pub fn new(output_size: (usize, usize)) -> Self {
+        Self { output_size }
+    }
+}
+
+impl SerializeModule for AdaptiveAvgPool2D {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+impl Module<GraphTensor> for AdaptiveAvgPool2D {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        // Input: batch_dims, dimx_in, dimy_in
+        let rank = input.shape.len();
+        assert!(
+            rank >= 2,
+            "AdaptiveAvgPool2D needs at least 2 dims, got shape {:?}",
+            input.dims()
+        );
+        [
+            (rank - 2, self.output_size.0),
+            (rank - 1, self.output_size.1),
+        ]
+        .into_iter()
+        .fold(input, |x, (axis, out)| {
+            let dims = x.dims();
+            let averages = adaptive_averages(x.graph(), dims[axis], out);
+            // Weight each input by its share of each output, then sum the inputs away
+            let weights = dims
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != axis)
+                .fold(averages, |w, (i, d)| {
+                    w.expand(if i < axis { i } else { i + 1 }, *d)
+                });
+            (x.expand(axis + 1, out) * weights).sum_reduce(axis)
+        })
+    }
+}
+
+/// `[n, o]` matrix averaging each of the `o` adaptive windows over `n` inputs
+fn adaptive_averages(cx: &mut Graph, n: Expression, o: usize) -> GraphTensor {
+    // Window i covers [floor(i * n / o), ceil((i + 1) * n / o)). Comparing j * o against i * n
+    // keeps everything in whole numbers, so float rounding can't shift a bound
+    let i = cx.arange(o).expand(0, n);
+    let j = cx.arange(n).expand(1, o) * o as f32;
+    let inside = (j + o as f32).greater_than(i * n) * j.less_than((i + 1.) * n);
+    inside / inside.sum_reduce(0).expand(0, n)
+}
+
+/// Average over every dim past the channels of a `[batch, channels, ..spatial]` input, giving
+/// `[batch, channels]`
+#[derive(Default)]
+pub struct GlobalAvgPool;
+
+impl SerializeModule for GlobalAvgPool {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+impl Module<GraphTensor> for GlobalAvgPool {
+    type Output = GraphTensor;
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        let rank = input.shape.len();
+        assert!(
+            rank >= 3,
+            "GlobalAvgPool needs a [batch, channels, ..spatial] input, got shape {:?}",
+            input.dims()
+        );
+        input.mean_reduce((2..rank).collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::{Device, Tensor};
+    use luminal::tests::{assert_close, random_vec};
+
+    #[test]
+    fn test_max_avg_pool2d() {
+        let mut cx = Graph::new();
+        // Keep the input positive so candle's zero padding never wins a max
+        let data = random_vec(2 * 3 * 7 * 6)
+            .into_iter()
+            .map(|i| i + 1.)
+            .collect::<Vec<_>>();
+        let inp = cx.tensor((2, 3, 7, 6)).set(data.clone());
+        let max = MaxPool2D::new((3, 3), (2, 2), (1, 1), (1, 1), false)
+            .forward(inp)
+            .retrieve();
+        let avg = AvgPool2D::new((3, 3), (2, 2), (1, 1), (1, 1), false)
+            .forward(inp)
+            .retrieve();
+        let rect = MaxPool2D::new((2, 3), (1, 2), (1, 1), (0, 0), false)
+            .forward(inp)
+            .retrieve();
+        cx.execute();
+
+        let c_inp = Tensor::from_vec(data, (2, 3, 7, 6), &Device::Cpu).unwrap();
+        let padded = c_inp
+            .pad_with_zeros(2, 1, 1)
+            .unwrap()
+            .pad_with_zeros(3, 1, 1)
+            .unwrap();
+        let flat = |t: Tensor| t.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+        assert_eq!(max.dims(), [2, 3, 4, 3].map(Expression::from).to_vec());
+        assert_close(
+            &max.data(),
+            &flat(padded.max_pool2d_with_stride(3, 2).unwrap()),
+        );
+        assert_close(
+            &avg.data(),
+            &flat(padded.avg_pool2d_with_stride(3, 2).unwrap()),
+        );
+        assert_close(
+            &rect.data(),
+            &flat(c_inp.max_pool2d_with_stride((2, 3), (1, 2)).unwrap()),
+        );
+    }
+
+    #[test]
+    fn test_dilated_pool2d() {
+        let mut cx = Graph::new();
+        let inp = cx
+            .tensor((1, 4, 4))
+            .set((0..16).map(|i| (i * 7 % 16) as f32).collect::<Vec<_>>());
+        let max = MaxPool2D::new((2, 2), (1, 1), (2, 2), (0, 0), false)
+            .forward(inp)
+            .retrieve();
+        let avg = AvgPool2D::new((2, 2), (2, 1), (1, 2), (0, 0), false)
+            .forward(inp)
+            .retrieve();
+        cx.execute();
+
+        // Input rows: [0, 7, 14, 5], [12, 3, 10, 1], [8, 15, 6, 13], [4, 11, 2, 9]
+        assert_close(&max.data(), &[14., 15., 12., 11.]);
+        assert_close(&avg.data(), &[9., 4., 5., 12.]);
+    }
+
+    #[test]
+    fn test_pool1d_ceil_mode() {
+        let mut cx = Graph::new();
+        let data = vec![1., 5., 2., 4., 3., 6., 0.];
+        let odd = cx.tensor((1, 7)).set(data.clone());
+        let dynamic = cx.tensor((1, 's')).set_dyn(data, (1, 7));
+        let even = cx.tensor((1, 6)).set(vec![1., 5., 2., 4., 3., 6.]);
+        let max = MaxPool1D::new(2, 2, 1, 0, true).forward(odd).retrieve();
+        let avg = AvgPool1D::new(2, 2, 1, 0, true).forward(odd).retrieve();
+        let floor = AvgPool1D::new(2, 2, 1, 0, false)
+            .forward(dynamic)
+            .retrieve();
+        let padded = AvgPool1D::new(3, 2, 1, 1, true).forward(even).retrieve();
+        cx.execute();
+
+        // Values from PyTorch's max_pool1d and avg_pool1d
+        assert_close(&max.data(), &[5., 4., 6., 0.]);
+        assert_close(&avg.data(), &[3., 3., 4.5, 0.]);
+        assert_close(&floor.data(), &[3., 3., 4.5]);
+        // The last window covers 6, the padding and then overhangs, so it averages over two
+        assert_close(&padded.data(), &[2., 11. / 3., 13. / 3., 3.]);
+    }
+
+    #[test]
+    fn test_adaptive_global_avg_pool() {
+        let mut cx = Graph::new();
+        let data = random_vec(2 * 5 * 7);
+        let inp = cx.tensor((1, 2, 5, 7)).set(data.clone());
+        let adaptive = AdaptiveAvgPool2D::new((3, 2)).forward(inp).retrieve();
+        // 7 doesn't divide evenly in floating point, so this checks the window bounds stay exact
+        let sevens = AdaptiveAvgPool2D::new((7, 7)).forward(inp).retrieve();
+        let global = GlobalAvgPool.forward(inp).retrieve();
+        cx.execute();
+
+        let data = &data;
+        let windows = |i: usize, n: usize, o: usize| i * n / o..((i + 1) * n).div_ceil(o);
+        let expected = |(o_x, o_y): (usize, usize)| {
+            (0..2 * o_x * o_y)
+                .map(|i| {
+                    let (c, x, y) = (i / (o_x * o_y), i / o_y % o_x, i % o_y);
+                    let (xs, ys) = (windows(x, 5, o_x), windows(y, 7, o_y));
+                    let n = xs.len() * ys.len();
+                    xs.flat_map(|x| ys.clone().map(move |y| data[c * 35 + x * 7 + y]))
+                        .sum::<f32>()
+                        / n as f32
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_close(&adaptive.data(), &expected((3, 2)));
+        assert_close(&sevens.data(), &expected((7, 7)));
+        assert_close(
+            &global.data(),
+            &data
+                .chunks(35)
+                .map(|c| c.iter().sum::<f32>() / 35.)
+                .collect::<Vec<_>>(),
+        );
+    }
+}
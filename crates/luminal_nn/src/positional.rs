@@ -0,0 +1,360 @@
+use luminal::{prelude::*, tests::random_vec};
+
+/// Broadcast a (seq, dim) table over the leading dims of `shape` (..., seq, dim)
+fn broadcast_table(mut table: GraphTensor, shape: &[Expression]) -> GraphTensor {
+    for (i, d) in shape[..shape.len() - 2].iter().enumerate() {
+        table = table.expand(i, *d);
+    }
+    table
+}
+
+/// Rotary positional embeddings (RoPE), applied to queries and keys.
+///
+/// The first `rotary_dim` features of the last dimension are rotated, and any remaining features pass through
+/// untouched. The sequence is assumed to be the second to last dimension.
+#[derive(Clone, Copy)]
+pub struct RotaryEmbedding {
+    pub rotary_dim: usize,
+    pub base: f32,
+    /// Rotate adjacent pairs (x0, x1), (x2, x3), ... as in GGML / GPT-J, rather than pairing the first half
+    /// of the features with the second half as in GPT-NeoX / HF Llama
+    pub interleaved: bool,
+}
+
+impl RotaryEmbedding {
+    pub fn new(rotary_dim: usize, base: f32, interleaved: bool) -> Self {
+        assert!(
+            rotary_dim > 0 && rotary_dim % 2 == 0,
+            "Rotary dim must be a positive multiple of 2, got {rotary_dim}"
+        );
+        Self {
+            rotary_dim,
+            base,
+            interleaved,
+        }
+    }
+
+    /// The rotation angles for positions `offset..offset + seq`, with shape (seq, rotary_dim / 2)
+    pub fn angles(
+        &self,
+        seq: impl Into<Expression>,
+        offset: impl Into<Expression>,
+        cx: &mut Graph,
+    ) -> GraphTensor {
+        let (seq, half) = (seq.into(), self.rotary_dim / 2);
+        let inv_freqs = (cx.arange(half) * (-2. * self.base.ln() / self.rotary_dim as f32)).exp();
+        let positions = cx.arange(seq) + offset.into();
+        positions.expand(1, half) * inv_freqs.expand(0, seq)
+    }
+}
+
+impl Module<GraphTensor> for RotaryEmbedding {
+    type Output = GraphTensor;
+
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        self.forward((input, Expression::from(0)))
+    }
+}
+
+/// Apply the embeddings to positions starting at an offset, such as the length of a KV cache
+impl Module<(GraphTensor, Expression)> for RotaryEmbedding {
+    type Output = GraphTensor;
+
+    fn forward(&self, (input, offset): (GraphTensor, Expression)) -> Self::Output {
+        let dims = input.dims();
+        let (rank, half) = (dims.len(), self.rotary_dim / 2);
+        assert!(
+            rank >= 2,
+            "Rotary embeddings need a sequence and feature dim"
+        );
+        assert!(
+            dims[rank - 1].to_usize().unwrap_or(self.rotary_dim) >= self.rotary_dim,
+            "Rotary dim {} is larger than the feature dim {}",
+            self.rotary_dim,
+            dims[rank - 1]
+        );
+        let x = input.slice_along(..self.rotary_dim, rank - 1);
+        let angles = broadcast_table(self.angles(dims[rank - 2], offset, input.graph()), &dims);
+
+        let rotated = if self.interleaved {
+            // Split features into evens and odds
+            let mut split_shape = x.dims();
+            split_shape[rank - 1] = half.into();
+            split_shape.push(2.into());
+            let split = x.reshape(split_shape);
+            let (x0, x1) = (split.slice_along(..1, rank), split.slice_along(1.., rank));
+            let (sin, cos) = (angles.sin().expand(rank, 1), angles.cos().expand(rank, 1));
+            (x0 * cos - x1 * sin)
+                .concat_along(x0 * sin + x1 * cos, rank)
+                .reshape(x.dims())
+        } else {
+            // Split features into first and second halves
+            let (x0, x1) = (
+                x.slice_along(..half, rank - 1),
+                x.slice_along(half.., rank - 1),
+            );
+            let (sin, cos) = (angles.sin(), angles.cos());
+            (x0 * cos - x1 * sin).concat_along(x0 * sin + x1 * cos, rank - 1)
+        };
+
+        if dims[rank - 1].to_usize() == Some(self.rotary_dim) {
+            rotated
+        } else {
+            rotated.concat_along(input.slice_along(self.rotary_dim.., rank - 1), rank - 1)
+        }
+    }
+}
+
+impl SerializeModule for RotaryEmbedding {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+/// Fixed sinusoidal positional encodings, added to the input. The sequence is assumed to be the second to
+/// last dimension.
+#[derive(Clone, Copy)]
+pub struct SinusoidalPositionalEncoding {
+    pub dim: usize,
+    pub max_timescale: f32,
+    concatenated: bool,
+}
+
+impl SinusoidalPositionalEncoding {
+    /// Encodings from "Attention Is All You Need", with sines on even features and cosines on odd features
+    pub fn new(dim: usize, max_timescale: f32) -> Self {
+        assert!(
+            dim > 0 && dim % 2 == 0,
+            "Encoding dim must be a positive multiple of 2, got {dim}"
+        );
+        Self {
+            dim,
+            max_timescale,
+            concatenated: false,
+        }
+    }
+
+    /// Encodings as in tensor2tensor and Whisper, with all sines followed by all cosines and the timescales
+    /// spread so the last one is exactly `max_timescale`
+    pub fn new_concatenated(dim: usize, max_timescale: f32) -> Self {
+        assert!(
+            dim > 2 && dim % 2 == 0,
+            "Encoding dim must be a multiple of 2 larger than 2, got {dim}"
+        );
+        Self {
+            dim,
+            max_timescale,
+            concatenated: true,
+        }
+    }
+
+    /// The encodings for positions `offset..offset + seq`, with shape (seq, dim)
+    pub fn encodings(
+        &self,
+        seq: impl Into<Expression>,
+        offset: impl Into<Expression>,
+        cx: &mut Graph,
+    ) -> GraphTensor {
+        let (seq, half) = (seq.into(), self.dim / 2);
+        let increment = if self.concatenated {
+            self.max_timescale.ln() / (half - 1) as f32
+        } else {
+            self.max_timescale.ln() / half as f32
+        };
+        let inv_timescales = (cx.arange(half) * -increment).exp();
+        let positions = cx.arange(seq) + offset.into();
+        let scaled_time = positions.expand(1, half) * inv_timescales.expand(0, seq);
+        if self.concatenated {
+            scaled_time.sin().concat_along(scaled_time.cos(), 1)
+        } else {
+            scaled_time
+                .sin()
+                .expand(2, 1)
+                .concat_along(scaled_time.cos().expand(2, 1), 2)
+                .reshape((seq, self.dim))
+        }
+    }
+}
+
+impl Module<GraphTensor> for SinusoidalPositionalEncoding {
+    type Output = GraphTensor;
+
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        self.forward((input, Expression::from(0)))
+    }
+}
+
+/// Encode positions starting at an offset, such as the length of a KV cache
+impl Module<(GraphTensor, Expression)> for SinusoidalPositionalEncoding {
+    type Output = GraphTensor;
+
+    fn forward(&self, (input, offset): (GraphTensor, Expression)) -> Self::Output {
+        let dims = input.dims();
+        assert!(
+            dims.len() >= 2,
+            "Positional encodings need a sequence and feature dim"
+        );
+        input
+            + broadcast_table(
+                self.encodings(dims[dims.len() - 2], offset, input.graph()),
+                &dims,
+            )
+    }
+}
+
+impl SerializeModule for SinusoidalPositionalEncoding {
+    fn serialize(&self, _: &mut Serializer) {}
+}
+
+/// A table of learned positional embeddings, added to the input. The sequence is assumed to be the second to
+/// last dimension.
+pub struct LearnedPositionalEmbedding {
+    pub weight: GraphTensor, // max positions x embedding dim
+}
+
+impl LearnedPositionalEmbedding {
+    pub fn new(max_positions: usize, embedding_dim: usize, cx: &mut Graph) -> Self {
+        Self {
+            weight: cx.named_tensor(
+                "Positional Embedding Weight",
+                (max_positions, embedding_dim),
+            ),
+        }
+    }
+
+    pub fn initialize(self) -> Self {
+        self.weight.set(random_vec(
+            self.weight.shape.n_elements().to_usize().unwrap(),
+        ));
+        self
+    }
+}
+
+impl Module<GraphTensor> for LearnedPositionalEmbedding {
+    type Output = GraphTensor;
+
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        self.forward((input, Expression::from(0)))
+    }
+}
+
+/// Embed positions starting at an offset, such as the length of a KV cache
+impl Module<(GraphTensor, Expression)> for LearnedPositionalEmbedding {
+    type Output = GraphTensor;
+
+    fn forward(&self, (input, offset): (GraphTensor, Expression)) -> Self::Output {
+        let dims = input.dims();
+        assert!(
+            dims.len() >= 2,
+            "Positional embeddings need a sequence and feature dim"
+        );
+        let seq = dims[dims.len() - 2];
+        let table = self.weight.slice((offset..seq + offset, ..)).contiguous();
+        input + broadcast_table(table, &dims)
+    }
+}
+
+impl SerializeModule for LearnedPositionalEmbedding {
+    fn serialize(&self, s: &mut Serializer) {
+        s.tensor("weight", self.weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LearnedPositionalEmbedding, RotaryEmbedding, SinusoidalPositionalEncoding};
+    use luminal::{
+        prelude::{Module, *},
+        tests::{assert_close, random_vec_rng},
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Reference RoPE over contiguous rows of `dim` features at the given positions
+    fn rope(
+        data: &[f32],
+        positions: &[usize],
+        dim: usize,
+        rot: usize,
+        interleaved: bool,
+    ) -> Vec<f32> {
+        let mut out = data.to_vec();
+        for (row, chunk) in out.chunks_mut(dim).enumerate() {
+            let pos = positions[row % positions.len()] as f32;
+            for i in 0..rot / 2 {
+                let theta = pos * 10_000_f32.powf(-2. * i as f32 / rot as f32);
+                let (a, b) = if interleaved {
+                    (2 * i, 2 * i + 1)
+                } else {
+                    (i, i + rot / 2)
+                };
+                let (x0, x1) = (chunk[a], chunk[b]);
+                chunk[a] = x0 * theta.cos() - x1 * theta.sin();
+                chunk[b] = x0 * theta.sin() + x1 * theta.cos();
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_rotary_embedding() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let data = random_vec_rng(2 * 3 * 8, &mut rng);
+        let inp = cx.tensor((2, 's', 8)).set_dyn(data.clone(), (2, 3, 8));
+        cx.set_dyn_dim('p', 4);
+        let offset = Expression::from('p');
+        let interleaved = RotaryEmbedding::new(8, 10_000., true)
+            .forward((inp, offset))
+            .retrieve();
+        let half_split = RotaryEmbedding::new(8, 10_000., false)
+            .forward(inp)
+            .retrieve();
+        let partial = RotaryEmbedding::new(4, 10_000., false)
+            .forward((inp, offset))
+            .retrieve();
+        cx.execute();
+
+        assert_close(&interleaved.data(), &rope(&data, &[4, 5, 6], 8, 8, true));
+        assert_close(&half_split.data(), &rope(&data, &[0, 1, 2], 8, 8, false));
+        assert_close(&partial.data(), &rope(&data, &[4, 5, 6], 8, 4, false));
+    }
+
+    #[test]
+    fn test_positional_encodings() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        let data = random_vec_rng(3 * 6, &mut rng);
+        let table = random_vec_rng(8 * 6, &mut rng);
+        let inp = cx.tensor(('s', 6)).set_dyn(data.clone(), (3, 6));
+        cx.set_dyn_dim('p', 2);
+        let offset = Expression::from('p');
+        let sinusoidal = SinusoidalPositionalEncoding::new(6, 10_000.)
+            .forward((inp, offset))
+            .retrieve();
+        let concatenated = SinusoidalPositionalEncoding::new_concatenated(6, 10_000.)
+            .forward(inp)
+            .retrieve();
+        let learned = LearnedPositionalEmbedding::new(8, 6, &mut cx);
+        learned.weight.set(table.clone());
+        let learned = learned.forward((inp, offset)).retrieve();
+        cx.execute();
+
+        let mut sinusoidal_expected = data.clone();
+        let mut concatenated_expected = data.clone();
+        let mut learned_expected = data.clone();
+        for s in 0..3 {
+            for i in 0..3 {
+                let pos = (s + 2) as f32 * 10_000_f32.powf(-(i as f32) / 3.);
+                sinusoidal_expected[s * 6 + 2 * i] += pos.sin();
+                sinusoidal_expected[s * 6 + 2 * i + 1] += pos.cos();
+                let pos = s as f32 * 10_000_f32.powf(-(i as f32) / 2.);
+                concatenated_expected[s * 6 + i] += pos.sin();
+                concatenated_expected[s * 6 + i + 3] += pos.cos();
+            }
+            for j in 0..6 {
+                learned_expected[s * 6 + j] += table[(s + 2) * 6 + j];
+            }
+        }
+        assert_close(&sinusoidal.data(), &sinusoidal_expected);
+        assert_close(&concatenated.data(), &concatenated_expected);
+        assert_close(&learned.data(), &learned_expected);
+    }
+}
@@ -0,0 +1,674 @@
+use rand::{thread_rng, Rng};
+
+use luminal::{module::Serializer, prelude::*};
+
+/// The weights for one direction of one layer of a recurrent module. Like PyTorch, each gate's weights are
+/// stacked along the first dimension.
+pub struct RecurrentWeights {
+    /// `[gates * hidden, input]`
+    pub weight_ih: GraphTensor,
+    /// `[gates * hidden, hidden]`
+    pub weight_hh: GraphTensor,
+    pub bias_ih: Option<GraphTensor>,
+    pub bias_hh: Option<GraphTensor>,
+}
+
+impl RecurrentWeights {
+    fn new(input: usize, hidden: usize, gates: usize, bias: bool, cx: &mut Graph) -> Self {
+        Self {
+            weight_ih: cx.named_tensor("Weight IH", (gates * hidden, input)),
+            weight_hh: cx.named_tensor("Weight HH", (gates * hidden, hidden)),
+            bias_ih: bias.then(|| cx.named_tensor("Bias IH", gates * hidden)),
+            bias_hh: bias.then(|| cx.named_tensor("Bias HH", gates * hidden)),
+        }
+    }
+
+    fn initialize(&self, hidden: usize) {
+        // Init everything as uniform(-1/sqrt(hidden), 1/sqrt(hidden)), like PyTorch
+        let bound = 1. / (hidden as f32).sqrt();
+        let mut rng = thread_rng();
+        for t in [
+            Some(self.weight_ih),
+            Some(self.weight_hh),
+            self.bias_ih,
+            self.bias_hh,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            t.set(
+                (0..t.shape.n_elements().to_usize().unwrap())
+                    .map(|_| rng.gen_range(-bound..bound))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    fn serialize(&self, suffix: &str, s: &mut Serializer) {
+        s.tensor(&format!("weight_ih{suffix}"), self.weight_ih);
+        s.tensor(&format!("weight_hh{suffix}"), self.weight_hh);
+        if let Some(b) = self.bias_ih {
+            s.tensor(&format!("bias_ih{suffix}"), b);
+        }
+        if let Some(b) = self.bias_hh {
+            s.tensor(&format!("bias_hh{suffix}"), b);
+        }
+    }
+
+    /// Run a cell over a `[seq, batch, input]` sequence, returning the `[seq, batch, hidden]` outputs and
+    /// the final state
+    fn scan(
+        &self,
+        input: GraphTensor,
+        state: &[GraphTensor],
+        cell: Cell,
+        reverse: bool,
+    ) -> (GraphTensor, Vec<GraphTensor>) {
+        // The input projection doesn't depend on the state, so do it for every step at once
+        let mut x = input.matmul(self.weight_ih.permute((1, 0)));
+        if let Some(b) = self.bias_ih {
+            x += b.expand_to(x.shape);
+        }
+        let captures = [Some(self.weight_hh), self.bias_hh]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let (finals, outputs) =
+            input
+                .graph()
+                .scan_loop(&[x], state, &captures, reverse, |_, x, state, w| {
+                    let mut h = state[0].matmul(w[0].permute((1, 0)));
+                    if let Some(b) = w.get(1) {
+                        h += b.expand_to(h.shape);
+                    }
+                    let next = cell.step(x[0], h, state);
+                    let output = next[0];
+                    (next, vec![output])
+                });
+        (outputs[0], finals)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Cell {
+    Tanh,
+    Relu,
+    Lstm,
+    Gru,
+}
+
+impl Cell {
+    fn gates(self) -> usize {
+        match self {
+            Cell::Tanh | Cell::Relu => 1,
+            Cell::Lstm => 4,
+            Cell::Gru => 3,
+        }
+    }
+
+    /// Get the next state from the projected input, the projected hidden state, and the current state
+    fn step(self, x: GraphTensor, h: GraphTensor, state: &[GraphTensor]) -> Vec<GraphTensor> {
+        match self {
+            Cell::Tanh => vec![(x + h).tanh()],
+            Cell::Relu => vec![(x + h).relu()],
+            Cell::Lstm => {
+                let gates = (x + h).chunk(4, 1);
+                let (i, f, g, o) = (
+                    gates[0].sigmoid(),
+                    gates[1].sigmoid(),
+                    gates[2].tanh(),
+                    gates[3].sigmoid(),
+                );
+                let c = f * state[1] + i * g;
+                vec![o * c.tanh(), c]
+            }
+            Cell::Gru => {
+                let (x, h) = (x.chunk(3, 1), h.chunk(3, 1));
+                let r = (x[0] + h[0]).sigmoid();
+                let z = (x[1] + h[1]).sigmoid();
+                let n = (x[2] + r * h[2]).tanh();
+                vec![(1. - z) * n + z * state[0]]
+            }
+        }
+    }
+}
+
+fn new_layers(
+    input: usize,
+    hidden: usize,
+    num_layers: usize,
+    bias: bool,
+    bidirectional: bool,
+    cell: Cell,
+    cx: &mut Graph,
+) -> Vec<RecurrentWeights> {
+    let dirs = if bidirectional { 2 } else { 1 };
+    (0..num_layers * dirs)
+        .map(|i| {
+            let input = if i < dirs { input } else { hidden * dirs };
+            RecurrentWeights::new(input, hidden, cell.gates(), bias, cx)
+        })
+        .collect()
+}
+
+fn serialize_layers(weights: &[RecurrentWeights], bidirectional: bool, s: &mut Serializer) {
+    let dirs = if bidirectional { 2 } else { 1 };
+    for (i, w) in weights.iter().enumerate() {
+        let reverse = if i % dirs == 1 { "_reverse" } else { "" };
+        w.serialize(&format!("_l{}{reverse}", i / dirs), s);
+    }
+}
+
+/// Run every layer over a `[seq, batch, input]` or unbatched `[seq, input]` input. Each initial state is
+/// `[layers * directions, (batch,) hidden]`, or zeros if not given.
+///
+/// Returns the `[seq, (batch,) directions * hidden]` outputs of the last layer, and the final states.
+fn forward_layers(
+    weights: &[RecurrentWeights],
+    bidirectional: bool,
+    cell: Cell,
+    input: GraphTensor,
+    initial: &[Option<GraphTensor>],
+) -> (GraphTensor, Vec<GraphTensor>) {
+    let unbatched = input.shape.len() == 2;
+    let mut x = if unbatched { input.unsqueeze(1) } else { input };
+    assert_eq!(
+        x.shape.len(),
+        3,
+        "Recurrent input should be [seq, batch, input] or [seq, input]"
+    );
+    let hidden = weights[0].weight_hh.dims()[1];
+    let batch = x.dims()[1];
+    let initial = initial
+        .iter()
+        .map(|s| s.map(|s| if unbatched { s.unsqueeze(1) } else { s }))
+        .collect::<Vec<_>>();
+    let dirs = if bidirectional { 2 } else { 1 };
+    let mut finals = vec![vec![]; initial.len()];
+    for layer in weights.chunks(dirs) {
+        let mut outputs = vec![];
+        for (dir, w) in layer.iter().enumerate() {
+            let i = finals[0].len();
+            let state = initial
+                .iter()
+                .map(|s| match s {
+                    Some(s) => s.slice_along(i..i + 1, 0).squeeze(0),
+                    None => input.graph().constant(0.).expand_to((batch, hidden)),
+                })
+                .collect::<Vec<_>>();
+            let (output, last) = w.scan(x, &state, cell, dir == 1);
+            outputs.push(output);
+            for (f, l) in finals.iter_mut().zip(last) {
+                f.push(l);
+            }
+        }
+        x = GraphTensor::concat(&outputs, 2);
+    }
+    let mut finals = finals
+        .iter()
+        .map(|f| GraphTensor::stack(f, 0))
+        .collect::<Vec<_>>();
+    if unbatched {
+        x = x.squeeze(1);
+        finals = finals.into_iter().map(|f| f.squeeze(1)).collect();
+    }
+    (x, finals)
+}
+
+/// An Elman RNN, computing `h' = tanh(x W_ih^T + b_ih + h W_hh^T + b_hh)` at each step (or relu instead
+/// of tanh). Runs over a dynamic number of steps without unrolling.
+///
+/// Inputs are `[seq, batch, input]` or unbatched `[seq, input]`, and the hidden state is
+/// `[layers * directions, (batch,) hidden]`. Outputs the last layer's hidden states for each step, and the
+/// final hidden state of each layer.
+pub struct RNN {
+    /// Indexed by `layer * directions + direction`
+    pub weights: Vec<RecurrentWeights>,
+    bidirectional: bool,
+    relu: bool,
+}
+
+impl RNN {
+    pub fn new(
+        input: usize,
+        hidden: usize,
+        num_layers: usize,
+        bias: bool,
+        bidirectional: bool,
+        cx: &mut Graph,
+    ) -> Self {
+        Self {
+            weights: new_layers(
+                input,
+                hidden,
+                num_layers,
+                bias,
+                bidirectional,
+                Cell::Tanh,
+                cx,
+            ),
+            bidirectional,
+            relu: false,
+        }
+    }
+
+    /// An RNN using relu rather than tanh
+    pub fn new_relu(
+        input: usize,
+        hidden: usize,
+        num_layers: usize,
+        bias: bool,
+        bidirectional: bool,
+        cx: &mut Graph,
+    ) -> Self {
+        Self {
+            relu: true,
+            ..Self::new(input, hidden, num_layers, bias, bidirectional, cx)
+        }
+    }
+
+    pub fn initialize(self) -> Self {
+        for w in &self.weights {
+            w.initialize(w.weight_hh.dims()[1].to_usize().unwrap());
+        }
+        self
+    }
+}
+
+impl SerializeModule for RNN {
+    fn serialize(&self, s: &mut Serializer) {
+        serialize_layers(&self.weights, self.bidirectional, s);
+    }
+}
+
+impl Module<GraphTensor> for RNN {
+    type Output = (GraphTensor, GraphTensor);
+
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        let cell = if self.relu { Cell::Relu } else { Cell::Tanh };
+        let (output, finals) =
+            forward_layers(&self.weights, self.bidirectional, cell, input, &[None]);
+        (output, finals[0])
+    }
+}
+
+impl Module<(GraphTensor, GraphTensor)> for RNN {
+    type Output = (GraphTensor, GraphTensor);
+
+    fn forward(&self, (input, h): (GraphTensor, GraphTensor)) -> Self::Output {
+        let cell = if self.relu { Cell::Relu } else { Cell::Tanh };
+        let (output, finals) =
+            forward_layers(&self.weights, self.bidirectional, cell, input, &[Some(h)]);
+        (output, finals[0])
+    }
+}
+
+/// A long short-term memory layer, with input, forget, cell and output gates stacked in that order like
+/// PyTorch. Runs over a dynamic number of steps without unrolling.
+///
+/// Inputs are `[seq, batch, input]` or unbatched `[seq, input]`, and the hidden and cell states are
+/// `[layers * directions, (batch,) hidden]`. Outputs the last layer's hidden states for each step, and the
+/// final hidden and cell states of each layer.
+pub struct LSTM {
+    /// Indexed by `layer * directions + direction`
+    pub weights: Vec<RecurrentWeights>,
+    bidirectional: bool,
+}
+
+impl LSTM {
+    pub fn new(
+        input: usize,
+        hidden: usize,
+        num_layers: usize,
+        bias: bool,
+        bidirectional: bool,
+        cx: &mut Graph,
+    ) -> Self {
+        Self {
+            weights: new_layers(
+                input,
+                hidden,
+                num_layers,
+                bias,
+                bidirectional,
+                Cell::Lstm,
+                cx,
+            ),
+            bidirectional,
+        }
+    }
+
+    pub fn initialize(self) -> Self {
+        for w in &self.weights {
+            w.initialize(w.weight_hh.dims()[1].to_usize().unwrap());
+        }
+        self
+    }
+}
+
+impl SerializeModule for LSTM {
+    fn serialize(&self, s: &mut Serializer) {
+        serialize_layers(&self.weights, self.bidirectional, s);
+    }
+}
+
+impl Module<GraphTensor> for LSTM {
+    type Output = (GraphTensor, (GraphTensor, GraphTensor));
+
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        let (output, finals) = forward_layers(
+            &self.weights,
+            self.bidirectional,
+            Cell::Lstm,
+            input,
+            &[None, None],
+        );
+        (output, (finals[0], finals[1]))
+    }
+}
+
+impl Module<(GraphTensor, (GraphTensor, GraphTensor))> for LSTM {
+    type Output = (GraphTensor, (GraphTensor, GraphTensor));
+
+    fn forward(&self, (input, (h, c)): (GraphTensor, (GraphTensor, GraphTensor))) -> Self::Output {
+        let (output, finals) = forward_layers(
+            &self.weights,
+            self.bidirectional,
+            Cell::Lstm,
+            input,
+            &[Some(h), Some(c)],
+        );
+        (output, (finals[0], finals[1]))
+    }
+}
+
+/// A gated recurrent unit layer, with reset, update and new gates stacked in that order like PyTorch. Runs
+/// over a dynamic number of steps without unrolling.
+///
+/// Inputs are `[seq, batch, input]` or unbatched `[seq, input]`, and the hidden state is
+/// `[layers * directions, (batch,) hidden]`. Outputs the last layer's hidden states for each step, and the
+/// final hidden state of each layer.
+pub struct GRU {
+    /// Indexed by `layer * directions + direction`
+    pub weights: Vec<RecurrentWeights>,
+    bidirectional: bool,
+}
+
+impl GRU {
+    pub fn new(
+        input: usize,
+        hidden: usize,
+        num_layers: usize,
+        bias: bool,
+        bidirectional: bool,
+        cx: &mut Graph,
+    ) -> Self {
+        Self {
+            weights: new_layers(
+                input,
+                hidden,
+                num_layers,
+                bias,
+                bidirectional,
+                Cell::Gru,
+                cx,
+            ),
+            bidirectional,
+        }
+    }
+
+    pub fn initialize(self) -> Self {
+        for w in &self.weights {
+            w.initialize(w.weight_hh.dims()[1].to_usize().unwrap());
+        }
+        self
+    }
+}
+
+impl SerializeModule for GRU {
+    fn serialize(&self, s: &mut Serializer) {
+        serialize_layers(&self.weights, self.bidirectional, s);
+    }
+}
+
+impl Module<GraphTensor> for GRU {
+    type Output = (GraphTensor, GraphTensor);
+
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        let (output, finals) =
+            forward_layers(&self.weights, self.bidirectional, Cell::Gru, input, &[None]);
+        (output, finals[0])
+    }
+}
+
+impl Module<(GraphTensor, GraphTensor)> for GRU {
+    type Output = (GraphTensor, GraphTensor);
+
+    fn forward(&self, (input, h): (GraphTensor, GraphTensor)) -> Self::Output {
+        let (output, finals) = forward_layers(
+            &self.weights,
+            self.bidirectional,
+            Cell::Gru,
+            input,
+            &[Some(h)],
+        );
+        (output, finals[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecurrentWeights, GRU, LSTM, RNN};
+    use luminal::{
+        prelude::{Module, *},
+        tests::{assert_close, random_vec_rng},
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Weights for one layer and direction: weight_ih, weight_hh, bias_ih, bias_hh
+    type Weights = [Vec<f32>; 4];
+
+    fn set_weights(weights: &[RecurrentWeights], rng: &mut StdRng) -> Vec<Weights> {
+        weights
+            .iter()
+            .map(|w| {
+                [
+                    w.weight_ih,
+                    w.weight_hh,
+                    w.bias_ih.unwrap(),
+                    w.bias_hh.unwrap(),
+                ]
+                .map(|t| {
+                    let data = random_vec_rng(t.shape.n_elements().to_usize().unwrap(), rng);
+                    t.set(data.clone());
+                    data
+                })
+            })
+            .collect()
+    }
+
+    /// `x W^T + b` for a single row
+    fn project(x: &[f32], w: &[f32], b: &[f32]) -> Vec<f32> {
+        b.iter()
+            .enumerate()
+            .map(|(o, b)| {
+                b + x
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| v * w[o * x.len() + i])
+                    .sum::<f32>()
+            })
+            .collect()
+    }
+
+    fn sigmoid(x: f32) -> f32 {
+        1. / (1. + (-x).exp())
+    }
+
+    /// Reference multi-layer recurrence over `[seq, batch, input]`, with states of
+    /// `[layers * directions, batch, hidden]`
+    #[allow(clippy::too_many_arguments)]
+    fn reference(
+        cell: &str,
+        weights: &[Weights],
+        input: &[f32],
+        (seq, batch, hidden): (usize, usize, usize),
+        dirs: usize,
+        h0: &[f32],
+        c0: &[f32],
+    ) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        let mut x = input.to_vec();
+        let (mut h_n, mut c_n) = (vec![], vec![]);
+        for layer in 0..weights.len() / dirs {
+            let in_size = x.len() / (seq * batch);
+            let mut out = vec![0.; seq * batch * dirs * hidden];
+            for dir in 0..dirs {
+                let ind = layer * dirs + dir;
+                let [w_ih, w_hh, b_ih, b_hh] = &weights[ind];
+                let state = ind * batch * hidden..(ind + 1) * batch * hidden;
+                let (mut h, mut c) = (h0[state.clone()].to_vec(), c0[state].to_vec());
+                for t in 0..seq {
+                    let t = if dir == 1 { seq - 1 - t } else { t };
+                    for b in 0..batch {
+                        let row = (t * batch + b) * in_size;
+                        let gx = project(&x[row..row + in_size], w_ih, b_ih);
+                        let hs = b * hidden..(b + 1) * hidden;
+                        let gh = project(&h[hs.clone()], w_hh, b_hh);
+                        for j in 0..hidden {
+                            let g = |k: usize| gx[k * hidden + j] + gh[k * hidden + j];
+                            let (hj, cj) = (hs.start + j, hs.start + j);
+                            match cell {
+                                "tanh" => h[hj] = g(0).tanh(),
+                                "relu" => h[hj] = g(0).max(0.),
+                                "lstm" => {
+                                    c[cj] = sigmoid(g(1)) * c[cj] + sigmoid(g(0)) * g(2).tanh();
+                                    h[hj] = sigmoid(g(3)) * c[cj].tanh();
+                                }
+                                _ => {
+                                    let r = sigmoid(g(0));
+                                    let z = sigmoid(g(1));
+                                    let n = (gx[2 * hidden + j] + r * gh[2 * hidden + j]).tanh();
+                                    h[hj] = (1. - z) * n + z * h[hj];
+                                }
+                            }
+                        }
+                        let o = ((t * batch + b) * dirs + dir) * hidden;
+                        out[o..o + hidden].copy_from_slice(&h[hs]);
+                    }
+                }
+                h_n.extend(h);
+                c_n.extend(c);
+            }
+            x = out;
+        }
+        (x, h_n, c_n)
+    }
+
+    #[test]
+    fn test_lstm() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let model = LSTM::new(3, 4, 2, true, true, &mut cx);
+        let weights = set_weights(&model.weights, &mut rng);
+        let input = cx.tensor(('s', 2, 3));
+        let h0_data = random_vec_rng(4 * 2 * 4, &mut rng);
+        let c0_data = random_vec_rng(4 * 2 * 4, &mut rng);
+        let h0 = cx.tensor((4, 2, 4)).set(h0_data.clone());
+        let c0 = cx.tensor((4, 2, 4)).set(c0_data.clone());
+        let (output, (h_n, c_n)) = model.forward((input, (h0, c0)));
+        let (mut output, mut h_n, mut c_n) = (output.retrieve(), h_n.retrieve(), c_n.retrieve());
+
+        cx.compile(
+            GenericCompiler::default(),
+            (&mut output, &mut h_n, &mut c_n),
+        );
+        for seq in [5, 1] {
+            let data = random_vec_rng(seq * 2 * 3, &mut rng);
+            input.set_dyn(data.clone(), (seq, 2, 3));
+            cx.execute();
+
+            let (ref_out, ref_h, ref_c) =
+                reference("lstm", &weights, &data, (seq, 2, 4), 2, &h0_data, &c0_data);
+            assert_close(&output.data(), &ref_out);
+            assert_close(&h_n.data(), &ref_h);
+            assert_close(&c_n.data(), &ref_c);
+            output.drop();
+            h_n.drop();
+            c_n.drop();
+        }
+    }
+
+    #[test]
+    fn test_gru_and_rnn() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(1);
+        let gru = GRU::new(3, 5, 2, true, false, &mut cx);
+        let rnn = RNN::new_relu(3, 5, 1, true, true, &mut cx);
+        let gru_weights = set_weights(&gru.weights, &mut rng);
+        let rnn_weights = set_weights(&rnn.weights, &mut rng);
+        let input = cx.tensor(('s', 3));
+        let h0_data = random_vec_rng(2 * 5, &mut rng);
+        let h0 = cx.tensor((2, 5)).set(h0_data.clone());
+
+        // GRU with no initial state, RNN with one, both unbatched
+        let (gru_out, gru_h) = gru.forward(input);
+        let (rnn_out, rnn_h) = rnn.forward((input, h0));
+        let (gru_out, gru_h, rnn_out, rnn_h) = (
+            gru_out.retrieve(),
+            gru_h.retrieve(),
+            rnn_out.retrieve(),
+            rnn_h.retrieve(),
+        );
+        assert_eq!(gru_h.dims().len(), 2);
+        assert_eq!(rnn_out.dims()[1].to_usize(), Some(10));
+
+        for seq in [4, 2] {
+            let data = random_vec_rng(seq * 3, &mut rng);
+            input.set_dyn(data.clone(), (seq, 3));
+            cx.execute();
+
+            let (ref_out, ref_h, _) = reference(
+                "gru",
+                &gru_weights,
+                &data,
+                (seq, 1, 5),
+                1,
+                &[0.; 10],
+                &[0.; 10],
+            );
+            assert_close(&gru_out.data(), &ref_out);
+            assert_close(&gru_h.data(), &ref_h);
+            let (ref_out, ref_h, _) = reference(
+                "relu",
+                &rnn_weights,
+                &data,
+                (seq, 1, 5),
+                2,
+                &h0_data,
+                &[0.; 10],
+            );
+            assert_close(&rnn_out.data(), &ref_out);
+            assert_close(&rnn_h.data(), &ref_h);
+            for t in [gru_out, gru_h, rnn_out, rnn_h] {
+                t.drop();
+            }
+        }
+    }
+
+    #[test]
+    fn test_recurrent_serialization() {
+        let mut cx = Graph::new();
+        let model = LSTM::new(3, 4, 2, true, true, &mut cx);
+        let names = param_dict(&model).into_keys().collect::<Vec<_>>();
+        for name in [
+            "weight_ih_l0",
+            "weight_hh_l0_reverse",
+            "bias_ih_l1",
+            "bias_hh_l1_reverse",
+        ] {
+            assert!(names.iter().any(|n| n == name), "Missing {name}");
+        }
+        assert_eq!(names.len(), 16);
+        assert_eq!(model.weights[2].weight_ih.dims()[1].to_usize(), Some(8));
+    }
+}
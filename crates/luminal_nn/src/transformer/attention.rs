@@ -1,31 +1,221 @@
 use std::ops::Mul;
 
-use crate::Linear;
+use crate::{KVCache, Linear};
 use luminal::prelude::*;
 
+/// A mask applied to attention weights before the softmax
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Default)]
+pub enum AttentionMask {
+    #[default]
+    None,
+    /// Each query only attends to keys at or before its own position, including everything in the cache
+    Causal,
+    /// Added onto the attention weights, with shape (query seq, key seq) or (batch dims.., query seq, key seq).
+    /// The key sequence includes any cached positions.
+    Additive(GraphTensor),
+    /// Excludes padded keys from the softmax, optionally along with causal masking. The padding mask covers
+    /// every key, including any cached positions.
+    Padded { padding: PaddingMask, causal: bool },
+}
+
+impl From<PaddingMask> for AttentionMask {
+    fn from(padding: PaddingMask) -> Self {
+        Self::Padded {
+            padding,
+            causal: false,
+        }
+    }
+}
+
+/// Marks which positions of each sequence in a padded batch hold real tokens
+#[derive(Clone, Copy)]
+pub struct PaddingMask {
+    pub valid: GraphTensor, // batch dims.. x seq, 1 for real tokens and 0 for padding
+}
+
+impl PaddingMask {
+    /// From a boolean mask (batch dims.. x seq) holding 1 at real tokens and 0 at padding
+    pub fn from_mask(mask: GraphTensor) -> Self {
+        Self { valid: mask }
+    }
+
+    /// From the length of each sequence (batch), where sequences are padded on the right up to `seq`
+    pub fn from_lengths(lengths: GraphTensor, seq: impl Into<Expression>) -> Self {
+        assert_eq!(lengths.shape.len(), 1, "Sequence lengths must be 1D");
+        let seq = seq.into();
+        let batch = lengths.dims()[0];
+        Self {
+            valid: lengths
+                .graph()
+                .arange(seq)
+                .expand(0, batch)
+                .less_than(lengths.expand(1, seq)),
+        }
+    }
+
+    /// Combine with causal masking
+    pub fn causal(self) -> AttentionMask {
+        AttentionMask::Padded {
+            padding: self,
+            causal: true,
+        }
+    }
+
+    /// Mean over the real tokens of each sequence, taking input (batch dims.., seq, dim) to (batch dims.., dim)
+    pub fn mean_pool(&self, input: GraphTensor) -> GraphTensor {
+        let rank = input.shape.len();
+        let dim = input.dims()[rank - 1];
+        let valid = self.valid.expand(rank - 1, dim);
+        (input * valid).sum_reduce(rank - 2) / valid.sum_reduce(rank - 2)
+    }
+
+    /// Max over the real tokens of each sequence, taking input (batch dims.., seq, dim) to (batch dims.., dim)
+    pub fn max_pool(&self, input: GraphTensor) -> GraphTensor {
+        let rank = input.shape.len();
+        let dim = input.dims()[rank - 1];
+        let padding = (-self.valid + 1.) * f32::MIN;
+        (input + padding.expand(rank - 1, dim)).max_reduce(rank - 2)
+    }
+}
+
 /// Multi-head self attention as layed out in [*Attention Is All You Need*](https://arxiv.org/abs/1706.03762).
+///
+/// With fewer key-value heads than query heads, each key-value head is shared by a group of query heads
+/// (grouped-query attention).
 pub struct MultiHeadSelfAttention {
     pub w_q: Linear, // dim x k_dim
-    pub w_k: Linear, // dim x k_dim
-    pub w_v: Linear, // dim x v_dim
+    pub w_k: Linear, // dim x k_dim / heads * kv_heads
+    pub w_v: Linear, // dim x v_dim / heads * kv_heads
     pub w_o: Linear, // v_dim x dim
     k_dim: usize,
     v_dim: usize,
     heads: usize,
+    kv_heads: usize,
 }
 
 impl MultiHeadSelfAttention {
     pub fn new(dim: usize, k_dim: usize, v_dim: usize, heads: usize, cx: &mut Graph) -> Self {
+        Self::new_grouped(dim, k_dim, v_dim, heads, heads, cx)
+    }
+
+    pub fn new_grouped(
+        dim: usize,
+        k_dim: usize,
+        v_dim: usize,
+        heads: usize,
+        kv_heads: usize,
+        cx: &mut Graph,
+    ) -> Self {
+        assert!(
+            k_dim % heads == 0 && v_dim % heads == 0,
+            "Key dim {k_dim} and value dim {v_dim} must be divisible by {heads} heads"
+        );
+        assert!(
+            kv_heads > 0 && heads % kv_heads == 0,
+            "{heads} heads must be divisible by {kv_heads} key-value heads"
+        );
         Self {
             w_q: Linear::new(dim, k_dim, false, cx),
-            w_k: Linear::new(dim, k_dim, false, cx),
-            w_v: Linear::new(dim, v_dim, false, cx),
+            w_k: Linear::new(dim, k_dim / heads * kv_heads, false, cx),
+            w_v: Linear::new(dim, v_dim / heads * kv_heads, false, cx),
             w_o: Linear::new(v_dim, dim, false, cx),
             k_dim,
             v_dim,
             heads,
+            kv_heads,
         }
     }
+
+    /// Attend from queries (batch, s2, dim) to keys and values (batch, s1, dim), returning the attended tokens
+    /// (batch, s2, v_dim) and the cache updated with this step's keys and values
+    fn attend(
+        &self,
+        queries: GraphTensor,
+        keys: GraphTensor,
+        values: GraphTensor,
+        cache: Option<KVCache>,
+        mask: AttentionMask,
+    ) -> (GraphTensor, KVCache) {
+        let (n_batches, s2, _) = queries.dims3();
+        let s1 = keys.dims()[1];
+        let (k_head_dim, v_head_dim) = (self.k_dim / self.heads, self.v_dim / self.heads);
+        let groups = self.heads / self.kv_heads;
+        let keys = self
+            .w_k
+            .forward(keys)
+            .reshape((n_batches, s1, self.kv_heads, k_head_dim))
+            .permute((0, 2, 1, 3));
+        let values = self
+            .w_v
+            .forward(values)
+            .reshape((n_batches, s1, self.kv_heads, v_head_dim))
+            .permute((0, 2, 1, 3));
+        let queries = self
+            .w_q
+            .forward(queries)
+            .reshape((n_batches, s2, self.heads, k_head_dim))
+            .permute((0, 2, 1, 3));
+
+        // Add this step's keys and values to the cache
+        let (prev, (cache, keys, values)) = match cache {
+            Some(cache) => (cache.len, cache.append(keys, values)),
+            None => {
+                let cache = KVCache::from_tensors(keys, values);
+                (0.into(), (cache, keys, values))
+            }
+        };
+        let total = keys.dims()[2];
+
+        let mut weights = if groups == 1 {
+            queries.matmul(keys.permute((0, 1, 3, 2)))
+        } else {
+            // Split query heads into groups sharing each key-value head
+            queries
+                .reshape((n_batches, self.kv_heads, groups, s2, k_head_dim))
+                .matmul(keys.expand(2, groups).permute((0, 1, 2, 4, 3)))
+        }
+        .mul((1.0 / (k_head_dim as f64).sqrt()) as f32);
+
+        let causal = || {
+            (queries.graph().triu(s2, 1) * f16::MIN.to_f32())
+                .pad(((0, 0), (prev, 0)))
+                .expand(0, n_batches)
+        };
+        let mask = match mask {
+            AttentionMask::None => None,
+            AttentionMask::Causal => Some(causal()),
+            AttentionMask::Additive(mask) if mask.shape.len() == 2 => {
+                Some(mask.expand(0, n_batches))
+            }
+            AttentionMask::Additive(mask) => Some(mask.reshape((n_batches, s2, total))),
+            AttentionMask::Padded { padding, causal: c } => {
+                let padding = ((-padding.valid + 1.) * f16::MIN.to_f32())
+                    .reshape((n_batches, total))
+                    .expand(1, s2);
+                Some(if c { padding + causal() } else { padding })
+            }
+        };
+        if let Some(mut mask) = mask {
+            mask = mask.expand(1, self.kv_heads);
+            if groups > 1 {
+                mask = mask.expand(2, groups);
+            }
+            weights += mask;
+        }
+
+        let tokens = if groups == 1 {
+            weights.softmax(3).matmul(values)
+        } else {
+            weights
+                .softmax(4)
+                .matmul(values.expand(2, groups))
+                .reshape((n_batches, self.heads, s2, v_head_dim))
+        }
+        .permute((0, 2, 1, 3))
+        .reshape((n_batches, s2, self.v_dim));
+        (tokens, cache)
+    }
 }
 
 impl SerializeModule for MultiHeadSelfAttention {
@@ -61,58 +251,169 @@ impl Module<(GraphTensor, GraphTensor, GraphTensor)> for MultiHeadSelfAttention
             GraphTensor, // batch, s2, dim
             GraphTensor, // batch, s1, dim
         ),
+    ) -> Self::Output {
+        self.forward((keys, queries, values, AttentionMask::None))
+    }
+}
+
+// Batched different key-query-value, with a mask
+impl Module<(GraphTensor, GraphTensor, GraphTensor, AttentionMask)> for MultiHeadSelfAttention {
+    type Output = GraphTensor;
+
+    fn forward(
+        &self,
+        (keys, queries, values, mask): (GraphTensor, GraphTensor, GraphTensor, AttentionMask),
     ) -> Self::Output {
         let orig_query_shape = queries.dims();
         let s1 = keys.dims()[keys.shape.len() - 2];
         let s2 = queries.dims()[queries.shape.len() - 2];
-        let n_batches = queries
-            .dims()
-            .into_iter()
-            .take(queries.shape.len() - 2)
-            .product::<Expression>()
-            .max(1);
+        let n_batches = batch_size(queries);
         let dim = *queries.dims().last().unwrap();
         let keys = keys.reshape((n_batches, s1, dim));
         let values = values.reshape((n_batches, s1, dim));
         let queries = queries.reshape((n_batches, s2, dim));
-        let values = self
-            .w_v
-            .forward(values)
-            .reshape((n_batches, s1, self.heads, self.k_dim / self.heads))
-            .permute((0, 2, 1, 3));
-        let keys = self
-            .w_k
-            .forward(keys)
-            .reshape((n_batches, s1, self.heads, self.k_dim / self.heads))
-            .permute((0, 2, 3, 1));
-        let queries = self
-            .w_q
-            .forward(queries)
-            .reshape((n_batches, s2, self.heads, self.k_dim / self.heads))
-            .permute((0, 2, 1, 3));
+        let (tokens, _) = self.attend(queries, keys, values, None, mask);
+        self.w_o.forward(tokens).reshape(orig_query_shape) // batch_dims, s2, dim
+    }
+}
 
-        let weights = queries
-            .matmul(keys)
-            .mul((1.0 / ((self.k_dim / self.heads) as f64).sqrt()) as f32)
-            .softmax(3);
+/// Masked self attention over the input and an optional cache of previous keys and values (batch x kv heads x
+/// seq x head dim). Returns the output along with the updated cache, which holds the input's keys and values
+/// when no cache was given.
+impl Module<(GraphTensor, Option<KVCache>, AttentionMask)> for MultiHeadSelfAttention {
+    type Output = (GraphTensor, KVCache);
 
-        let tokens = weights
-            .matmul(values)
-            .permute((0, 2, 1, 3))
-            .reshape((n_batches, s2, self.v_dim));
-        self.w_o.forward(tokens).reshape(orig_query_shape) // batch_dims, s2, dim
+    fn forward(
+        &self,
+        (input, cache, mask): (GraphTensor, Option<KVCache>, AttentionMask),
+    ) -> Self::Output {
+        let orig_shape = input.dims();
+        let seq = input.dims()[input.shape.len() - 2];
+        let n_batches = batch_size(input);
+        let dim = *input.dims().last().unwrap();
+        let input = input.reshape((n_batches, seq, dim));
+        let (tokens, cache) = self.attend(input, input, input, cache, mask);
+        (self.w_o.forward(tokens).reshape(orig_shape), cache)
     }
 }
 
+/// Product of all dims before the sequence and feature dims, treating unbatched inputs as a batch of 1
+fn batch_size(input: GraphTensor) -> Expression {
+    input
+        .dims()
+        .into_iter()
+        .take(input.shape.len() - 2)
+        .product::<Expression>()
+        .max(1)
+}
+
 #[cfg(test)]
 mod tests {
     use dfdx::prelude::{Module as DfdxModule, *};
     use luminal::{
         prelude::{Module, *},
-        tests::assert_close,
+        tests::{assert_close, random_vec_rng},
     };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{AttentionMask, MultiHeadSelfAttention};
+    use crate::{feed_back_caches, KVCache};
+
+    /// Grouped-query attention with 4 query heads sharing 2 key-value heads
+    fn grouped_attention(weights: &[Vec<f32>], cx: &mut Graph) -> MultiHeadSelfAttention {
+        let model = MultiHeadSelfAttention::new_grouped(8, 8, 8, 4, 2, cx);
+        model.w_q.weight.set(weights[0].clone());
+        model.w_k.weight.set(weights[1].clone());
+        model.w_v.weight.set(weights[2].clone());
+        model.w_o.weight.set(weights[3].clone());
+        model
+    }
+
+    /// Run the input through a cached model in two steps, returning the outputs of both steps
+    fn run_cached(
+        data: &[f32],
+        cx: &mut Graph,
+        cache: KVCache,
+        mask: AttentionMask,
+        model: &MultiHeadSelfAttention,
+        step: GraphTensor,
+    ) -> Vec<f32> {
+        let (out, new_cache) = model.forward((step, Some(cache), mask));
+        out.retrieve();
+        new_cache.keep();
+        let mut outputs = vec![];
+        for (start, len) in [(0, 3), (3, 2)] {
+            step.set_dyn(data[start * 8..(start + len) * 8].to_vec(), (1, len, 8));
+            cx.set_dyn_dim('p', start);
+            cx.execute();
+            outputs.extend(out.data());
+            out.drop();
+            feed_back_caches(new_cache, cache, cx);
+        }
+        outputs
+    }
+
+    #[test]
+    fn test_cached_grouped_attention() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let weights = [64, 32, 32, 64].map(|n| random_vec_rng(n, &mut rng));
+        let data = random_vec_rng(5 * 8, &mut rng);
+
+        // Full sequence, checked against regular attention with each key-value head repeated for its group
+        let mut cx = Graph::new();
+        let inp = cx.tensor((1, 's', 8)).set_dyn(data.clone(), (1, 5, 8));
+        let model = grouped_attention(&weights, &mut cx);
+        let (out, cache) = model.forward((inp, None, AttentionMask::Causal));
+        out.retrieve();
+        cache.keys.retrieve();
+        let reference = MultiHeadSelfAttention::new(8, 8, 8, 4, &mut cx);
+        let repeat_heads = |w: &[f32]| {
+            (0..64)
+                .map(|i| w[i / 8 * 4 + (i % 8) / 4 * 2 + i % 2])
+                .collect::<Vec<_>>()
+        };
+        reference.w_q.weight.set(weights[0].clone());
+        reference.w_k.weight.set(repeat_heads(&weights[1]));
+        reference.w_v.weight.set(repeat_heads(&weights[2]));
+        reference.w_o.weight.set(weights[3].clone());
+        let (reference_out, _) = reference.forward((inp, None, AttentionMask::Causal));
+        reference_out.retrieve();
+        cx.execute();
+        assert_close(&out.data(), &reference_out.data());
+        assert_eq!(cache.keys.data().len(), 2 * 5 * 2);
+
+        // Causal masking means each step matches the corresponding rows of the full sequence
+        let mut concat_cx = Graph::new();
+        let cache = KVCache::new_concat(1, 2, 2, 'p', &mut concat_cx);
+        let step = concat_cx.tensor((1, 't', 8));
+        let model = grouped_attention(&weights, &mut concat_cx);
+        let outputs = run_cached(
+            &data,
+            &mut concat_cx,
+            cache,
+            AttentionMask::Causal,
+            &model,
+            step,
+        );
+        assert_close(&outputs, &out.data());
+
+        // Preallocated cache with the causal mask given as an additive mask
+        let mut prealloc_cx = Graph::new();
+        let cache = KVCache::new(1, 2, 8, 2, 'p', &mut prealloc_cx);
+        let step = prealloc_cx.tensor((1, 't', 8));
+        let model = grouped_attention(&weights, &mut prealloc_cx);
+        let mask = (prealloc_cx.triu('t', 1) * -1e9).pad(((0, 0), ('p', 0)));
+        let outputs = run_cached(
+            &data,
+            &mut prealloc_cx,
+            cache,
+            AttentionMask::Additive(mask),
+            &model,
+            step,
+        );
+        assert_close(&outputs, &out.data());
+    }
 
-    use super::MultiHeadSelfAttention;
     #[test]
     fn test_self_attention() {
         let mut cx = Graph::new();
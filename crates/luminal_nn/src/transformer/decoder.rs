@@ -1,7 +1,7 @@
 use crate::{Linear, ReLU};
 use luminal::prelude::*;
 
-use super::attention::MultiHeadSelfAttention;
+use super::attention::{AttentionMask, MultiHeadSelfAttention};
 
 /// A transformer decoder as layed out in [*Attention Is All You Need*](https://arxiv.org/abs/1706.03762).
 pub struct TransformerDecoder {
@@ -29,9 +29,26 @@ impl SerializeModule for TransformerDecoder {
 impl Module<(GraphTensor, GraphTensor)> for TransformerDecoder {
     type Output = GraphTensor;
 
-    fn forward(&self, (mut input, from_enc): (GraphTensor, GraphTensor)) -> Self::Output {
+    fn forward(&self, (input, from_enc): (GraphTensor, GraphTensor)) -> Self::Output {
+        self.forward((input, from_enc, AttentionMask::None, AttentionMask::None))
+    }
+}
+
+/// Decode with masks for the self attention and the cross attention over the encoder output
+impl Module<(GraphTensor, GraphTensor, AttentionMask, AttentionMask)> for TransformerDecoder {
+    type Output = GraphTensor;
+
+    fn forward(
+        &self,
+        (mut input, from_enc, self_mask, cross_mask): (
+            GraphTensor,
+            GraphTensor,
+            AttentionMask,
+            AttentionMask,
+        ),
+    ) -> Self::Output {
         for layer in &self.layers {
-            input = layer.forward((input, from_enc));
+            input = layer.forward((input, from_enc, self_mask, cross_mask));
         }
         input
     }
@@ -70,6 +87,23 @@ impl Module<(GraphTensor, GraphTensor)> for TransformerDecoderBlock {
     type Output = GraphTensor;
 
     fn forward(&self, (input, from_enc): (GraphTensor, GraphTensor)) -> Self::Output {
+        self.forward((input, from_enc, AttentionMask::None, AttentionMask::None))
+    }
+}
+
+/// Decode with masks for the self attention and the cross attention over the encoder output
+impl Module<(GraphTensor, GraphTensor, AttentionMask, AttentionMask)> for TransformerDecoderBlock {
+    type Output = GraphTensor;
+
+    fn forward(
+        &self,
+        (input, from_enc, self_mask, cross_mask): (
+            GraphTensor,
+            GraphTensor,
+            AttentionMask,
+            AttentionMask,
+        ),
+    ) -> Self::Output {
         // Input: batch_dims, seq1, dim
         // From_enc: batch_dims, seq2, dim
         // Flatten to single batch dim
@@ -85,9 +119,9 @@ impl Module<(GraphTensor, GraphTensor)> for TransformerDecoderBlock {
         let inp = input.reshape((n_batches, seq1, dim));
         let fe = from_enc.reshape((n_batches, seq2, dim));
         // Batched forward pass
-        let y = self.self_attention.forward(inp);
+        let y = self.self_attention.forward((inp, inp, inp, self_mask));
         let x = (y + inp).layer_norm(2, 1e-5);
-        let y = self.cross_attention.forward((fe, x, fe));
+        let y = self.cross_attention.forward((fe, x, fe, cross_mask));
         let x = (y + x).layer_norm(2, 1e-5);
         let y = self.ff.forward(x);
         (y + x).layer_norm(2, 1e-5).reshape(input.shape)
@@ -1,7 +1,7 @@
 use crate::{Linear, ReLU};
 use luminal::prelude::*;
 
-use super::attention::MultiHeadSelfAttention;
+use super::attention::{AttentionMask, MultiHeadSelfAttention};
 
 /// A transformer encoder as layed out in [*Attention Is All You Need*](https://arxiv.org/abs/1706.03762).
 pub struct TransformerEncoder {
@@ -29,9 +29,17 @@ impl SerializeModule for TransformerEncoder {
 impl Module<GraphTensor> for TransformerEncoder {
     type Output = GraphTensor;
 
-    fn forward(&self, mut input: GraphTensor) -> Self::Output {
+    fn forward(&self, input: GraphTensor) -> Self::Output {
+        self.forward((input, AttentionMask::None))
+    }
+}
+
+impl Module<(GraphTensor, AttentionMask)> for TransformerEncoder {
+    type Output = GraphTensor;
+
+    fn forward(&self, (mut input, mask): (GraphTensor, AttentionMask)) -> Self::Output {
         for layer in &self.layers {
-            input = layer.forward(input);
+            input = layer.forward((input, mask));
         }
         input
     }
@@ -68,6 +76,15 @@ impl Module<GraphTensor> for TransformerEncoderBlock {
     type Output = GraphTensor;
 
     fn forward(&self, input: GraphTensor) -> Self::Output {
+        self.forward((input, AttentionMask::None))
+    }
+}
+
+// Batched, with a mask over the sequence
+impl Module<(GraphTensor, AttentionMask)> for TransformerEncoderBlock {
+    type Output = GraphTensor;
+
+    fn forward(&self, (input, mask): (GraphTensor, AttentionMask)) -> Self::Output {
         // Input: batch_dims, sequence, dim
         // Reshape to 1 batch dim, sequence, dim
         let n_batches = input
@@ -79,7 +96,7 @@ impl Module<GraphTensor> for TransformerEncoderBlock {
         let sequence = input.dims()[input.shape.len() - 2];
         let dim = input.dims()[input.shape.len() - 1];
         let x = input.reshape((n_batches, sequence, dim));
-        let x = x + self.attention.forward(x);
+        let x = x + self.attention.forward((x, x, x, mask));
         let x = x.layer_norm(2, 1e-5);
         let x = x + self.ff.forward(x);
         x.layer_norm(2, 1e-5).reshape(input.dims())
@@ -0,0 +1,211 @@
+use luminal::prelude::*;
+
+/// A key-value cache for attention, holding the keys and values of every position processed so far.
+///
+/// A preallocated cache writes each step's keys and values into fixed buffers in-place at the current position,
+/// rather than concatenating them onto the previous cache, so only the new entries get copied. Attention reads
+/// a prefix view of the buffers covering everything written so far. A concatenated cache instead grows by one
+/// concatenation per step, and is sized by a dynamic dimension.
+#[derive(Clone, Copy)]
+pub struct KVCache {
+    pub keys: GraphTensor,   // batch x heads x (max) seq x head dim
+    pub values: GraphTensor, // batch x heads x (max) seq x head dim
+    /// Number of positions written to the cache so far
+    pub len: Expression,
+    preallocated: bool,
+}
+
+impl KVCache {
+    /// A cache preallocated to `max_seq` positions, with the first `len` of them already written
+    pub fn new(
+        batch: usize,
+        heads: usize,
+        max_seq: usize,
+        head_dim: usize,
+        len: impl Into<Expression>,
+        cx: &mut Graph,
+    ) -> Self {
+        let n_elements = batch * heads * max_seq * head_dim;
+        Self {
+            keys: cx
+                .named_tensor("Key Cache", (batch, heads, max_seq, head_dim))
+                .set(vec![0.; n_elements]),
+            values: cx
+                .named_tensor("Value Cache", (batch, heads, max_seq, head_dim))
+                .set(vec![0.; n_elements]),
+            len: len.into(),
+            preallocated: true,
+        }
+    }
+
+    /// An empty cache that grows by concatenation, with its length given by the dynamic dimension `len`
+    pub fn new_concat(
+        batch: usize,
+        heads: usize,
+        head_dim: usize,
+        len: char,
+        cx: &mut Graph,
+    ) -> Self {
+        Self {
+            keys: cx
+                .named_tensor("Key Cache", (batch, heads, len, head_dim))
+                .set_dyn(vec![], (batch, heads, 0, head_dim)),
+            values: cx
+                .named_tensor("Value Cache", (batch, heads, len, head_dim))
+                .set_dyn(vec![], (batch, heads, 0, head_dim)),
+            len: len.into(),
+            preallocated: false,
+        }
+    }
+
+    /// A concatenated cache holding exactly the given keys and values (batch x heads x seq x head dim)
+    pub fn from_tensors(keys: GraphTensor, values: GraphTensor) -> Self {
+        assert_eq!(keys.shape.len(), 4, "Cached keys must be 4D");
+        assert_eq!(values.shape.len(), 4, "Cached values must be 4D");
+        Self {
+            keys,
+            values,
+            len: keys.dims()[2],
+            preallocated: false,
+        }
+    }
+
+    /// Add keys and values (batch x heads x seq x head dim) to the end of the cache.
+    ///
+    /// Returns the updated cache, along with the keys and values of every position in it.
+    pub fn append(
+        &self,
+        keys: GraphTensor,
+        values: GraphTensor,
+    ) -> (KVCache, GraphTensor, GraphTensor) {
+        if !self.preallocated {
+            let cache = KVCache::from_tensors(
+                self.keys.concat_along(keys, 2),
+                self.values.concat_along(values, 2),
+            );
+            return (cache, cache.keys, cache.values);
+        }
+        let end = self.len + keys.dims()[2];
+        let cache = KVCache {
+            keys: self.keys.write_along(keys, 2, self.len),
+            values: self.values.write_along(values, 2, self.len),
+            len: end,
+            preallocated: true,
+        };
+        (
+            cache,
+            cache.keys.slice((.., .., ..end, ..)),
+            cache.values.slice((.., .., ..end, ..)),
+        )
+    }
+}
+
+/// Move the updated caches produced by the last execution into the cache inputs, ready for the next step.
+///
+/// The updated caches should be kept so they survive execution, and if the graph has been compiled the inputs
+/// should be remapped with `downstream` first. Dynamic dimensions like the cache length still need to be set.
+pub fn feed_back_caches(updated: impl ToIds, inputs: impl ToIds, cx: &mut Graph) {
+    let (updated, inputs) = (updated.to_ids(), inputs.to_ids());
+    assert_eq!(
+        updated.len(),
+        inputs.len(),
+        "Got {} updated cache tensors for {} cache inputs",
+        updated.len(),
+        inputs.len()
+    );
+    transfer_data_same_graph(updated, inputs, cx);
+}
+
+impl MarkTensors for KVCache {
+    fn keep(&self) {
+        self.keys.keep();
+        self.values.keep();
+    }
+    fn retrieve(&self) {
+        self.keys.retrieve();
+        self.values.retrieve();
+    }
+    fn drop(&self) {
+        self.keys.drop();
+        self.values.drop();
+    }
+    fn set_dyn(&self, data: impl Data + Clone, shape: impl ToShape + Copy) {
+        self.keys.set_dyn(data.clone(), shape);
+        self.values.set_dyn(data, shape);
+    }
+}
+
+impl ToIds for KVCache {
+    fn to_ids(&self) -> Vec<NodeIndex> {
+        vec![self.keys.id, self.values.id]
+    }
+}
+
+impl ToIdsMut for KVCache {
+    fn to_ids_mut(&mut self) -> Vec<&mut NodeIndex> {
+        vec![&mut self.keys.id, &mut self.values.id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{feed_back_caches, KVCache};
+    luminal::test_imports!();
+
+    #[test]
+    fn test_kv_cache() {
+        // In-place cache
+        let mut cx = Graph::new();
+        let cache_src = KVCache::new(1, 2, 8, 4, 'p', &mut cx);
+        let keys = cx.tensor((1, 2, 's', 4));
+        let values = cx.tensor((1, 2, 's', 4));
+        let (cache_dest, prefix_keys, prefix_values) = cache_src.append(keys, values);
+        prefix_keys.retrieve();
+        prefix_values.retrieve();
+        cx.keep_tensors(cache_dest);
+
+        // Concatenated cache
+        let mut concat_cx = Graph::new();
+        let concat_src = (
+            concat_cx
+                .tensor((1, 2, 'p', 4))
+                .set_dyn(vec![], (1, 2, 0, 4)),
+            concat_cx
+                .tensor((1, 2, 'p', 4))
+                .set_dyn(vec![], (1, 2, 0, 4)),
+        );
+        let concat_keys = concat_cx.tensor((1, 2, 's', 4));
+        let concat_values = concat_cx.tensor((1, 2, 's', 4));
+        let concat_dest = (
+            concat_src.0.concat_along(concat_keys, 2).retrieve(),
+            concat_src.1.concat_along(concat_values, 2).retrieve(),
+        );
+
+        let mut pos = 0;
+        for seq in [3, 1, 2, 1] {
+            let (k, v) = (random_vec(2 * seq * 4), random_vec(2 * seq * 4));
+            keys.set_dyn(k.clone(), (1, 2, seq, 4));
+            values.set_dyn(v.clone(), (1, 2, seq, 4));
+            cx.set_dyn_dim('p', pos);
+            cx.execute();
+            concat_keys.set_dyn(k, (1, 2, seq, 4));
+            concat_values.set_dyn(v, (1, 2, seq, 4));
+            concat_cx.set_dyn_dim('p', pos);
+            concat_cx.execute();
+
+            assert_close(&prefix_keys.data(), &concat_dest.0.data());
+            assert_close(&prefix_values.data(), &concat_dest.1.data());
+            // Both buffers are written in-place
+            assert_eq!(cx.memory_stats.in_place_outputs, 2);
+
+            // Feed the caches back in for the next step
+            feed_back_caches(cache_dest, cache_src, &mut cx);
+            let (k, v) = (concat_dest.0.data(), concat_dest.1.data());
+            concat_dest.0.drop();
+            concat_dest.1.drop();
+            pos += seq;
+            concat_src.0.set_dyn(k, (1, 2, pos, 4));
+            concat_src.1.set_dyn(v, (1, 2, pos, 4));
+        }
+    }
+}
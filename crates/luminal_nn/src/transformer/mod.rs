@@ -6,6 +6,8 @@ mod decoder;
 pub use decoder::*;
 mod encoder;
 pub use encoder::*;
+mod kv_cache;
+pub use kv_cache::*;
 
 pub struct Transformer {
     pub encoder: encoder::TransformerEncoder,
@@ -56,10 +58,97 @@ mod tests {
 
     use luminal::{
         prelude::{Module, *},
-        tests::assert_close,
+        tests::{assert_close, random_vec_rng},
     };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{
+        MultiHeadSelfAttention, PaddingMask, Transformer, TransformerDecoder, TransformerEncoder,
+    };
+    use crate::{AttentionMask, Linear, ReLU};
+
+    fn randomize_attention(attention: &MultiHeadSelfAttention, rng: &mut StdRng) {
+        for w in [
+            &attention.w_q,
+            &attention.w_k,
+            &attention.w_v,
+            &attention.w_o,
+        ] {
+            w.weight.set(random_vec_rng(16, rng));
+        }
+    }
+
+    fn randomize_ff(ff: &(Linear, ReLU, Linear), rng: &mut StdRng) {
+        ff.0.weight.set(random_vec_rng(32, rng));
+        ff.2.weight.set(random_vec_rng(32, rng));
+    }
+
+    /// Rows `start..end` of each (seq, 4) sequence in a batch
+    fn rows(data: &[f32], seq: usize, batch: usize, start: usize, end: usize) -> Vec<f32> {
+        data[(batch * seq + start) * 4..(batch * seq + end) * 4].to_vec()
+    }
+
+    #[test]
+    fn test_padded_batches() {
+        let mut cx = Graph::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let encoder = TransformerEncoder::new(4, 8, 2, 2, &mut cx);
+        let decoder = TransformerDecoder::new(4, 8, 2, 1, &mut cx);
+        for layer in &encoder.layers {
+            randomize_attention(&layer.attention, &mut rng);
+            randomize_ff(&layer.ff, &mut rng);
+        }
+        randomize_attention(&decoder.layers[0].self_attention, &mut rng);
+        randomize_attention(&decoder.layers[0].cross_attention, &mut rng);
+        randomize_ff(&decoder.layers[0].ff, &mut rng);
+
+        // Sources of length 2 and 4 padded on the right, and targets of length 3 and 1 padded on the left
+        let src_data = random_vec_rng(2 * 4 * 4, &mut rng);
+        let tgt_data = random_vec_rng(2 * 3 * 4, &mut rng);
+        let src = cx.tensor((2, 4, 4)).set(src_data.clone());
+        let tgt = cx.tensor((2, 3, 4)).set(tgt_data.clone());
+        let src_padding = PaddingMask::from_lengths(cx.tensor(2).set(vec![2., 4.]), 4);
+        let tgt_padding =
+            PaddingMask::from_mask(cx.tensor((2, 3)).set(vec![1., 1., 1., 0., 0., 1.]));
+        let enc = encoder.forward((src, src_padding.into())).retrieve();
+        let dec = decoder
+            .forward((tgt, enc, tgt_padding.causal(), src_padding.into()))
+            .retrieve();
+        let mean = src_padding.mean_pool(enc).retrieve();
+        let max = src_padding.max_pool(enc).retrieve();
+
+        // Each sequence run on its own
+        let unbatched = [((0, 2), (0, 3)), ((0, 4), (2, 3))]
+            .into_iter()
+            .enumerate()
+            .map(|(b, ((src_start, src_end), (tgt_start, tgt_end)))| {
+                let src = cx
+                    .tensor((src_end - src_start, 4))
+                    .set(rows(&src_data, 4, b, src_start, src_end));
+                let tgt = cx
+                    .tensor((tgt_end - tgt_start, 4))
+                    .set(rows(&tgt_data, 3, b, tgt_start, tgt_end));
+                let enc = encoder.forward(src).retrieve();
+                let dec = decoder
+                    .forward((tgt, enc, AttentionMask::Causal, AttentionMask::None))
+                    .retrieve();
+                let pooled = (enc.mean_reduce(0).retrieve(), enc.max_reduce(0).retrieve());
+                (enc, dec, pooled)
+            })
+            .collect::<Vec<_>>();
+        cx.execute();
+
+        let (enc_data, dec_data) = (enc.data(), dec.data());
+        for (b, (u_enc, u_dec, (u_mean, u_max))) in unbatched.iter().enumerate() {
+            let src_len = [2, 4][b];
+            let tgt_start = [0, 2][b];
+            assert_close(&rows(&enc_data, 4, b, 0, src_len), &u_enc.data());
+            assert_close(&rows(&dec_data, 3, b, tgt_start, 3), &u_dec.data());
+            assert_close(&mean.data()[b * 4..(b + 1) * 4], &u_mean.data());
+            assert_close(&max.data()[b * 4..(b + 1) * 4], &u_max.data());
+        }
+    }
 
-    use super::Transformer;
     #[test]
     fn test_transformer_full() {
         let mut cx = Graph::new();
@@ -6,12 +6,15 @@ use rustc_hash::{FxHashMap, FxHashSet};
 
 use luminal::{
     op::{
-        Add, Contiguous, Exp2, Function, LessThan, Log2, MaxReduce, Mod, Mul, Recip, Sin, Sqrt,
-        SumReduce,
+        Add, Contiguous, Exp2, Function, Gather, LessThan, Log2, Loop, MaxReduce, Mod, Mul, Recip,
+        Scan, ScanKind, Scatter, Sin, Sqrt, SumReduce,
     },
     prelude::{tinyvec::ArrayVec, *},
 };
 
+#[cfg(feature = "linalg")]
+use crate::linalg::{add_linalg_grads, linalg_output_shape};
+
 #[derive(Clone, Debug)]
 pub struct Autograd(Vec<NodeIndex>, NodeIndex);
 
@@ -75,7 +78,7 @@ impl Compiler for Autograd {
             if op == TypeId::of::<Function>() {
                 continue;
             }
-            if op == TypeId::of::<Mod>() || op == TypeId::of::<LessThan>() {
+            if op == TypeId::of::<LessThan>() {
                 assert!(
                     !weight_set.contains(&fwd_node),
                     "{fwd_node:?} is marked as a weight but is undifferentiable: {:?}",
@@ -84,18 +87,25 @@ impl Compiler for Autograd {
                 continue;
             }
 
+            // Nodes that only feed into undifferentiable inputs (like comparisons and select
+            // conditions) have a zero gradient, so there's nothing to propagate
+            let Some(&(grad_id, grad_shape)) = grads.get(&fwd_node) else {
+                continue;
+            };
+
             // Differentiate through fwd_node to get gradients for it's sources
             // Get input tensors
             let inps = graph
                 .edges_directed(fwd_node, Direction::Incoming)
                 .filter_map(|e| e.weight().as_data().map(|i| (e.source(), i)))
                 .sorted_by_key(|(_, (a, _, _))| *a)
-                .map(|(node, (_, _, sh))| GraphTensor::from_id(node, sh, graph_ref))
+                .map(|(node, (_, out, sh))| GraphTensor::from_output(node, out, sh, graph_ref))
                 .collect::<Vec<_>>();
-            let mut prev_grad = {
-                let (id, sh) = grads[&fwd_node];
-                GraphTensor::from_id(id, sh, graph_ref)
-            };
+            let mut prev_grad = GraphTensor::from_id(grad_id, grad_shape, graph_ref);
+            #[cfg(feature = "linalg")]
+            if add_linalg_grads(fwd_node, &inps, prev_grad, &valid_set, graph, &mut grads) {
+                continue;
+            }
             if op == TypeId::of::<Add>() {
                 // f(a, b) = a + b
                 // df/da = 1
@@ -116,6 +126,28 @@ impl Compiler for Autograd {
                 if valid_set.contains(&inps[1].id) {
                     add_grad(inps[0] * prev_grad, inps[1], graph, &mut grads);
                 }
+            } else if op == TypeId::of::<Where>() {
+                // f(c, a, b) = c ? a : b
+                // Gradients only flow to the selected branch
+                let (cond, a, b) = (inps[0], inps[1], inps[2]);
+                let zeros = graph.constant(0.).expand_to(prev_grad.shape);
+                if valid_set.contains(&a.id) {
+                    add_grad(prev_grad.where_(cond, zeros), a, graph, &mut grads);
+                }
+                if valid_set.contains(&b.id) {
+                    add_grad(zeros.where_(cond, prev_grad), b, graph, &mut grads);
+                }
+            } else if op == TypeId::of::<Mod>() {
+                // f(a, b) = a % b = a - trunc(a / b) * b
+                // df/da = 1
+                if valid_set.contains(&inps[0].id) {
+                    add_grad(prev_grad, inps[0], graph, &mut grads);
+                }
+                // df/db = -trunc(a / b)
+                if valid_set.contains(&inps[1].id) {
+                    let quotient = (inps[0] / inps[1]).trunc();
+                    add_grad(-quotient * prev_grad, inps[1], graph, &mut grads);
+                }
             } else if let Some(op) = unsafe { graph_ref.as_ref().unwrap() } // Needed to get around multiple borrows
                 .try_get_op::<SumReduce>(fwd_node)
                 .cloned()
@@ -143,11 +175,97 @@ impl Compiler for Autograd {
                     let grad = inps[0].equals(reduced) * prev_grad;
                     add_grad(grad, inps[0], graph, &mut grads);
                 }
+            } else if let Some(op) = unsafe { graph_ref.as_ref().unwrap() } // Needed to get around multiple borrows
+                .try_get_op::<Scan>(fwd_node)
+                .cloned()
+            {
+                // Each input contributes to every output from its position onwards, so gradients get summed in reverse
+                if valid_set.contains(&inps[0].id) {
+                    let (axis, n) = (op.axis, inps[0].dims()[op.axis]);
+                    let out_shape = ShapeTracker::new(inps[0].dims());
+                    let out = GraphTensor::from_id(fwd_node, out_shape, graph_ref);
+                    // Spread an (input i, output j) matrix over the other dims, with j right after i
+                    let spread = |mut m: GraphTensor| {
+                        for (i, d) in inps[0].dims().into_iter().enumerate() {
+                            if i != axis {
+                                m = m.expand(if i < axis { i } else { i + 1 }, d);
+                            }
+                        }
+                        m
+                    };
+                    // Sum each input's partial derivatives with respect to the outputs at or after it
+                    let causal = spread(graph.triu(n, 0));
+                    let zeros = graph.constant(0.).expand_to(causal.shape);
+                    let pairwise = |partials: GraphTensor| {
+                        (partials.where_(causal, zeros) * prev_grad.expand(axis, n))
+                            .sum_reduce(axis + 1)
+                    };
+                    let grad = match op.kind {
+                        // f(x)_j = sum_{i <= j} x_i
+                        // df_j/dx_i = 1
+                        ScanKind::Sum => {
+                            prev_grad.sum_reduce(axis).expand(axis, n) - prev_grad.cumsum(axis)
+                                + prev_grad
+                        }
+                        // f(x)_j = prod_{i <= j} x_i
+                        // df_j/dx_i = prod_{k <= j, k != i} x_k, which stays finite when x_i is zero
+                        ScanKind::Prod => {
+                            let eye = spread(graph.triu(n, 0) * graph.tril(n, 0));
+                            let ones = graph.constant(1.).expand_to(eye.shape);
+                            pairwise(ones.where_(eye, inps[0].expand(axis, n)).cumprod(axis + 1))
+                        }
+                        // f(x)_j = ln(sum_{i <= j} exp(x_i))
+                        // df_j/dx_i = exp(x_i - f(x)_j), where x_i <= f(x)_j so it can't overflow
+                        ScanKind::LogSumExp => {
+                            pairwise((inps[0].expand(axis + 1, n) - out.expand(axis, n)).exp())
+                        }
+                        // f(x)_j = max_{i <= j} x_i (or min)
+                        // df_j/dx_i = x_i == f(x)_j
+                        ScanKind::Max | ScanKind::Min => {
+                            pairwise(inps[0].expand(axis + 1, n).equals(out.expand(axis, n)))
+                        }
+                    };
+                    add_grad(grad, inps[0], graph, &mut grads);
+                }
+            } else if let Some(op) = unsafe { graph_ref.as_ref().unwrap() } // Needed to get around multiple borrows
+                .try_get_op::<Gather>(fwd_node)
+                .cloned()
+            {
+                // f(x)_i = x_{index_i}
+                // Each gathered entry sends its gradient back to where it was gathered from
+                if valid_set.contains(&inps[1].id) {
+                    let zeros = graph.constant(0.).expand_to(inps[1].shape);
+                    let grad = zeros.scatter_add(op.axis, inps[0], prev_grad);
+                    add_grad(grad, inps[1], graph, &mut grads);
+                }
+            } else if let Some(op) = unsafe { graph_ref.as_ref().unwrap() } // Needed to get around multiple borrows
+                .try_get_op::<Scatter>(fwd_node)
+                .cloned()
+            {
+                let (dest, index, src) = (inps[0], inps[1], inps[2]);
+                // Overwritten entries of the destination don't reach the output
+                if valid_set.contains(&dest.id) {
+                    let grad = if op.add {
+                        prev_grad
+                    } else {
+                        let zeros = graph.constant(0.).expand_to(src.shape);
+                        prev_grad.scatter(op.axis, index, zeros)
+                    };
+                    add_grad(grad, dest, graph, &mut grads);
+                }
+                // Each scattered entry gets the gradient of where it was written to
+                if valid_set.contains(&src.id) {
+                    add_grad(prev_grad.gather(op.axis, index), src, graph, &mut grads);
+                }
             } else if op == TypeId::of::<Contiguous>() {
                 if valid_set.contains(&inps[0].id) {
                     add_grad(prev_grad, inps[0], graph, &mut grads);
                 }
             } else {
+                assert!(
+                    op != TypeId::of::<Loop>(),
+                    "Loops aren't differentiable, so {fwd_node:?} can't be backpropagated through"
+                );
                 if !valid_set.contains(&inps[0].id) {
                     continue;
                 }
@@ -183,7 +301,58 @@ impl Compiler for Autograd {
     }
 }
 
-fn add_grad(
+/// The shape a node's output is laid out in, worked out from its inputs
+fn output_shape(graph: &Graph, node: NodeIndex) -> Option<ShapeTracker> {
+    #[cfg(feature = "linalg")]
+    if let Some(shape) = linalg_output_shape(graph, node) {
+        return Some(shape);
+    }
+    let sources = graph.get_sources(node);
+    let (_, _, mut shape) = *sources.first()?;
+    if let Some(SumReduce(dim)) = graph.try_get_op(node) {
+        shape.remove_dim(*dim);
+    } else if let Some(MaxReduce(dim)) = graph.try_get_op(node) {
+        shape.remove_dim(*dim);
+    }
+    Some(shape)
+}
+
+/// Sum the gradient of padding that reads from the dimension itself back onto the elements it read
+fn fold_padding(
+    grad: GraphTensor,
+    axis: usize,
+    (left, right): (Expression, Expression),
+    dim: Expression,
+    fill: PadFill,
+) -> GraphTensor {
+    let graph = grad.graph();
+    let pos = graph.arange(left + dim + right) - left;
+    let source = match fill {
+        PadFill::Replicate => pos
+            .max_f32(0.)
+            .min(graph.constant(dim - 1).expand_to(pos.shape)),
+        PadFill::Reflect => {
+            let abs = pos.abs();
+            abs.min(graph.constant((dim - 1) * 2).expand_to(pos.shape) - abs)
+        }
+        PadFill::Circular => (pos + dim) % dim,
+        PadFill::Zero => unreachable!(),
+    };
+    let mut index = source;
+    let mut dims = grad.dims();
+    for (i, d) in dims.iter().enumerate() {
+        if i != axis {
+            index = index.expand(i, *d);
+        }
+    }
+    dims[axis] = dim;
+    graph
+        .constant(0.)
+        .expand_to(dims)
+        .scatter_add(axis, index, grad)
+}
+
+pub(crate) fn add_grad(
     mut grad: GraphTensor,
     fwd: GraphTensor,
     graph: &mut Graph,
@@ -203,22 +372,37 @@ fn add_grad(
         if fwd.shape.fake[i] {
             grad.id = graph
                 .add_op(SumReduce(i))
-                .input(grad.id, 0, grad.shape)
+                .input(grad.id, grad.output, grad.shape)
                 .finish();
+            grad.output = 0;
             grad.shape.remove_dim(i);
             grad.shape = grad.shape.contiguous();
         }
     }
 
-    // Check to see if a reshape was done here. If so, we may need to assert grad shape is contiguous or insert a contiguous call
-    if let Some((_, _, mut pre_fwd_shape)) = graph.get_sources(fwd.id).first() {
-        if let Some(SumReduce(dim)) = graph.try_get_op(fwd.id) {
-            pre_fwd_shape.remove_dim(*dim);
-        } else if let Some(MaxReduce(dim)) = graph.try_get_op(fwd.id) {
-            pre_fwd_shape.remove_dim(*dim);
+    // Undo padding (slice it back off) and slices (pad back out with zeros)
+    let mut axis = 0;
+    for i in 0..fwd.shape.len() {
+        if fwd.shape.fake[i] {
+            continue;
         }
+        let ((start, end), (left, right)) = (fwd.shape.mask[i], fwd.shape.padding[i]);
+        let dim = fwd.shape.dims[i];
+        if fwd.shape.pad_fill[i] != PadFill::Zero {
+            grad = fold_padding(grad, axis, (left, right), dim, fwd.shape.pad_fill[i]);
+        } else if left != 0 || right != 0 {
+            grad = grad.slice_along(left..left + end.min(dim) - start, axis);
+        }
+        if start != 0 || end != i32::MAX {
+            grad = grad.pad_along(start, dim - end.min(dim), axis);
+        }
+        axis += 1;
+    }
+
+    // Check to see if a reshape was done here. If so, we may need to assert grad shape is contiguous or insert a contiguous call
+    if let Some(pre_fwd_shape) = output_shape(graph, fwd.id) {
         if grad.shape.dims() != pre_fwd_shape.dims() {
-            if !grad.shape.is_contiguous() {
+            if grad.shape.is_reshaped() {
                 grad = grad.contiguous();
             }
             grad.shape = pre_fwd_shape.contiguous();
@@ -264,6 +448,317 @@ mod tests {
         assert_exact(&get_vec(grads[0], &mut cx), &d_grads.get(&d_a).as_vec());
     }
 
+    #[test]
+    fn test_autograd_cumsum() {
+        let mut cx = Graph::new();
+        let a = cx.named_tensor("Input", 3).set([10., 5., 2.]);
+        let b = (a.cumsum(0) * a).sum_reduce(0);
+
+        let grads = cx.compile(Autograd::new(a, b), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // d/da_i of sum_j a_j * cumsum(a)_j = cumsum(a)_i + sum_{j >= i} a_j
+        assert_close(&get_vec(grads[0], &mut cx), &[27., 22., 19.]);
+    }
+
+    #[test]
+    fn test_autograd_scans() {
+        let mut cx = Graph::new();
+        let p = cx
+            .named_tensor("Product", (2, 3))
+            .set([[2., 0., 3.], [1., 4., 2.]]);
+        let l = cx.named_tensor("LogSumExp", 3).set([200., 200., 200.]);
+        let m = cx.named_tensor("Extrema", 5).set([1., 3., 2., 0., 5.]);
+        let p_weights = cx.tensor((2, 3)).set([[1., 2., 3.], [4., 5., 6.]]);
+        let m_weights = cx.tensor(5).set([1., 2., 3., 4., 5.]);
+        let b = (p.cumprod(1) * p_weights).sum_reduce((0, 1))
+            + l.logcumsumexp(0).sum_reduce(0)
+            + (m.cummax(0) * m_weights).sum_reduce(0)
+            + (m.cummin(0) * m_weights).sum_reduce(0);
+
+        let grads = cx.compile(Autograd::new((p, l, m), b), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // Products skip the differentiated entry, so zeros don't divide
+        assert_close(&get_vec(grads[0], &mut cx), &[1., 22., 0., 72., 17., 24.]);
+        // d/dl_i = sum_{j >= i} exp(l_i - out_j) = sum_{j >= i} 1 / (j + 1)
+        assert_close(&get_vec(grads[1], &mut cx), &[11. / 6., 5. / 6., 1. / 3.]);
+        // Each output's gradient goes to the entry holding the running max (1, 3, 3, 3, 5) or
+        // min (1, 1, 1, 0, 0)
+        assert_close(&get_vec(grads[2], &mut cx), &[1. + 6., 9., 0., 9., 5.]);
+    }
+
+    #[test]
+    fn test_autograd_gather() {
+        let mut cx = Graph::new();
+        let w = cx
+            .named_tensor("Weight", (3, 2))
+            .set([[1., 2.], [3., 4.], [5., 6.]]);
+        let rows = cx.named_tensor("Rows", 3).set([2., 0., 2.]);
+        let coeffs = cx
+            .named_tensor("Coefficients", (3, 2))
+            .set([[1., 2.], [3., 4.], [5., 6.]]);
+        let columns = cx.named_tensor("Columns", (3, 1)).set([[1.], [0.], [1.]]);
+        let selected = (w.index_select(0, rows) * coeffs).sum_reduce((0, 1));
+        let gathered = w.gather(1, columns).sum_reduce((0, 1));
+        let b = selected + gathered;
+
+        let grads = cx.compile(Autograd::new(w, b), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // Selected rows accumulate the coefficients they were multiplied by
+        assert_exact(&get_vec(grads[0], &mut cx), &[3., 5., 1., 0., 6., 9.]);
+    }
+
+    #[test]
+    fn test_autograd_scatter() {
+        let mut cx = Graph::new();
+        let a = cx
+            .named_tensor("A", (2, 3))
+            .set([[1., 2., 3.], [4., 5., 6.]]);
+        let src = cx
+            .named_tensor("Source", (2, 2))
+            .set([[10., 20.], [30., 40.]]);
+        let indices = cx.named_tensor("Indices", (2, 2)).set([[2., 0.], [1., 1.]]);
+        let coeffs = cx
+            .named_tensor("Coefficients", (2, 3))
+            .set([[1., 2., 3.], [4., 5., 6.]]);
+        let b = (a.scatter(1, indices, src) * coeffs).sum_reduce((0, 1))
+            + (a.scatter_add(1, indices, src) * coeffs).sum_reduce((0, 1));
+
+        let grads = cx.compile(Autograd::new((a, src), b), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // Overwritten entries only get gradients through the add
+        assert_exact(&get_vec(grads[0], &mut cx), &[1., 4., 3., 8., 5., 12.]);
+        assert_exact(&get_vec(grads[1], &mut cx), &[6., 2., 10., 10.]);
+    }
+
+    #[test]
+    fn test_autograd_where() {
+        let mut cx = Graph::new();
+        let a = cx.named_tensor("A", 4).set([1., 2., 3., 4.]);
+        let b = cx
+            .named_tensor("B", 4)
+            .set([f32::INFINITY, 6., 7., f32::NEG_INFINITY]);
+        let cond = cx.named_tensor("Cond", 4).set([1., 0., 0., 1.]);
+        let out = (a.where_(cond, b) * a).sum_reduce(0);
+
+        let grads = cx.compile(Autograd::new((a, b), out), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // d/da of a * where(c, a, b) = where(c, 2a, b)
+        assert_exact(&get_vec(grads[0], &mut cx), &[2., 6., 7., 8.]);
+        assert_exact(&get_vec(grads[1], &mut cx), &[0., 2., 3., 0.]);
+    }
+
+    #[test]
+    fn test_autograd_special_functions() {
+        let mut cx = Graph::new();
+        let a = cx.named_tensor("A", 4).set([-1.7, -0.3, 0.4, 2.5]);
+        let b = cx.named_tensor("B", 4).set([1.5, 2., 3., 0.5]);
+        let rounded = (a.floor() + a.ceil() + a.round() + a.trunc() + a % 0.75).sum_reduce(0);
+        let special = (a.erf() + a.log1p().abs() + a.expm1() + a.atan2(b) + b.pow(a)).sum_reduce(0);
+
+        let rounded_grads = cx.compile(Autograd::new(a, rounded), ());
+        let special_grads = cx.compile(Autograd::new((a, b), special), ());
+        cx.keep_tensors(&rounded_grads);
+        cx.keep_tensors(&special_grads);
+        cx.execute();
+
+        // Only the mod has a nonzero gradient
+        assert_exact(&get_vec(rounded_grads[0], &mut cx), &[1., 1., 1., 1.]);
+
+        let (a, b) = ([-1.7_f32, -0.3, 0.4, 2.5], [1.5_f32, 2., 3., 0.5]);
+        let d_a = a
+            .iter()
+            .zip(&b)
+            .map(|(a, b)| {
+                let erf = 2. / std::f32::consts::PI.sqrt() * (-a * a).exp();
+                let log1p = (1. + a).recip() * a.signum();
+                let atan2 = b / (a * a + b * b);
+                erf + log1p + a.exp() + atan2 + b.powf(*a) * b.ln()
+            })
+            .collect::<Vec<_>>();
+        let d_b = a
+            .iter()
+            .zip(&b)
+            .map(|(a, b)| -a / (a * a + b * b) + a * b.powf(a - 1.))
+            .collect::<Vec<_>>();
+        assert_close(&get_vec(special_grads[0], &mut cx), &d_a);
+        assert_close(&get_vec(special_grads[1], &mut cx), &d_b);
+    }
+
+    #[test]
+    fn test_autograd_reshape_pad() {
+        let mut cx = Graph::new();
+        let a = cx
+            .named_tensor("A", (2, 3))
+            .set([[1., 2., 3.], [4., 5., 6.]]);
+        let weights = cx.tensor(8).set([1., 2., 3., 4., 5., 6., 7., 8.]);
+        let flat = (a * 2.).reshape(6).pad_along(1, 1, 0);
+        let out = (flat * weights).sum_reduce(0);
+
+        let grads = cx.compile(Autograd::new(a, out), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        assert_exact(&get_vec(grads[0], &mut cx), &[4., 6., 8., 10., 12., 14.]);
+    }
+
+    /// Gradient of a weighted sum of each row of [1, 2, 3, 4, 5] padded by (2, 1) with `mode`
+    fn pad_with_grad(mode: PadMode) -> Vec<f32> {
+        let mut cx = Graph::new();
+        let a = cx.named_tensor("A", (2, 5)).set(random_vec(10));
+        let weights = cx.tensor(8).set([1., 2., 3., 4., 5., 6., 7., 8.]);
+        let padded = a.pad_with(((0, 0), (2, 1)), mode);
+        let out = (padded * weights.expand(0, 2)).sum_reduce((0, 1));
+
+        let grads = cx.compile(Autograd::new(a, out), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+        get_vec(grads[0], &mut cx)
+    }
+
+    #[test]
+    fn test_autograd_pad_reflect() {
+        // Padding reads [2, 1, ..., 3]
+        let grad = [3., 2. + 4., 1. + 5., 6. + 8., 7.];
+        assert_exact(&pad_with_grad(PadMode::Reflect), &[grad, grad].concat());
+    }
+
+    #[test]
+    fn test_autograd_pad_replicate() {
+        // Padding reads [0, 0, ..., 4]
+        let grad = [1. + 2. + 3., 4., 5., 6., 7. + 8.];
+        assert_exact(&pad_with_grad(PadMode::Replicate), &[grad, grad].concat());
+    }
+
+    #[test]
+    fn test_autograd_pad_circular() {
+        // Padding reads [3, 4, ..., 0]
+        let grad = [3. + 8., 4., 5., 1. + 6., 2. + 7.];
+        assert_exact(&pad_with_grad(PadMode::Circular), &[grad, grad].concat());
+    }
+
+    #[test]
+    #[should_panic(expected = "Loops aren't differentiable")]
+    fn test_autograd_loop() {
+        let mut cx = Graph::new();
+        let w = cx.named_tensor("Weight", 2).set([1., 2.]);
+        let xs = cx.tensor((3, 2)).set([[1., 2.], [3., 4.], [5., 6.]]);
+        let h = cx.tensor(2).set([0., 0.]);
+        let (last, _) = cx.scan_loop(&[xs], &[h], &[w], false, |_, x, h, w| {
+            (vec![h[0] * w[0] + x[0]], vec![])
+        });
+        cx.compile(Autograd::new(w, last[0].sum_reduce(0)), ());
+    }
+
+    #[test]
+    fn test_autograd_slice_pad() {
+        let mut cx = Graph::new();
+        let a = cx
+            .named_tensor("A", (2, 3))
+            .set([[1., 2., 3.], [4., 5., 6.]]);
+        let weights = cx.tensor(4).set([1., 2., 3., 4.]);
+        let sliced = a.slice_along(1.., 1).sum_reduce(0);
+        let padded =
+            (a.pad_along(1, 0, 0).sum_reduce(1) * weights.slice_along(..3, 0)).sum_reduce(0);
+        let out = (sliced * sliced).sum_reduce(0) + padded;
+
+        let grads = cx.compile(Autograd::new(a, out), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // d/da of sum(colsum(a[:, 1:])^2) = 2 * colsum for the sliced columns
+        // d/da of padded = the weight each row lands on after padding
+        assert_exact(
+            &get_vec(grads[0], &mut cx),
+            &[2., 14. + 2., 18. + 2., 3., 14. + 3., 18. + 3.],
+        );
+    }
+
+    #[cfg(feature = "linalg")]
+    #[test]
+    fn test_autograd_linalg() {
+        use luminal_linalg::{cholesky, det, qr, slogdet, triangular_solve};
+
+        // Weight each output differently so every entry's gradient matters
+        fn weighted_sum(out: GraphTensor) -> GraphTensor {
+            let dims = out.shape.shape_usize();
+            let weights = (0..dims.iter().product::<usize>())
+                .map(|i| (i as f32 + 1.).sin())
+                .collect::<Vec<_>>();
+            let weights = out.graph().tensor(dims).set(weights);
+            (out * weights).sum_reduce(out.shape.all_axes())
+        }
+
+        fn check(data: &[f32], shape: (usize, usize), f: fn(GraphTensor) -> GraphTensor) {
+            // Central finite differences. Dropping a graph clears shape storage, so these run
+            // before the graph being differentiated is built
+            let eval = |data: Vec<f32>| {
+                let mut cx = Graph::new();
+                let out = weighted_sum(f(cx.tensor(shape).set(data))).retrieve();
+                cx.execute();
+                out.data()[0]
+            };
+            let numeric = (0..data.len())
+                .map(|i| {
+                    let (mut plus, mut minus) = (data.to_vec(), data.to_vec());
+                    plus[i] += 1e-2;
+                    minus[i] -= 1e-2;
+                    (eval(plus) - eval(minus)) / 2e-2
+                })
+                .collect::<Vec<_>>();
+
+            let mut cx = Graph::new();
+            let a = cx.tensor(shape).set(data.to_vec());
+            let loss = weighted_sum(f(a));
+            let grads = cx.compile(Autograd::new(a, loss), ());
+            cx.keep_tensors(&grads);
+            cx.execute();
+            assert_close_precision(&get_vec(grads[0], &mut cx), &numeric, 2e-2);
+        }
+
+        let spd = [4., 2., 0.6, 2., 5., 1., 0.6, 1., 3.];
+        fn with_rhs(a: GraphTensor) -> (GraphTensor, GraphTensor) {
+            let b = a
+                .graph()
+                .tensor((3, 2))
+                .set(vec![1., -2., 3., 0.5, -1., 2.]);
+            (a, b)
+        }
+        // Symmetrize so perturbations keep the input symmetric, like the analytic gradient assumes
+        check(&spd, (3, 3), |a| cholesky((a + a.permute((1, 0))) * 0.5));
+        check(&spd, (3, 3), |a| {
+            let (a, b) = with_rhs(a);
+            luminal_linalg::solve(a, b)
+        });
+        check(&spd, (3, 3), |a| {
+            let (a, b) = with_rhs(a);
+            triangular_solve(a, b, true) + triangular_solve(a, b, false)
+        });
+        // Gradients flowing to the right hand side, and through a transposed matrix
+        check(&[1., -2., 3., 0.5, -1., 2.], (3, 2), |b| {
+            let a = b
+                .graph()
+                .tensor((3, 3))
+                .set(vec![1., 2., 0., -1., 3., 1., 2., 0., 1.]);
+            luminal_linalg::solve(a.permute((1, 0)), b * b)
+        });
+        check(&[1., 2., 0., -1., 3., 1., 2., 0., 1.], (3, 3), det);
+        check(&spd, (3, 3), |a| slogdet(a).1);
+        check(&[1., 2., -3., 4., 5., 6., 7., -8.], (4, 2), |a| {
+            let (q, r) = qr(a);
+            q + r.sum_reduce(0).expand(0, 4) * 0.1
+        });
+    }
+
     #[test]
     fn test_autograd_matmul() {
         let mut cx = Graph::new();
@@ -324,6 +819,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_autograd_pooling() {
+        /// The input each tap of each PyTorch-style window reads, with `None` for padding. Taps
+        /// overhanging the padding in `ceil_mode` are left out.
+        fn taps(len: usize, kernel: usize, stride: usize, pad: usize) -> Vec<Vec<Option<usize>>> {
+            (0..)
+                .map(|o| o * stride)
+                .take_while(|start| *start < len + pad)
+                .map(|start| {
+                    (start..start + kernel)
+                        .take_while(|i| *i < len + 2 * pad)
+                        .map(|i| (pad..len + pad).contains(&i).then(|| i - pad))
+                        .collect()
+                })
+                .collect()
+        }
+
+        let mut cx = Graph::new();
+        // Positive, so zero padded taps never win a max in the reference
+        let data = random_vec(2 * 4 * 6)
+            .into_iter()
+            .map(|i| i + 1.)
+            .collect::<Vec<_>>();
+        let a = cx.named_tensor("A", (1, 2, 4, 6)).set(data.clone());
+        let max_weights = random_vec(2 * 2 * 3);
+        let avg_weights = random_vec(2 * 4 * 4);
+        let max = luminal_nn::MaxPool2D::new((2, 2), (2, 2), (1, 1), (0, 0), false).forward(a);
+        let avg = luminal_nn::AvgPool1D::new(3, 2, 1, 1, true).forward(a.reshape((2, 4, 6)));
+        let out = (max * cx.tensor((1, 2, 2, 3)).set(max_weights.clone())).sum_reduce((0, 1, 2, 3))
+            + (avg * cx.tensor((2, 4, 4)).set(avg_weights.clone())).sum_reduce((0, 1, 2));
+
+        let grads = cx.compile(Autograd::new(a, out), ());
+        cx.keep_tensors(&grads);
+        cx.execute();
+
+        // Express both pools as matmuls with selection matrices so dfdx can differentiate them
+        let dev = Cpu::default();
+        let rows = taps(4, 2, 2, 0);
+        let cols = taps(6, 2, 2, 0);
+        let mut select = vec![0.; 24 * 6 * 4];
+        for (o, (r, c)) in rows.iter().cartesian_product(&cols).enumerate() {
+            for (t, (y, x)) in r.iter().cartesian_product(c).enumerate() {
+                if let (Some(y), Some(x)) = (y, x) {
+                    select[(y * 6 + x) * 24 + o * 4 + t] = 1.;
+                }
+            }
+        }
+        let d_a = dev.tensor_from_vec(data.clone(), (2, 24));
+        let d_max = d_a
+            .clone()
+            .trace(Gradients::leaky())
+            .matmul(dev.tensor_from_vec(select, (24, 24)))
+            .reshape_like(&(2, 6, 4))
+            .max::<_, Axis<2>>()
+            * dev.tensor_from_vec(max_weights, (2, 6));
+        let max_grads = d_max.sum().backward();
+
+        let windows = taps(6, 3, 2, 1);
+        let mut average = vec![0.; 6 * windows.len()];
+        for (o, window) in windows.iter().enumerate() {
+            for x in window.iter().flatten() {
+                average[x * windows.len() + o] = 1. / window.len() as f32;
+            }
+        }
+        let d_a_rows = dev.tensor_from_vec(data, (8, 6));
+        let d_avg = d_a_rows
+            .clone()
+            .trace(Gradients::leaky())
+            .matmul(dev.tensor_from_vec(average, (6, windows.len())))
+            * dev.tensor_from_vec(avg_weights, (8, windows.len()));
+        let avg_grads = d_avg.sum().backward();
+
+        let expected = max_grads
+            .get(&d_a)
+            .as_vec()
+            .into_iter()
+            .zip(avg_grads.get(&d_a_rows).as_vec())
+            .map(|(a, b)| a + b)
+            .collect::<Vec<_>>();
+        assert_close(&get_vec(grads[0], &mut cx), &expected);
+    }
+
     #[test]
     fn test_autograd_layer_norm() {
         let mut cx = Graph::new();
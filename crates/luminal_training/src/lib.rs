@@ -1,5 +1,7 @@
 mod autograd;
 pub use autograd::*;
+#[cfg(feature = "linalg")]
+mod linalg;
 mod loss;
 pub use loss::*;
 mod optimizer;
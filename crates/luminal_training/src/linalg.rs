@@ -0,0 +1,136 @@
+use luminal::prelude::*;
+use luminal_linalg::{
+    eye_like, solve, transpose, triangular_solve, Cholesky, Slogdet, Solve, TriangularSolve, QR,
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::add_grad;
+
+/// Propagate gradients through the linear algebra ops, returning false if `fwd_node` isn't one
+pub(crate) fn add_linalg_grads(
+    fwd_node: NodeIndex,
+    inps: &[GraphTensor],
+    prev_grad: GraphTensor,
+    valid_set: &FxHashSet<NodeIndex>,
+    graph: &mut Graph,
+    grads: &mut FxHashMap<NodeIndex, (NodeIndex, ShapeTracker)>,
+) -> bool {
+    let graph_ref: *mut Graph = graph;
+    if graph.check_node_type::<Cholesky>(fwd_node) {
+        // L = chol(A)
+        // dA = sym(L^-T Phi(L^T dL) L^-1), where Phi takes the lower triangle and halves the diagonal
+        if valid_set.contains(&inps[0].id) {
+            let l = GraphTensor::from_id(fwd_node, ShapeTracker::new(prev_grad.dims()), graph_ref);
+            let n = *l.dims().last().unwrap();
+            let phi = batch_mask(graph.tril(n, 0) - eye_like(l) * 0.5, l);
+            let p = transpose(l).matmul(prev_grad) * phi;
+            let x = triangular_solve(transpose(l), p, false);
+            let s = transpose(triangular_solve(transpose(l), transpose(x), false));
+            add_grad((s + transpose(s)) * 0.5, inps[0], graph, grads);
+        }
+    } else if let Some(op) = graph.try_get_op::<TriangularSolve>(fwd_node).cloned() {
+        // X = A^-1 B
+        // dB = A^-T dX
+        // dA = -dB X^T, on the triangle that was read
+        let (a, b) = (inps[0], inps[1]);
+        let x = GraphTensor::from_id(fwd_node, ShapeTracker::new(prev_grad.dims()), graph_ref);
+        let grad_b = triangular_solve(transpose(a), prev_grad, !op.lower);
+        if valid_set.contains(&a.id) {
+            let n = *a.dims().last().unwrap();
+            let triangle = if op.lower {
+                graph.tril(n, 0)
+            } else {
+                graph.triu(n, 0)
+            };
+            let grad_a = -grad_b.matmul(transpose(x)) * batch_mask(triangle, a);
+            add_grad(grad_a, a, graph, grads);
+        }
+        if valid_set.contains(&b.id) {
+            add_grad(grad_b, b, graph, grads);
+        }
+    } else if graph.check_node_type::<Solve>(fwd_node) {
+        // X = A^-1 B
+        // dB = A^-T dX
+        // dA = -dB X^T
+        let (a, b) = (inps[0], inps[1]);
+        let x = GraphTensor::from_id(fwd_node, ShapeTracker::new(prev_grad.dims()), graph_ref);
+        let grad_b = solve(transpose(a), prev_grad);
+        if valid_set.contains(&a.id) {
+            add_grad(-grad_b.matmul(transpose(x)), a, graph, grads);
+        }
+        if valid_set.contains(&b.id) {
+            add_grad(grad_b, b, graph, grads);
+        }
+    } else if graph.check_node_type::<Slogdet>(fwd_node) {
+        // f(A) = (sign(det(A)), ln|det(A)|)
+        // The sign is piecewise constant, and d ln|det(A)| / dA = A^-T
+        if valid_set.contains(&inps[0].id) {
+            let a = inps[0];
+            let axis = prev_grad.shape.len() - 1;
+            let n = *a.dims().last().unwrap();
+            let grad_log_abs = prev_grad
+                .slice_along(1.., axis)
+                .squeeze(axis)
+                .expand(axis, n)
+                .expand(axis + 1, n);
+            let inv_t = transpose(solve(a, eye_like(a)));
+            add_grad(inv_t * grad_log_abs, a, graph, grads);
+        }
+    } else if graph.check_node_type::<QR>(fwd_node) {
+        // A = Q R
+        // dA = (dQ + Q copyltu(M)) R^-T, where M = R dR^T - dQ^T Q and copyltu mirrors the lower triangle
+        if valid_set.contains(&inps[0].id) {
+            let a = inps[0];
+            let dims = a.dims();
+            let (m, n) = (dims[dims.len() - 2], dims[dims.len() - 1]);
+            let axis = dims.len() - 2;
+            let out =
+                GraphTensor::from_id(fwd_node, ShapeTracker::new(prev_grad.dims()), graph_ref);
+            let (q, r) = (out.slice_along(..m, axis), out.slice_along(m.., axis));
+            let (grad_q, grad_r) = (
+                prev_grad.slice_along(..m, axis),
+                prev_grad.slice_along(m.., axis),
+            );
+            let mm = r.matmul(transpose(grad_r)) - transpose(grad_q).matmul(q);
+            let copyltu = mm * batch_mask(graph.tril(n, 0), mm)
+                + transpose(mm * batch_mask(graph.tril(n, -1), mm));
+            let b = grad_q + q.matmul(copyltu);
+            let grad_a = transpose(triangular_solve(r, transpose(b), false));
+            add_grad(grad_a, a, graph, grads);
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+/// The shape a linear algebra op's output is laid out in, worked out from its inputs
+pub(crate) fn linalg_output_shape(graph: &Graph, node: NodeIndex) -> Option<ShapeTracker> {
+    let sources = graph.get_sources(node);
+    let (_, _, shape) = *sources.first()?;
+    if graph.check_node_type::<Solve>(node) || graph.check_node_type::<TriangularSolve>(node) {
+        // Solves come out in the shape of the right hand side
+        Some(sources[1].2)
+    } else if graph.check_node_type::<Slogdet>(node) {
+        let mut dims = shape.dims();
+        dims.truncate(dims.len() - 2);
+        dims.push(2.into());
+        Some(ShapeTracker::new(dims))
+    } else if graph.check_node_type::<QR>(node) {
+        let mut dims = shape.dims();
+        let rows = dims.len() - 2;
+        dims[rows] = dims[rows] + dims[rows + 1];
+        Some(ShapeTracker::new(dims))
+    } else {
+        None
+    }
+}
+
+/// Expand a matrix mask over the batch dims of a batch of matrices
+fn batch_mask(mut mask: GraphTensor, like: GraphTensor) -> GraphTensor {
+    let dims = like.dims();
+    for (i, dim) in dims[..dims.len() - 2].iter().enumerate() {
+        mask = mask.expand(i, *dim);
+    }
+    mask
+}
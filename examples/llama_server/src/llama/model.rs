@@ -1,5 +1,5 @@
-use luminal::prelude::{binary::F32Pow, *};
-use luminal_nn::{Embedding, LayerNorm, Linear};
+use luminal::prelude::*;
+use luminal_nn::{Embedding, KVCache, LayerNorm, Linear, RotaryEmbedding};
 
 // Llama3 8B Config
 pub const VOCAB_SIZE: usize = 128256;
@@ -13,8 +13,6 @@ pub const N_ATTENTION_GROUPS: usize = N_HEADS / N_KV_HEADS;
 pub const HEAD_DIM: usize = HIDDEN_DIM / N_HEADS;
 pub const ATTN_PROJ_DIM: usize = HEAD_DIM * N_KV_HEADS;
 
-pub type KVCache = (GraphTensor, GraphTensor);
-
 pub struct Mlp {
     pub gate_proj: Linear, // hidden -> intermediate
     pub down_proj: Linear, // intermediate -> hidden
@@ -49,28 +47,6 @@ impl SerializeModule for Mlp {
     }
 }
 
-fn apply_rotary_embeddings_ggml(input: GraphTensor, prev_seq: Expression) -> GraphTensor {
-    assert_eq!(input.shape.len(), 4); // batch, n_heads, seq, head_dim
-    let (batch, n_heads, seq, head_dim) = input.dims4();
-    // Get freqs
-    let freqs = (input.graph().arange(head_dim / 2) * 2.0) / (head_dim.to_usize().unwrap() as f32);
-    let freqs = 500_000_f32.pow(freqs);
-    let pos = input.graph().arange(seq) + prev_seq;
-    let emb = pos.expand(1, 1).matmul(freqs.expand(0, 1));
-
-    // Split input into evens and odds
-    let split = input.reshape((batch, n_heads, seq, head_dim / 2, 2));
-    let x0 = split.slice((.., .., .., .., ..1));
-    let x1 = split.slice((.., .., .., .., 1..));
-
-    // Apply sin and cos embeddings
-    let x0_out = x0 * emb.cos().expand_to(x0.shape) - x1 * emb.sin().expand_to(x1.shape);
-    let x1_out = x0 * emb.sin().expand_to(x0.shape) + x1 * emb.cos().expand_to(x1.shape);
-
-    // Combine back into output
-    x0_out.concat_along(x1_out, 4).reshape(input.shape)
-}
-
 pub struct SelfAttention {
     pub q_proj: GraphTensor, // Hidden -> hidden
     pub k_proj: GraphTensor, // Proj dim -> hidden
@@ -80,11 +56,11 @@ pub struct SelfAttention {
 
 impl Module<(GraphTensor, KVCache)> for SelfAttention {
     type Output = (GraphTensor, KVCache);
-    fn forward(&self, (x, (k_cache, v_cache)): (GraphTensor, KVCache)) -> Self::Output {
+    fn forward(&self, (x, cache): (GraphTensor, KVCache)) -> Self::Output {
         // x: batch, seq, hidden
         // cache: batch, kv_heads, prev_seq, head_dim
         let (batch, seq, _) = x.dims3();
-        let (_, _, prev_seq, _) = k_cache.dims4();
+        let prev_seq = cache.len;
         // Apply the Projections
         let queries = x
             .matmul(self.q_proj.permute((1, 0)))
@@ -102,12 +78,12 @@ impl Module<(GraphTensor, KVCache)> for SelfAttention {
             .permute((0, 2, 1, 3));
 
         // Rotary embed queries and keys
-        let queries = apply_rotary_embeddings_ggml(queries, prev_seq);
-        let keys = apply_rotary_embeddings_ggml(keys, prev_seq);
+        let rotary = RotaryEmbedding::new(HEAD_DIM, 500_000., true);
+        let queries = rotary.forward((queries, prev_seq));
+        let keys = rotary.forward((keys, prev_seq));
 
         // Add KV cache
-        let keys = k_cache.concat_along(keys, 2);
-        let values = v_cache.concat_along(values, 2);
+        let (cache, keys, values) = cache.append(keys, values);
 
         // Repeat the KV States for Grouped-Query Attention
         let repeated_keys = keys.expand(2, N_ATTENTION_GROUPS);
@@ -137,7 +113,7 @@ impl Module<(GraphTensor, KVCache)> for SelfAttention {
         let output = output
             // Apply output projection
             .matmul(self.o_proj.permute((1, 0)));
-        (output, (keys.contiguous(), values.contiguous())) // Cache needs to be contiguous for transferring to another graph
+        (output, cache)
     }
 }
 
@@ -6,11 +6,12 @@ use std::{
 
 use itertools::Itertools;
 use luminal::prelude::*;
+use luminal_nn::KVCache;
 use tokenizers::Tokenizer;
 
 use crate::llama::{
     loader,
-    model::{KVCache, Llama, HEAD_DIM, NUM_LAYERS, N_KV_HEADS},
+    model::{Llama, HEAD_DIM, NUM_LAYERS, N_KV_HEADS},
 };
 
 /// Define the model
@@ -49,14 +50,8 @@ impl Model {
 
         let mut input = cx.named_tensor("Input", (1, 's'));
         let mut cache_src: Vec<KVCache> = (0..NUM_LAYERS)
-            .map(|_| {
-                (
-                    cx.named_tensor("Key Cache", (1, N_KV_HEADS, 'p', HEAD_DIM)),
-                    cx.named_tensor("Value Cache", (1, N_KV_HEADS, 'p', HEAD_DIM)),
-                )
-            })
+            .map(|_| KVCache::new_concat(1, N_KV_HEADS, HEAD_DIM, 'p', &mut cx))
             .collect();
-        cache_src.set_dyn(vec![], (1, N_KV_HEADS, 0, HEAD_DIM));
         let model = Llama::new(&mut cx);
         let mut model_weights = params(&model);
         cx.keep_tensors(&model_weights);
@@ -176,7 +171,7 @@ impl Model {
         self.last_generated_token = Some(output_id);
 
         // Swap cache
-        transfer_data_same_graph(
+        luminal_nn::feed_back_caches(
             &self.kv_cache_dest_set,
             &self.kv_cache_src_set,
             &mut self.graph,
@@ -199,7 +194,7 @@ impl Model {
             self.last_generated_token = Some(output_id);
 
             // Swap cache
-            transfer_data_same_graph(
+            luminal_nn::feed_back_caches(
                 &self.kv_cache_dest_set,
                 &self.kv_cache_src_set,
                 &mut self.graph,
@@ -13,8 +13,8 @@ mod gguf;
 mod loader;
 mod model;
 
-use crate::model::KVCache;
 use luminal::prelude::*;
+use luminal_nn::KVCache;
 
 // Command args parser
 #[derive(Debug, Parser)]
@@ -41,14 +41,8 @@ fn main() {
     let mut cx = Graph::new();
     let mut input = cx.named_tensor("Input", (1, 's'));
     let mut cache_src: Vec<KVCache> = (0..model::NUM_LAYERS)
-        .map(|_| {
-            (
-                cx.named_tensor("Key Cache", (1, N_HEADS, 'p', HEAD_DIM)),
-                cx.named_tensor("Value Cache", (1, N_HEADS, 'p', HEAD_DIM)),
-            )
-        })
+        .map(|_| KVCache::new_concat(1, N_HEADS, HEAD_DIM, 'p', &mut cx))
         .collect();
-    cache_src.set_dyn(vec![], (1, N_HEADS, 0, HEAD_DIM));
     let model = Phi::new(&mut cx);
     let mut model_weights = params(&model);
     cx.keep_tensors(&model_weights);
@@ -107,7 +101,7 @@ fn main() {
     cx.set_dyn_dim('t', 1);
     cx.execute();
     logits.drop();
-    transfer_data_same_graph(&cache_dest, &cache_src, &mut cx);
+    luminal_nn::feed_back_caches(&cache_dest, &cache_src, &mut cx);
     println!("\t\t - {}ms", now.elapsed().as_millis());
 
     // Now that weights are loaded, delete the loading nodes so they don't run again
@@ -145,7 +139,7 @@ fn main() {
     io::stdout().flush().unwrap();
 
     // Swap caches
-    transfer_data_same_graph(&cache_dest, &cache_src, &mut cx);
+    luminal_nn::feed_back_caches(&cache_dest, &cache_src, &mut cx);
 
     // Decode loop
     let start_decode = std::time::Instant::now();
@@ -171,7 +165,7 @@ fn main() {
         prev_output_len = current_output.len();
 
         // Swap caches
-        transfer_data_same_graph(&cache_dest, &cache_src, &mut cx);
+        luminal_nn::feed_back_caches(&cache_dest, &cache_src, &mut cx);
     }
 
     println!();
@@ -1,5 +1,5 @@
-use luminal::prelude::{binary::F32Pow, *};
-use luminal_nn::{Embedding, LayerNorm, Linear};
+use luminal::prelude::*;
+use luminal_nn::{Embedding, KVCache, Linear, RMSNorm, RotaryEmbedding};
 
 // Phi3 mini Config
 pub const VOCAB_SIZE: usize = 32064;
@@ -11,8 +11,6 @@ pub const MLP_DIM: usize = 8192;
 pub const HEAD_DIM: usize = HIDDEN_DIM / N_HEADS;
 pub const ATTN_PROJ_DIM: usize = HEAD_DIM * N_HEADS;
 
-pub type KVCache = (GraphTensor, GraphTensor);
-
 pub struct Mlp {
     pub gate_proj: Linear, // hidden -> intermediate
     pub down_proj: Linear, // intermediate -> hidden
@@ -47,28 +45,6 @@ impl SerializeModule for Mlp {
     }
 }
 
-fn apply_rotary_embeddings_ggml(input: GraphTensor, prev_seq: Expression) -> GraphTensor {
-    assert_eq!(input.shape.len(), 4); // batch, n_heads, seq, head_dim
-    let (batch, n_heads, seq, head_dim) = input.dims4();
-    // Get freqs
-    let freqs = (input.graph().arange(head_dim / 2) * 2.0) / (head_dim.to_usize().unwrap() as f32);
-    let freqs = 10_000_f32.pow(freqs);
-    let pos = input.graph().arange(seq) + prev_seq;
-    let emb = pos.expand(1, 1).matmul(freqs.expand(0, 1));
-
-    // Split input into evens and odds
-    let split = input.reshape((batch, n_heads, seq, head_dim / 2, 2));
-    let x0 = split.slice((.., .., .., .., ..1));
-    let x1 = split.slice((.., .., .., .., 1..));
-
-    // Apply sin and cos embeddings
-    let x0_out = x0 * emb.cos().expand_to(x0.shape) - x1 * emb.sin().expand_to(x1.shape);
-    let x1_out = x0 * emb.sin().expand_to(x0.shape) + x1 * emb.cos().expand_to(x1.shape);
-
-    // Combine back into output
-    x0_out.concat_along(x1_out, 4).reshape(input.shape)
-}
-
 pub struct SelfAttention {
     pub q_proj: GraphTensor, // Hidden -> hidden
     pub k_proj: GraphTensor, // Proj dim -> hidden
@@ -78,11 +54,11 @@ pub struct SelfAttention {
 
 impl Module<(GraphTensor, KVCache)> for SelfAttention {
     type Output = (GraphTensor, KVCache);
-    fn forward(&self, (x, (k_cache, v_cache)): (GraphTensor, KVCache)) -> Self::Output {
+    fn forward(&self, (x, cache): (GraphTensor, KVCache)) -> Self::Output {
         // x: batch, seq, hidden
         // cache: batch, kv_heads, prev_seq, head_dim
         let (batch, seq, _) = x.dims3();
-        let (_, _, prev_seq, _) = k_cache.dims4();
+        let prev_seq = cache.len;
         // Apply the Projections
         let queries = x
             .matmul(self.q_proj.permute((1, 0)))
@@ -100,12 +76,12 @@ impl Module<(GraphTensor, KVCache)> for SelfAttention {
             .permute((0, 2, 1, 3));
 
         // Rotary embed queries and keys
-        let queries = apply_rotary_embeddings_ggml(queries, prev_seq);
-        let keys = apply_rotary_embeddings_ggml(keys, prev_seq);
+        let rotary = RotaryEmbedding::new(HEAD_DIM, 10_000., true);
+        let queries = rotary.forward((queries, prev_seq));
+        let keys = rotary.forward((keys, prev_seq));
 
         // Add KV cache
-        let keys = k_cache.concat_along(keys, 2);
-        let values = v_cache.concat_along(values, 2);
+        let (cache, keys, values) = cache.append(keys, values);
 
         // Calculate attention weights
         let mut attention_weights =
@@ -128,7 +104,7 @@ impl Module<(GraphTensor, KVCache)> for SelfAttention {
         let output = output
             // Apply output projection
             .matmul(self.o_proj.permute((1, 0)));
-        (output, (keys.contiguous(), values.contiguous())) // Cache needs to be contiguous for transferring to another graph
+        (output, cache)
     }
 }
 
@@ -154,9 +130,9 @@ impl SerializeModule for SelfAttention {
 
 pub struct TransformerBlock {
     pub attention: SelfAttention,
-    pub attention_norm: LayerNorm,
+    pub attention_norm: RMSNorm,
     pub feed_forward: Mlp,
-    pub feed_forward_norm: LayerNorm,
+    pub feed_forward_norm: RMSNorm,
 }
 
 impl Module<(GraphTensor, KVCache)> for TransformerBlock {
@@ -182,9 +158,9 @@ impl TransformerBlock {
     pub fn new(cx: &mut Graph) -> Self {
         Self {
             attention: SelfAttention::new(cx),
-            attention_norm: LayerNorm::new(HIDDEN_DIM, true, false, false, 1e-5, cx),
+            attention_norm: RMSNorm::new(HIDDEN_DIM, 1e-5, cx),
             feed_forward: Mlp::new(HIDDEN_DIM, MLP_DIM, cx),
-            feed_forward_norm: LayerNorm::new(HIDDEN_DIM, true, false, false, 1e-5, cx),
+            feed_forward_norm: RMSNorm::new(HIDDEN_DIM, 1e-5, cx),
         }
     }
 }
@@ -204,7 +180,7 @@ pub struct Phi {
     // Transformer layers
     pub layers: Vec<TransformerBlock>,
     // Norm + LM head
-    pub head: (LayerNorm, Linear),
+    pub head: (RMSNorm, Linear),
 }
 
 impl Module<(GraphTensor, &[KVCache])> for Phi {
@@ -230,7 +206,7 @@ impl Phi {
         Self {
             embedding: Embedding::new(VOCAB_SIZE, HIDDEN_DIM, cx),
             head: (
-                LayerNorm::new(HIDDEN_DIM, true, false, false, 1e-5, cx),
+                RMSNorm::new(HIDDEN_DIM, 1e-5, cx),
                 Linear::new_permuted(HIDDEN_DIM, VOCAB_SIZE, false, cx),
             ),
             layers: (0..NUM_LAYERS).map(|_| TransformerBlock::new(cx)).collect(),
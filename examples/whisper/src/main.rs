@@ -4,7 +4,8 @@ use std::{io::Write, marker::PhantomData};
 use itertools::Itertools;
 // WIP
 use luminal::prelude::*;
-use model::{KVCache, D_MODEL, HEADS, HEAD_DIM, N_MEL_BINS};
+use luminal_nn::KVCache;
+use model::{D_MODEL, HEADS, HEAD_DIM, N_MEL_BINS};
 use tokenizers::Tokenizer;
 
 mod audio;
@@ -35,14 +36,8 @@ fn main() {
     let mut text_input = dec_cx.tensor((1, 's'));
     let mut encoder_output = dec_cx.named_tensor("Enc Output", (1, 'e', D_MODEL));
     let mut cache_src = (0..model::DEC_LAYERS)
-        .map(|_| {
-            (
-                dec_cx.named_tensor("Keys", (1, HEADS, HEAD_DIM, 'p')),
-                dec_cx.named_tensor("Values", (1, HEADS, 'p', HEAD_DIM)),
-            )
-        })
+        .map(|_| KVCache::new_concat(1, HEADS, HEAD_DIM, 'p', &mut dec_cx))
         .collect::<Vec<_>>();
-    cache_src.set_dyn(vec![], (1, 6, 64, 0));
     let (logits, _, mut cache_dest) = decoder.forward((encoder_output, text_input, &cache_src));
     let mut logits = logits
         .slice((.., Expression::from('s') - 1.., ..))
@@ -109,7 +104,7 @@ fn main() {
     transfer_data(encoded, &mut enc_cx, &encoder_output, &mut dec_cx);
     dec_cx.execute();
     logits.drop();
-    transfer_data_same_graph(&cache_dest, &cache_src, &mut dec_cx);
+    luminal_nn::feed_back_caches(&cache_dest, &cache_src, &mut dec_cx);
     delete_inputs(&cache_src, &mut dec_cx);
     delete_inputs(&downstream(decoder_params, &dec_cx), &mut dec_cx);
     println!("\t\t - {}ms", now.elapsed().as_millis());
@@ -148,7 +143,7 @@ fn main() {
     let mut prev_output_len = output_str.len();
 
     for i in 3..100 {
-        transfer_data_same_graph(&cache_dest, &cache_src, &mut dec_cx);
+        luminal_nn::feed_back_caches(&cache_dest, &cache_src, &mut dec_cx);
         text_input.set_dyn(vec![output_token as f32], (1, 1));
         dec_cx.set_dyn_dim('p', i);
         dec_cx.execute();
@@ -1,5 +1,8 @@
-use luminal::prelude::{binary::F32Pow, *};
-use luminal_nn::{Conv1D, Embedding, GeLU, LayerNorm, Linear};
+use luminal::prelude::*;
+use luminal_nn::{
+    Conv1D, Embedding, GeLU, KVCache, LayerNorm, LearnedPositionalEmbedding, Linear,
+    SinusoidalPositionalEncoding,
+};
 use std::marker::PhantomData;
 use std::ops::{Add, Mul};
 
@@ -41,8 +44,6 @@ pub const NO_TIMESTAMPS_TOKEN: &str = "<|notimestamps|>";
 pub const EOT_TOKEN: &str = "<|endoftext|>";
 pub const NO_SPEECH_TOKENS: [&str; 2] = ["<|nocaptions|>", "<|nospeech|>"];
 
-pub type KVCache = (GraphTensor, GraphTensor);
-
 pub struct SelfAttention {
     pub q_proj: Linear,
     pub k_proj: Linear,
@@ -64,34 +65,32 @@ impl Module<(GraphTensor, Option<KVCache>, bool)> for SelfAttention {
             .reshape((batch, seq, HEADS, HEAD_DIM))
             .permute((0, 2, 1, 3));
 
-        let mut keys = self
+        let keys = self
             .k_proj
             .forward(x)
             .mul(scale)
             .reshape((batch, seq, HEADS, HEAD_DIM))
-            .permute((0, 2, 3, 1))
-            .contiguous();
+            .permute((0, 2, 1, 3));
 
-        let mut values = self
+        let values = self
             .v_proj
             .forward(x)
             .reshape((batch, seq, HEADS, HEAD_DIM))
             .permute((0, 2, 1, 3));
 
         // Add KV cache
-        if let Some((k_cache, v_cache)) = cache {
-            keys = k_cache.concat_along(keys, 3);
-            values = v_cache.concat_along(values, 2);
-        }
+        let (new_cache, keys, values) = match cache {
+            Some(cache) => cache.append(keys, values),
+            None => (KVCache::from_tensors(keys, values), keys, values),
+        };
 
         // Calculate attention weights
-        let mut attention_weights = queries.matmul(keys);
+        let mut attention_weights = queries.matmul(keys.permute((0, 1, 3, 2)));
 
         if mask {
             let mut attention_mask = queries.graph().triu(seq, 1) * f16::MIN.to_f32();
-            if let Some((c, _)) = cache {
-                let (_, _, _, prev_seq) = c.dims4();
-                attention_mask = attention_mask.pad(((0, 0), (prev_seq, 0)));
+            if let Some(cache) = cache {
+                attention_mask = attention_mask.pad(((0, 0), (cache.len, 0)));
             }
             attention_weights += attention_mask.expand(0, batch).expand(1, HEADS);
         }
@@ -105,10 +104,7 @@ impl Module<(GraphTensor, Option<KVCache>, bool)> for SelfAttention {
             .permute((0, 2, 1, 3))
             .reshape((batch, seq, hidden));
         // Apply output projection
-        (
-            self.o_proj.forward(output),
-            (keys.contiguous(), values.contiguous()), // Cache needs to be contiguous
-        )
+        (self.o_proj.forward(output), new_cache)
     }
 }
 
@@ -137,8 +133,7 @@ impl SelfAttention {
             .forward(keys)
             .mul(scale)
             .reshape((batch, enc_seq, HEADS, HEAD_DIM))
-            .permute((0, 2, 3, 1))
-            .contiguous();
+            .permute((0, 2, 1, 3));
         let values = self
             .v_proj
             .forward(values)
@@ -146,7 +141,7 @@ impl SelfAttention {
             .permute((0, 2, 1, 3));
 
         // Calculate attention weights
-        let mut attention_weights = queries.matmul(keys);
+        let attention_weights = queries.matmul(keys.permute((0, 1, 3, 2)));
 
         // Calculate final outputs
         let output = attention_weights
@@ -158,7 +153,10 @@ impl SelfAttention {
             .reshape((batch, dec_seq, hidden));
 
         // Apply output projection
-        (self.o_proj.forward(output), (keys, values))
+        (
+            self.o_proj.forward(output),
+            KVCache::from_tensors(keys, values),
+        )
     }
 }
 
@@ -242,29 +240,16 @@ pub struct AudioEncoder {
     pub post_ln: LayerNorm,
 }
 
-fn sinusoids(channels: usize, length: Expression, cx: &mut Graph) -> GraphTensor {
-    let max_timescale = 10_000_f32;
-    let log_timescale_increment = max_timescale.ln() / (channels / 2 - 1) as f32;
-    let inv_timescales = (0..channels / 2)
-        .map(|i| (i as f32 * (-log_timescale_increment)).exp())
-        .collect::<Vec<_>>();
-    let mut inv_timescales = cx.tensor(channels / 2).set(inv_timescales);
-    let scaled_time = cx.arange(length).expand(1, channels / 2) * inv_timescales.expand(0, length);
-    scaled_time.sin().concat_along(scaled_time.cos(), 1)
-}
-
 impl Module<GraphTensor> for AudioEncoder {
     type Output = GraphTensor;
     fn forward(&self, x: GraphTensor) -> Self::Output {
-        let (_, _, seq) = x.dims3();
-
         // Conv layers
         let x = self.conv1.forward(x).gelu();
         let x = self.conv2.forward(x).gelu();
-        let mut x = x.permute((0, 2, 1));
+        let x = x.permute((0, 2, 1));
 
         // Sinusoidal positional embedding
-        x += sinusoids(D_MODEL, seq / 2, x.graph()).expand_to(x.shape);
+        let x = SinusoidalPositionalEncoding::new_concatenated(D_MODEL, 10_000.).forward(x);
 
         // Transformer layers
         let out = self.layers.forward(x);
@@ -277,8 +262,8 @@ impl Module<GraphTensor> for AudioEncoder {
 impl AudioEncoder {
     pub fn new(cx: &mut Graph) -> Self {
         Self {
-            conv1: Conv1D::new(N_MEL_BINS, D_MODEL, 3, 1, 1, 1, true, cx),
-            conv2: Conv1D::new(D_MODEL, D_MODEL, 3, 2, 1, 1, true, cx),
+            conv1: Conv1D::new(N_MEL_BINS, D_MODEL, 3, 1, 1, 1, 1, true, cx),
+            conv2: Conv1D::new(D_MODEL, D_MODEL, 3, 2, 1, 1, 1, true, cx),
             layers: (0..ENC_LAYERS)
                 .map(|_| EncoderTransformerBlock::new(D_MODEL, ENC_FFN_DIM, cx))
                 .collect(),
@@ -371,7 +356,7 @@ impl SerializeModule for DecoderTransformerBlock {
 pub struct TextDecoder {
     // Embeddings
     pub embedding: Embedding,
-    pub pos_embedding: GraphTensor,
+    pub pos_embedding: LearnedPositionalEmbedding,
     // Transformer layers
     pub layers: Vec<DecoderTransformerBlock>,
     // Final layer norm
@@ -388,16 +373,11 @@ impl Module<(GraphTensor, GraphTensor, &[KVCache])> for TextDecoder {
         &self,
         (enc_output, input, cache): (GraphTensor, GraphTensor, &[KVCache]),
     ) -> Self::Output {
-        let (_, cur_dec_seq) = input.dims2();
-        let (_, _, _, prev_dec_seq) = cache[0].0.dims4();
+        let prev_dec_seq = cache[0].len;
 
         // Embed text
-        let mut x = self.embedding.forward(input);
-        x += self
-            .pos_embedding
-            .slice((prev_dec_seq..cur_dec_seq + prev_dec_seq, ..))
-            .contiguous()
-            .expand_to(x.shape);
+        let x = self.embedding.forward(input);
+        let mut x = self.pos_embedding.forward((x, prev_dec_seq));
 
         // Run through layers and collect new caches
         let (mut new_caches, mut enc_states) = (vec![], vec![]);
@@ -424,7 +404,7 @@ impl TextDecoder {
     pub fn new(cx: &mut Graph) -> Self {
         Self {
             embedding: Embedding::new_permuted(VOCAB_SIZE, D_MODEL, cx),
-            pos_embedding: cx.tensor((MAX_TARGET_POSITION, D_MODEL)),
+            pos_embedding: LearnedPositionalEmbedding::new(MAX_TARGET_POSITION, D_MODEL, cx),
             layer_norm: LayerNorm::new(D_MODEL, true, true, true, 1e-5, cx),
             layers: (0..DEC_LAYERS)
                 .map(|_| DecoderTransformerBlock::new(cx))
@@ -436,7 +416,7 @@ impl TextDecoder {
 impl SerializeModule for TextDecoder {
     fn serialize(&self, s: &mut Serializer) {
         s.module("model/decoder/embed_tokens", &self.embedding);
-        s.tensor("model/decoder/embed_positions/weight", self.pos_embedding);
+        s.module("model/decoder/embed_positions", &self.pos_embedding);
         for (i, layer) in self.layers.iter().enumerate() {
             s.module(&format!("model/decoder/layers/{i}"), layer);
         }
@@ -14,15 +14,13 @@ impl Upsample {
 impl Module<GraphTensor> for Upsample {
     type Output = GraphTensor;
     fn forward(&self, xs: GraphTensor) -> GraphTensor {
-        let (batch, channels, h, w) = xs.dims4();
-        xs.expand(3, self.scale_factor)
-            .expand(5, self.scale_factor)
-            .reshape((
-                batch,
-                channels,
-                self.scale_factor * h,
-                self.scale_factor * w,
-            )) // Double height and width
+        let (_, _, h, w) = xs.dims4();
+        // Integer scales stay views, even with dynamic image sizes
+        xs.interpolate(
+            (self.scale_factor * h, self.scale_factor * w),
+            InterpolateMode::Nearest,
+            false,
+        )
     }
 }
 
@@ -45,7 +43,17 @@ impl ConvBlock {
         cx: &mut Graph,
     ) -> Self {
         let eps = 1e-3;
-        let mut conv = Conv2D::new(ch_in, ch_out, kernel, stride, dilation, false, cx);
+        let mut conv = Conv2D::new(
+            ch_in,
+            ch_out,
+            kernel,
+            stride,
+            dilation,
+            (0, 0),
+            1,
+            false,
+            cx,
+        );
         let original_weight = conv.weight;
         let running_mean = cx.constant(0.).expand(0, ch_out);
         let running_var = cx.constant(1.).expand(0, ch_out);
@@ -255,7 +263,7 @@ impl SerializeModule for DFL {
 impl DFL {
     pub fn new(num_classes: usize, cx: &mut Graph) -> Self {
         Self {
-            conv: Conv2D::new(num_classes, 1, (1, 1), (1, 1), (1, 1), false, cx),
+            conv: Conv2D::new(num_classes, 1, (1, 1), (1, 1), (1, 1), (0, 0), 1, false, cx),
             num_classes,
         }
     }
@@ -506,7 +514,7 @@ impl DetectionHead {
         (
             ConvBlock::new(filter, c1, (3, 3), (1, 1), (1, 1), cx),
             ConvBlock::new(c1, c1, (3, 3), (1, 1), (1, 1), cx),
-            Conv2D::new(c1, nc, (1, 1), (1, 1), (1, 1), true, cx),
+            Conv2D::new(c1, nc, (1, 1), (1, 1), (1, 1), (0, 0), 1, true, cx),
         )
     }
 
@@ -519,7 +527,7 @@ impl DetectionHead {
         (
             ConvBlock::new(filter, c2, (3, 3), (1, 1), (1, 1), cx),
             ConvBlock::new(c2, c2, (3, 3), (1, 1), (1, 1), cx),
-            Conv2D::new(c2, 4 * ch, (1, 1), (1, 1), (1, 1), true, cx),
+            Conv2D::new(c2, 4 * ch, (1, 1), (1, 1), (1, 1), (0, 0), 1, true, cx),
         )
     }
 }
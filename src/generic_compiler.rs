@@ -9,7 +9,10 @@ use petgraph::{
 };
 
 use crate::{
-    op::{Add, Constant, ConstantValue, Function, MaxReduce, Mul, Operator, Recip, SumReduce},
+    op::{
+        Add, Constant, ConstantValue, Function, Gather, MaxReduce, Mul, Operator, Recip, Scan,
+        SumReduce,
+    },
     prelude::*,
 };
 
@@ -28,12 +31,12 @@ pub struct CSE;
 impl Compiler for CSE {
     type Output = ();
     fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut ids: T) {
-        // Look for nodes that have the exact same srcs
+        // Look for nodes that have the exact same srcs (including which output of each src they use)
         // Loop cause I'm lazy
         let mut eliminated = true;
         while eliminated {
             eliminated = false;
-            let mut srcs_set: HashMap<Vec<NodeIndex>, Vec<NodeIndex>> = HashMap::new();
+            let mut srcs_set: HashMap<Vec<(NodeIndex, u8)>, Vec<NodeIndex>> = HashMap::new();
             for node in graph.graph.node_indices().collect_vec() {
                 if graph
                     .graph
@@ -49,7 +52,7 @@ impl Compiler for CSE {
                     .edges_directed(node, petgraph::Direction::Incoming)
                     .filter(|e| !e.weight().is_schedule())
                     .sorted_by_key(|e| e.weight().as_data().unwrap().0)
-                    .map(|e| e.source())
+                    .map(|e| (e.source(), e.weight().as_data().unwrap().1))
                     .collect_vec();
 
                 if let Some(other_nodes) = srcs_set.get(&srcs) {
@@ -176,6 +179,50 @@ impl Compiler for RemoveUnusedNodes {
     }
 }
 
+/// Lower scans into pooling and reductions, for backends without a dedicated scan kernel
+#[derive(Default, Debug)]
+pub struct DecomposeScans;
+
+impl Compiler for DecomposeScans {
+    type Output = ();
+    fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut ids: T) {
+        for node in graph.node_indices().collect_vec() {
+            let Some(scan) = graph.try_get_op::<Scan>(node).cloned() else {
+                continue;
+            };
+            let (src, out, shape) = graph.get_sources(node)[0];
+            let decomposed = GraphTensor::from_output(src, out, shape, graph)
+                .decompose_scan(scan.axis, scan.kind);
+            move_outgoing_edge(node, decomposed.id, graph);
+            remap(node, decomposed.id, &mut ids, graph);
+            graph.remove_node(node);
+        }
+    }
+}
+
+/// Lower gathers into one-hot reductions, for backends without a dedicated gather kernel
+#[derive(Default, Debug)]
+pub struct DecomposeGathers;
+
+impl Compiler for DecomposeGathers {
+    type Output = ();
+    fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut ids: T) {
+        for node in graph.node_indices().collect_vec() {
+            let Some(gather) = graph.try_get_op::<Gather>(node).cloned() else {
+                continue;
+            };
+            let srcs = graph.get_sources(node);
+            let ((ind, ind_out, ind_shape), (src, src_out, src_shape)) = (srcs[0], srcs[1]);
+            let indices = GraphTensor::from_output(ind, ind_out, ind_shape, graph);
+            let decomposed = GraphTensor::from_output(src, src_out, src_shape, graph)
+                .decompose_gather(gather.axis, indices);
+            move_outgoing_edge(node, decomposed.id, graph);
+            remap(node, decomposed.id, &mut ids, graph);
+            graph.remove_node(node);
+        }
+    }
+}
+
 /// Enforce the graph gets ran in strictly depth-first order
 #[derive(Default, Debug)]
 pub struct DepthFirst;
@@ -34,6 +34,62 @@ pub struct Graph {
     pub linearized_graph: Option<Vec<(NodeIndex, Vec<(NodeIndex, u8, ShapeTracker)>)>>,
     /// Cached consumers (for execution only)
     pub consumers_map: Option<FxHashMap<(NodeIndex, u8), usize>>,
+    /// Memory usage of the last execution
+    pub memory_stats: MemoryStats,
+}
+
+/// Memory usage of an execution of the graph
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes of output buffers newly allocated by ops
+    pub allocated_bytes: usize,
+    /// Bytes of outputs written in-place into an input buffer rather than a new allocation
+    pub reused_bytes: usize,
+    /// Number of outputs written in-place into an input buffer
+    pub in_place_outputs: usize,
+    /// Most bytes held by tensors at any one time
+    pub peak_bytes: usize,
+    /// Bytes currently held by tensors
+    live_bytes: usize,
+}
+
+impl MemoryStats {
+    fn new(tensors: &FxHashMap<(NodeIndex, u8), Tensor>) -> Self {
+        let live_bytes = tensors.values().map(|t| t.size_bytes()).sum();
+        Self {
+            peak_bytes: live_bytes,
+            live_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Record the outputs of an op, given the (address, size) of the owned input buffers it consumed
+    fn record(&mut self, consumed: &[(*const u8, usize)], outputs: &[Tensor]) {
+        for output in outputs {
+            let bytes = output.size_bytes();
+            if bytes > 0 && consumed.iter().any(|(p, _)| *p == output.buffer_ptr()) {
+                self.reused_bytes += bytes;
+                self.in_place_outputs += 1;
+            } else {
+                self.allocated_bytes += bytes;
+            }
+            self.live_bytes += bytes;
+        }
+        self.live_bytes = self
+            .live_bytes
+            .saturating_sub(consumed.iter().map(|(_, b)| b).sum());
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+    }
+}
+
+/// Get the (address, size) of the input buffers an op takes ownership of
+fn owned_buffers(srcs: &[(InputTensor, ShapeTracker)]) -> Vec<(*const u8, usize)> {
+    srcs.iter()
+        .filter_map(|(t, _)| match t {
+            InputTensor::Owned(t) => Some((t.buffer_ptr(), t.size_bytes())),
+            InputTensor::Borrowed(_) => None,
+        })
+        .collect()
 }
 
 /// A dependency between two nodes
@@ -125,6 +181,7 @@ impl Graph {
                 format!("{name} Load"),
                 Box::new(move |_| panic!("You must set a value for this tensor! ({name})")),
             ))),
+            output: 0,
             graph_ref: self,
             shape: ShapeTracker::new(shape),
         }
@@ -196,6 +253,7 @@ impl Graph {
         }
         let mut consumers = self.consumers_map.as_ref().unwrap().clone();
         let mut dim_stack = Vec::new();
+        self.memory_stats = MemoryStats::new(&self.tensors);
 
         for (node, src_ids) in self.linearized_graph.as_ref().unwrap() {
             if self.tensors.contains_key(&(*node, 0)) {
@@ -211,7 +269,9 @@ impl Graph {
             }
 
             // Execute
+            let consumed = owned_buffers(&srcs);
             let tensors = self.graph.node_weight_mut(*node).unwrap().process(srcs);
+            self.memory_stats.record(&consumed, &tensors);
             for (i, tensor) in tensors.into_iter().enumerate() {
                 self.tensors.insert((*node, i as u8), tensor);
             }
@@ -231,6 +291,7 @@ impl Graph {
             self.toposort();
         }
         let mut dim_stack = Vec::new();
+        self.memory_stats = MemoryStats::new(&self.tensors);
         for (node, src_ids) in self.linearized_graph.as_ref().unwrap().iter() {
             if self.tensors.contains_key(&(*node, 0)) {
                 continue;
@@ -252,6 +313,7 @@ impl Graph {
 
             // All sources are ready, execute
             let tensors = self.graph.node_weight_mut(*node).unwrap().process(srcs);
+            self.memory_stats.record(&[], &tensors);
             for (i, tensor) in tensors.into_iter().enumerate() {
                 self.tensors.insert((*node, i as u8), tensor);
             }
@@ -269,6 +331,17 @@ impl Graph {
                 format!("{}µs", duration.as_micros())
             }
         }
+        fn format_bytes(bytes: usize) -> String {
+            if bytes >= 1 << 30 {
+                format!("{:.2}GB", bytes as f32 / (1 << 30) as f32)
+            } else if bytes >= 1 << 20 {
+                format!("{:.2}MB", bytes as f32 / (1 << 20) as f32)
+            } else if bytes >= 1 << 10 {
+                format!("{:.2}KB", bytes as f32 / (1 << 10) as f32)
+            } else {
+                format!("{bytes}B")
+            }
+        }
         // Track the number of views pointing to each tensor so we know when to clear
         if self.linearized_graph.is_none() {
             self.toposort();
@@ -276,6 +349,7 @@ impl Graph {
         let mut dim_stack = Vec::new();
         let mut consumers = self.consumers_map.as_ref().unwrap().clone();
         let mut op_times = FxHashMap::default();
+        self.memory_stats = MemoryStats::new(&self.tensors);
         let width = term_size::dimensions().unwrap().0;
 
         println!(
@@ -319,9 +393,11 @@ impl Graph {
             print!("{shapes_string}");
             std::io::stdout().flush().unwrap();
             // Execute
+            let consumed = owned_buffers(&srcs);
             let now = std::time::Instant::now();
             let tensors = self.graph.node_weight_mut(*node).unwrap().process(srcs);
             let elapsed = now.elapsed();
+            self.memory_stats.record(&consumed, &tensors);
             println!(
                 "{:.>1$}",
                 format_duration(&elapsed).bold(),
@@ -362,6 +438,12 @@ impl Graph {
             );
         }
         println!("Total: {}", format_duration(&start.elapsed()).bold());
+        println!(
+            "Memory: {} allocated, {} reused in-place, {} peak",
+            format_bytes(self.memory_stats.allocated_bytes).bold(),
+            format_bytes(self.memory_stats.reused_bytes).bold(),
+            format_bytes(self.memory_stats.peak_bytes).bold()
+        );
         self.reset();
     }
 }
@@ -17,6 +17,8 @@ use petgraph::graph::NodeIndex;
 #[derive(Clone, Copy)]
 pub struct GraphTensor {
     pub id: NodeIndex,
+    /// Which output of the node this tensor refers to
+    pub output: u8,
     pub graph_ref: *mut Graph,
     pub shape: ShapeTracker,
 }
@@ -24,8 +26,19 @@ pub struct GraphTensor {
 impl GraphTensor {
     /// Create a GraphTensor from a NodeIndex
     pub fn from_id(id: NodeIndex, shape: ShapeTracker, graph_ref: *mut Graph) -> Self {
+        Self::from_output(id, 0, shape, graph_ref)
+    }
+
+    /// Create a GraphTensor from one of the outputs of a node with multiple outputs
+    pub fn from_output(
+        id: NodeIndex,
+        output: u8,
+        shape: ShapeTracker,
+        graph_ref: *mut Graph,
+    ) -> Self {
         Self {
             id,
+            output,
             graph_ref,
             shape,
         }
@@ -40,7 +53,9 @@ impl GraphTensor {
     /// Mark this tensor to be retrieved later
     pub fn retrieve(self) -> Self {
         self.keep();
-        self.graph().to_retrieve.insert(self.id, (0, self.shape));
+        self.graph()
+            .to_retrieve
+            .insert(self.id, (self.output, self.shape));
         self
     }
 
@@ -84,7 +99,7 @@ impl GraphTensor {
     pub fn data(&self) -> Vec<f32> {
         let tensor = self
             .graph()
-            .get_tensor_ref(self.id, 0)
+            .get_tensor_ref(self.id, self.output)
             .expect("Tensor not found in the graph!");
         let orig_data = tensor
             .downcast_ref::<Vec<f32>>()
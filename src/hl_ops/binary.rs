@@ -25,8 +25,8 @@ impl Add for GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Add)
-            .input(self.id, 0, self.shape)
-            .input(rhs.id, 0, rhs.shape)
+            .input(self.id, self.output, self.shape)
+            .input(rhs.id, rhs.output, rhs.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -80,8 +80,8 @@ impl Mul for GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Mul)
-            .input(self.id, 0, self.shape)
-            .input(rhs.id, 0, rhs.shape)
+            .input(self.id, self.output, self.shape)
+            .input(rhs.id, rhs.output, rhs.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -133,8 +133,8 @@ impl Rem<GraphTensor> for GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Mod)
-            .input(self.id, 0, self.shape)
-            .input(rhs.id, 0, rhs.shape)
+            .input(self.id, self.output, self.shape)
+            .input(rhs.id, rhs.output, rhs.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -234,8 +234,8 @@ impl GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::LessThan)
-            .input(self.id, 0, self.shape)
-            .input(rhs.id, 0, rhs.shape)
+            .input(self.id, self.output, self.shape)
+            .input(rhs.id, rhs.output, rhs.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -260,17 +260,110 @@ impl GraphTensor {
         -self.not_equals(rhs) + 1.0
     }
 
-    /// Raise the tensor to a power
-    pub fn pow<T>(self, e: T) -> GraphTensor
-    where
-        Self: Mul<T, Output = Self>,
-    {
-        // Approximate, see full impl here: https://github.com/tinygrad/tinygrad/blob/a32c67760140dd26b60d7932268f2e62e96a66e0/tinygrad/tensor.py#L568
-        self.abs().ln().mul(e).exp()
+    /// Raise the tensor to a power, which can be a float, an expression or another tensor.
+    /// Negative bases give NaN unless the exponent is an integer, and anything to the power of 0 is 1.
+    pub fn pow(self, e: impl Exponent) -> GraphTensor {
+        e.raise(self)
+    }
+
+    /// Raise the tensor to a constant power, which lets common powers skip the log and exp
+    fn pow_f32(self, e: f32) -> GraphTensor {
+        if e == 0. {
+            self.graph().constant(1.).expand_to(self.shape)
+        } else if e == 1. {
+            self
+        } else if e == 2. {
+            self * self
+        } else if e == 0.5 {
+            self.sqrt()
+        } else if e == -1. {
+            self.recip()
+        } else if e.fract() != 0. {
+            (self.ln() * e).exp()
+        } else {
+            let mag = (self.abs().ln() * e).exp();
+            if e % 2. == 0. {
+                mag
+            } else {
+                let zero = self.graph().constant(0.).expand_to(self.shape);
+                (-mag).where_(self.less_than(zero), mag)
+            }
+        }
+    }
+
+    fn pow_tensor(self, e: GraphTensor) -> GraphTensor {
+        let (zero, one) = (
+            self.graph().constant(0.).expand_to(self.shape),
+            self.graph().constant(1.).expand_to(self.shape),
+        );
+        // Only take the log of a negative base when the result is meant to be NaN, so NaNs don't
+        // leak into gradients of the other branches
+        let fractional = e.not_equals(e.trunc());
+        let mag = (self.where_(fractional, self.abs()).ln() * e).exp();
+        let flip_sign = self.less_than(zero) * (e % 2.).not_equals(zero);
+        one.where_(e.equals(zero), (-mag).where_(flip_sign, mag))
+    }
+
+    /// The four-quadrant arctangent of `self / x` in radians, with `self` as the y coordinate.
+    /// Uses a degree 11 polynomial, which has a max error of around 2e-6 radians.
+    #[allow(clippy::excessive_precision)]
+    pub fn atan2(self, x: GraphTensor) -> GraphTensor {
+        assert_eq!(self.dims(), x.dims(), "Dims must match to atan2 tensors.");
+        let zero = self.graph().constant(0.).expand_to(self.shape);
+        let (abs_y, abs_x) = (self.abs(), x.abs());
+        // Reduce to atan(a) with a in [0, 1]
+        let a = abs_y.min(abs_x) / abs_y.max(abs_x).max_f32(f32::MIN_POSITIVE);
+        let s = a * a;
+        let mut out = (((((s * -0.0117212 + 0.05265332) * s - 0.11643287) * s + 0.19354346) * s
+            - 0.33262347)
+            * s
+            + 0.99997726)
+            * a;
+        out = (std::f32::consts::FRAC_PI_2 - out).where_(abs_x.less_than(abs_y), out);
+        out = (std::f32::consts::PI - out).where_(x.less_than(zero), out);
+        (-out).where_(self.less_than(zero), out)
     }
 }
 
-// Clipping ops (min, max, clip)
+// Selection ops (where, masked_fill)
+impl GraphTensor {
+    /// Take elements of this tensor where `cond` is nonzero, and elements of `other` elsewhere.
+    /// Unlike `cond * a + (1 - cond) * b`, infinities and NaNs in the unselected tensor don't leak
+    /// into the output.
+    pub fn where_(self, cond: GraphTensor, other: GraphTensor) -> GraphTensor {
+        // Symbolic dims can be equal without matching structurally, so only check the static ones
+        for t in [cond, other] {
+            assert_eq!(
+                self.shape.len(),
+                t.shape.len(),
+                "Dims must match to select tensors."
+            );
+            for (a, b) in self.dims().into_iter().zip(t.dims()) {
+                if let (Some(a), Some(b)) = (a.to_usize(), b.to_usize()) {
+                    assert_eq!(a, b, "Dims must match to select tensors.");
+                }
+            }
+        }
+        let new_id = self
+            .graph()
+            .add_op(op::Where)
+            .input(cond.id, cond.output, cond.shape)
+            .input(self.id, self.output, self.shape)
+            .input(other.id, other.output, other.shape)
+            .finish();
+        GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
+    }
+
+    /// Replace elements with `value` wherever `mask` is nonzero
+    pub fn masked_fill(self, mask: GraphTensor, value: f32) -> GraphTensor {
+        self.graph()
+            .constant(value)
+            .expand_to(self.shape)
+            .where_(mask, self)
+    }
+}
+
+// Clipping ops (min, max, clip, clamp)
 impl GraphTensor {
     /// Take the elementwise maximum of two tensors
     pub fn max(self, rhs: GraphTensor) -> GraphTensor {
@@ -296,6 +389,13 @@ impl GraphTensor {
     pub fn clip(self, min: f32, max: f32) -> GraphTensor {
         self.max_f32(min).min_f32(max)
     }
+
+    /// Clamp a tensor elementwise into the range given by the tensors `min` and `max`
+    pub fn clamp(self, min: GraphTensor, max: GraphTensor) -> GraphTensor {
+        // Select rather than mask so infinite bounds don't turn into NaNs
+        let lower = min.where_(self.less_than(min), self);
+        max.where_(max.less_than(lower), lower)
+    }
 }
 
 pub trait F32Pow {
@@ -303,13 +403,43 @@ pub trait F32Pow {
 }
 
 impl F32Pow for f32 {
+    /// Raise a float to a tensor power, agreeing with `GraphTensor::pow`
     fn pow(self, e: GraphTensor) -> GraphTensor {
-        e.mul(self.abs().ln()).exp().recip()
+        if self > 0. {
+            // Positive bases don't need any sign handling
+            (e * self.ln()).exp()
+        } else {
+            e.graph().constant(self).expand_to(e.shape).pow(e)
+        }
+    }
+}
+
+/// Anything a tensor can be raised to the power of
+pub trait Exponent {
+    fn raise(self, base: GraphTensor) -> GraphTensor;
+}
+
+impl Exponent for f32 {
+    fn raise(self, base: GraphTensor) -> GraphTensor {
+        base.pow_f32(self)
+    }
+}
+
+impl Exponent for GraphTensor {
+    fn raise(self, base: GraphTensor) -> GraphTensor {
+        base.pow_tensor(self)
+    }
+}
+
+impl<S: Into<Expression>> Exponent for S {
+    fn raise(self, base: GraphTensor) -> GraphTensor {
+        base.pow_tensor(base.graph().constant(1.).expand_to(base.shape) * self)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::F32Pow;
     crate::test_imports!();
 
     #[test]
@@ -327,4 +457,144 @@ mod tests {
 
         assert_close(&result.data(), &expected_result.data());
     }
+
+    #[test]
+    fn test_where() {
+        let mut cx = Graph::new();
+        let cond = cx.tensor(4).set(vec![1., 0., 1., 0.]);
+        let a = cx.tensor(4).set(vec![1., f32::INFINITY, 3., f32::NAN]);
+        let b = cx.tensor(4).set(vec![f32::NEG_INFINITY, 6., f32::NAN, 8.]);
+        let selected = a.where_(cond, b).retrieve();
+        cx.execute();
+
+        assert_exact(&selected.data(), &[1., 6., 3., 8.]);
+    }
+
+    #[test]
+    fn test_where_symbolic_dims() {
+        let mut cx = Graph::new();
+        let n = Expression::from('n');
+        let cond = cx.tensor(n).set_dyn(vec![1., 0., 1.], 3);
+        // Equal to `n`, but not structurally
+        let a = cx.tensor((n + 2) - 2).set_dyn(vec![1., 2., 3.], 3);
+        let b = cx.tensor(n).set_dyn(vec![4., 5., 6.], 3);
+        let selected = a.where_(cond, b).retrieve();
+        cx.set_dyn_dim('n', 3);
+        cx.execute();
+
+        assert_exact(&selected.data(), &[1., 5., 3.]);
+    }
+
+    #[test]
+    fn test_masked_fill() {
+        let mut cx = Graph::new();
+        let scores = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let mask = cx.triu(3, 1).slice((..2, ..));
+        let filled = scores.masked_fill(mask, f32::NEG_INFINITY).retrieve();
+        let weights = filled.softmax(1).retrieve();
+        cx.execute();
+
+        assert_exact(
+            &filled.data(),
+            &[
+                1.,
+                f32::NEG_INFINITY,
+                f32::NEG_INFINITY,
+                4.,
+                5.,
+                f32::NEG_INFINITY,
+            ],
+        );
+        let e = 1_f32.exp();
+        assert_close(
+            &weights.data(),
+            &[1., 0., 0., 1. / (1. + e), e / (1. + e), 0.],
+        );
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut cx = Graph::new();
+        let a = cx.tensor(4).set(vec![-3., 0.5, 2., f32::INFINITY]);
+        let min = cx.tensor(4).set(vec![-1., 0., 0., f32::NEG_INFINITY]);
+        let max = cx.tensor(4).set(vec![1., 0.25, 5., 10.]);
+        let result = a.clamp(min, max).retrieve();
+        cx.execute();
+
+        assert_exact(&result.data(), &[-1., 0.25, 2., 10.]);
+    }
+
+    #[test]
+    fn test_pow() {
+        let mut cx = Graph::new();
+        let a = cx.tensor(6).set(vec![2., -2., -2., 0., 0., -3.]);
+        let e = cx.tensor(6).set(vec![0.5, 3., 2., 2., 0., 0.5]);
+        let tensor_pow = a.pow(e).retrieve();
+        let float_pows = [3., 2., 0.5, -1., 4., -3., 0.]
+            .map(|e| a.pow(e).retrieve())
+            .to_vec();
+        let float_bases = [2_f32, -2., 0.].map(|b| {
+            (
+                b.pow(e).retrieve(),
+                cx.constant(b).expand_to(e.shape).pow(e).retrieve(),
+            )
+        });
+        cx.execute();
+
+        let out = tensor_pow.data();
+        assert_close(&out[..5], &[2_f32.sqrt(), -8., 4., 0., 1.]);
+        assert!(out[5].is_nan());
+        let a = [2_f32, -2., -2., 0., 0., -3.];
+        for (e, pow) in [3., 2., 0.5, -1., 4., -3., 0.].into_iter().zip(float_pows) {
+            for (x, y) in a.iter().zip(pow.data()) {
+                let expected = x.powf(e);
+                assert!(
+                    expected == y
+                        || (expected.is_nan() && y.is_nan())
+                        || (expected - y).abs() < 1e-3,
+                    "{x}^{e} should be {expected}, got {y}"
+                );
+            }
+        }
+        // Float bases agree with constant tensor bases
+        for (b, (f32_base, tensor_base)) in [2_f32, -2., 0.].into_iter().zip(float_bases) {
+            for ((x, y), e) in f32_base
+                .data()
+                .into_iter()
+                .zip(tensor_base.data())
+                .zip([0.5, 3., 2., 2., 0., 0.5])
+            {
+                let expected = b.powf(e);
+                assert!(
+                    (expected.is_nan() && x.is_nan() && y.is_nan())
+                        || ((expected - x).abs() < 1e-3 && (expected - y).abs() < 1e-3),
+                    "{b}^{e} should be {expected}, got {x} and {y}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_atan2() {
+        let mut cx = Graph::new();
+        let y_data = random_vec(16)
+            .into_iter()
+            .chain([0., 0., 1., -1., 3., -3.])
+            .collect::<Vec<_>>();
+        let x_data = random_vec(16)
+            .into_iter()
+            .chain([0., -1., 0., 0., -1e-3, -2.])
+            .collect::<Vec<_>>();
+        let y = cx.tensor(y_data.len()).set(y_data.clone());
+        let x = cx.tensor(x_data.len()).set(x_data.clone());
+        let out = y.atan2(x).retrieve();
+        cx.execute();
+
+        let expected = y_data
+            .iter()
+            .zip(&x_data)
+            .map(|(y, x)| y.atan2(*x))
+            .collect::<Vec<_>>();
+        assert_close_precision(&out.data(), &expected, 1e-5);
+    }
 }
@@ -0,0 +1,423 @@
+use rustc_hash::FxHashMap;
+
+use crate::prelude::*;
+
+impl GraphTensor {
+    /// Contract tensors according to an einsum equation, like `"bhqd,bhkd->bhqk"`.
+    ///
+    /// Each operand is labelled with one letter per dimension. Letters shared between operands
+    /// are multiplied together, and letters missing from the output are summed over. If the
+    /// output is left out (no `->`), it's the letters appearing exactly once, in alphabetical
+    /// order. A letter repeated within an operand takes its diagonal.
+    ///
+    /// Operands are contracted pairwise, smallest result first, and each contraction is laid out
+    /// as a (batched) matmul where possible so backend matmul compilers can pick it up.
+    pub fn einsum(equation: &str, tensors: &[GraphTensor]) -> GraphTensor {
+        let (inputs, output) = parse_equation(equation, tensors.len());
+
+        // Check every letter has one size
+        let mut sizes = FxHashMap::<char, Expression>::default();
+        for (i, (letters, tensor)) in inputs.iter().zip(tensors).enumerate() {
+            let dims = tensor.dims();
+            assert_eq!(
+                letters.len(),
+                dims.len(),
+                "Einsum operand {i} has subscripts '{}' but {} dimensions",
+                letters.iter().collect::<String>(),
+                dims.len()
+            );
+            for (c, d) in letters.iter().zip(dims) {
+                if let Some(size) = sizes.get(c) {
+                    assert!(
+                        *size == d,
+                        "Einsum subscript '{c}' has size {size} but is {d} in operand {i}"
+                    );
+                } else {
+                    sizes.insert(*c, d);
+                }
+            }
+        }
+
+        let mut operands = tensors
+            .iter()
+            .zip(inputs)
+            .map(|(t, letters)| take_diagonals(*t, letters))
+            .collect::<Vec<_>>();
+        // Sum out letters only used by a single operand up front
+        for i in 0..operands.len() {
+            let keep = needed_letters(&operands, &output, &[i]);
+            operands[i] = sum_out(operands[i].0, &operands[i].1, &keep);
+        }
+
+        while operands.len() > 1 {
+            // Contract the pair with the smallest result
+            let mut best = None;
+            for i in 0..operands.len() {
+                for j in i + 1..operands.len() {
+                    let keep = needed_letters(&operands, &output, &[i, j]);
+                    let size = operands[i]
+                        .1
+                        .iter()
+                        .chain(operands[j].1.iter().filter(|c| !operands[i].1.contains(c)))
+                        .filter(|c| keep.contains(c))
+                        .map(|c| sizes[c].to_usize().unwrap_or(usize::MAX))
+                        .fold(1_usize, |acc, s| acc.saturating_mul(s));
+                    if best.map(|(_, _, s)| size < s).unwrap_or(true) {
+                        best = Some((i, j, size));
+                    }
+                }
+            }
+            let (i, j, _) = best.unwrap();
+            let keep = needed_letters(&operands, &output, &[i, j]);
+            let b = operands.remove(j);
+            let a = operands.remove(i);
+            operands.insert(i, contract(a, b, &keep));
+        }
+
+        let (result, letters) = operands.pop().unwrap();
+        let (result, letters) = sum_out(result, &letters, &output);
+        if letters.is_empty() {
+            return result;
+        }
+        result.permute(
+            output
+                .iter()
+                .map(|c| letters.iter().position(|l| l == c).unwrap())
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Split an einsum equation into the letters of each operand and of the output
+fn parse_equation(equation: &str, n_tensors: usize) -> (Vec<Vec<char>>, Vec<char>) {
+    assert!(
+        !equation.contains("..."),
+        "Ellipses aren't supported in einsum equations: '{equation}'"
+    );
+    let eq = equation
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let (lhs, rhs) = match eq.split_once("->") {
+        Some((lhs, rhs)) => (lhs, Some(rhs)),
+        None => (eq.as_str(), None),
+    };
+    let to_letters = |s: &str| {
+        s.chars()
+            .inspect(|c| {
+                assert!(
+                    c.is_ascii_alphabetic(),
+                    "Invalid character '{c}' in einsum equation '{equation}'"
+                );
+            })
+            .collect::<Vec<_>>()
+    };
+    let inputs = lhs.split(',').map(to_letters).collect::<Vec<_>>();
+    assert_eq!(
+        inputs.len(),
+        n_tensors,
+        "Einsum equation '{equation}' has {} operands but {n_tensors} tensors were given",
+        inputs.len()
+    );
+    let count = |c: &char| inputs.iter().flatten().filter(|l| *l == c).count();
+    let output = if let Some(rhs) = rhs {
+        let output = to_letters(rhs);
+        for (i, c) in output.iter().enumerate() {
+            assert!(
+                count(c) > 0,
+                "Output subscript '{c}' doesn't appear in the operands of einsum equation '{equation}'"
+            );
+            assert!(
+                !output[..i].contains(c),
+                "Output subscript '{c}' is repeated in einsum equation '{equation}'"
+            );
+        }
+        output
+    } else {
+        let mut output = inputs
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|c| count(c) == 1)
+            .collect::<Vec<_>>();
+        output.sort();
+        output
+    };
+    (inputs, output)
+}
+
+/// Letters which can't be summed out yet, because they're in the output or used by an operand
+/// other than the ones given
+fn needed_letters(
+    operands: &[(GraphTensor, Vec<char>)],
+    output: &[char],
+    skip: &[usize],
+) -> Vec<char> {
+    operands
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !skip.contains(i))
+        .flat_map(|(_, (_, letters))| letters)
+        .chain(output)
+        .copied()
+        .collect()
+}
+
+/// Take the diagonal over any letter repeated within an operand
+fn take_diagonals(mut tensor: GraphTensor, mut letters: Vec<char>) -> (GraphTensor, Vec<char>) {
+    while let Some((p, q)) = (0..letters.len()).find_map(|q| {
+        letters[..q]
+            .iter()
+            .position(|c| *c == letters[q])
+            .map(|p| (p, q))
+    }) {
+        // Mask out everything off the diagonal, then sum over the repeated dimension
+        let dims = tensor.dims();
+        let along = |axis: usize| {
+            let mut t = tensor.graph().arange(dims[axis]);
+            for (i, d) in dims.iter().enumerate() {
+                if i != axis {
+                    t = t.expand(i, *d);
+                }
+            }
+            t
+        };
+        tensor = (tensor * along(p).equals(along(q))).sum_reduce(q);
+        letters.remove(q);
+    }
+    (tensor, letters)
+}
+
+/// Sum over every letter not in `keep`
+fn sum_out(tensor: GraphTensor, letters: &[char], keep: &[char]) -> (GraphTensor, Vec<char>) {
+    let axes = (0..letters.len())
+        .filter(|i| !keep.contains(&letters[*i]))
+        .collect::<Vec<_>>();
+    if axes.is_empty() {
+        return (tensor, letters.to_vec());
+    }
+    (
+        tensor.sum_reduce(axes),
+        letters
+            .iter()
+            .copied()
+            .filter(|c| keep.contains(c))
+            .collect(),
+    )
+}
+
+/// Contract two operands, keeping only the letters in `keep`. The result is laid out as
+/// (batch, lhs only, rhs only).
+fn contract(
+    (a, a_letters): (GraphTensor, Vec<char>),
+    (b, b_letters): (GraphTensor, Vec<char>),
+    keep: &[char],
+) -> (GraphTensor, Vec<char>) {
+    let (a, a_letters) = sum_out(a, &a_letters, &[keep, &b_letters[..]].concat());
+    let (b, b_letters) = sum_out(b, &b_letters, &[keep, &a_letters[..]].concat());
+    let (mut batch, mut m, mut k) = (vec![], vec![], vec![]);
+    for c in &a_letters {
+        match (b_letters.contains(c), keep.contains(c)) {
+            (true, true) => batch.push(*c),
+            (true, false) => k.push(*c),
+            (false, _) => m.push(*c),
+        }
+    }
+    let n = b_letters
+        .iter()
+        .copied()
+        .filter(|c| !a_letters.contains(c))
+        .collect::<Vec<_>>();
+
+    let all_letters = [a_letters.as_slice(), &b_letters].concat();
+    let dims = a.dims().into_iter().chain(b.dims()).collect::<Vec<_>>();
+    let position = |letters: &[char], c: &char| letters.iter().position(|l| l == c).unwrap();
+    let size = |letters: &[char]| {
+        letters.iter().fold(Expression::from(1), |acc, c| {
+            acc * dims[position(&all_letters, c)]
+        })
+    };
+
+    // Lay out as [batch, M, K] x [batch, K, N]
+    let axes = |letters: &[char], order: &[&[char]]| {
+        order
+            .concat()
+            .iter()
+            .map(|c| position(letters, c))
+            .collect::<Vec<_>>()
+    };
+    let mut a = a.permute(axes(&a_letters, &[&batch, &m, &k]));
+    let mut b = b.permute(axes(&b_letters, &[&batch, &k, &n]));
+    // Merge groups of letters so this is a single matmul
+    let merge = [&batch, &m, &k, &n].iter().any(|g| g.len() > 1);
+    if merge {
+        let merged = |groups: &[&Vec<char>]| {
+            groups
+                .iter()
+                .filter(|g| !g.is_empty())
+                .map(|g| size(g.as_slice()))
+                .collect::<Vec<_>>()
+        };
+        a = a.reshape(merged(&[&batch, &m, &k]));
+        b = b.reshape(merged(&[&batch, &k, &n]));
+    }
+
+    let [n_batch, n_m, n_k, n_n] =
+        [&batch, &m, &k, &n].map(|g| if merge { g.len().min(1) } else { g.len() });
+    let out = if n_m == 1 && n_k == 1 && n_n == 1 {
+        a.matmul(b)
+    } else {
+        // Broadcasted multiply to [batch, M, N, K], then sum over K
+        let b_dims = b.dims();
+        let mut b_axes = (0..b_dims.len()).collect::<Vec<_>>();
+        b_axes[n_batch..].rotate_left(n_k);
+        let mut b = b.permute(b_axes);
+        let mut a = a;
+        for i in 0..n_n {
+            a = a.expand(n_batch + n_m + i, b_dims[n_batch + n_k + i]);
+        }
+        let a_dims = a.dims();
+        for i in 0..n_m {
+            b = b.expand(n_batch + i, a_dims[n_batch + i]);
+        }
+        let mul = a * b;
+        if n_k == 0 {
+            mul
+        } else {
+            let rank = n_batch + n_m + n_n + n_k;
+            mul.sum_reduce((rank - n_k..rank).collect::<Vec<_>>())
+        }
+    };
+
+    let letters = [batch, m, n].concat();
+    if merge {
+        (
+            out.reshape(letters.iter().map(|c| size(&[*c])).collect::<Vec<_>>()),
+            letters,
+        )
+    } else {
+        (out, letters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    crate::test_imports!();
+
+    #[test]
+    fn test_einsum_matmul() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(random_vec(6));
+        let b = cx.tensor((3, 4)).set(random_vec(12));
+        let c = cx.tensor((4, 2)).set(random_vec(8));
+        let einsum = GraphTensor::einsum("ij,jk->ik", &[a, b]).retrieve();
+        let implicit = GraphTensor::einsum("ij,jk", &[a, b]).retrieve();
+        let transposed = GraphTensor::einsum("ij,jk->ki", &[a, b]).retrieve();
+        let chain = GraphTensor::einsum("ij, jk, kl -> il", &[a, b, c]).retrieve();
+        let matmul = a.matmul(b).retrieve();
+        let matmul_t = a.matmul(b).permute((1, 0)).contiguous().retrieve();
+        let chain_matmul = a.matmul(b).matmul(c).retrieve();
+        cx.execute();
+
+        assert_close(&einsum.data(), &matmul.data());
+        assert_close(&implicit.data(), &matmul.data());
+        assert_close(&transposed.data(), &matmul_t.data());
+        assert_close(&chain.data(), &chain_matmul.data());
+    }
+
+    #[test]
+    fn test_einsum_attention() {
+        let mut cx = Graph::new();
+        let (q_data, k_data, v_data) = (random_vec(120), random_vec(180), random_vec(180));
+        let q = cx.tensor((2, 3, 4, 5)).set(q_data.clone());
+        let k = cx.tensor((2, 3, 6, 5)).set(k_data.clone());
+        let v = cx.tensor((2, 6, 3, 5)).set(v_data.clone());
+        let scores = GraphTensor::einsum("bhqd,bhkd->bhqk", &[q, k]).retrieve();
+        let out = GraphTensor::einsum("bhqk,bkhd->bqhd", &[scores, v]).retrieve();
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_q =
+            d_dev.tensor_from_vec(q_data, (DConst::<2>, DConst::<3>, DConst::<4>, DConst::<5>));
+        let d_k =
+            d_dev.tensor_from_vec(k_data, (DConst::<2>, DConst::<3>, DConst::<6>, DConst::<5>));
+        let d_v =
+            d_dev.tensor_from_vec(v_data, (DConst::<2>, DConst::<6>, DConst::<3>, DConst::<5>));
+        let d_scores = d_q.matmul(d_k.permute::<Rank4<2, 3, 5, 6>, DAxes4<0, 1, 3, 2>>());
+        let d_out = d_scores
+            .clone()
+            .matmul(d_v.permute::<Rank4<2, 3, 6, 5>, DAxes4<0, 2, 1, 3>>())
+            .permute::<Rank4<2, 4, 3, 5>, DAxes4<0, 2, 1, 3>>();
+
+        assert_close(&scores.data(), &d_scores.as_vec());
+        assert_close(&out.data(), &d_out.as_vec());
+    }
+
+    #[test]
+    fn test_einsum_reductions() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((3, 3))
+            .set(vec![1., 2., 3., 4., 5., 6., 7., 8., 9.]);
+        let x = cx.tensor(3).set(vec![1., 2., 3.]);
+        let y = cx.tensor(2).set(vec![4., 5.]);
+        let b_data = random_vec(12);
+        let b = cx.tensor((2, 3, 2)).set(b_data.clone());
+        let trace = GraphTensor::einsum("ii->", &[a]).retrieve();
+        let diagonal = GraphTensor::einsum("ii->i", &[a]).retrieve();
+        let sum = GraphTensor::einsum("ij->", &[a]).retrieve();
+        let column_sums = GraphTensor::einsum("ij->j", &[a]).retrieve();
+        let outer = GraphTensor::einsum("i,j->ij", &[x, y]).retrieve();
+        let bilinear = GraphTensor::einsum("i,ij,j", &[x, a, x]).retrieve();
+        let batched = GraphTensor::einsum("bij,bjk->bik", &[b.permute((2, 0, 1)), b]).retrieve();
+        let merged = GraphTensor::einsum("abc,abd->cd", &[b, b]).retrieve();
+        cx.execute();
+
+        assert_exact(&trace.data(), &[15.]);
+        assert_exact(&diagonal.data(), &[1., 5., 9.]);
+        assert_exact(&sum.data(), &[45.]);
+        assert_exact(&column_sums.data(), &[12., 15., 18.]);
+        assert_exact(&outer.data(), &[4., 5., 8., 10., 12., 15.]);
+        assert_exact(&bilinear.data(), &[228.]);
+
+        let d_dev = Cpu::default();
+        let d_b = d_dev.tensor_from_vec(b_data, (DConst::<2>, DConst::<3>, DConst::<2>));
+        let d_batched = d_b
+            .clone()
+            .permute::<Rank3<2, 2, 3>, DAxes3<2, 0, 1>>()
+            .matmul(d_b.clone());
+        let d_merged = d_b
+            .clone()
+            .reshape::<Rank2<6, 2>>()
+            .permute::<Rank2<2, 6>, DAxes2<1, 0>>()
+            .matmul(d_b.reshape::<Rank2<6, 2>>());
+        assert_close(&batched.data(), &d_batched.as_vec());
+        assert_close(&merged.data(), &d_merged.as_vec());
+    }
+
+    #[test]
+    #[should_panic(expected = "Einsum subscript 'j' has size 3 but is 4 in operand 1")]
+    fn test_einsum_dim_mismatch() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3));
+        let b = cx.tensor((4, 5));
+        GraphTensor::einsum("ij,jk->ik", &[a, b]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Einsum operand 1 has subscripts 'jkl' but 2 dimensions")]
+    fn test_einsum_rank_mismatch() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3));
+        let b = cx.tensor((3, 5));
+        GraphTensor::einsum("ij,jkl->ik", &[a, b]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Output subscript 'z' doesn't appear in the operands")]
+    fn test_einsum_parse_error() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3));
+        GraphTensor::einsum("ij->iz", &[a]);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::prelude::*;
+
+/// How `GraphTensor::interpolate` fills in values between input samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpolateMode {
+    /// Take the nearest sample to the left, like PyTorch's `nearest`
+    Nearest,
+    /// Blend the two neighbouring samples along each axis (linear, bilinear or trilinear)
+    Linear,
+    /// Cubic convolution over the four neighbouring samples along each axis (bicubic), with
+    /// `a = -0.75` like PyTorch
+    Cubic,
+}
+
+impl GraphTensor {
+    /// Resize the spatial dims of a `[batch, channels, ..spatial]` tensor to `size`, with 1 to 3
+    /// spatial dims. Matches PyTorch's `interpolate`: with `align_corners` the corner samples of the
+    /// input and output line up, otherwise their outer edges do.
+    pub fn interpolate(
+        self,
+        size: impl ToShape,
+        mode: InterpolateMode,
+        align_corners: bool,
+    ) -> GraphTensor {
+        let size = size.to_shape();
+        assert!(
+            (1..=3).contains(&size.len()),
+            "Can only interpolate 1 to 3 spatial dims, got {}",
+            size.len()
+        );
+        assert_eq!(
+            self.shape.len(),
+            size.len() + 2,
+            "Interpolating {} spatial dims needs a [batch, channels, ..spatial] input",
+            size.len()
+        );
+        assert!(
+            !align_corners || mode != InterpolateMode::Nearest,
+            "align_corners only applies to linear and cubic interpolation"
+        );
+        // Resizing is separable, so do one axis at a time
+        size.into_iter().enumerate().fold(self, |x, (i, s)| {
+            x.resample_axis(i + 2, s, mode, align_corners)
+        })
+    }
+
+    fn resample_axis(
+        self,
+        axis: usize,
+        out: Expression,
+        mode: InterpolateMode,
+        align_corners: bool,
+    ) -> GraphTensor {
+        let dims = self.dims();
+        let inp = dims[axis];
+        if let (Some(i), Some(o)) = (inp.to_usize(), out.to_usize()) {
+            if i == o {
+                return self;
+            }
+        }
+        if mode == InterpolateMode::Nearest {
+            // Integer scales line up exactly, so they're just views
+            if let Some(k) = exact_ratio(out, inp) {
+                return self.repeat_interleave(axis, k);
+            }
+            if let Some(k) = exact_ratio(inp, out) {
+                let mut split = dims.clone();
+                split[axis] = out;
+                split.insert(axis + 1, k.into());
+                return self
+                    .reshape(split)
+                    .slice_along(..1, axis + 1)
+                    .squeeze(axis + 1);
+            }
+        }
+
+        let cx = self.graph();
+        let dst = cx.arange(out);
+        let last = cx.constant(inp - 1).expand(0, out);
+        let select = |index: GraphTensor| self.index_select(axis, index);
+        // Broadcast per-output weights over the rest of the output
+        let weight = |w: GraphTensor| {
+            let mut out_dims = dims.clone();
+            out_dims[axis] = out;
+            out_dims
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| *i != axis)
+                .fold(w, |w, (i, d)| w.expand(i, d))
+        };
+        match mode {
+            InterpolateMode::Nearest => {
+                let index = (dst * scale(cx, inp, out).expand(0, out)).floor();
+                select(index.min(last))
+            }
+            InterpolateMode::Linear => {
+                let src = source_index(cx, dst, inp, out, align_corners);
+                let src = if align_corners { src } else { src.max_f32(0.) };
+                let left = src.floor().min(last);
+                let right = (left + 1.).min(last);
+                let lambda = src - left;
+                select(left) * weight(1. - lambda) + select(right) * weight(lambda)
+            }
+            InterpolateMode::Cubic => {
+                const A: f32 = -0.75;
+                let src = source_index(cx, dst, inp, out, align_corners);
+                let start = src.floor();
+                let t = src - start;
+                // Cubic convolution weights for taps at distance 1 + t, t, 1 - t and 2 - t
+                let near = |x: GraphTensor| ((x * (A + 2.) - (A + 3.)) * x * x) + 1.;
+                let far = |x: GraphTensor| ((x * A - 5. * A) * x + 8. * A) * x - 4. * A;
+                let weights = [far(t + 1.), near(t), near(1. - t), far(2. - t)];
+                let zero = cx.constant(0.).expand(0, out);
+                weights
+                    .into_iter()
+                    .enumerate()
+                    .map(|(k, w)| {
+                        // Taps past the edges repeat the edge sample
+                        let index = (start + (k as f32 - 1.)).max(zero).min(last);
+                        select(index) * weight(w)
+                    })
+                    .reduce(|a, b| a + b)
+                    .unwrap()
+            }
+        }
+    }
+}
+
+/// `a / b` if it's a whole number, which can be known even for symbolic dims like `2h / h`
+fn exact_ratio(a: Expression, b: Expression) -> Option<usize> {
+    let k = (a / b).simplify().to_usize()?;
+    (k > 0 && (b * k).simplify() == a.simplify()).then_some(k)
+}
+
+/// `num / den` as a scalar, computed at build time when both are known so it rounds the same way
+/// PyTorch's scale does
+fn scale(cx: &mut Graph, num: Expression, den: Expression) -> GraphTensor {
+    if let (Some(n), Some(d)) = (num.to_usize(), den.to_usize()) {
+        cx.constant(n as f32 / d as f32)
+    } else {
+        cx.constant(num) / cx.constant(den)
+    }
+}
+
+/// Map output positions to (fractional) input positions
+fn source_index(
+    cx: &mut Graph,
+    dst: GraphTensor,
+    inp: Expression,
+    out: Expression,
+    align_corners: bool,
+) -> GraphTensor {
+    let n = dst.dims()[0];
+    if align_corners {
+        // A single output takes the first input
+        dst * scale(cx, inp - 1, (out - 1).max(1)).expand(0, n)
+    } else {
+        (dst + 0.5) * scale(cx, inp, out).expand(0, n) - 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    crate::test_imports!();
+
+    #[test]
+    fn test_interpolate_1d() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((1, 1, 4)).set(vec![0., 1., 2., 3.]);
+        let linear = a.interpolate(8, InterpolateMode::Linear, false).retrieve();
+        let aligned = a.interpolate(7, InterpolateMode::Linear, true).retrieve();
+        let down = a.interpolate(3, InterpolateMode::Linear, false).retrieve();
+        let nearest = a.interpolate(6, InterpolateMode::Nearest, false).retrieve();
+        let repeated = a.interpolate(8, InterpolateMode::Nearest, false).retrieve();
+        let strided = a.interpolate(2, InterpolateMode::Nearest, false).retrieve();
+        cx.execute();
+
+        assert_close(
+            &linear.data(),
+            &[0., 0.25, 0.75, 1.25, 1.75, 2.25, 2.75, 3.],
+        );
+        assert_close(&aligned.data(), &[0., 0.5, 1., 1.5, 2., 2.5, 3.]);
+        assert_close(&down.data(), &[1. / 6., 1.5, 17. / 6.]);
+        assert_exact(&nearest.data(), &[0., 0., 1., 2., 2., 3.]);
+        assert_exact(&repeated.data(), &[0., 0., 1., 1., 2., 2., 3., 3.]);
+        assert_exact(&strided.data(), &[0., 2.]);
+    }
+
+    #[test]
+    fn test_interpolate_nearest_views() {
+        let mut cx = Graph::new();
+        let data = random_vec(48);
+        let a = cx.tensor((2, 3, 2, 4)).set(data.clone());
+        let out = a
+            .interpolate((6, 2), InterpolateMode::Nearest, false)
+            .retrieve();
+        cx.execute();
+
+        // Integer scales don't need a gather
+        assert!(!cx
+            .graph
+            .node_indices()
+            .any(|n| cx.check_node_type::<crate::op::Gather>(n)));
+        let expected = (0..72)
+            .map(|i| {
+                let (bc, h, w) = (i / 12, i / 2 % 6, i % 2);
+                data[bc * 8 + h / 3 * 4 + w * 2]
+            })
+            .collect::<Vec<_>>();
+        assert_exact(&out.data(), &expected);
+    }
+
+    #[test]
+    fn test_interpolate_2d() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((1, 1, 2, 2)).set(vec![1., 2., 3., 4.]);
+        let bilinear = a
+            .interpolate((4, 4), InterpolateMode::Linear, false)
+            .retrieve();
+        let bicubic = a
+            .interpolate((4, 4), InterpolateMode::Cubic, false)
+            .retrieve();
+        let aligned = a
+            .interpolate((3, 1), InterpolateMode::Cubic, true)
+            .retrieve();
+        cx.execute();
+
+        assert_close(
+            &bilinear.data(),
+            &[
+                1., 1.25, 1.75, 2., //
+                1.5, 1.75, 2.25, 2.5, //
+                2.5, 2.75, 3.25, 3.5, //
+                3., 3.25, 3.75, 4.,
+            ],
+        );
+        assert_close(
+            &bicubic.data(),
+            &[
+                0.68359375, 1.015625, 1.5625, 1.8945312, //
+                1.3476562, 1.6796875, 2.2265625, 2.5585938, //
+                2.4414062, 2.7734375, 3.3203125, 3.6523438, //
+                3.1054688, 3.4375, 3.984375, 4.3164062,
+            ],
+        );
+        // Cubic weights are exact at the aligned corners, and a single column takes the first
+        assert_close(&aligned.data(), &[1., 2., 3.]);
+    }
+
+    #[test]
+    fn test_interpolate_dynamic() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((1, 2, 'a', 2, 2)).set_dyn(
+            (0..16).map(|i| i as f32).collect::<Vec<_>>(),
+            (1, 2, 2, 2, 2),
+        );
+        let b = a
+            .interpolate(('b', 4, 3), InterpolateMode::Linear, true)
+            .retrieve();
+        let c = a
+            .interpolate(('b', 1, 1), InterpolateMode::Nearest, false)
+            .retrieve();
+        let doubled = a
+            .interpolate((a.dims()[2] * 2, 2, 2), InterpolateMode::Nearest, false)
+            .retrieve();
+        assert!(!cx
+            .graph
+            .neighbors_directed(doubled.id, petgraph::Direction::Incoming)
+            .any(|n| cx.check_node_type::<crate::op::Gather>(n)));
+        cx.set_dyn_dim('b', 3);
+        cx.execute();
+
+        let b = b.data();
+        assert_eq!(b.len(), 2 * 3 * 4 * 3);
+        // Trilinear with aligned corners on a linear ramp stays a linear ramp
+        for (i, v) in b.iter().enumerate() {
+            let (ch, d, h, w) = (i / 36, i / 12 % 3, i / 3 % 4, i % 3);
+            let expected = ch as f32 * 8. + d as f32 * 2. + h as f32 * 2. / 3. + w as f32 * 0.5;
+            assert!((v - expected).abs() < 1e-4, "{v} != {expected} at {i}");
+        }
+        assert_exact(&c.data(), &[0., 0., 4., 8., 8., 12.]);
+        // Each depth slice is repeated twice
+        let expected = (0..32)
+            .map(|i| (i / 16 * 8 + i / 8 % 2 * 4 + i % 4) as f32)
+            .collect::<Vec<_>>();
+        assert_exact(&doubled.data(), &expected);
+    }
+}
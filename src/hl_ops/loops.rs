@@ -0,0 +1,184 @@
+use crate::{op, prelude::*};
+
+impl Graph {
+    /// Run `body` once for each step along the first dimension of `sequences`, without unrolling it, so the
+    /// number of steps can be a dynamic dimension.
+    ///
+    /// The body builds its own graph, given the current step's slice of each sequence, the carried state,
+    /// and `captures`, which are tensors every step reads (like weights). It returns the next carried state,
+    /// matching the shapes of `init`, along with any per-step outputs.
+    ///
+    /// Returns the final carried state, and the per-step outputs stacked along a new first dimension. With
+    /// `reverse` the steps run from the end of the sequences to the start, but outputs are still stacked in
+    /// sequence order.
+    #[allow(clippy::type_complexity)]
+    pub fn scan_loop(
+        &mut self,
+        sequences: &[GraphTensor],
+        init: &[GraphTensor],
+        captures: &[GraphTensor],
+        reverse: bool,
+        body: impl FnOnce(
+            &mut Graph,
+            &[GraphTensor],
+            &[GraphTensor],
+            &[GraphTensor],
+        ) -> (Vec<GraphTensor>, Vec<GraphTensor>),
+    ) -> (Vec<GraphTensor>, Vec<GraphTensor>) {
+        assert!(!sequences.is_empty(), "A loop needs at least one sequence");
+        let steps = sequences[0].dims()[0];
+        for seq in sequences {
+            assert_eq!(
+                seq.dims()[0],
+                steps,
+                "All sequences in a loop must have the same length"
+            );
+        }
+
+        // The body lives on the heap so the pointers its tensors hold stay valid once it's moved into the op
+        let mut body_graph = Box::new(Graph::new());
+        let inputs = |name: &str, tensors: &[GraphTensor], first: usize, cx: &mut Graph| {
+            tensors
+                .iter()
+                .enumerate()
+                .map(|(i, t)| {
+                    cx.named_tensor(&format!("Loop {name} {i}"), t.dims()[first..].to_vec())
+                })
+                .collect::<Vec<_>>()
+        };
+        let step_inputs = inputs("Sequence", sequences, 1, &mut body_graph);
+        let carry_inputs = inputs("Carry", init, 0, &mut body_graph);
+        let capture_inputs = inputs("Capture", captures, 0, &mut body_graph);
+        let (next, step_outputs) = body(
+            &mut body_graph,
+            &step_inputs,
+            &carry_inputs,
+            &capture_inputs,
+        );
+        assert_eq!(
+            next.len(),
+            init.len(),
+            "Loop body returned {} carries for {} initial carries",
+            next.len(),
+            init.len()
+        );
+        for (n, i) in next.iter().zip(init) {
+            assert_eq!(n.dims(), i.dims(), "Loop carries must keep their shape");
+        }
+        let outputs = next
+            .iter()
+            .chain(&step_outputs)
+            .map(|t| t.contiguous())
+            .collect::<Vec<_>>();
+        body_graph.keep_tensors(&outputs);
+        body_graph.keep_tensors(&capture_inputs);
+
+        let mut op = self.add_op(op::Loop {
+            inputs: step_inputs
+                .iter()
+                .chain(&carry_inputs)
+                .chain(&capture_inputs)
+                .map(|t| t.id)
+                .collect(),
+            outputs: outputs.iter().map(|t| t.id).collect(),
+            output_indexes: outputs.iter().map(|t| t.output).collect(),
+            output_shapes: outputs.iter().map(|t| t.shape).collect(),
+            n_sequences: sequences.len(),
+            n_carries: init.len(),
+            reverse,
+            dyn_map: &self.dyn_map,
+            body: body_graph,
+        });
+        for t in sequences.iter().chain(init).chain(captures) {
+            op = op.input(t.id, t.output, t.shape);
+        }
+        let id = op.finish();
+        let finals = init
+            .iter()
+            .enumerate()
+            .map(|(i, t)| GraphTensor::from_output(id, i as u8, ShapeTracker::new(t.dims()), self))
+            .collect();
+        let stacked = step_outputs
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let mut dims = vec![steps];
+                dims.extend(t.dims());
+                GraphTensor::from_output(id, (init.len() + i) as u8, ShapeTracker::new(dims), self)
+            })
+            .collect();
+        (finals, stacked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    crate::test_imports!();
+
+    #[test]
+    fn test_scan_loop() {
+        let mut cx = Graph::new();
+        let xs = cx.tensor(('s', 2, 3));
+        let h0 = cx.tensor((2, 3)).set(vec![1., -1., 0.5, 0., 2., -0.5]);
+        let decay = cx.tensor(3).set(vec![0.5, 0.9, -0.3]);
+        let mut run = |reverse: bool| {
+            let (finals, outputs) = cx.scan_loop(&[xs], &[h0], &[decay], reverse, |_, x, h, c| {
+                let h = (h[0] * c[0].expand(0, 2) + x[0]).sin();
+                (vec![h], vec![h, h.sum_reduce(1)])
+            });
+            (
+                finals[0].retrieve(),
+                outputs[0].retrieve(),
+                outputs[1].retrieve(),
+            )
+        };
+        let mut forward = run(false);
+        let mut backward = run(true);
+
+        // Reference recurrence
+        let reference = |data: &[f32], steps: usize, reverse: bool| {
+            let mut h = vec![1., -1., 0.5, 0., 2., -0.5];
+            let decay = [0.5, 0.9, -0.3];
+            let mut outputs = vec![0.; steps * 6];
+            for s in 0..steps {
+                let s = if reverse { steps - 1 - s } else { s };
+                for i in 0..6 {
+                    h[i] = (h[i] * decay[i % 3] + data[s * 6 + i]).sin();
+                }
+                outputs[s * 6..(s + 1) * 6].copy_from_slice(&h);
+            }
+            let sums = outputs
+                .chunks(3)
+                .map(|c| c.iter().sum::<f32>())
+                .collect::<Vec<_>>();
+            (h, outputs, sums)
+        };
+
+        for steps in [4, 1] {
+            let data = random_vec(steps * 6);
+            xs.set_dyn(data.clone(), (steps, 2, 3));
+            cx.execute();
+            for ((finals, outputs, sums), reverse) in [(&forward, false), (&backward, true)] {
+                let (h, ref_outputs, ref_sums) = reference(&data, steps, reverse);
+                assert_close(&finals.data(), &h);
+                assert_close(&outputs.data(), &ref_outputs);
+                assert_close(&sums.data(), &ref_sums);
+                assert_eq!(outputs.dims()[0].exec(&cx.dyn_map), Some(steps));
+            }
+            for t in [
+                forward.0, forward.1, forward.2, backward.0, backward.1, backward.2,
+            ] {
+                t.drop();
+            }
+        }
+
+        // Compiling the outer graph leaves the loop intact
+        cx.compile(GenericCompiler::default(), (&mut forward, &mut backward));
+        let data = random_vec(3 * 6);
+        xs.set_dyn(data.clone(), (3, 2, 3));
+        cx.execute();
+        let (h, ref_outputs, _) = reference(&data, 3, false);
+        assert_close(&forward.0.data(), &h);
+        assert_close(&forward.1.data(), &ref_outputs);
+    }
+}
@@ -1,5 +1,9 @@
 // The high level interface implemented on GraphTensor. All of these ops get translated to primitive ops.
 pub mod binary;
+pub mod einsum;
+pub mod interpolate;
+pub use interpolate::InterpolateMode;
+pub mod loops;
 pub mod matmul;
 pub mod movement;
 pub mod other;
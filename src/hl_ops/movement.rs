@@ -39,8 +39,9 @@ impl GraphTensor {
         self.id = self
             .graph()
             .add_op(op::Contiguous)
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
+        self.output = 0;
         self.shape = self.shape.contiguous();
         self
     }
@@ -143,7 +144,8 @@ impl GraphTensor {
         if padding.iter().zip(self.shape.indexes).any(|(range, ind)| {
             (range.0 != 0 || range.1 != 0)
                 && (self.shape.mask[self.shape.indexes[ind]].0 != 0
-                    || self.shape.mask[self.shape.indexes[ind]].1 != i32::MAX)
+                    || self.shape.mask[self.shape.indexes[ind]].1 != i32::MAX
+                    || self.shape.pad_fill[ind] != PadFill::Zero)
         }) {
             self = self.contiguous();
         }
@@ -151,6 +153,56 @@ impl GraphTensor {
         self
     }
 
+    /// Pad the tensor, filling the padding according to `mode`. Other than constant padding, the
+    /// padding is read from the tensor itself through the view, so nothing gets copied.
+    pub fn pad_with(mut self, padding: impl ToPad, mode: PadMode) -> GraphTensor {
+        let padding = padding.to_pad_vec();
+        let is_padded =
+            |(s, e): &(Expression, Expression)| s.to_usize() != Some(0) || e.to_usize() != Some(0);
+        let fill = match mode {
+            PadMode::Constant(value) => {
+                let dims = self.dims();
+                let padded = self.pad(padding.clone());
+                // Mask out the padding along each padded dimension
+                let mut inside: Option<GraphTensor> = None;
+                for (axis, (s, e)) in padding.iter().enumerate() {
+                    if !is_padded(&(*s, *e)) {
+                        continue;
+                    }
+                    let pos = self.graph().arange(padded.dims()[axis]) - *s;
+                    let zero = self.graph().constant(0.).expand_to(pos.shape);
+                    let size = self.graph().constant(dims[axis]).expand_to(pos.shape);
+                    let mut mask = pos.greater_than_equal(zero) * pos.less_than(size);
+                    for (i, d) in padded.dims().into_iter().enumerate() {
+                        if i != axis {
+                            mask = mask.expand(i, d);
+                        }
+                    }
+                    inside = Some(inside.map(|m| m * mask).unwrap_or(mask));
+                }
+                let Some(inside) = inside else {
+                    return self;
+                };
+                let value = self.graph().constant(value).expand_to(padded.shape);
+                return padded.where_(inside, value);
+            }
+            PadMode::Reflect => PadFill::Reflect,
+            PadMode::Replicate => PadFill::Replicate,
+            PadMode::Circular => PadFill::Circular,
+        };
+        // Padding can only be read from dimensions that haven't been padded or sliced already
+        if padding.iter().zip(self.shape.indexes).any(|(p, ind)| {
+            is_padded(p)
+                && !self.shape.fake[ind]
+                && (self.shape.padding[ind] != (0.into(), 0.into())
+                    || self.shape.mask[ind] != (0.into(), i32::MAX.into()))
+        }) {
+            self = self.contiguous();
+        }
+        self.shape.pad_with(&padding, fill);
+        self
+    }
+
     pub fn pad_along(
         self,
         left: impl Into<Expression>,
@@ -163,9 +215,165 @@ impl GraphTensor {
     }
 
     pub fn concat_along(self, rhs: GraphTensor, axis: usize) -> GraphTensor {
-        // Pad and add
-        self.pad_along(0, rhs.shape.dims()[axis], axis)
-            + rhs.pad_along(self.shape.dims()[axis], 0, axis)
+        GraphTensor::concat(&[self, rhs], axis)
+    }
+
+    /// Concatenate any number of tensors along an axis. All other dimensions must match.
+    ///
+    /// Each tensor is padded once out to the full size and the results are summed, so no padding
+    /// gets nested however many tensors there are.
+    pub fn concat(tensors: &[GraphTensor], axis: usize) -> GraphTensor {
+        assert!(!tensors.is_empty(), "Can't concat an empty list of tensors");
+        let sizes = tensors.iter().map(|t| t.dims()[axis]).collect::<Vec<_>>();
+        let sum = |sizes: &[Expression]| {
+            sizes
+                .iter()
+                .fold(Expression::from(0), |acc, s| (acc + *s).simplify())
+        };
+        let mut out: Option<GraphTensor> = None;
+        for (i, t) in tensors.iter().enumerate() {
+            let padded = t.pad_along(sum(&sizes[..i]), sum(&sizes[i + 1..]), axis);
+            out = Some(match out {
+                Some(o) => o + padded,
+                None => padded,
+            });
+        }
+        out.unwrap()
+    }
+
+    /// Stack tensors of the same shape along a new axis
+    pub fn stack(tensors: &[GraphTensor], axis: usize) -> GraphTensor {
+        let tensors = tensors
+            .iter()
+            .map(|t| t.unsqueeze(axis))
+            .collect::<Vec<_>>();
+        GraphTensor::concat(&tensors, axis)
+    }
+
+    /// Split into pieces of the given sizes along an axis. The sizes must add up to the dimension.
+    pub fn split(self, sizes: impl ToShape, axis: usize) -> Vec<GraphTensor> {
+        let sizes = sizes.to_shape();
+        let dim = self.dims()[axis];
+        let total = sizes
+            .iter()
+            .fold(Expression::from(0), |acc, s| (acc + *s).simplify());
+        if let (Some(total), Some(dim)) = (total.to_usize(), dim.to_usize()) {
+            assert_eq!(
+                total, dim,
+                "Split sizes add up to {total} but dimension {axis} has size {dim}"
+            );
+        }
+        let mut start = Expression::from(0);
+        sizes
+            .into_iter()
+            .map(|size| {
+                let end = (start + size).simplify();
+                let piece = self.slice_along(start..end, axis);
+                start = end;
+                piece
+            })
+            .collect()
+    }
+
+    /// Split into `n` pieces along an axis. Every piece has the same size except the last, which
+    /// is smaller if the dimension doesn't divide evenly. Like PyTorch, fewer than `n` pieces come
+    /// back if the last ones would be empty.
+    pub fn chunk(self, n: usize, axis: usize) -> Vec<GraphTensor> {
+        let dim = self.dims()[axis];
+        if let Some(dim) = dim.to_usize() {
+            let size = dim.div_ceil(n);
+            (0..dim.div_ceil(size.max(1)))
+                .map(|i| self.slice_along(i * size..((i + 1) * size).min(dim), axis))
+                .collect()
+        } else {
+            let size = ((dim + (n - 1)) / n).simplify();
+            (0..n)
+                .map(|i| {
+                    let start = (size * i).min(dim).simplify();
+                    let end = (size * (i + 1)).min(dim).simplify();
+                    self.slice_along(start..end, axis)
+                })
+                .collect()
+        }
+    }
+
+    /// Repeat each element `times` times along an axis, like PyTorch's `repeat_interleave`
+    pub fn repeat_interleave(self, axis: usize, times: impl Into<Expression>) -> GraphTensor {
+        let times = times.into();
+        let mut dims = self.dims();
+        dims[axis] = (dims[axis] * times).simplify();
+        self.expand(axis + 1, times).reshape(dims)
+    }
+
+    /// Repeat the whole tensor `times` times along an axis, like numpy's `tile` or PyTorch's `repeat`
+    pub fn tile(self, axis: usize, times: impl Into<Expression>) -> GraphTensor {
+        let times = times.into();
+        let mut dims = self.dims();
+        dims[axis] = (dims[axis] * times).simplify();
+        self.expand(axis, times).reshape(dims)
+    }
+
+    /// Insert a new dimension of size 1
+    pub fn unsqueeze(mut self, axis: usize) -> GraphTensor {
+        // Unlike an expanded dimension this one is real, so it can be padded
+        self.shape.add_dim(axis, 1);
+        self
+    }
+
+    /// Remove a dimension of size 1
+    pub fn squeeze(mut self, axis: usize) -> GraphTensor {
+        assert!(
+            self.dims()[axis].to_usize() == Some(1),
+            "Can only squeeze dimensions of size 1, but dimension {axis} has size {}",
+            self.dims()[axis]
+        );
+        let ind = self.shape.indexes[axis];
+        // Slicing or padding offsets this dimension, so can't just drop it
+        if !self.shape.fake[ind]
+            && (self.shape.dims[ind].to_usize() != Some(1)
+                || self.shape.mask[ind].0 != 0
+                || self.shape.padding[ind] != (0.into(), 0.into()))
+        {
+            self = self.contiguous();
+        }
+        self.shape.remove_dim(axis);
+        self
+    }
+
+    /// Merge the dimensions from `start` to `end` (inclusive) into one
+    pub fn flatten(self, start: usize, end: usize) -> GraphTensor {
+        let dims = self.dims();
+        let merged = dims[start..=end]
+            .iter()
+            .fold(Expression::from(1), |acc, d| (acc * *d).simplify());
+        let mut new_dims = dims[..start].to_vec();
+        new_dims.push(merged);
+        new_dims.extend_from_slice(&dims[end + 1..]);
+        self.reshape(new_dims)
+    }
+
+    /// Write `values` into this tensor along `axis`, starting at `pos`. All other dimensions must match.
+    ///
+    /// This is meant for buffers preallocated to a maximum length, like kv caches. Unlike `concat_along`, when nothing
+    /// else uses this tensor it's written in-place, so only the new values are copied.
+    pub fn write_along(
+        self,
+        values: GraphTensor,
+        axis: usize,
+        pos: impl Into<Expression>,
+    ) -> GraphTensor {
+        let dyn_map = &self.graph().dyn_map as *const _;
+        let new_id = self
+            .graph()
+            .add_op(op::WriteAlong {
+                dim: axis,
+                pos: pos.into(),
+                dyn_map,
+            })
+            .input(self.id, self.output, self.shape)
+            .input(values.id, values.output, values.shape)
+            .finish();
+        GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
 }
 
@@ -250,6 +458,114 @@ mod tests {
         assert_close(&d.data(), &d_d.as_vec());
     }
 
+    #[test]
+    fn test_concat_many() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 1)).set(vec![1., 2.]);
+        let b = cx.tensor((2, 2)).set(vec![3., 4., 5., 6.]);
+        let c = cx
+            .tensor((2, 'c'))
+            .set_dyn(vec![7., 8., 9., 10., 11., 12.], (2, 3));
+        let cat = GraphTensor::concat(&[a, b, c], 1).retrieve();
+        let stacked = GraphTensor::stack(&[b, b * 2., b * 3.], 1).retrieve();
+        cx.execute();
+
+        assert_eq!(cat.dims()[1].simplify(), Expression::from('c') + 3);
+        assert_exact(
+            &cat.data(),
+            &[1., 3., 4., 7., 8., 9., 2., 5., 6., 10., 11., 12.],
+        );
+        assert_eq!(stacked.shape.shape_usize(), vec![2, 3, 2]);
+        assert_exact(
+            &stacked.data(),
+            &[3., 4., 6., 8., 9., 12., 5., 6., 10., 12., 15., 18.],
+        );
+    }
+
+    #[test]
+    fn test_split_chunk() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((2, 5))
+            .set(vec![1., 2., 3., 4., 5., 6., 7., 8., 9., 10.]);
+        let split = a
+            .split((2, 3), 1)
+            .into_iter()
+            .map(|t| t.retrieve())
+            .collect::<Vec<_>>();
+        let chunks = a
+            .chunk(2, 1)
+            .into_iter()
+            .map(|t| t.retrieve())
+            .collect::<Vec<_>>();
+        let rows = a.chunk(4, 0);
+        cx.execute();
+
+        assert_exact(&split[0].data(), &[1., 2., 6., 7.]);
+        assert_exact(&split[1].data(), &[3., 4., 5., 8., 9., 10.]);
+        assert_exact(&chunks[0].data(), &[1., 2., 3., 6., 7., 8.]);
+        assert_exact(&chunks[1].data(), &[4., 5., 9., 10.]);
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[test]
+    fn test_repeat_tile() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 2)).set(vec![1., 2., 3., 4.]);
+        let repeated = a.repeat_interleave(1, 2).retrieve();
+        let tiled = a.tile(0, 2).retrieve();
+        let tiled_t = a.permute((1, 0)).tile(1, 2).retrieve();
+        cx.execute();
+
+        assert_exact(&repeated.data(), &[1., 1., 2., 2., 3., 3., 4., 4.]);
+        assert_exact(&tiled.data(), &[1., 2., 3., 4., 1., 2., 3., 4.]);
+        assert_exact(&tiled_t.data(), &[1., 3., 1., 3., 2., 4., 2., 4.]);
+    }
+
+    #[test]
+    fn test_squeeze_flatten() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3, 2)).set(random_vec(12));
+        let unsqueezed = a.unsqueeze(1);
+        assert_eq!(unsqueezed.shape.shape_usize(), vec![2, 1, 3, 2]);
+        let squeezed = unsqueezed.squeeze(1).retrieve();
+        let flat = a.flatten(0, 1).retrieve();
+        let flat_t = a.permute((2, 0, 1)).flatten(1, 2).retrieve();
+        let sliced = a.slice_along(1..2, 1).squeeze(1).retrieve();
+        let expected_t = a.permute((2, 0, 1)).contiguous().retrieve();
+        cx.execute();
+
+        assert_exact(&squeezed.data(), &a.data());
+        assert_eq!(flat.shape.shape_usize(), vec![6, 2]);
+        assert_exact(&flat.data(), &a.data());
+        assert_eq!(flat_t.shape.shape_usize(), vec![2, 6]);
+        assert_exact(&flat_t.data(), &expected_t.data());
+        let a_data = a.data();
+        assert_exact(
+            &sliced.data(),
+            &[a_data[2], a_data[3], a_data[8], a_data[9]],
+        );
+    }
+
+    #[test]
+    fn test_write_along() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((3, 4)).set(vec![0.; 12]);
+        let b = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let c = a.write_along(b.permute((1, 0)), 1, 'p').retrieve();
+        // Buffer is used elsewhere, so this one gets written into a copy
+        let e = cx.tensor((1, 4)).set(vec![7., 8., 9., 10.]);
+        let d = a.write_along(e, 0, 1).retrieve();
+        cx.set_dyn_dim('p', 2);
+        cx.execute();
+
+        assert_exact(&c.data(), &[0., 0., 1., 4., 0., 0., 2., 5., 0., 0., 3., 6.]);
+        assert_exact(
+            &d.data(),
+            &[0., 0., 0., 0., 7., 8., 9., 10., 0., 0., 0., 0.],
+        );
+    }
+
     #[test]
     fn test_pad_2d() {
         let mut cx = Graph::new();
@@ -278,6 +594,79 @@ mod tests {
         assert_close(&b.data(), &d_b.as_vec());
     }
 
+    #[test]
+    fn test_pad_with() {
+        let mut cx = Graph::new();
+        let a = cx.tensor(4).set(vec![1., 2., 3., 4.]);
+        let reflect = a.pad_with((2, 3), PadMode::Reflect).retrieve();
+        let replicate = a.pad_with((2, 3), PadMode::Replicate).retrieve();
+        let circular = a.pad_with((2, 3), PadMode::Circular).retrieve();
+        let constant = a.pad_with((1, 2), PadMode::Constant(-7.)).retrieve();
+        let neg_inf = a
+            .pad_with((1, 0), PadMode::Constant(f32::NEG_INFINITY))
+            .retrieve();
+        // Padding the padding
+        let nested = a
+            .pad_with((1, 1), PadMode::Replicate)
+            .pad_with((1, 1), PadMode::Reflect)
+            .retrieve();
+        let sliced = a
+            .pad_with((2, 2), PadMode::Reflect)
+            .slice_along(1..6, 0)
+            .retrieve();
+        cx.execute();
+
+        assert_exact(&reflect.data(), &[3., 2., 1., 2., 3., 4., 3., 2., 1.]);
+        assert_exact(&replicate.data(), &[1., 1., 1., 2., 3., 4., 4., 4., 4.]);
+        assert_exact(&circular.data(), &[3., 4., 1., 2., 3., 4., 1., 2., 3.]);
+        assert_exact(&constant.data(), &[-7., 1., 2., 3., 4., -7., -7.]);
+        assert_exact(&neg_inf.data(), &[f32::NEG_INFINITY, 1., 2., 3., 4.]);
+        assert_exact(&nested.data(), &[1., 1., 1., 2., 3., 4., 4., 4.]);
+        assert_exact(&sliced.data(), &[2., 1., 2., 3., 4.]);
+    }
+
+    #[test]
+    fn test_pad_with_2d() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let reflect = a.pad_with(((1, 1), (2, 0)), PadMode::Reflect).retrieve();
+        let permuted = a
+            .permute((1, 0))
+            .pad_with(((0, 1), (1, 0)), PadMode::Replicate)
+            .retrieve();
+        let constant = a
+            .pad_with(((0, 1), (1, 0)), PadMode::Constant(9.))
+            .retrieve();
+        let expanded = a
+            .expand(0, 2)
+            .pad_with(((1, 0), (0, 0), (0, 1)), PadMode::Circular)
+            .retrieve();
+        cx.execute();
+
+        assert_exact(
+            &reflect.data(),
+            &[
+                6., 5., 4., 5., 6., //
+                3., 2., 1., 2., 3., //
+                6., 5., 4., 5., 6., //
+                3., 2., 1., 2., 3.,
+            ],
+        );
+        assert_exact(
+            &permuted.data(),
+            &[1., 1., 4., 2., 2., 5., 3., 3., 6., 3., 3., 6.],
+        );
+        assert_exact(
+            &constant.data(),
+            &[9., 1., 2., 3., 9., 4., 5., 6., 9., 9., 9., 9.],
+        );
+        assert_eq!(expanded.shape.shape_usize(), vec![3, 2, 4]);
+        assert_exact(
+            &expanded.data(),
+            &[1., 2., 3., 1., 4., 5., 6., 4.].repeat(3),
+        );
+    }
+
     #[test]
     fn test_slice_2d() {
         let mut cx = Graph::new();
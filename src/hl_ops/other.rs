@@ -4,36 +4,145 @@ use colored::Colorize;
 use itertools::Itertools;
 
 use crate::{
-    op::{self, Constant, ConstantValue},
+    op::{self, Constant, ConstantValue, ScanKind},
     prelude::*,
 };
 
 impl GraphTensor {
+    /// Cumulative sum along a dimension
+    pub fn cumsum(self, axis: usize) -> GraphTensor {
+        self.scan(axis, ScanKind::Sum)
+    }
+
+    /// Cumulative max along a dimension
+    pub fn cummax(self, axis: usize) -> GraphTensor {
+        self.scan(axis, ScanKind::Max)
+    }
+
+    /// Cumulative min along a dimension
+    pub fn cummin(self, axis: usize) -> GraphTensor {
+        self.scan(axis, ScanKind::Min)
+    }
+
+    /// Cumulative product along a dimension
+    pub fn cumprod(self, axis: usize) -> GraphTensor {
+        self.scan(axis, ScanKind::Prod)
+    }
+
+    /// Log of the cumulative sum of exponentials along a dimension, computed stably
+    pub fn logcumsumexp(self, axis: usize) -> GraphTensor {
+        self.scan(axis, ScanKind::LogSumExp)
+    }
+
     /// Cumulative sum last dimension
-    pub fn cumsum_last_dim(mut self) -> Self {
-        let axis = self.shape.len() - 1;
-        if !self.shape.is_contiguous() {
-            self = self.contiguous();
-        }
-        // Pad out length
-        let orig_length = self.shape.dims[self.shape.indexes[axis]];
-        self.shape.padding[self.shape.indexes[axis]].0 = orig_length - 1;
-        self = self.contiguous();
+    pub fn cumsum_last_dim(self) -> Self {
+        self.cumsum(self.shape.len() - 1)
+    }
 
-        // Pool
-        let mut pooled = self.pool_last_dim(orig_length, 1, 1);
-        // Sum Reduce along new dimension
-        let final_id = self
+    /// Cumulative max last dimension
+    pub fn cummax_last_dim(self) -> Self {
+        self.cummax(self.shape.len() - 1)
+    }
+
+    /// Cumulative product last dimension
+    pub fn cumprod_last_dim(self) -> Self {
+        self.cumprod(self.shape.len() - 1)
+    }
+
+    fn scan(self, axis: usize, kind: ScanKind) -> GraphTensor {
+        let new_id = self
             .graph()
-            .add_op(op::SumReduce(axis))
-            .input(pooled.id, 0, pooled.shape)
+            .add_op(op::Scan { axis, kind })
+            .input(self.id, self.output, self.shape)
             .finish();
-        pooled.shape.remove_dim(axis + 1);
-        GraphTensor::from_id(final_id, pooled.shape, self.graph_ref)
+        GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
 
-    /// Cumulative max last dimension
-    pub fn cummax_last_dim(mut self) -> Self {
+    /// The `k` largest elements along a dimension in descending order, along with their indices
+    pub fn topk(self, k: usize, axis: usize) -> (GraphTensor, GraphTensor) {
+        self.sort_along(axis, true, Some(k))
+    }
+
+    /// Sort along a dimension
+    pub fn sort(self, axis: usize, descending: bool) -> GraphTensor {
+        self.sort_along(axis, descending, None).0
+    }
+
+    /// The indices that would sort a dimension in ascending order
+    pub fn argsort(self, axis: usize) -> GraphTensor {
+        self.sort_along(axis, false, None).1
+    }
+
+    fn sort_along(
+        self,
+        axis: usize,
+        descending: bool,
+        k: Option<usize>,
+    ) -> (GraphTensor, GraphTensor) {
+        let new_id = self
+            .graph()
+            .add_op(op::Sort {
+                axis,
+                descending,
+                k,
+            })
+            .input(self.id, self.output, self.shape)
+            .finish();
+        let mut dims = self.dims();
+        if let Some(k) = k {
+            dims[axis] = k.into();
+        }
+        let shape = ShapeTracker::new(dims);
+        (
+            GraphTensor::from_output(new_id, 0, shape, self.graph_ref),
+            GraphTensor::from_output(new_id, 1, shape, self.graph_ref),
+        )
+    }
+
+    /// Build a scan out of pooling and reductions, for backends without a dedicated scan kernel.
+    /// This does O(n^2) work along the dimension.
+    pub(crate) fn decompose_scan(self, axis: usize, kind: ScanKind) -> GraphTensor {
+        // Move the scanned dimension to the end
+        let n_dims = self.shape.len();
+        let mut axes = (0..n_dims).filter(|i| *i != axis).collect::<Vec<_>>();
+        axes.push(axis);
+        let mut back = vec![0; n_dims];
+        for (i, a) in axes.iter().enumerate() {
+            back[*a] = i;
+        }
+        let x = self.permute(axes);
+        let cummax = |x: GraphTensor| {
+            // Windows are padded with zeros, so shift everything to be non-negative first
+            let min = -(-x).max_reduce(n_dims - 1).expand_to(x.shape);
+            (x - min).pooled_scan_last_dim(true) + min
+        };
+        let out = match kind {
+            ScanKind::Sum => x.pooled_scan_last_dim(false),
+            ScanKind::Max => cummax(x),
+            ScanKind::Min => -cummax(-x),
+            ScanKind::Prod => x.ln().pooled_scan_last_dim(false).exp(),
+            ScanKind::LogSumExp => {
+                // Stabilise each prefix against its own running max rather than the max of the
+                // whole dimension, otherwise prefixes far below a later element underflow to -inf
+                let n = x.dims()[n_dims - 1];
+                let running = cummax(x);
+                let mut future = self.graph().triu(n, 1);
+                for (i, d) in x.dims()[..n_dims - 1].iter().enumerate() {
+                    future = future.expand(i, *d);
+                }
+                (x.expand(n_dims - 1, n) - running.expand(n_dims, n))
+                    .masked_fill(future, f32::NEG_INFINITY)
+                    .exp()
+                    .sum_reduce(n_dims)
+                    .ln()
+                    + running
+            }
+        };
+        out.permute(back).contiguous()
+    }
+
+    /// Sum or max over windows of every prefix of the last dimension
+    fn pooled_scan_last_dim(mut self, max: bool) -> Self {
         let axis = self.shape.len() - 1;
         if !self.shape.is_contiguous() {
             self = self.contiguous();
@@ -45,20 +154,17 @@ impl GraphTensor {
 
         // Pool
         let mut pooled = self.pool_last_dim(orig_length, 1, 1);
-        // Max Reduce along new dimension
-        let final_id = self
-            .graph()
-            .add_op(op::MaxReduce(axis))
-            .input(pooled.id, 0, pooled.shape)
-            .finish();
+        // Reduce along new dimension
+        let final_id = if max {
+            self.graph().add_op(op::MaxReduce(axis))
+        } else {
+            self.graph().add_op(op::SumReduce(axis))
+        }
+        .input(pooled.id, pooled.output, pooled.shape)
+        .finish();
         pooled.shape.remove_dim(axis + 1);
         GraphTensor::from_id(final_id, pooled.shape, self.graph_ref)
     }
-
-    /// Cumulative product last dimension
-    pub fn cumprod_last_dim(self) -> Self {
-        self.ln().cumsum_last_dim().exp()
-    }
 }
 
 impl Graph {
@@ -78,7 +184,8 @@ impl Graph {
             // Single number ARange is just 0
             self.constant(0.).expand(0, to)
         } else {
-            self.constant(1.).expand(0, to).cumsum_last_dim() - 1.
+            // Backends recognize this pattern, so build it from pooling rather than a scan
+            self.constant(1.).expand(0, to).pooled_scan_last_dim(false) - 1.
         }
     }
 
@@ -106,16 +213,130 @@ impl Graph {
 }
 
 impl GraphTensor {
-    /// Gather a batch of vectors from a matrix
-    pub fn gather(self, indexes: GraphTensor) -> GraphTensor {
-        let (vocab, dim) = self.dims2();
-        let batch = indexes.dims1();
-        let one_hot = indexes
+    /// Select entries along an axis by index. The axis is replaced by the dimensions of `indices`,
+    /// so a matrix indexed along the first axis gives a batch of its rows.
+    pub fn index_select(self, axis: usize, indices: GraphTensor) -> GraphTensor {
+        let dims = self.dims();
+        let index_dims = indices.dims();
+        // Flatten batches of indices
+        let mut indices = if index_dims.len() == 1 {
+            indices
+        } else {
+            indices.reshape(indices.shape.n_elements())
+        };
+        // Every entry along the other axes uses the same indices
+        for (i, d) in dims.iter().enumerate() {
+            if i != axis {
+                indices = indices.expand(i, *d);
+            }
+        }
+        let out = self.gather(axis, indices);
+        if index_dims.len() == 1 {
+            out
+        } else {
+            // Unflatten
+            let mut new_shape = dims[..axis].to_vec();
+            new_shape.extend(index_dims);
+            new_shape.extend_from_slice(&dims[axis + 1..]);
+            out.reshape(new_shape)
+        }
+    }
+
+    /// Gather entries along an axis, where the output at each position takes the entry at the
+    /// matching index in `indices` along the axis. Like PyTorch's gather, `indices` has the same
+    /// rank as the tensor, and every dimension other than `axis` must match.
+    pub fn gather(self, axis: usize, indices: GraphTensor) -> GraphTensor {
+        check_index_dims(self, axis, indices);
+        let new_id = self
             .graph()
-            .arange(vocab)
-            .expand(0, batch)
-            .equals(indexes.expand(1, vocab));
-        (one_hot.expand(2, dim) * self.expand(0, batch)).sum_reduce(1)
+            .add_op(op::Gather { axis })
+            .input(indices.id, indices.output, indices.shape)
+            .input(self.id, self.output, self.shape)
+            .finish();
+        GraphTensor::from_id(new_id, indices.shape.contiguous(), self.graph_ref)
+    }
+
+    /// Build a gather out of a one-hot matmul, for backends without a dedicated gather kernel.
+    /// This does O(n) work for every gathered entry.
+    pub(crate) fn decompose_gather(self, axis: usize, indices: GraphTensor) -> GraphTensor {
+        let (dims, index_dims) = (self.dims(), indices.dims());
+        let n = dims[axis];
+        let is_fake = |i: usize| indices.shape.fake[indices.shape.indexes[i]];
+        if (0..index_dims.len()).all(|i| i == axis || is_fake(i)) {
+            // The same indices are used across the other axes, so only build the one-hot once
+            let mut indices = indices;
+            for i in (0..index_dims.len()).rev() {
+                if i != axis {
+                    indices.shape.remove_dim(i);
+                }
+            }
+            let batch = index_dims[axis];
+            let mut one_hot = indices
+                .graph()
+                .arange(n)
+                .expand(0, batch)
+                .equals(indices.expand(1, n));
+            for (i, d) in dims.iter().enumerate() {
+                match i.cmp(&axis) {
+                    std::cmp::Ordering::Less => one_hot = one_hot.expand(i, *d),
+                    std::cmp::Ordering::Greater => one_hot = one_hot.expand(i + 1, *d),
+                    std::cmp::Ordering::Equal => {}
+                }
+            }
+            (one_hot * self.expand(axis, batch)).sum_reduce(axis + 1)
+        } else {
+            let mut arange = self.graph().arange(n);
+            for (i, d) in index_dims.iter().enumerate() {
+                arange = arange.expand(if i <= axis { i } else { i + 1 }, *d);
+            }
+            let one_hot = arange.equals(indices.expand(axis + 1, n));
+            (one_hot * self.expand(axis, index_dims[axis])).sum_reduce(axis + 1)
+        }
+    }
+
+    /// Write `src` into the tensor along an axis, where each entry goes to the matching index in
+    /// `indices` along the axis. `indices` and `src` have the same shape, which matches the tensor
+    /// in every dimension other than `axis`.
+    pub fn scatter(self, axis: usize, indices: GraphTensor, src: GraphTensor) -> GraphTensor {
+        self.scatter_op(axis, indices, src, false)
+    }
+
+    /// Like `scatter`, but entries are summed onto the tensor, so repeated indices accumulate
+    pub fn scatter_add(self, axis: usize, indices: GraphTensor, src: GraphTensor) -> GraphTensor {
+        self.scatter_op(axis, indices, src, true)
+    }
+
+    fn scatter_op(
+        self,
+        axis: usize,
+        indices: GraphTensor,
+        src: GraphTensor,
+        add: bool,
+    ) -> GraphTensor {
+        check_index_dims(self, axis, indices);
+        assert_eq!(
+            indices.dims(),
+            src.dims(),
+            "Indices and source must have the same shape"
+        );
+        let new_id = self
+            .graph()
+            .add_op(op::Scatter { axis, add })
+            .input(self.id, self.output, self.shape)
+            .input(indices.id, indices.output, indices.shape)
+            .input(src.id, src.output, src.shape)
+            .finish();
+        GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
+    }
+
+    /// Convert a tensor of class indices into one-hot vectors along a new last dimension
+    pub fn one_hot(self, num_classes: impl Into<Expression>) -> GraphTensor {
+        let num_classes = num_classes.into();
+        let mut classes = self.graph().arange(num_classes);
+        for (i, dim) in self.dims().into_iter().enumerate() {
+            classes = classes.expand(i, dim);
+        }
+        classes.equals(self.expand(self.shape.len(), num_classes))
     }
 
     /// Print the value of this tensor when the graph is ran
@@ -142,7 +363,7 @@ impl GraphTensor {
                     vec![]
                 }),
             ))
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         self.graph().no_delete.insert(id);
         *self
@@ -313,13 +534,29 @@ impl GraphTensor {
                     vec![]
                 }),
             ))
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         self.graph().no_delete.insert(id);
         *self
     }
 }
 
+/// Indices index along `axis`, so they must match the tensor in every other dimension
+fn check_index_dims(tensor: GraphTensor, axis: usize, indices: GraphTensor) {
+    let (dims, index_dims) = (tensor.dims(), indices.dims());
+    assert_eq!(
+        dims.len(),
+        index_dims.len(),
+        "Indices must have the same rank as the tensor they index"
+    );
+    for (i, (a, b)) in dims.iter().zip(&index_dims).enumerate() {
+        assert!(
+            i == axis || a == b,
+            "Dimension {i} of the indices doesn't match the tensor"
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     crate::test_imports!();
@@ -344,6 +581,230 @@ mod tests {
         assert_close(&b.data(), &[3., 6., 30.]);
     }
 
+    #[test]
+    fn test_cumulative() {
+        let mut cx = Graph::new();
+        let a = cx.tensor(4).set(vec![-3., -5., -1., 2.]);
+        let sum = a.cumsum(0).retrieve();
+        let max = a.cummax(0).retrieve();
+        let min = a.cummin(0).retrieve();
+        let prod = a.cumprod(0).retrieve();
+        let b = cx.tensor(3).set(vec![-1000., 0., 1.]);
+        let lse = b.logcumsumexp(0).retrieve();
+        cx.execute();
+
+        assert_exact(&sum.data(), &[-3., -8., -9., -7.]);
+        assert_exact(&max.data(), &[-3., -3., -1., 2.]);
+        assert_exact(&min.data(), &[-3., -5., -5., -5.]);
+        assert_exact(&prod.data(), &[-3., 15., -15., -30.]);
+        assert_close(&lse.data(), &[-1000., 0., 1. + (-1_f32).exp().ln_1p()]);
+    }
+
+    #[test]
+    fn test_decompose_scans() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((3, 4, 5)).set(random_vec(60));
+        let positive = cx.tensor((3, 4, 5)).set(random_vec(60)).abs() + 0.5;
+        let mut outs = vec![];
+        for axis in 0..3 {
+            outs.push(a.cumsum(axis).retrieve());
+            outs.push(a.cummax(axis).retrieve());
+            outs.push(a.cummin(axis).retrieve());
+            outs.push(a.logcumsumexp(axis).retrieve());
+            outs.push(positive.cumprod(axis).retrieve());
+        }
+        // Scans along a permuted input
+        outs.push(a.permute((2, 0, 1)).cumsum(1).retrieve());
+        cx.execute();
+        let scanned = outs.iter().map(|o| o.data()).collect::<Vec<_>>();
+        for o in &outs {
+            o.drop();
+        }
+
+        cx.compile(DecomposeScans, &mut outs);
+        assert!(!cx
+            .graph
+            .node_indices()
+            .any(|n| cx.check_node_type::<crate::op::Scan>(n)));
+        cx.execute();
+        for (o, s) in outs.iter().zip(&scanned) {
+            assert_close(&o.data(), s);
+        }
+    }
+
+    #[test]
+    fn test_decompose_logcumsumexp_spread() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((2, 4))
+            .set(vec![-1000., 0., 1., -50., 3., -200., 90., 1.]);
+        let mut outs = vec![a.logcumsumexp(1).retrieve(), a.logcumsumexp(0).retrieve()];
+        cx.execute();
+        let scanned = outs.iter().map(|o| o.data()).collect::<Vec<_>>();
+        for o in &outs {
+            o.drop();
+        }
+
+        cx.compile(DecomposeScans, &mut outs);
+        cx.execute();
+        assert_close(&scanned[0][..2], &[-1000., 0.]);
+        for (o, s) in outs.iter().zip(&scanned) {
+            assert_close(&o.data(), s);
+        }
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((2, 4))
+            .set(vec![3., -1., 3., 7., 0., 2., -4., 1.]);
+        let (values, indices) = a.topk(2, 1);
+        let (values, indices) = (values.retrieve(), indices.retrieve());
+        // Outputs other than the first can be used like any other tensor
+        let shifted = (indices * 2. + values).retrieve();
+        let sorted = a.permute((1, 0)).sort(1, true).retrieve();
+        let ascending = a.sort(1, false).retrieve();
+        let argsort = a.argsort(1).retrieve();
+        let column_sort = a.argsort(0).retrieve();
+        cx.execute();
+
+        assert_exact(&values.data(), &[7., 3., 2., 1.]);
+        assert_exact(&indices.data(), &[3., 0., 1., 3.]);
+        assert_exact(&shifted.data(), &[13., 3., 4., 7.]);
+        assert_exact(&sorted.data(), &[3., 0., 2., -1., 3., -4., 7., 1.]);
+        assert_exact(&ascending.data(), &[-1., 3., 3., 7., -4., 0., 1., 2.]);
+        // Ties keep their original order
+        assert_exact(&argsort.data(), &[1., 0., 2., 3., 2., 0., 3., 1.]);
+        assert_exact(&column_sort.data(), &[1., 0., 1., 1., 0., 1., 0., 0.]);
+    }
+
+    #[test]
+    fn test_gather() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let rows = a
+            .index_select(0, cx.tensor(3).set(vec![1., 0., 1.]))
+            .retrieve();
+        let columns = a
+            .index_select(1, cx.tensor((2, 2)).set(vec![2., 2., 0., 1.]))
+            .retrieve();
+        let gathered = a
+            .gather(1, cx.tensor((2, 2)).set(vec![2., 0., 1., 1.]))
+            .retrieve();
+        cx.execute();
+
+        assert_exact(&rows.data(), &[4., 5., 6., 1., 2., 3., 4., 5., 6.]);
+        assert_eq!(columns.dims().len(), 3);
+        assert_exact(&columns.data(), &[3., 3., 1., 2., 6., 6., 4., 5.]);
+        assert_exact(&gathered.data(), &[3., 1., 5., 5.]);
+    }
+
+    #[test]
+    fn test_decompose_gathers() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((3, 4, 5)).set(random_vec(60));
+        let rows = cx.tensor(6).set(vec![2., 0., 2., 1., 0., 1.]);
+        let indices = cx
+            .tensor((3, 4, 2))
+            .set((0..24).map(|i| ((i * 7) % 5) as f32).collect::<Vec<_>>());
+        let mut outs = vec![
+            a.index_select(0, rows).retrieve(),
+            a.index_select(1, rows).retrieve(),
+            a.permute((2, 0, 1)).index_select(1, rows).retrieve(),
+            a.gather(2, indices).retrieve(),
+        ];
+        cx.execute();
+        let gathered = outs.iter().map(|o| o.data()).collect::<Vec<_>>();
+        for o in &outs {
+            o.drop();
+        }
+
+        cx.compile(DecomposeGathers, &mut outs);
+        assert!(!cx
+            .graph
+            .node_indices()
+            .any(|n| cx.check_node_type::<crate::op::Gather>(n)));
+        cx.execute();
+        for (o, g) in outs.iter().zip(&gathered) {
+            assert_exact(&o.data(), g);
+        }
+    }
+
+    #[test]
+    fn test_scatter() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let src = cx.tensor((2, 2)).set(vec![10., 20., 30., 40.]);
+        let indices = cx.tensor((2, 2)).set(vec![2., 0., 1., 1.]);
+        let scattered = a.scatter(1, indices, src).retrieve();
+        let added = a.scatter_add(1, indices, src).retrieve();
+        let rows = a
+            .scatter_add(
+                0,
+                cx.tensor((1, 3)).set(vec![1., 0., 1.]),
+                a.slice((..1, ..)),
+            )
+            .retrieve();
+        let one_hot = cx.tensor(3).set(vec![2., 0., 1.]).one_hot(4).retrieve();
+        let batched = cx
+            .tensor((2, 2))
+            .set(vec![1., 0., 2., 1.])
+            .one_hot(3)
+            .retrieve();
+        cx.execute();
+
+        assert_exact(&scattered.data(), &[20., 2., 10., 4., 40., 6.]);
+        assert_exact(&added.data(), &[21., 2., 13., 4., 75., 6.]);
+        assert_exact(&rows.data(), &[1., 4., 3., 5., 5., 9.]);
+        assert_exact(
+            &one_hot.data(),
+            &[0., 0., 1., 0., 1., 0., 0., 0., 0., 1., 0., 0.],
+        );
+        assert_exact(
+            &batched.data(),
+            &[0., 1., 0., 1., 0., 0., 0., 0., 1., 0., 1., 0.],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Index -1 out of bounds for dimension of size 3")]
+    fn test_gather_negative_index() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        a.gather(1, cx.tensor((2, 1)).set(vec![0., -1.])).retrieve();
+        cx.execute();
+    }
+
+    #[test]
+    #[should_panic(expected = "Index -1 out of bounds for dimension of size 3")]
+    fn test_scatter_negative_index() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+        let src = cx.tensor((2, 1)).set(vec![10., 20.]);
+        a.scatter(1, cx.tensor((2, 1)).set(vec![-1., 0.]), src)
+            .retrieve();
+        cx.execute();
+    }
+
+    #[test]
+    fn test_multi_output_cse() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 4)).set(random_vec(8));
+        let (values, indices) = a.topk(3, 1);
+        // The same op on different outputs of one node must not be merged
+        let mut outs = (values.sin().retrieve(), indices.sin().retrieve());
+        cx.execute();
+        let unoptimized = (outs.0.data(), outs.1.data());
+        outs.0.drop();
+        outs.1.drop();
+
+        cx.compile(GenericCompiler::default(), &mut outs);
+        cx.execute();
+        assert_exact(&outs.0.data(), &unoptimized.0);
+        assert_exact(&outs.1.data(), &unoptimized.1);
+    }
+
     #[test]
     fn test_dyn_arange() {
         let mut cx = Graph::new();
@@ -6,30 +6,35 @@ use crate::{
 impl GraphTensor {
     /// Reduce a dimension of the tensor by summing all elements along that axis.
     pub fn sum_reduce(self, axes: impl ToAxes) -> GraphTensor {
-        let (mut shape, mut id) = (self.shape, self.id);
+        let (mut shape, mut id, mut output) = (self.shape, self.id, self.output);
         // Sum reduce each dimension
         for dim in axes.to_axes().into_iter().rev() {
             id = self
                 .graph()
                 .add_op(op::SumReduce(dim))
-                .input(id, 0, shape)
+                .input(id, output, shape)
                 .finish();
+            output = 0;
+            // The reduced output is laid out in order, even if the input was permuted
             shape.remove_dim(dim);
+            shape = shape.contiguous();
         }
         GraphTensor::from_id(id, shape, self.graph_ref)
     }
 
     /// Reduce a dimension of the tensor by taking the maximum of all elements along that axis.
     pub fn max_reduce(self, axes: impl ToAxes) -> GraphTensor {
-        let (mut shape, mut id) = (self.shape, self.id);
+        let (mut shape, mut id, mut output) = (self.shape, self.id, self.output);
         // Max reduce each dimension
         for dim in axes.to_axes().into_iter().rev() {
             id = self
                 .graph()
                 .add_op(op::MaxReduce(dim))
-                .input(id, 0, shape)
+                .input(id, output, shape)
                 .finish();
+            output = 0;
             shape.remove_dim(dim);
+            shape = shape.contiguous();
         }
         GraphTensor::from_id(id, shape, self.graph_ref)
     }
@@ -48,6 +53,72 @@ impl GraphTensor {
     pub fn prod_reduce(self, axes: impl ToAxes) -> GraphTensor {
         self.ln().sum_reduce(axes).exp()
     }
+
+    /// Reduce a dimension of the tensor by taking the minimum of all elements along that axis.
+    pub fn min_reduce(self, axes: impl ToAxes) -> GraphTensor {
+        -(-self).max_reduce(axes)
+    }
+
+    /// Take the variance along the axes, dividing by `N - correction`. A `correction` of 0 gives
+    /// the population variance, and 1 gives the unbiased sample variance.
+    pub fn var(self, axes: impl ToAxes, correction: usize) -> GraphTensor {
+        let axes = axes.to_axes();
+        let n = axes.iter().map(|i| self.dims()[*i]).product::<Expression>();
+        let centered = self.mean_norm(axes.clone());
+        (centered * centered).sum_reduce(axes) / (n - correction)
+    }
+
+    /// Take the standard deviation along the axes, dividing by `N - correction`.
+    pub fn std(self, axes: impl ToAxes, correction: usize) -> GraphTensor {
+        self.var(axes, correction).sqrt()
+    }
+
+    /// Take `ln(sum(exp(x)))` along the axes, subtracting the max first so large inputs don't
+    /// overflow.
+    pub fn logsumexp(self, axes: impl ToAxes) -> GraphTensor {
+        let axes = axes.to_axes();
+        let max = self.max_reduce(axes.clone());
+        // Rows that are all infinite would give inf - inf, so don't shift those
+        let bound = self.graph().constant(f32::MAX).expand_to(max.shape);
+        let finite = max.less_than_equal(bound) * (-bound).less_than_equal(max);
+        let max = max.where_(finite, self.graph().constant(0.).expand_to(max.shape));
+        (self - max.expand_to(self.shape))
+            .exp()
+            .sum_reduce(axes)
+            .ln()
+            + max
+    }
+
+    /// Take the p-norm along the axes. `p` can be `f32::INFINITY` for the max norm or
+    /// `f32::NEG_INFINITY` for the min norm, and a `p` of 0 counts the nonzero elements.
+    pub fn norm(self, p: f32, axes: impl ToAxes) -> GraphTensor {
+        if p == f32::INFINITY {
+            self.abs().max_reduce(axes)
+        } else if p == f32::NEG_INFINITY {
+            self.abs().min_reduce(axes)
+        } else if p == 0. {
+            self.not_equals(self.graph().constant(0.).expand_to(self.shape))
+                .sum_reduce(axes)
+        } else if p == 1. {
+            self.abs().sum_reduce(axes)
+        } else if p == 2. {
+            (self * self).sum_reduce(axes).sqrt()
+        } else {
+            self.abs().pow(p).sum_reduce(axes).pow(1. / p)
+        }
+    }
+
+    /// Check if any element along the axes is nonzero, giving 1 if so and 0 otherwise.
+    pub fn any(self, axes: impl ToAxes) -> GraphTensor {
+        self.not_equals(self.graph().constant(0.).expand_to(self.shape))
+            .max_reduce(axes)
+    }
+
+    /// Check if all elements along the axes are nonzero, giving 1 if so and 0 otherwise.
+    pub fn all(self, axes: impl ToAxes) -> GraphTensor {
+        self.not_equals(self.graph().constant(0.).expand_to(self.shape))
+            .min_reduce(axes)
+    }
 }
 
 #[cfg(test)]
@@ -86,6 +157,27 @@ mod tests {
         assert_close(&b.data(), &d_b.as_vec());
     }
 
+    #[test]
+    fn test_permuted_reduce() {
+        let mut cx = Graph::new();
+        let a_data = random_vec(24);
+        let a = cx.tensor((2, 3, 4)).set(a_data.clone());
+        let b = a.permute((2, 0, 1)).max_reduce(1).retrieve();
+        let c = a.permute((2, 0, 1)).sum_reduce((0, 2)).retrieve();
+
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_a = d_dev
+            .tensor_from_vec(a_data, (DConst::<2>, DConst::<3>, DConst::<4>))
+            .permute::<_, DAxes3<2, 0, 1>>();
+        let d_b = d_a.clone().max::<_, DAxis<1>>();
+        let d_c = d_a.sum::<_, DAxes2<0, 2>>();
+
+        assert_close(&b.data(), &d_b.as_vec());
+        assert_close(&c.data(), &d_c.as_vec());
+    }
+
     #[test]
     fn test_mean_reduce() {
         let mut cx = Graph::new();
@@ -101,4 +193,110 @@ mod tests {
 
         assert_close(&b.data(), &d_b.as_vec());
     }
+
+    #[test]
+    fn test_min_reduce() {
+        let mut cx = Graph::new();
+        let a_data = random_vec(6);
+        let a = cx.tensor((2, 3)).set(a_data.clone());
+        let b = a.min_reduce(1).retrieve();
+        let c = a.min_reduce(0).retrieve();
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_a = d_dev.tensor_from_vec(a_data, (DConst::<2>, DConst::<3>));
+        assert_close(&b.data(), &d_a.clone().min::<_, DAxis<1>>().as_vec());
+        assert_close(&c.data(), &d_a.min::<_, DAxis<0>>().as_vec());
+    }
+
+    #[test]
+    fn test_var_std() {
+        let mut cx = Graph::new();
+        let a_data = random_vec(24);
+        let a = cx.tensor((2, 3, 4)).set(a_data.clone());
+        let var = a.var(2, 0).retrieve();
+        let var_many = a.var((0, 2), 0).retrieve();
+        let std = a.std(1, 0).retrieve();
+        let sample_var = a.var(2, 1).retrieve();
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_a = d_dev.tensor_from_vec(a_data, (DConst::<2>, DConst::<3>, DConst::<4>));
+        let d_var = d_a.clone().var::<_, DAxis<2>>();
+        assert_close(&var.data(), &d_var.as_vec());
+        assert_close(
+            &var_many.data(),
+            &d_a.clone().var::<_, DAxes2<0, 2>>().as_vec(),
+        );
+        assert_close(&std.data(), &d_a.stddev::<_, DAxis<1>>(0.).as_vec());
+        assert_close(
+            &sample_var.data(),
+            &d_var
+                .as_vec()
+                .into_iter()
+                .map(|v| v * 4. / 3.)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn test_logsumexp() {
+        let mut cx = Graph::new();
+        let a_data = random_vec(6);
+        let a = cx.tensor((2, 3)).set(a_data.clone());
+        let b = a.logsumexp(1).retrieve();
+        let large = cx
+            .tensor((2, 3))
+            .set(vec![1000., 1000., 999., f32::NEG_INFINITY, 2., 1.])
+            .logsumexp(1)
+            .retrieve();
+        let neg_inf = cx
+            .tensor(2)
+            .set(vec![f32::NEG_INFINITY, f32::NEG_INFINITY])
+            .logsumexp(0)
+            .retrieve();
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_a = d_dev.tensor_from_vec(a_data, (DConst::<2>, DConst::<3>));
+        assert_close(&b.data(), &d_a.logsumexp::<_, DAxis<1>>().as_vec());
+        let lse = |v: &[f32]| v.iter().map(|i| i.exp()).sum::<f32>().ln();
+        assert_close(
+            &large.data(),
+            &[
+                1000. + lse(&[0., 0., -1.]),
+                lse(&[f32::NEG_INFINITY, 2., 1.]),
+            ],
+        );
+        assert_exact(&neg_inf.data(), &[f32::NEG_INFINITY]);
+    }
+
+    #[test]
+    fn test_norms_any_all() {
+        let mut cx = Graph::new();
+        let a = cx.tensor((2, 3)).set(vec![3., -4., 0., -1., 2., 2.]);
+        let norms =
+            [1., 2., 3., 0., f32::INFINITY, f32::NEG_INFINITY].map(|p| a.norm(p, 1).retrieve());
+        let total = a.norm(2., (0, 1)).retrieve();
+        let any = a.any(1).retrieve();
+        let all = a.all(1).retrieve();
+        let none = (a * 0.).any(0).retrieve();
+        cx.execute();
+
+        let expected = [
+            [7., 5.],
+            [5., 3.],
+            [91_f32.powf(1. / 3.), 17_f32.powf(1. / 3.)],
+            [2., 3.],
+            [4., 2.],
+            [0., 1.],
+        ];
+        for (norm, expected) in norms.iter().zip(expected) {
+            assert_close(&norm.data(), &expected);
+        }
+        assert_close(&total.data(), &[34_f32.sqrt()]);
+        assert_exact(&any.data(), &[1., 1.]);
+        assert_exact(&all.data(), &[0., 1.]);
+        assert_exact(&none.data(), &[0., 0., 0.]);
+    }
 }
@@ -15,7 +15,7 @@ impl GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Log2)
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -25,7 +25,7 @@ impl GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Exp2)
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -45,7 +45,7 @@ impl GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Recip)
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -55,7 +55,7 @@ impl GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Sin)
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -75,7 +75,7 @@ impl GraphTensor {
         let new_id = self
             .graph()
             .add_op(op::Sqrt)
-            .input(self.id, 0, self.shape)
+            .input(self.id, self.output, self.shape)
             .finish();
         GraphTensor::from_id(new_id, self.shape.contiguous(), self.graph_ref)
     }
@@ -116,23 +116,36 @@ impl GraphTensor {
 
     /// Applies a log softmax function along an axis
     pub fn log_softmax(self, axes: impl ToAxes) -> GraphTensor {
-        let m = self - self.max_reduce(axes.to_axes()).expand_to(self.shape);
-        m - m.exp().sum_reduce(axes.to_axes()).ln().expand_to(m.shape)
-    }
-
-    /// Get the indicies of the max elements along the last axis
-    pub fn argmax(self) -> GraphTensor {
-        // Get one-hot along last dimension
-        let x_equal = self.equals(self.max_reduce(self.shape.len() - 1).expand_to(self.shape));
-        // Create index arange for last dimension
-        let r = self
-            .graph()
-            .constant(1.)
-            .expand_to(ShapeTracker::new(self.shape.dims().last().unwrap()))
-            .cumsum_last_dim()
-            - 1.;
-        // Multiply one-hot by expanded index arange
-        (x_equal * r.expand_to(self.shape)).max_reduce(self.shape.len() - 1)
+        self - self.logsumexp(axes).expand_to(self.shape)
+    }
+
+    /// Get the indices of the max elements along an axis, keeping the reduced axis with size 1 if
+    /// `keepdim` is set. Ties resolve to the first index.
+    ///
+    /// Indices are returned as floats, since tensors don't have an integer type yet.
+    pub fn argmax(self, axis: usize, keepdim: bool) -> GraphTensor {
+        let dims = self.dims();
+        let n = dims[axis];
+        // Count down from n, so the first of several equal maxes gets the highest weight
+        let mut countdown = self.graph().arange(n) * -1. + n;
+        for (i, d) in dims.iter().enumerate() {
+            if i != axis {
+                countdown = countdown.expand(i, *d);
+            }
+        }
+        let x_equal = self.equals(self.max_reduce(axis).expand(axis, n));
+        let argmax = (x_equal * countdown).max_reduce(axis) * -1. + n;
+        if keepdim {
+            argmax.expand(axis, 1)
+        } else {
+            argmax
+        }
+    }
+
+    /// Get the indices of the min elements along an axis, keeping the reduced axis with size 1 if
+    /// `keepdim` is set. Ties resolve to the first index.
+    pub fn argmin(self, axis: usize, keepdim: bool) -> GraphTensor {
+        (-self).argmax(axis, keepdim)
     }
 
     /// Take the absolute value
@@ -177,6 +190,84 @@ impl GraphTensor {
         // Based on https://github.com/tinygrad/tinygrad/blob/9fc4465557831b614b56dd645eebc940ca0fa1bb/tinygrad/tensor.py#L1162C26-L1162C104
         0.5 * self * (1. + (0.7978845608 * self * (1. + 0.044715 * self * self)).tanh())
     }
+
+    /// The Gaussian Error Linear Unit activation function, computed exactly with `erf` rather than
+    /// the tanh approximation
+    pub fn exact_gelu(self) -> GraphTensor {
+        0.5 * self * (1. + (self * std::f32::consts::FRAC_1_SQRT_2).erf())
+    }
+}
+
+// Rounding and special functions, decomposed into primitives
+impl GraphTensor {
+    /// Round towards zero
+    pub fn trunc(self) -> GraphTensor {
+        // Every float at or above 2^23 is already an integer, which also keeps infinities intact
+        let exact = self
+            .abs()
+            .less_than(self.graph().constant(8388608.).expand_to(self.shape));
+        (self - self % 1.).where_(exact, self)
+    }
+
+    /// Round down to the nearest integer
+    pub fn floor(self) -> GraphTensor {
+        let t = self.trunc();
+        t - self.less_than(t)
+    }
+
+    /// Round up to the nearest integer
+    pub fn ceil(self) -> GraphTensor {
+        let t = self.trunc();
+        t + t.less_than(self)
+    }
+
+    /// Round to the nearest integer, rounding halfway cases away from zero like `f32::round`
+    pub fn round(self) -> GraphTensor {
+        let t = self.trunc();
+        let frac = self - t;
+        let half = self.graph().constant(0.5).expand_to(self.shape);
+        t + frac.greater_than_equal(half) - (-frac).greater_than_equal(half)
+    }
+
+    /// The Gauss error function. Uses the Abramowitz and Stegun approximation 7.1.26, which has
+    /// a max absolute error of 1.5e-7.
+    #[allow(clippy::excessive_precision)]
+    pub fn erf(self) -> GraphTensor {
+        let x = self.abs();
+        let t = (x * 0.3275911 + 1.).recip();
+        let poly = ((((t * 1.061405429 - 1.453152027) * t + 1.421413741) * t - 0.284496736) * t
+            + 0.254829592)
+            * t;
+        let out = 1. - poly * (-(x * x)).exp();
+        let zero = self.graph().constant(0.).expand_to(self.shape);
+        (-out).where_(self.less_than(zero), out)
+    }
+
+    /// Natural log of `1 + x`, accurate for `x` near zero
+    pub fn log1p(self) -> GraphTensor {
+        let (zero, one) = (
+            self.graph().constant(0.).expand_to(self.shape),
+            self.graph().constant(1.).expand_to(self.shape),
+        );
+        let u = self + 1.;
+        let d = u - 1.;
+        // Goldberg's trick: correct for the rounding error in u. If u rounds to 1, ln(1 + x) ~= x
+        let small = (self * u.ln() / d).where_(d.not_equals(zero), self);
+        small.where_(self.abs().less_than(one), u.ln())
+    }
+
+    /// `e^x - 1`, accurate for `x` near zero
+    pub fn expm1(self) -> GraphTensor {
+        let (zero, one) = (
+            self.graph().constant(0.).expand_to(self.shape),
+            self.graph().constant(1.).expand_to(self.shape),
+        );
+        let u = self.exp();
+        let d = u - 1.;
+        // Kahan's trick: correct for the rounding error in u. If u rounds to 1, e^x - 1 ~= x
+        let small = (d * self / u.ln()).where_(d.not_equals(zero), self);
+        small.where_(self.abs().less_than(one), d)
+    }
 }
 
 #[cfg(test)]
@@ -338,4 +429,95 @@ mod tests {
         let d_b = d_a.tanh();
         assert_close(&b.data(), &d_b.as_vec());
     }
+
+    #[test]
+    fn test_argmax() {
+        let mut cx = Graph::new();
+        let a = cx
+            .tensor((2, 's'))
+            .set_dyn(vec![1., 5., 5., -2., 3., -2., 0., 3.], (2, 4));
+        let last = a.argmax(1, false).retrieve();
+        let first = a.argmax(0, true).retrieve();
+        let min = a.argmin(1, true).retrieve();
+        let permuted = a.permute((1, 0)).argmin(0, false).retrieve();
+        cx.execute();
+
+        // Ties resolve to the first index
+        assert_exact(&last.data(), &[1., 0.]);
+        assert_eq!(first.dims().len(), 2);
+        assert_exact(&first.data(), &[1., 0., 0., 1.]);
+        assert_exact(&min.data(), &[3., 1.]);
+        assert_exact(&permuted.data(), &[3., 1.]);
+    }
+
+    #[test]
+    fn test_rounding() {
+        let mut cx = Graph::new();
+        let data = vec![
+            -2.5,
+            -1.7,
+            -0.5,
+            -0.2,
+            0.,
+            0.3,
+            0.5,
+            1.5,
+            2.7,
+            1e9,
+            f32::INFINITY,
+        ];
+        let a = cx.tensor(data.len()).set(data.clone());
+        let trunc = a.trunc().retrieve();
+        let floor = a.floor().retrieve();
+        let ceil = a.ceil().retrieve();
+        let round = a.round().retrieve();
+        cx.execute();
+
+        let map = |f: fn(f32) -> f32| data.iter().map(|i| f(*i)).collect::<Vec<_>>();
+        assert_exact(&trunc.data(), &map(f32::trunc));
+        assert_exact(&floor.data(), &map(f32::floor));
+        assert_exact(&ceil.data(), &map(f32::ceil));
+        assert_exact(&round.data(), &map(f32::round));
+    }
+
+    #[test]
+    fn test_erf() {
+        let mut cx = Graph::new();
+        let a_data = random_vec(6);
+        let a = cx.tensor((2, 3)).set(a_data.clone());
+        let b = cx.tensor(5).set(vec![0., 0.5, 1., -2., f32::NEG_INFINITY]);
+        let gelu = a.exact_gelu().retrieve();
+        let erf = b.erf().retrieve();
+        cx.execute();
+
+        let d_dev = Cpu::default();
+        let d_a = d_dev.tensor_from_vec(a_data, (DConst::<2>, DConst::<3>));
+        assert_close(&gelu.data(), &d_a.accurate_gelu().as_vec());
+        assert_close_precision(
+            &erf.data(),
+            &[0., 0.5204999, 0.8427008, -0.9953223, -1.],
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn test_log1p_expm1() {
+        let mut cx = Graph::new();
+        let data = vec![1e-7, -3e-5, 0.25, -0.6, 4., 20.];
+        let a = cx.tensor(data.len()).set(data.clone());
+        let log1p = a.log1p().retrieve();
+        let expm1 = a.expm1().retrieve();
+        cx.execute();
+
+        // Check relative error, which the naive versions lose for small inputs
+        for (out, f) in [(log1p, f64::ln_1p as fn(f64) -> f64), (expm1, f64::exp_m1)] {
+            for (o, i) in out.data().into_iter().zip(&data) {
+                let expected = f(*i as f64);
+                assert!(
+                    ((o as f64 - expected) / expected).abs() < 1e-5,
+                    "{o} is not close to {expected}"
+                );
+            }
+        }
+    }
 }
@@ -31,12 +31,28 @@ impl Tensor {
     pub fn is<T: Data>(&self) -> bool {
         self.data.as_any().is::<T>()
     }
+    /// Size of the tensor's buffer in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.data.size_bytes()
+    }
+    /// Address of the tensor's buffer
+    pub fn buffer_ptr(&self) -> *const u8 {
+        self.data.buffer_ptr()
+    }
 }
 
 /// Some sort of data, for instance a Vec<f32> on CPU, CudaSlice<f32> on Nvidia GPUs, or metal::Buffer for Apple GPUs
 pub trait Data: Any + Debug + DynClone {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Size of the underlying buffer in bytes, used for memory statistics
+    fn size_bytes(&self) -> usize {
+        0
+    }
+    /// Address of the underlying buffer, used to spot outputs written in-place into an input
+    fn buffer_ptr(&self) -> *const u8 {
+        std::ptr::null()
+    }
 }
 
 clone_trait_object!(Data);
@@ -48,6 +64,12 @@ impl Data for Vec<f32> {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
+    fn size_bytes(&self) -> usize {
+        self.len() * std::mem::size_of::<f32>()
+    }
+    fn buffer_ptr(&self) -> *const u8 {
+        self.as_ptr() as *const u8
+    }
 }
 
 /// Either an owned or borrowed tensor that gets consumed by ops
@@ -185,10 +207,15 @@ impl Operator for Constant {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Contiguous;
 impl Operator for Contiguous {
-    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    fn process(&mut self, mut inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        // Already contiguous and not used anywhere else, so pass the buffer straight through
+        let len = inp[0].1.n_elements().to_usize().unwrap();
+        if let Some(data) = reuse_buffer(&mut inp[0], len) {
+            return vec![Tensor::new(data)];
+        }
         // Copy data over to new tensor
         let inp_data = get_vec(&inp[0].0);
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
+        let mut out_data = vec![0.; len];
         let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
         let mut stack = vec![];
         for (i, out) in out_data.iter_mut().enumerate() {
@@ -202,14 +229,7 @@ impl Operator for Contiguous {
 pub struct Log2;
 impl Operator for Log2 {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let inp_data = get_vec(&inp[0].0);
-        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(inp_data, &expr, &mut stack, i).log2();
-        }
-        vec![Tensor::new(out_data)]
+        unary_elementwise(inp, |a| a.log2())
     }
 }
 
@@ -217,14 +237,7 @@ impl Operator for Log2 {
 pub struct Exp2;
 impl Operator for Exp2 {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let inp_data = get_vec(&inp[0].0);
-        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(inp_data, &expr, &mut stack, i).exp2();
-        }
-        vec![Tensor::new(out_data)]
+        unary_elementwise(inp, |a| a.exp2())
     }
 }
 
@@ -232,14 +245,7 @@ impl Operator for Exp2 {
 pub struct Sin;
 impl Operator for Sin {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let inp_data = get_vec(&inp[0].0);
-        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(inp_data, &expr, &mut stack, i).sin();
-        }
-        vec![Tensor::new(out_data)]
+        unary_elementwise(inp, |a| a.sin())
     }
 }
 
@@ -247,14 +253,7 @@ impl Operator for Sin {
 pub struct Recip;
 impl Operator for Recip {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let inp_data = get_vec(&inp[0].0);
-        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(inp_data, &expr, &mut stack, i).recip();
-        }
-        vec![Tensor::new(out_data)]
+        unary_elementwise(inp, |a| a.recip())
     }
 }
 
@@ -262,14 +261,7 @@ impl Operator for Recip {
 pub struct Sqrt;
 impl Operator for Sqrt {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let inp_data = get_vec(&inp[0].0);
-        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(inp_data, &expr, &mut stack, i).sqrt();
-        }
-        vec![Tensor::new(out_data)]
+        unary_elementwise(inp, |a| a.sqrt())
     }
 }
 
@@ -279,15 +271,7 @@ impl Operator for Sqrt {
 pub struct Add;
 impl Operator for Add {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let (lhs, rhs) = (get_vec(&inp[0].0), get_vec(&inp[1].0));
-        let lexpr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let rexpr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
-        let mut stack = vec![];
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(lhs, &lexpr, &mut stack, i) + get_index(rhs, &rexpr, &mut stack, i);
-        }
-        vec![Tensor::new(out_data)]
+        binary_elementwise(inp, |a, b| a + b)
     }
 }
 
@@ -295,15 +279,7 @@ impl Operator for Add {
 pub struct Mul;
 impl Operator for Mul {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let (lhs, rhs) = (get_vec(&inp[0].0), get_vec(&inp[1].0));
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let lexpr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let rexpr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(lhs, &lexpr, &mut stack, i) * get_index(rhs, &rexpr, &mut stack, i);
-        }
-        vec![Tensor::new(out_data)]
+        binary_elementwise(inp, |a, b| a * b)
     }
 }
 
@@ -311,15 +287,7 @@ impl Operator for Mul {
 pub struct Mod;
 impl Operator for Mod {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let (lhs, rhs) = (get_vec(&inp[0].0), get_vec(&inp[1].0));
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let lexpr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let rexpr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
-        let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = get_index(lhs, &lexpr, &mut stack, i) % get_index(rhs, &rexpr, &mut stack, i);
-        }
-        vec![Tensor::new(out_data)]
+        binary_elementwise(inp, |a, b| a % b)
     }
 }
 
@@ -327,16 +295,95 @@ impl Operator for Mod {
 pub struct LessThan;
 impl Operator for LessThan {
     fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
-        let (lhs, rhs) = (get_vec(&inp[0].0), get_vec(&inp[1].0));
-        let mut out_data = vec![0.; inp[0].1.n_elements().to_usize().unwrap()];
-        let lexpr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
-        let rexpr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
+        binary_elementwise(inp, |a, b| (a < b) as i32 as f32)
+    }
+}
+
+// Ternary Ops (A x A x A -> A)
+
+/// Select elements from the second input where the first input is nonzero, and from the third
+/// input otherwise. The unselected element is never read, so infinities and NaNs in it don't reach
+/// the output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Where;
+impl Operator for Where {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let len = inp[0].1.n_elements().to_usize().unwrap();
+        let data = inp.iter().map(|(t, _)| get_vec(t)).collect::<Vec<_>>();
+        let exprs = inp
+            .iter()
+            .map(|(_, sh)| (sh.index_expression(), sh.valid_expression()))
+            .collect::<Vec<_>>();
         let mut stack = vec![];
-        for (i, out) in out_data.iter_mut().enumerate() {
-            *out = (get_index(lhs, &lexpr, &mut stack, i) < get_index(rhs, &rexpr, &mut stack, i))
-                as i32 as f32;
+        let out = (0..len)
+            .map(|i| {
+                let branch = if get_index(data[0], &exprs[0], &mut stack, i) != 0. {
+                    1
+                } else {
+                    2
+                };
+                get_index(data[branch], &exprs[branch], &mut stack, i)
+            })
+            .collect::<Vec<_>>();
+        vec![Tensor::new(out)]
+    }
+}
+
+// Write Ops (A x B -> A)
+
+/// Write a tensor into a buffer along a dimension, starting at a position.
+///
+/// The first input is the buffer, the second is the values to write, which must match the buffer on
+/// every dimension other than the one being written along. When nothing else uses the buffer it's
+/// written in-place, so writing costs the size of the values rather than the size of the buffer.
+#[derive(Clone, PartialEq)]
+pub struct WriteAlong {
+    pub dim: usize,
+    pub pos: Expression,
+    pub dyn_map: *const FxHashMap<char, usize>,
+}
+impl Debug for WriteAlong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WriteAlong({}, {:?})", self.dim, self.pos)
+    }
+}
+
+impl Operator for WriteAlong {
+    fn process(&mut self, mut inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let len = inp[0].1.n_elements().to_usize().unwrap();
+        let mut buffer = reuse_buffer(&mut inp[0], len).unwrap_or_else(|| {
+            // Buffer is used elsewhere, so write into a copy
+            let data = get_vec(&inp[0].0);
+            let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+            let mut stack = vec![];
+            (0..len)
+                .map(|i| get_index(data, &expr, &mut stack, i))
+                .collect()
+        });
+        let pos = self
+            .pos
+            .exec(unsafe { self.dyn_map.as_ref().unwrap() })
+            .unwrap();
+        let (buf_shape, val_shape) = (inp[0].1.shape_usize(), inp[1].1.shape_usize());
+        let (dim_size, max_size) = (val_shape[self.dim], buf_shape[self.dim]);
+        assert!(
+            pos + dim_size <= max_size,
+            "Writing {dim_size} elements at position {pos} overflows a buffer of size {max_size}"
+        );
+        let back_size = val_shape[self.dim + 1..].iter().product::<usize>();
+        let values = get_vec(&inp[1].0);
+        let expr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
+        let mut stack = vec![];
+        for i in 0..inp[1].1.n_elements().to_usize().unwrap() {
+            let (front, j, back) = (
+                i / (dim_size * back_size),
+                (i / back_size) % dim_size,
+                i % back_size,
+            );
+            buffer[(front * max_size + pos + j) * back_size + back] =
+                get_index(values, &expr, &mut stack, i);
         }
-        vec![Tensor::new(out_data)]
+        vec![Tensor::new(buffer)]
     }
 }
 
@@ -393,10 +440,425 @@ impl Operator for MaxReduce {
     }
 }
 
+// Scan Ops (A -> A)
+
+/// How elements are combined in a scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanKind {
+    Sum,
+    Prod,
+    Max,
+    Min,
+    LogSumExp,
+}
+
+impl ScanKind {
+    fn identity(&self) -> f32 {
+        match self {
+            ScanKind::Sum => 0.,
+            ScanKind::Prod => 1.,
+            ScanKind::Max | ScanKind::LogSumExp => f32::NEG_INFINITY,
+            ScanKind::Min => f32::INFINITY,
+        }
+    }
+
+    fn combine(&self, acc: f32, x: f32) -> f32 {
+        match self {
+            ScanKind::Sum => acc + x,
+            ScanKind::Prod => acc * x,
+            ScanKind::Max => acc.max(x),
+            ScanKind::Min => acc.min(x),
+            ScanKind::LogSumExp => {
+                let (max, min) = (acc.max(x), acc.min(x));
+                if min == f32::NEG_INFINITY {
+                    max
+                } else {
+                    max + (min - max).exp().ln_1p()
+                }
+            }
+        }
+    }
+}
+
+/// Inclusive scan along a dimension: each output is every input up to and including it along the
+/// dimension combined together. Backends without a scan kernel can lower this with `DecomposeScans`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+    pub axis: usize,
+    pub kind: ScanKind,
+}
+impl Operator for Scan {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let sh = inp[0].1.shape_usize();
+        let front_size = sh.iter().take(self.axis).product::<usize>();
+        let back_size = sh.iter().skip(self.axis + 1).product::<usize>();
+        let dim_size = sh[self.axis];
+        let mut result = vec![0.0; front_size * dim_size * back_size];
+        let input = get_vec(&inp[0].0);
+        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+        let mut stack = vec![];
+        for i in 0..front_size {
+            for j in 0..back_size {
+                let mut acc = self.kind.identity();
+                for k in 0..dim_size {
+                    let index = i * dim_size * back_size + k * back_size + j;
+                    acc = self
+                        .kind
+                        .combine(acc, get_index(input, &expr, &mut stack, index));
+                    result[index] = acc;
+                }
+            }
+        }
+        vec![Tensor::new(result)]
+    }
+}
+
+// Sort Ops (A -> B x B)
+
+/// Sort along a dimension, keeping the first `k` elements (or all of them if `k` is None).
+///
+/// Has two outputs: the sorted values, and the indices they came from along the dimension. Ties keep
+/// their original order. There's no device kernel for this yet, so GPU backends copy its input back
+/// to the host to run it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort {
+    pub axis: usize,
+    pub descending: bool,
+    pub k: Option<usize>,
+}
+impl Operator for Sort {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let sh = inp[0].1.shape_usize();
+        let front_size = sh.iter().take(self.axis).product::<usize>();
+        let back_size = sh.iter().skip(self.axis + 1).product::<usize>();
+        let dim_size = sh[self.axis];
+        let k = self.k.unwrap_or(dim_size);
+        assert!(
+            k <= dim_size,
+            "Can't take the top {k} of a dimension of size {dim_size}"
+        );
+        let mut values = vec![0.0; front_size * k * back_size];
+        let mut indices = vec![0.0; front_size * k * back_size];
+        let input = get_vec(&inp[0].0);
+        let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+        let mut stack = vec![];
+        let mut line = Vec::with_capacity(dim_size);
+        for i in 0..front_size {
+            for j in 0..back_size {
+                line.clear();
+                line.extend((0..dim_size).map(|d| {
+                    let index = i * dim_size * back_size + d * back_size + j;
+                    (d, get_index(input, &expr, &mut stack, index))
+                }));
+                if self.descending {
+                    line.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+                } else {
+                    line.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+                }
+                for (d, (ind, val)) in line.iter().take(k).enumerate() {
+                    let index = i * k * back_size + d * back_size + j;
+                    values[index] = *val;
+                    indices[index] = *ind as f32;
+                }
+            }
+        }
+        vec![Tensor::new(values), Tensor::new(indices)]
+    }
+}
+
+// Index Ops
+
+/// Gather entries along a dimension of a source by index.
+///
+/// Input 0 is the indices, laid out in the shape of the output, and input 1 is the source. Every
+/// dimension other than `axis` matches between the two. Backends without a gather kernel can lower
+/// this with `DecomposeGathers`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gather {
+    pub axis: usize,
+}
+impl Operator for Gather {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let sh = inp[0].1.shape_usize();
+        let dim_size = sh[self.axis];
+        let back_size = sh.iter().skip(self.axis + 1).product::<usize>();
+        let n = inp[1].1.shape_usize()[self.axis];
+        let (indexes, source) = (get_vec(&inp[0].0), get_vec(&inp[1].0));
+        let index_expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+        let source_expr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
+        let mut stack = vec![];
+        let out = (0..sh.iter().product::<usize>())
+            .map(|i| {
+                let ind = to_index(get_index(indexes, &index_expr, &mut stack, i), n);
+                let (front, back) = (i / (dim_size * back_size), i % back_size);
+                get_index(
+                    source,
+                    &source_expr,
+                    &mut stack,
+                    front * n * back_size + ind * back_size + back,
+                )
+            })
+            .collect::<Vec<_>>();
+        vec![Tensor::new(out)]
+    }
+}
+
+/// Write entries of a source into a tensor along a dimension by index, the inverse of `Gather`.
+///
+/// Input 0 is the tensor written into, input 1 is the indices and input 2 is the source, laid out
+/// in the shape of the indices. Every dimension other than `axis` matches between the inputs. With
+/// `add` set, entries written to the same place are summed onto the tensor, otherwise the last
+/// write wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scatter {
+    pub axis: usize,
+    pub add: bool,
+}
+impl Operator for Scatter {
+    fn process(&mut self, mut inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let len = inp[0].1.n_elements().to_usize().unwrap();
+        let mut out = reuse_buffer(&mut inp[0], len).unwrap_or_else(|| {
+            let data = get_vec(&inp[0].0);
+            let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+            let mut stack = vec![];
+            (0..len)
+                .map(|i| get_index(data, &expr, &mut stack, i))
+                .collect()
+        });
+        let n = inp[0].1.shape_usize()[self.axis];
+        let sh = inp[1].1.shape_usize();
+        let dim_size = sh[self.axis];
+        let back_size = sh.iter().skip(self.axis + 1).product::<usize>();
+        let (indexes, source) = (get_vec(&inp[1].0), get_vec(&inp[2].0));
+        let index_expr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
+        let source_expr = (inp[2].1.index_expression(), inp[2].1.valid_expression());
+        let mut stack = vec![];
+        for i in 0..sh.iter().product::<usize>() {
+            let ind = to_index(get_index(indexes, &index_expr, &mut stack, i), n);
+            let (front, back) = (i / (dim_size * back_size), i % back_size);
+            let val = get_index(source, &source_expr, &mut stack, i);
+            let o = &mut out[front * n * back_size + ind * back_size + back];
+            if self.add {
+                *o += val;
+            } else {
+                *o = val;
+            }
+        }
+        vec![Tensor::new(out)]
+    }
+}
+
+// Loop Ops
+
+/// Run a body graph once per step along the first dimension of the sequence inputs, carrying state from each
+/// step to the next. Since the body isn't unrolled, the number of steps can be a dynamic dimension.
+///
+/// Inputs are the sequences, then the initial carried state, then captured tensors that every step reads.
+/// Outputs are the final carried state, then each per-step output stacked along a new first dimension. Only
+/// runs on the host, so the body's inputs and outputs are `Vec<f32>` tensors.
+pub struct Loop {
+    pub body: Box<Graph>,
+    /// Body input nodes for each sequence, carry and capture, in that order
+    pub inputs: Vec<NodeIndex>,
+    /// Body output nodes for each carry and then each per-step output
+    pub outputs: Vec<NodeIndex>,
+    pub output_indexes: Vec<u8>,
+    /// Shapes of the body outputs
+    pub output_shapes: Vec<ShapeTracker>,
+    pub n_sequences: usize,
+    pub n_carries: usize,
+    /// Run from the last step to the first. Per-step outputs are still stacked in sequence order.
+    pub reverse: bool,
+    pub dyn_map: *const FxHashMap<char, usize>,
+}
+
+impl Debug for Loop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Each loop has its own body, so never let two compare equal
+        write!(f, "Loop({:p})", self.body)
+    }
+}
+
+impl Loop {
+    /// Compile the body graph, keeping track of its inputs and outputs
+    pub fn compile_body<C: Compiler>(&mut self, compiler: C) {
+        self.body
+            .compile(compiler, (&mut self.inputs, &mut self.outputs));
+    }
+}
+
+impl Operator for Loop {
+    fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+        let dyn_map = unsafe { self.dyn_map.as_ref().unwrap() };
+        self.body.dyn_map = dyn_map.clone();
+        let contiguous = |(tensor, shape): &(InputTensor, ShapeTracker)| {
+            let data = get_vec(tensor);
+            let expr = (shape.index_expression(), shape.valid_expression());
+            let mut stack = vec![];
+            (0..shape.n_elements().to_usize().unwrap())
+                .map(|i| get_index(data, &expr, &mut stack, i))
+                .collect::<Vec<_>>()
+        };
+        let (n_seqs, n_carries) = (self.n_sequences, self.n_carries);
+        let steps = inp[0].1.shape_usize()[0];
+        let sequences = inp[..n_seqs].iter().map(contiguous).collect::<Vec<_>>();
+        let step_sizes = inp[..n_seqs]
+            .iter()
+            .map(|(_, sh)| sh.shape_usize()[1..].iter().product::<usize>())
+            .collect::<Vec<_>>();
+        let mut carries = inp[n_seqs..n_seqs + n_carries]
+            .iter()
+            .map(contiguous)
+            .collect::<Vec<_>>();
+        let captures = &inp[n_seqs + n_carries..];
+        for (id, capture) in self.inputs[n_seqs + n_carries..].iter().zip(captures) {
+            self.body
+                .tensors
+                .insert((*id, 0), Tensor::new(contiguous(capture)));
+        }
+        let output_sizes = self.output_shapes[n_carries..]
+            .iter()
+            .map(|sh| sh.n_elements().exec(dyn_map).unwrap())
+            .collect::<Vec<_>>();
+        let mut stacked = output_sizes
+            .iter()
+            .map(|size| vec![0.; size * steps])
+            .collect::<Vec<_>>();
+
+        for step in 0..steps {
+            let step = if self.reverse { steps - 1 - step } else { step };
+            for (i, id) in self.inputs[..n_seqs].iter().enumerate() {
+                let size = step_sizes[i];
+                let slice = sequences[i][step * size..(step + 1) * size].to_vec();
+                self.body.tensors.insert((*id, 0), Tensor::new(slice));
+            }
+            for (id, carry) in self.inputs[n_seqs..].iter().zip(&mut carries) {
+                self.body
+                    .tensors
+                    .insert((*id, 0), Tensor::new(std::mem::take(carry)));
+            }
+            self.body.execute();
+            // The same node can be several outputs (like a hidden state that's both carried and output)
+            let outputs = self
+                .outputs
+                .iter()
+                .zip(&self.output_indexes)
+                .zip(&self.output_shapes)
+                .map(|((id, ind), sh)| {
+                    let len = sh.n_elements().exec(dyn_map).unwrap();
+                    self.body.tensors[&(*id, *ind)]
+                        .downcast_ref::<Vec<f32>>()
+                        .unwrap()[..len]
+                        .to_vec()
+                })
+                .collect::<Vec<_>>();
+            for (id, ind) in self.outputs.iter().zip(&self.output_indexes) {
+                self.body.tensors.remove(&(*id, *ind));
+            }
+            let mut outputs = outputs.into_iter();
+            for carry in carries.iter_mut() {
+                *carry = outputs.next().unwrap();
+            }
+            for ((out, size), buffer) in outputs.zip(&output_sizes).zip(&mut stacked) {
+                buffer[step * size..(step + 1) * size].copy_from_slice(&out);
+            }
+        }
+        self.body.tensors.clear();
+        carries
+            .into_iter()
+            .chain(stacked)
+            .map(Tensor::new)
+            .collect()
+    }
+}
+
+/// Take the buffer of an owned input so an op can write its output into it in-place.
+///
+/// Only possible when no other op uses the input (so it's owned), and it's laid out exactly like an
+/// output of `len` elements, with the same length and type.
+pub fn reuse_buffer(inp: &mut (InputTensor, ShapeTracker), len: usize) -> Option<Vec<f32>> {
+    let InputTensor::Owned(tensor) = &mut inp.0 else {
+        return None;
+    };
+    if inp.1.is_reshaped() {
+        return None;
+    }
+    let data = tensor.downcast_mut::<Vec<f32>>()?;
+    (data.len() == len).then(|| std::mem::take(data))
+}
+
+/// Apply a function to each element of a tensor, reusing the input buffer if possible
+pub fn unary_elementwise(
+    mut inp: Vec<(InputTensor, ShapeTracker)>,
+    f: impl Fn(f32) -> f32,
+) -> Vec<Tensor> {
+    let len = inp[0].1.n_elements().to_usize().unwrap();
+    if let Some(mut data) = reuse_buffer(&mut inp[0], len) {
+        for a in data.iter_mut() {
+            *a = f(*a);
+        }
+        return vec![Tensor::new(data)];
+    }
+    let mut out_data = vec![0.; len];
+    let inp_data = get_vec(&inp[0].0);
+    let expr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+    let mut stack = vec![];
+    for (i, out) in out_data.iter_mut().enumerate() {
+        *out = f(get_index(inp_data, &expr, &mut stack, i));
+    }
+    vec![Tensor::new(out_data)]
+}
+
+/// Combine the elements of two tensors, reusing either input buffer if possible
+pub fn binary_elementwise(
+    mut inp: Vec<(InputTensor, ShapeTracker)>,
+    f: impl Fn(f32, f32) -> f32,
+) -> Vec<Tensor> {
+    let len = inp[0].1.n_elements().to_usize().unwrap();
+    let mut stack = vec![];
+    if let Some(mut data) = reuse_buffer(&mut inp[0], len) {
+        let rhs = get_vec(&inp[1].0);
+        let rexpr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
+        for (i, a) in data.iter_mut().enumerate() {
+            *a = f(*a, get_index(rhs, &rexpr, &mut stack, i));
+        }
+        return vec![Tensor::new(data)];
+    }
+    if let Some(mut data) = reuse_buffer(&mut inp[1], len) {
+        let lhs = get_vec(&inp[0].0);
+        let lexpr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = f(get_index(lhs, &lexpr, &mut stack, i), *b);
+        }
+        return vec![Tensor::new(data)];
+    }
+    let (lhs, rhs) = (get_vec(&inp[0].0), get_vec(&inp[1].0));
+    let lexpr = (inp[0].1.index_expression(), inp[0].1.valid_expression());
+    let rexpr = (inp[1].1.index_expression(), inp[1].1.valid_expression());
+    let mut out_data = vec![0.; len];
+    for (i, out) in out_data.iter_mut().enumerate() {
+        *out = f(
+            get_index(lhs, &lexpr, &mut stack, i),
+            get_index(rhs, &rexpr, &mut stack, i),
+        );
+    }
+    vec![Tensor::new(out_data)]
+}
+
 fn get_vec<'a>(tensor: &'a InputTensor<'a>) -> &'a Vec<f32> {
     tensor.borrowed().downcast_ref::<Vec<f32>>().unwrap()
 }
 
+/// Convert a float index into a position along a dimension of size `n`, checking it's in bounds.
+/// Casting alone would saturate negative indices to 0.
+fn to_index(ind: f32, n: usize) -> usize {
+    assert!(
+        ind >= 0. && ind.fract() == 0. && (ind as usize) < n,
+        "Index {ind} out of bounds for dimension of size {n}"
+    );
+    ind as usize
+}
+
 fn get_index(
     data: &[f32],
     (ind, val): &(Expression, Expression),
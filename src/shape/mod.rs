@@ -182,6 +182,19 @@ impl<const N: usize, A: Into<Expression> + Copy, B: Into<Expression> + Copy> ToS
     }
 }
 
+/// How to fill padding added by `GraphTensor::pad_with`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PadMode {
+    /// Fill the padding with a value
+    Constant(f32),
+    /// Mirror the tensor at its edges, not repeating the edge element
+    Reflect,
+    /// Repeat the edge element
+    Replicate,
+    /// Wrap around to the other end of the tensor
+    Circular,
+}
+
 pub trait ToPad {
     fn to_pad_vec(self) -> Vec<(Expression, Expression)>;
 }
@@ -197,9 +197,30 @@ impl Expression {
         if self.terms.read().len() == 1 {
             return self;
         }
+        // Static expressions can be evaluated outright, which also folds inexact division
+        if let Some(n) = self.fold_constants() {
+            return n.into();
+        }
         egg_simplify(self)
     }
 
+    /// Evaluate an expression with no variables, if it doesn't overflow or divide by zero
+    fn fold_constants(&self) -> Option<i32> {
+        let mut stack = Vec::new();
+        for term in self.terms.read().iter() {
+            match term {
+                Term::Num(n) => stack.push(*n as i64),
+                Term::Var(_) => return None,
+                _ => {
+                    let a = stack.pop()?;
+                    let b = stack.pop()?;
+                    stack.push(term.as_op()?(a, b)?);
+                }
+            }
+        }
+        i32::try_from(stack.pop()?).ok()
+    }
+
     /// Simplify the expression to its minimal terms, using a cache to retrieve / store the simplification
     #[allow(clippy::mutable_key_type)]
     pub fn simplify_cache(self, cache: &mut FxHashMap<Expression, Expression>) -> Self {
@@ -1012,6 +1033,19 @@ fn is_const_positive(vars: &[&str]) -> impl Fn(&mut EGraph, Id, &Subst) -> bool
     }
 }
 
+/// Whether `var` is known to be a whole multiple of `of`, so dividing it doesn't round
+fn is_multiple(var: &str, of: &str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+    let (var, of) = (var.parse().unwrap(), of.parse().unwrap());
+    move |egraph, _, subst| {
+        let (a, b) = (egraph.find(subst[var]), egraph.find(subst[of]));
+        match (egraph[a].data, egraph[b].data) {
+            (Some(a), Some(b)) => b != 0 && a % b == 0,
+            (_, Some(0)) => false,
+            _ => a == b,
+        }
+    }
+}
+
 fn make_rules() -> Vec<Rewrite> {
     vec![
         // Communative properties
@@ -1025,12 +1059,13 @@ fn make_rules() -> Vec<Rewrite> {
         rewrite!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
         rewrite!("assoc-mul"; "(* ?a (* ?b ?c))" => "(* (* ?a ?b) ?c)"),
         rewrite!("assoc-div"; "(/ (/ ?a ?b) ?c)" => "(/ ?a (* ?b ?c))"),
-        rewrite!("mul-div-associative"; "(/ (* ?a ?b) ?c)" => "(* ?a (/ ?b ?c))"),
+        rewrite!("mul-div-associative"; "(/ (* ?a ?b) ?c)" => "(* ?a (/ ?b ?c))" if is_multiple("?b", "?c")),
         // rewrite!("mul-div-associative-rev"; "(* ?a (/ ?b ?c))" => "(/ (* ?a ?b) ?c)"), // BAD? Makes test_pool_1d fail
         rewrite!("sub-canon"; "(- ?a ?b)" => "(+ ?a (* -1 ?b))"),
         // Distributive
         rewrite!("distribute-mul"; "(* ?a (+ ?b ?c))" => "(+ (* ?a ?b) (* ?a ?c))"),
-        rewrite!("distribute-div"; "(/ (+ ?a ?b) ?c)" => "(+ (/ ?a ?c) (/ ?b ?c))"),
+        // Only whole multiples of the divisor can be split out of an integer division
+        rewrite!("distribute-div"; "(/ (+ (* ?a ?c) ?b) ?c)" => "(+ ?a (/ ?b ?c))" if is_not_zero("?c")),
         rewrite!("distribute-max"; "(* ?a (max ?b ?c))" => "(max (* ?a ?b) (* ?a ?c))" if is_const_positive(&["?a"])),
         rewrite!("distribute-min"; "(* ?a (min ?b ?c))" => "(min (* ?a ?b) (* ?a ?c))"),
         // rewrite!("distribute-mod"; "(* (% ?b ?c) ?a)" => "(% (* ?b ?a) (* ?c ?a))"),
@@ -1114,6 +1149,14 @@ mod tests {
         expression_cleanup();
     }
 
+    #[test]
+    fn test_fold_constants() {
+        let x = Expression::from('x');
+        let expr = ((x / 2) * 2 + (x % 3)).substitute('x', 7).simplify();
+        assert_eq!(expr, 7);
+        expression_cleanup();
+    }
+
     #[test]
     fn test_group_terms() {
         let s = Expression::from('s');
@@ -1130,6 +1173,31 @@ mod tests {
         expression_cleanup();
     }
 
+    #[test]
+    fn test_div_rounding() {
+        // Integer division doesn't distribute, so rounding has to survive simplification
+        let w = Expression::from('w');
+        let windows = ((w + 2) - 3) / 2 + 1;
+        let exprs = [
+            (w + 1) / 2,
+            (w + 2) * windows,
+            (w * 3) / 2,
+            (w * 3 + 3) / 2,
+            (w * 4 + 3) / 2,
+        ];
+        for w in 1..8 {
+            let dyn_map = [('w', w)].into_iter().collect();
+            for e in exprs {
+                assert_eq!(
+                    e.simplify().exec(&dyn_map),
+                    e.exec(&dyn_map),
+                    "{e} at w = {w}"
+                );
+            }
+        }
+        expression_cleanup();
+    }
+
     #[test]
     fn test_other() {
         let z = Expression::from('z');
@@ -3,6 +3,20 @@ use tinyvec::ArrayVec;
 
 use crate::prelude::*;
 
+/// What the padded positions of a dimension read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PadFill {
+    /// Padding reads as 0
+    #[default]
+    Zero,
+    /// Padding mirrors the dimension, not repeating the edge element
+    Reflect,
+    /// Padding repeats the edge element
+    Replicate,
+    /// Padding wraps around to the other end of the dimension
+    Circular,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ShapeTracker {
     pub dims: ArrayVec<[Expression; 6]>,
@@ -10,6 +24,7 @@ pub struct ShapeTracker {
     pub fake: ArrayVec<[bool; 6]>,
     pub mask: ArrayVec<[(Expression, Expression); 6]>,
     pub padding: ArrayVec<[(Expression, Expression); 6]>,
+    pub pad_fill: ArrayVec<[PadFill; 6]>,
 }
 
 impl ShapeTracker {
@@ -21,6 +36,7 @@ impl ShapeTracker {
             fake: Default::default(),
             mask: Default::default(),
             padding: Default::default(),
+            pad_fill: Default::default(),
         };
         for (i, d) in dims.to_shape().into_iter().enumerate() {
             s.dims.push(d);
@@ -28,6 +44,7 @@ impl ShapeTracker {
             s.fake.push(false);
             s.mask.push((0.into(), i32::MAX.into())); // Unset upper bound mask are i32::MAX
             s.padding.push((0.into(), 0.into()));
+            s.pad_fill.push(PadFill::Zero);
         }
         s
     }
@@ -46,6 +63,7 @@ impl ShapeTracker {
         self.fake.push(false);
         self.mask.push((0.into(), i32::MAX.into()));
         self.padding.push((0.into(), 0.into()));
+        self.pad_fill.push(PadFill::Zero);
     }
 
     /// Add fake dim along a certian axis
@@ -65,6 +83,7 @@ impl ShapeTracker {
         }
         self.mask.remove(index);
         self.padding.remove(index);
+        self.pad_fill.remove(index);
         self.dims.remove(index)
     }
 
@@ -122,8 +141,18 @@ impl ShapeTracker {
                 dim_ind /= current_elem_size;
                 // Get position in current dim
                 dim_ind %= current_size;
-                // Add offset
-                dim_ind += self.mask[i].0 - self.padding[i].0;
+                if self.pad_fill[i] == PadFill::Zero {
+                    // Add offset
+                    dim_ind += self.mask[i].0 - self.padding[i].0;
+                } else {
+                    // Map positions in the padding back into the dimension
+                    dim_ind = fill_padding(
+                        dim_ind + self.mask[i].0,
+                        self.padding[i].0,
+                        self.dims[i],
+                        self.pad_fill[i],
+                    );
+                }
                 // Multiply by stride
                 dim_ind *= strides[i];
                 // Add to index expression
@@ -151,13 +180,19 @@ impl ShapeTracker {
         for i in self.indexes.into_iter().rev() {
             let (bottom_slice, top_slice) = self.mask[i];
             let logical_sh = pad_mask_dim(self.dims[i], self.padding[i], self.mask[i]);
-            if !self.fake[i] {
-                let dim_ind = (logical / acc) % logical_sh;
-                let greater_than = self.padding[i].0 - bottom_slice;
-                if greater_than != 0 {
-                    ret &= dim_ind.gte(greater_than);
+            // Fake dimensions aren't read from, but padding or slicing them still masks elements out
+            let padded_or_sliced = self.padding[i] != (0.into(), 0.into())
+                || self.mask[i] != (0.into(), i32::MAX.into());
+            if !self.fake[i] || padded_or_sliced {
+                // Padding that isn't zeros is read from the dimension, so it's always valid
+                if self.pad_fill[i] == PadFill::Zero {
+                    let dim_ind = (logical / acc) % logical_sh;
+                    let greater_than = self.padding[i].0 - bottom_slice;
+                    if greater_than != 0 {
+                        ret &= dim_ind.gte(greater_than);
+                    }
+                    ret &= dim_ind.lt(self.dims[i] + self.padding[i].0);
                 }
-                ret &= dim_ind.lt(self.dims[i] + self.padding[i].0);
                 if top_slice
                     .to_usize()
                     .map(|s| self.dims[i].to_usize().map(|dim| s < dim).unwrap_or(true))
@@ -285,6 +320,48 @@ impl ShapeTracker {
         }
     }
 
+    /// Add padding which reads from the dimension itself rather than being zeros. Padding must be
+    /// smaller than the dimension for `PadFill::Reflect`, and no bigger than it for
+    /// `PadFill::Circular`.
+    pub fn pad_with(&mut self, padding: &[(Expression, Expression)], fill: PadFill) {
+        for (ind, (s, e)) in padding
+            .iter()
+            .enumerate()
+            .map(|(i, m)| (self.indexes[i], m))
+        {
+            if s.to_usize() == Some(0) && e.to_usize() == Some(0) {
+                continue;
+            }
+            if self.fake[ind] {
+                // Every element of an expanded dimension is the same, so just expand it further
+                self.dims[ind] = self.dims[ind] + *s + *e;
+                continue;
+            }
+            assert!(
+                self.padding[ind] == (0.into(), 0.into())
+                    && self.mask[ind] == (0.into(), i32::MAX.into()),
+                "Adding {fill:?} padding to a padded or masked dimension isn't supported"
+            );
+            if let (Some(s), Some(e), Some(dim)) =
+                (s.to_usize(), e.to_usize(), self.dims[ind].to_usize())
+            {
+                match fill {
+                    PadFill::Reflect => assert!(
+                        s < dim && e < dim,
+                        "Reflect padding must be smaller than the dimension ({dim})"
+                    ),
+                    PadFill::Circular => assert!(
+                        s <= dim && e <= dim,
+                        "Circular padding can't be bigger than the dimension ({dim})"
+                    ),
+                    _ => {}
+                }
+            }
+            self.padding[ind] = (*s, *e);
+            self.pad_fill[ind] = fill;
+        }
+    }
+
     /// Given a dyn dim map, resolve global dyn dims into known dims
     pub fn resolve_global_dyn_dims(&mut self, dyn_dim_map: &FxHashMap<char, usize>) {
         self.resolve_global_dyn_dims_stack(dyn_dim_map, &mut Vec::new());
@@ -324,6 +401,30 @@ impl ShapeTracker {
     }
 }
 
+/// Map a position in a padded dimension (counting from the start of the padding) to a position in
+/// the dimension, for padding that isn't zeros
+fn fill_padding(
+    pos: Expression,
+    left_padding: Expression,
+    dim: Expression,
+    fill: PadFill,
+) -> Expression {
+    // Positions before the dimension are negative once the padding is removed, so these avoid
+    // going through negative numbers, which expression simplification assumes don't happen
+    match fill {
+        PadFill::Zero => pos - left_padding,
+        // min(max(p, 0), n - 1)
+        PadFill::Replicate => (pos.max(left_padding) - left_padding).min(dim - 1),
+        // |p| reflected off the far end: min(|p|, 2(n - 1) - |p|)
+        PadFill::Reflect => {
+            let abs = pos.max(left_padding) - pos.min(left_padding);
+            abs.min((dim - 1) * 2 - abs)
+        }
+        // (p + n) % n
+        PadFill::Circular => (pos + dim - left_padding) % dim,
+    }
+}
+
 fn pad_mask_dim(
     dim: impl Into<Expression>,
     padding: (Expression, Expression),
@@ -334,7 +435,7 @@ fn pad_mask_dim(
 
 #[cfg(test)]
 mod tests {
-    use crate::prelude::*;
+    use crate::{prelude::*, tests::assert_exact};
     #[test]
     fn test_idx_expr() {
         let mut tracker = ShapeTracker::new([
@@ -364,4 +465,17 @@ mod tests {
 
         println!("x0: {:?}", x0.shape.index_expression());
     }
+
+    #[test]
+    fn test_pad_fake_dim() {
+        let mut cx = Graph::new();
+        let a = cx.tensor(3).set([1., 2., 3.]);
+        let padded = a.expand(0, 2).pad_along(1, 1, 0).retrieve();
+        cx.execute();
+
+        assert_exact(
+            &padded.data(),
+            &[0., 0., 0., 1., 2., 3., 1., 2., 3., 0., 0., 0.],
+        );
+    }
 }
@@ -67,6 +67,31 @@ fn test_shapes() {
     assert_exact(&b.data(), &[1., 3., 2., 4.]);
 }
 
+#[test]
+fn test_in_place() {
+    let mut cx = Graph::new();
+    let a = cx.tensor(3).set(vec![1., 2., 3.]);
+    let b = cx.tensor(3).set(vec![4., 5., 6.]);
+    let c = (a.exp2() * b).sqrt().retrieve();
+    let d = cx.tensor((2, 3)).set(vec![1., 2., 3., 4., 5., 6.]);
+    let e = d.permute((1, 0)).sin().retrieve();
+    cx.execute();
+
+    assert_close(
+        &c.data(),
+        &[
+            (2_f32 * 4.).sqrt(),
+            (4_f32 * 5.).sqrt(),
+            (8_f32 * 6.).sqrt(),
+        ],
+    );
+    assert_close(&e.data(), &[1_f32, 4., 2., 5., 3., 6.].map(|i| i.sin()));
+    // Exp2, Mul and Sqrt all write into a's buffer, Sin can't since its input is permuted
+    assert_eq!(cx.memory_stats.in_place_outputs, 3);
+    assert_eq!(cx.memory_stats.reused_bytes, 3 * 3 * 4);
+    assert_eq!(cx.memory_stats.allocated_bytes, (3 + 3 + 6 + 6) * 4);
+}
+
 /// Ensure two arrays are nearly equal
 pub fn assert_close(a_vec: &[f32], b_vec: &[f32]) {
     assert_close_precision(a_vec, b_vec, 1e-3);